@@ -0,0 +1,143 @@
+//! Time from sending the attach signal to the process detecting it, for each attacher.
+//!
+//! The process signals itself, so finding the working directory of the target process is not
+//! part of the measure.
+//!
+//! On UNIX, the time for a client to connect to a socket bound while it waits is measured too.
+//! With the `inotify` feature, the client is woken as soon as the socket is bound instead of
+//! polling for it.
+
+use std::{
+    pin::pin,
+    task::Poll,
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::{executor::block_on, poll};
+#[cfg(unix)]
+use futures::{join, StreamExt};
+#[cfg(feature = "inotify")]
+use teleop::attach::attacher::inotify::InotifyAttacher;
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+use teleop::attach::attacher::kqueue::KqueueAttacher;
+#[cfg(unix)]
+use teleop::attach::attacher::unix::UnixAttacher;
+#[cfg(windows)]
+use teleop::attach::attacher::windows_dir::WindowsDirAttacher;
+use teleop::attach::attacher::{dummy::DummyAttacher, Attacher, AttacherSignal};
+#[cfg(unix)]
+use teleop::attach::unix_socket::{connect_at, listen_at};
+
+/// Measures `iters` attachments.
+///
+/// The watch is set up before the clock starts, and the attach file is removed after it stops.
+fn attach_latency<A>(iters: u64) -> Duration
+where
+    A: Attacher,
+{
+    block_on(async {
+        let pid = std::process::id();
+        let mut total = Duration::ZERO;
+        for _ in 0..iters {
+            let mut signaled = pin!(A::signaled());
+            // Only the dummy attacher is signaled right away
+            let pending = match poll!(signaled.as_mut()) {
+                Poll::Ready(outcome) => {
+                    outcome.unwrap();
+                    false
+                }
+                Poll::Pending => true,
+            };
+            let mut signal = A::signal(pid).unwrap();
+
+            let start = Instant::now();
+            signal.send().await.unwrap();
+            if pending {
+                signaled.await.unwrap();
+            }
+            total += start.elapsed();
+
+            drop(signal);
+        }
+        total
+    })
+}
+
+/// Measures `iters` connections, the socket being bound 1 millisecond after the client starts
+/// waiting for it.
+#[cfg(unix)]
+fn connect_latency(iters: u64) -> Duration {
+    let pid = std::process::id();
+    let socket_file_path = std::env::temp_dir().join(format!(".teleop_bench_connect_{pid}"));
+    block_on(async {
+        let mut total = Duration::ZERO;
+        for _ in 0..iters {
+            let (_handle, connections) = listen_at::<DummyAttacher>(socket_file_path.clone());
+            let mut connections = pin!(connections);
+
+            let start = Instant::now();
+            let (conn, client) = join!(
+                async {
+                    async_io::Timer::after(Duration::from_millis(1)).await;
+                    connections.next().await
+                },
+                connect_at::<DummyAttacher>(pid, &socket_file_path)
+            );
+            total += start.elapsed();
+
+            conn.unwrap().unwrap();
+            client.unwrap();
+        }
+        total
+    })
+}
+
+fn attach_latency_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("attach_latency");
+
+    group.bench_function("dummy", |b| b.iter_custom(attach_latency::<DummyAttacher>));
+    #[cfg(feature = "inotify")]
+    group.bench_function("inotify", |b| {
+        b.iter_custom(attach_latency::<InotifyAttacher>)
+    });
+    #[cfg(any(
+        target_os = "freebsd",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    ))]
+    group.bench_function("kqueue", |b| {
+        b.iter_custom(attach_latency::<KqueueAttacher>)
+    });
+    #[cfg(unix)]
+    group.bench_function("unix", |b| b.iter_custom(attach_latency::<UnixAttacher>));
+    #[cfg(windows)]
+    group.bench_function("windows_dir", |b| {
+        b.iter_custom(attach_latency::<WindowsDirAttacher>)
+    });
+
+    group.finish();
+}
+
+#[cfg(unix)]
+fn connect_latency_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("connect_latency");
+    // Each connection takes up to the polling interval without inotify
+    group.sample_size(10);
+
+    group.bench_function("unix_socket", |b| b.iter_custom(connect_latency));
+
+    group.finish();
+}
+
+#[cfg(not(unix))]
+fn connect_latency_benchmark(_c: &mut Criterion) {}
+
+criterion_group!(benches, attach_latency_benchmark, connect_latency_benchmark);
+criterion_main!(benches);
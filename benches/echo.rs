@@ -0,0 +1,137 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use futures::{executor::LocalPool, task::LocalSpawnExt};
+use teleop::operate::capnp::{
+    client_connection_with_options,
+    compression::Compression,
+    echo::{echo_capnp, EchoServer},
+    run_server_connection_with_options, teleop_capnp, ConnectedStream, ConnectionOptions,
+    TeleopServer,
+};
+
+fn setup(exec: &mut LocalPool, options: ConnectionOptions) -> echo_capnp::echo::Client {
+    let (client_input, server_output) = sluice::pipe::pipe();
+    let (server_input, client_output) = sluice::pipe::pipe();
+
+    let mut server = TeleopServer::new();
+    server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+    let client = capnp_rpc::new_client::<teleop_capnp::teleop::Client, _>(server);
+
+    let spawn = exec.spawner();
+
+    spawn
+        .spawn_local({
+            let options = options.clone();
+            async move {
+                if let Err(e) = run_server_connection_with_options(
+                    server_input,
+                    server_output,
+                    client.client.hook,
+                    options,
+                )
+                .await
+                {
+                    eprintln!("Server connection interrupted {e}");
+                }
+            }
+        })
+        .unwrap();
+
+    exec.run_until(async move {
+        let ConnectedStream {
+            rpc_system, teleop, ..
+        } = client_connection_with_options(client_input, client_output, options)
+            .await
+            .unwrap();
+
+        spawn
+            .spawn_local(async {
+                if let Err(e) = rpc_system.await {
+                    eprintln!("Connection interrupted {e}");
+                }
+            })
+            .unwrap();
+
+        let mut req = teleop.service_request();
+        req.get().set_name("echo");
+        let echo = req.send().promise.await.unwrap();
+        echo.get().unwrap().get_service().get_as().unwrap()
+    })
+}
+
+fn echo_once(exec: &mut LocalPool, echo: &echo_capnp::echo::Client, message: &str) {
+    exec.run_until(async {
+        let mut req = echo.echo_request();
+        req.get().set_message(message);
+        let reply = req.send().promise.await.unwrap();
+        assert_eq!(
+            reply.get().unwrap().get_reply().unwrap().len(),
+            message.len()
+        );
+    })
+}
+
+fn echo_round_trip(c: &mut Criterion) {
+    let mut group = c.benchmark_group("echo_round_trip");
+    for (name, packed) in [("unpacked", false), ("packed", true)] {
+        let mut exec = LocalPool::new();
+        let echo = setup(
+            &mut exec,
+            ConnectionOptions {
+                packed,
+                ..Default::default()
+            },
+        );
+        group.bench_function(name, |b| b.iter(|| echo_once(&mut exec, &echo, "hello!")));
+    }
+    group.finish();
+}
+
+fn echo_large_payload(c: &mut Criterion) {
+    let message = "x".repeat(1024 * 1024);
+    let mut group = c.benchmark_group("echo_large_payload");
+    group.throughput(Throughput::Bytes(message.len() as u64));
+    for (name, capacity) in [
+        ("8KiB", 8 * 1024),
+        ("64KiB", 64 * 1024),
+        ("1MiB", 1024 * 1024),
+    ] {
+        let mut exec = LocalPool::new();
+        let echo = setup(
+            &mut exec,
+            ConnectionOptions {
+                read_buffer_capacity: capacity,
+                write_buffer_capacity: capacity,
+                ..Default::default()
+            },
+        );
+        group.bench_function(name, |b| b.iter(|| echo_once(&mut exec, &echo, &message)));
+    }
+    group.finish();
+}
+
+fn echo_compression(c: &mut Criterion) {
+    // Highly compressible payload
+    let message = "teleop ".repeat(150_000);
+    let mut group = c.benchmark_group("echo_compression");
+    group.throughput(Throughput::Bytes(message.len() as u64));
+    for (name, compression) in [("none", Compression::None), ("lz4", Compression::Lz4)] {
+        let mut exec = LocalPool::new();
+        let echo = setup(
+            &mut exec,
+            ConnectionOptions {
+                compression,
+                ..Default::default()
+            },
+        );
+        group.bench_function(name, |b| b.iter(|| echo_once(&mut exec, &echo, &message)));
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    echo_round_trip,
+    echo_large_payload,
+    echo_compression
+);
+criterion_main!(benches);
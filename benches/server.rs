@@ -0,0 +1,73 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use futures::{executor::LocalPool, future::join_all, task::LocalSpawnExt};
+use teleop::operate::capnp::{
+    client_connection,
+    echo::{echo_capnp, EchoServer},
+    run_server_connection, teleop_capnp, TeleopServer,
+};
+
+/// Connects the passed number of clients to one shared server.
+fn setup(exec: &mut LocalPool, connections: usize) -> Vec<teleop_capnp::teleop::Client> {
+    let mut server = TeleopServer::new();
+    server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+    let server = server.into_client();
+
+    let spawn = exec.spawner();
+
+    (0..connections)
+        .map(|_| {
+            let (client_input, server_output) = sluice::pipe::pipe();
+            let (server_input, client_output) = sluice::pipe::pipe();
+
+            spawn
+                .spawn_local({
+                    let server = server.client.hook.clone();
+                    async move {
+                        if let Err(e) =
+                            run_server_connection(server_input, server_output, server).await
+                        {
+                            eprintln!("Server connection interrupted {e}");
+                        }
+                    }
+                })
+                .unwrap();
+
+            let (rpc_system, teleop) =
+                exec.run_until(client_connection(client_input, client_output));
+            spawn
+                .spawn_local(async {
+                    if let Err(e) = rpc_system.await {
+                        eprintln!("Connection interrupted {e}");
+                    }
+                })
+                .unwrap();
+
+            teleop
+        })
+        .collect()
+}
+
+fn service_lookup(exec: &mut LocalPool, clients: &[teleop_capnp::teleop::Client]) {
+    exec.run_until(join_all(clients.iter().map(|teleop| async {
+        let mut req = teleop.service_request();
+        req.get().set_name("echo");
+        req.send().promise.await.unwrap();
+    })));
+}
+
+fn service_lookup_contention(c: &mut Criterion) {
+    let mut group = c.benchmark_group("service_lookup_contention");
+    for connections in [1, 16, 64] {
+        let mut exec = LocalPool::new();
+        let clients = setup(&mut exec, connections);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(connections),
+            &clients,
+            |b, clients| b.iter(|| service_lookup(&mut exec, clients)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, service_lookup_contention);
+criterion_main!(benches);
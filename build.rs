@@ -6,10 +6,69 @@ fn main() {
         .run()
         .expect("compiled teleop");
 
+    capnpc::CompilerCommand::new()
+        .src_prefix("schema")
+        .file("schema/clock.capnp")
+        .default_parent_module(vec!["operate".to_owned(), "capnp::clock".to_owned()])
+        .run()
+        .expect("compiled clock");
+
+    capnpc::CompilerCommand::new()
+        .src_prefix("schema")
+        .file("schema/diagnostics.capnp")
+        .default_parent_module(vec!["operate".to_owned(), "capnp::diagnostics".to_owned()])
+        .run()
+        .expect("compiled diagnostics");
+
+    capnpc::CompilerCommand::new()
+        .src_prefix("schema")
+        .file("schema/dynamic.capnp")
+        .default_parent_module(vec!["operate".to_owned(), "capnp::dynamic".to_owned()])
+        .run()
+        .expect("compiled dynamic");
+
     capnpc::CompilerCommand::new()
         .src_prefix("schema")
         .file("schema/echo.capnp")
         .default_parent_module(vec!["operate".to_owned(), "capnp::echo".to_owned()])
         .run()
         .expect("compiled echo");
+
+    capnpc::CompilerCommand::new()
+        .src_prefix("schema")
+        .file("schema/factory.capnp")
+        .default_parent_module(vec!["operate".to_owned(), "capnp::factory".to_owned()])
+        .run()
+        .expect("compiled factory");
+
+    capnpc::CompilerCommand::new()
+        .src_prefix("schema")
+        .file("schema/file_transfer.capnp")
+        .default_parent_module(vec![
+            "operate".to_owned(),
+            "capnp::file_transfer".to_owned(),
+        ])
+        .run()
+        .expect("compiled file_transfer");
+
+    capnpc::CompilerCommand::new()
+        .src_prefix("schema")
+        .file("schema/handoff.capnp")
+        .default_parent_module(vec!["operate".to_owned(), "capnp::handoff".to_owned()])
+        .run()
+        .expect("compiled handoff");
+
+    capnpc::CompilerCommand::new()
+        .src_prefix("schema")
+        .file("schema/logging.capnp")
+        .default_parent_module(vec!["operate".to_owned(), "capnp::logging".to_owned()])
+        .run()
+        .expect("compiled logging");
+
+    capnpc::CompilerCommand::new()
+        .src_prefix("schema")
+        .file("schema/tower.capnp")
+        .default_parent_module(vec!["operate".to_owned(), "capnp::tower".to_owned()])
+        .run()
+        .expect("compiled tower");
 }
@@ -2,10 +2,9 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     use std::env::args;
 
-    use futures::{task::LocalSpawnExt, AsyncReadExt};
+    use futures::task::LocalSpawnExt;
     use teleop::{
-        attach::{attacher::DefaultAttacher, connect},
-        operate::capnp::{client_connection, echo::echo_capnp},
+        attach::attacher::DefaultAttacher, client::attach_client, operate::capnp::echo::echo_capnp,
     };
 
     let mut args = args();
@@ -19,10 +18,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let spawn = exec.spawner();
 
     let res = exec.run_until(async move {
-        let stream = connect::<DefaultAttacher>(pid).await?;
-        let (input, output) = stream.split();
-        let (rpc_system, teleop) = client_connection(input, output).await;
-        let rpc_disconnect = rpc_system.get_disconnector();
+        let (rpc_system, teleop, rpc_disconnect) = attach_client::<DefaultAttacher>(pid).await?;
 
         spawn.spawn_local(async {
             if let Err(e) = rpc_system.await {
@@ -50,7 +46,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
         .await;
 
-        let res2 = rpc_disconnect.await;
+        let res2 = rpc_disconnect.disconnect().await;
 
         res?;
 
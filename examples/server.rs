@@ -1,18 +1,15 @@
 #[cfg(any(unix, windows))]
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    use std::{
-        pin::pin,
-        sync::LazyLock,
-        time::{Duration, Instant},
-    };
+    use std::time::Duration;
+
+    use futures::task::LocalSpawnExt;
 
-    use async_io::Timer;
-    use futures::{task::LocalSpawnExt, AsyncReadExt, FutureExt};
     use teleop::{
-        attach::{attacher::DefaultAttacher, listen},
+        attach::attacher::DefaultAttacher,
+        cancellation::{CancelOutcome, CancellationToken},
         operate::capnp::{
             echo::{echo_capnp, EchoServer},
-            run_server_connection, teleop_capnp, TeleopServer,
+            serve, TeleopServer,
         },
     };
 
@@ -23,52 +20,25 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("Wrote it to {pid_file}");
     }
 
-    let mut exec = futures::executor::LocalPool::new();
-    let spawn = exec.spawner();
-
-    let res = exec.run_until(async {
-        let mut server_main = Timer::after(Duration::from_secs(7))
-            .map(|_: Instant| ())
-            .fuse();
-
-        let client = LazyLock::new(|| {
-            let mut server = TeleopServer::new();
-            server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
-            capnp_rpc::new_client::<teleop_capnp::teleop::Client, _>(server)
-        });
+    let mut server = TeleopServer::new();
+    server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
 
-        let mut conn_stream = pin!(listen::<DefaultAttacher>());
-        loop {
-            futures::select! {
-                stream = futures::StreamExt::next(&mut conn_stream).fuse() => {
-                    if let Some(stream) = stream {
-                        let (stream, _addr) = stream?;
-                        if let Err(e) = spawn.spawn_local({
-                            let client = client.client.hook.clone();
-                            async move {
-                                let (input, output) = stream.split();
-                                match run_server_connection(input, output, client).await {
-                                    Ok(()) => {}
-                                    Err(err) => {
-                                        eprintln!("Error while running server connection: {err}");
-                                    }
-                                }
-                            }
-                        }) {
-                            eprintln!("Error while spawning connection handler: {e}");
-                        }
-                    } else {
-                        break;
-                    }
-                }
-                () = server_main => {
-                    break;
-                }
+    let mut exec = futures::executor::LocalPool::new();
+    let spawner = exec.spawner();
+
+    // Stop listening after a while
+    let token = CancellationToken::new();
+    spawner.spawn_local({
+        let token = token.clone();
+        async move {
+            let timeout = token.cancelled_or_timeout(Duration::from_secs(7));
+            if timeout.await == CancelOutcome::TimedOut {
+                token.cancel();
             }
         }
+    })?;
 
-        Ok::<_, Box<dyn std::error::Error>>(())
-    });
+    let res = exec.run_until(serve::<DefaultAttacher, _>(server, token, &spawner));
 
     exec.run();
 
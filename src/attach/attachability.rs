@@ -0,0 +1,73 @@
+use std::path::PathBuf;
+
+use nix::{errno::Errno, sys::signal::kill, unistd::Pid};
+
+use crate::internal::{attach_file_path, process_exists};
+
+/// Result of [`is_attachable`], telling which preconditions of attaching to a process are met.
+#[derive(Clone, Debug)]
+pub struct AttachabilityReport {
+    /// Whether the process is running.
+    pub process_exists: bool,
+    /// Directory where the attach file would be created, or why it cannot be found, e.g. the
+    /// working directory of the process is not readable.
+    pub attach_file_dir: Result<PathBuf, String>,
+    /// Whether the current user is allowed to signal the process, or why not.
+    pub signal_permission: Result<(), String>,
+}
+
+impl AttachabilityReport {
+    /// Returns `true` if all the preconditions are met.
+    ///
+    /// Attaching may still fail, e.g. if the process does not run a Teleop server.
+    pub fn is_attachable(&self) -> bool {
+        self.process_exists && self.attach_file_dir.is_ok() && self.signal_permission.is_ok()
+    }
+}
+
+/// Checks whether attaching to the passed process is plausible, without signaling it.
+///
+/// Signal permission is probed with the null signal, which the process never receives.
+pub fn is_attachable(pid: u32) -> AttachabilityReport {
+    let attach_file_dir = attach_file_path(pid)
+        .map(|path| path.parent().map(PathBuf::from).unwrap_or_default())
+        .map_err(|err| err.to_string());
+    let signal_permission = match i32::try_from(pid) {
+        // 0 would probe the process group instead
+        Ok(raw_pid) if raw_pid > 0 => match kill(Pid::from_raw(raw_pid), None) {
+            Ok(()) => Ok(()),
+            Err(Errno::EPERM) => Err("not allowed to signal the process".to_owned()),
+            Err(err) => Err(err.to_string()),
+        },
+        _ => Err(format!("invalid PID {pid}")),
+    };
+    AttachabilityReport {
+        process_exists: process_exists(pid),
+        attach_file_dir,
+        signal_permission,
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_attachable() {
+        let report = is_attachable(std::process::id());
+        assert!(report.process_exists);
+        assert_eq!(
+            report.attach_file_dir.unwrap(),
+            std::env::current_dir().unwrap()
+        );
+        assert_eq!(report.signal_permission, Ok(()));
+
+        // Above the maximum PID of Linux
+        let report = is_attachable(i32::MAX as u32);
+        assert!(!report.is_attachable());
+        assert!(!report.process_exists);
+        assert!(report.attach_file_dir.is_err());
+        assert!(report.signal_permission.is_err());
+    }
+}
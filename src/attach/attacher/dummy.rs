@@ -1,6 +1,6 @@
 //! Dummy attacher which listens immediately.
 
-use crate::attach::attacher::{Attacher, AttacherSignal};
+use crate::attach::attacher::{Attacher, AttacherSignal, SelfId, SignalOutcome};
 
 /// Dummy attacher.
 ///
@@ -14,8 +14,9 @@ impl Attacher for DummyAttacher {
         Ok(DummyAttacherSignal)
     }
 
-    async fn signaled() -> Result<(), Box<dyn std::error::Error>> {
-        Ok(())
+    async fn signaled_as(_self_id: SelfId) -> Result<SignalOutcome, Box<dyn std::error::Error>> {
+        // There is no attach file which could be stale
+        Ok(SignalOutcome::Freshly)
     }
 }
 
@@ -39,7 +40,7 @@ mod tests {
     use futures::{select, FutureExt};
 
     use super::DummyAttacher;
-    use crate::attach::attacher::{Attacher, AttacherSignal};
+    use crate::attach::attacher::{Attacher, AttacherSignal, SignalOutcome};
 
     #[test]
     fn test_dummy_attacher() {
@@ -47,7 +48,8 @@ mod tests {
 
         let res = exec.run_until(async {
             let job = async {
-                DummyAttacher::signaled().await?;
+                assert_eq!(DummyAttacher::signaled().await?, SignalOutcome::Freshly);
+                assert!(DummyAttacher::signaled_timeout(Duration::from_millis(100)).await?);
                 DummyAttacher::signal(std::process::id())?.send().await?;
                 Ok::<_, Box<dyn std::error::Error>>(())
             };
@@ -0,0 +1,190 @@
+//! Fanotify attacher which creates a file in the process working directory and waits for process
+//! to detect it.
+//!
+//! Unlike inotify, fanotify reports the ID of the process which wrote the attach file.
+//!
+//! # Privileges
+//!
+//! Fanotify requires the `CAP_SYS_ADMIN` capability in the process to be teleoperated, which
+//! usually means running as root. Without it, [`signaled`](Attacher::signaled) fails with `EPERM`.
+//! Clients do not need any privilege.
+
+use std::{fs::File, future::Future, os::fd::AsRawFd, path::Path};
+
+use async_io::Async;
+use nix::sys::fanotify::{EventFFlags, Fanotify, InitFlags, MarkFlags, MaskFlags};
+
+use crate::{
+    attach::attacher::{outcome_now, Attacher, AttacherSignal, RetryOpts, SelfId, SignalOutcome},
+    internal::{attach_file_path, self_attach_file_path, AutoDropFile},
+};
+
+/// Fanotify attacher.
+///
+/// It waits for the attach file to be written in the working directory.
+pub struct FanotifyAttacher;
+
+impl FanotifyAttacher {
+    /// Same as [`signaled_as`](Attacher::signaled_as) but also returns the ID of the process which
+    /// wrote the attach file.
+    ///
+    /// The ID is unknown if the attach file was already present, or if the writer runs in another
+    /// PID namespace.
+    pub fn signaled_by(
+        self_id: SelfId,
+    ) -> impl Future<Output = Result<(SignalOutcome, Option<u32>), Box<dyn std::error::Error>>>
+    {
+        // Same as the other attachers, the process starts waiting right now
+        let outcome = outcome_now(self_id);
+        async move {
+            let outcome = outcome?;
+            let pid = Self::wait_for_writer(self_id).await?;
+            Ok((outcome, pid))
+        }
+    }
+
+    /// Waits for the attach file to be written, returns the ID of the writer if known.
+    async fn wait_for_writer(self_id: SelfId) -> Result<Option<u32>, Box<dyn std::error::Error>> {
+        let attach_file_path = self_attach_file_path(self_id)?;
+        let parent = attach_file_path.parent().unwrap_or_else(|| Path::new("."));
+        let file_name = attach_file_path.file_name().unwrap();
+        let fanotify = Fanotify::init(
+            InitFlags::FAN_CLASS_NOTIF | InitFlags::FAN_CLOEXEC | InitFlags::FAN_NONBLOCK,
+            EventFFlags::O_RDONLY | EventFFlags::O_CLOEXEC,
+        )?;
+        fanotify.mark(
+            MarkFlags::FAN_MARK_ADD,
+            MaskFlags::FAN_CLOSE_WRITE | MaskFlags::FAN_EVENT_ON_CHILD,
+            File::open(parent)?,
+            None::<&Path>,
+        )?;
+        let async_fanotify = Async::new(fanotify)?;
+        // Detect creation before listening to fanotify
+        if std::fs::exists(&attach_file_path)? {
+            return Ok(None);
+        }
+        loop {
+            let events = async_fanotify
+                .read_with(|inner| Ok(inner.read_events()?))
+                .await?;
+            for event in events {
+                let Some(fd) = event.fd() else {
+                    // Queue overflow, the attach file may have been missed
+                    if std::fs::exists(&attach_file_path)? {
+                        return Ok(None);
+                    }
+                    continue;
+                };
+                let path = std::fs::read_link(format!("/proc/self/fd/{}", fd.as_raw_fd()))?;
+                if path.file_name() == Some(file_name) {
+                    let pid = u32::try_from(event.pid()).ok().filter(|pid| *pid != 0);
+                    return Ok(pid);
+                }
+            }
+        }
+    }
+}
+
+impl Attacher for FanotifyAttacher {
+    type Signal = FanotifyAttacherSignal;
+
+    // The attach file stays until the process sees it
+    const DEFAULT_RETRY: RetryOpts = RetryOpts::SIGNAL_ONCE;
+
+    fn signal(pid: u32) -> Result<Self::Signal, Box<dyn std::error::Error>> {
+        Ok(FanotifyAttacherSignal { pid, file: None })
+    }
+
+    fn signaled_as(
+        self_id: SelfId,
+    ) -> impl Future<Output = Result<SignalOutcome, Box<dyn std::error::Error>>> {
+        let signaled = Self::signaled_by(self_id);
+        async move {
+            let (outcome, _pid) = signaled.await?;
+            #[cfg(feature = "tracing")]
+            if let Some(pid) = _pid {
+                tracing::info!(pid, "Attach file written by process");
+            }
+            Ok(outcome)
+        }
+    }
+}
+
+/// Fanotify attacher signal.
+///
+/// It creates the attach file.
+pub struct FanotifyAttacherSignal {
+    pid: u32,
+    file: Option<AutoDropFile>,
+}
+
+impl AttacherSignal for FanotifyAttacherSignal {
+    async fn send(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        // Recreate the file if necessary
+        if self
+            .file
+            .as_ref()
+            .map(|file| file.exists())
+            .transpose()?
+            .is_none_or(|exists| !exists)
+        {
+            self.file = Some(AutoDropFile::create(attach_file_path(self.pid)?)?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::time::Duration;
+
+    use async_io::Timer;
+    use nix::errno::Errno;
+
+    use super::*;
+    use crate::internal::{set_attach_file_token, unique_attach_file_token};
+
+    /// Tells whether the current process is allowed to use fanotify.
+    fn fanotify_available() -> bool {
+        match Fanotify::init(InitFlags::FAN_CLASS_NOTIF, EventFFlags::O_RDONLY) {
+            Ok(_) => true,
+            Err(Errno::EPERM | Errno::ENOSYS) => false,
+            Err(err) => panic!("fanotify_init failed: {err}"),
+        }
+    }
+
+    #[test]
+    fn test_fanotify_attacher() {
+        if !fanotify_available() {
+            eprintln!("Skipping test, fanotify requires CAP_SYS_ADMIN");
+            return;
+        }
+
+        set_attach_file_token(Some(unique_attach_file_token()));
+
+        let self_id = SelfId(u32::MAX - 16 - std::process::id());
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (signaled, file) = futures::join!(FanotifyAttacher::signaled_by(self_id), async {
+                // Wait so that the attacher watches the directory first
+                Timer::after(Duration::from_millis(100)).await;
+                AutoDropFile::create(self_attach_file_path(self_id)?)
+                    .map_err(Box::<dyn std::error::Error>::from)
+            });
+            let _file = file?;
+            assert_eq!(
+                signaled?,
+                (SignalOutcome::Freshly, Some(std::process::id()))
+            );
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        exec.run();
+
+        res.unwrap();
+    }
+}
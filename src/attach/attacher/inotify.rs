@@ -1,15 +1,24 @@
 //! Inotify attacher which creates a file in the process working directory and waits for process to detect it.
+//!
+//! When the kernel queue of events overflows, e.g. in a busy directory, the creation of the attach
+//! file may be lost, so the attacher checks whether the file exists instead.
 
-use std::path::Path;
+use std::{future::Future, path::Path};
 
 use async_io::Async;
-use inotify::{Inotify, WatchMask};
+use inotify::{EventMask, Inotify, WatchMask};
 
 use crate::{
-    attach::attacher::{Attacher, AttacherSignal},
-    internal::{attach_file_path, AutoDropFile},
+    attach::attacher::{
+        signaled_since_call, Attacher, AttacherSignal, RetryOpts, SelfId, SignalOutcome,
+    },
+    config::TeleopConfig,
+    internal::{attach_file_path, self_attach_file_path, AutoDropFile},
 };
 
+/// Size of the largest event, with a file name of `NAME_MAX` bytes and its terminating null byte.
+const MAX_EVENT_SIZE: usize = 16 + 255 + 1;
+
 /// Inotify attacher.
 ///
 /// It waits for the attach file to be created in the working directory.
@@ -18,39 +27,64 @@ pub struct InotifyAttacher;
 impl Attacher for InotifyAttacher {
     type Signal = InotifyAttacherSignal;
 
+    // The attach file stays until the process sees it
+    const DEFAULT_RETRY: RetryOpts = RetryOpts::SIGNAL_ONCE;
+
     fn signal(pid: u32) -> Result<Self::Signal, Box<dyn std::error::Error>> {
         Ok(InotifyAttacherSignal { pid, file: None })
     }
 
-    async fn signaled() -> Result<(), Box<dyn std::error::Error>> {
-        let attach_file_path = attach_file_path(std::process::id())?;
-        let parent = attach_file_path.parent().unwrap_or_else(|| Path::new("."));
-        let file_name = attach_file_path.file_name().unwrap();
-        let inotify = Inotify::init()?;
-        inotify.watches().add(parent, WatchMask::CREATE)?;
-        let mut async_inotify = Async::new(inotify)?;
-        let mut buffer = [0u8; 1024];
-        // Detect creation before listening to inotify
-        if std::fs::exists(&attach_file_path)? {
-            return Ok(());
-        }
-        loop {
-            let read = |inner: &mut Inotify| {
-                let events = inner.read_events(&mut buffer)?;
-                for event in events {
-                    if let Some(name) = event.name {
-                        if name == file_name {
-                            return Ok(true);
-                        }
+    fn signaled_as(
+        self_id: SelfId,
+    ) -> impl Future<Output = Result<SignalOutcome, Box<dyn std::error::Error>>> {
+        signaled_since_call(self_id, async move {
+            wait_for_file(&self_attach_file_path(self_id)?).await?;
+            Ok(())
+        })
+    }
+}
+
+/// Waits for the file to be created, returns `true` if it already existed.
+pub(crate) async fn wait_for_file(path: &Path) -> Result<bool, Box<dyn std::error::Error>> {
+    let parent = path.parent().unwrap_or_else(|| Path::new("."));
+    let file_name = path
+        .file_name()
+        .ok_or("Cannot wait for a path without file name")?;
+    let inotify = Inotify::init()?;
+    inotify.watches().add(parent, WatchMask::CREATE)?;
+    let mut async_inotify = Async::new(inotify)?;
+    let mut buffer = vec![
+        0u8;
+        TeleopConfig::current()
+            .inotify_buffer_size
+            .max(MAX_EVENT_SIZE)
+    ];
+    // Detect creation before listening to inotify
+    if std::fs::exists(path)? {
+        return Ok(true);
+    }
+    loop {
+        let read = |inner: &mut Inotify| {
+            let events = inner.read_events(&mut buffer)?;
+            for event in events {
+                if event.mask.contains(EventMask::Q_OVERFLOW) {
+                    // Events were dropped, maybe the creation of the file
+                    if std::fs::exists(path)? {
+                        return Ok(true);
                     }
+                    continue;
                 }
-                Ok(false)
-            };
-            if unsafe { async_inotify.read_with_mut(read) }.await? {
-                break;
-            };
-        }
-        Ok(())
+                if let Some(name) = event.name {
+                    if name == file_name {
+                        return Ok(true);
+                    }
+                }
+            }
+            Ok(false)
+        };
+        if unsafe { async_inotify.read_with_mut(read) }.await? {
+            return Ok(false);
+        };
     }
 }
 
@@ -81,14 +115,19 @@ impl AttacherSignal for InotifyAttacherSignal {
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
-    use std::time::Duration;
+    use std::{pin::pin, time::Duration};
 
     use async_io::Timer;
+    use futures::future::{select, Either};
 
     use super::InotifyAttacher;
     use crate::{
-        attach::attacher::tests::test_attacher,
-        internal::{attach_file_path, AutoDropFile},
+        attach::attacher::{tests::test_attacher, Attacher, SelfId, SignalOutcome},
+        config::{AttachFileLocation, TeleopConfig},
+        internal::{
+            attach_file_path, self_attach_file_path, set_attach_file_token,
+            unique_attach_file_token, AutoDropFile,
+        },
     };
 
     #[test]
@@ -104,4 +143,99 @@ mod tests {
             Timer::after(Duration::from_millis(200)).await;
         });
     }
+
+    #[test]
+    fn test_inotify_attacher_self_id() {
+        set_attach_file_token(Some(unique_attach_file_token()));
+
+        let self_id = SelfId(u32::MAX - std::process::id());
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (outcome, file) = futures::join!(InotifyAttacher::signaled_as(self_id), async {
+                // Wait so that the attacher watches the directory first
+                Timer::after(Duration::from_millis(100)).await;
+                AutoDropFile::create(self_attach_file_path(self_id)?)
+                    .map_err(Box::<dyn std::error::Error>::from)
+            });
+            let _file = file?;
+            assert_eq!(outcome?, SignalOutcome::Freshly);
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        exec.run();
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_inotify_attacher_queue_overflow() {
+        let max_queued_events: usize =
+            std::fs::read_to_string("/proc/sys/fs/inotify/max_queued_events")
+                .unwrap()
+                .trim()
+                .parse()
+                .unwrap();
+
+        let dir = std::env::temp_dir().join(format!("teleop_inotify_flood_{}", std::process::id()));
+        std::fs::create_dir(&dir).unwrap();
+
+        // Isolate the directory from the other tests
+        TeleopConfig::install_for_thread(Some(TeleopConfig {
+            attach_file_location: AttachFileLocation::Directory(dir.clone()),
+            inotify_buffer_size: 64 * 1024,
+            ..TeleopConfig::default()
+        }));
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let signaled = pin!(InotifyAttacher::signaled_as(SelfId::default()));
+            let flood = async {
+                // Nothing is read meanwhile, the creation of the attach file is lost
+                for i in 0..=max_queued_events {
+                    std::fs::File::create(dir.join(format!("flood_{i}")))?;
+                }
+                let file = AutoDropFile::create(self_attach_file_path(SelfId::default())?)?;
+                // Let the attacher read the events
+                Timer::after(Duration::from_secs(10)).await;
+                Ok::<_, Box<dyn std::error::Error>>(file)
+            };
+            match select(signaled, pin!(flood)).await {
+                Either::Left((outcome, _)) => assert_eq!(outcome?, SignalOutcome::Freshly),
+                Either::Right(_) => panic!("attach file not detected"),
+            }
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        TeleopConfig::install_for_thread(None);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_inotify_attacher_dropped_releases_fd() {
+        set_attach_file_token(Some(unique_attach_file_token()));
+
+        let fd_count = || std::fs::read_dir("/proc/self/fd").unwrap().count();
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let before = fd_count();
+        exec.run_until(async {
+            for _ in 0..1000 {
+                let signaled = pin!(InotifyAttacher::signaled_as(SelfId::default()));
+                // Start watching, then abandon the future as a timeout would
+                assert!(futures::poll!(signaled).is_pending());
+            }
+        });
+        let after = fd_count();
+
+        // Leave some room for the tests running concurrently
+        assert!(after < before + 100, "{before} fds before, {after} after");
+    }
 }
@@ -1,6 +1,7 @@
 //! Inotify attacher which creates a file in the process working directory and waits for process to detect it.
 
 use std::{
+    future::Future,
     ops::{Deref, DerefMut},
     os::fd::{AsFd, AsRawFd, BorrowedFd},
     path::Path,
@@ -10,8 +11,10 @@ use async_io::Async;
 use kqueue::{EventFilter, FilterFlag, Watcher};
 
 use crate::{
-    attach::attacher::{Attacher, AttacherSignal},
-    internal::{attach_file_path, AutoDropFile},
+    attach::attacher::{
+        signaled_since_call, Attacher, AttacherSignal, RetryOpts, SelfId, SignalOutcome,
+    },
+    internal::{attach_file_path, self_attach_file_path, AutoDropFile},
 };
 
 /// Kqueue attacher.
@@ -47,28 +50,35 @@ pub struct KqueueAttacher;
 impl Attacher for KqueueAttacher {
     type Signal = KqueueAttacherSignal;
 
+    // The attach file stays until the process sees it
+    const DEFAULT_RETRY: RetryOpts = RetryOpts::SIGNAL_ONCE;
+
     fn signal(pid: u32) -> Result<Self::Signal, Box<dyn std::error::Error>> {
         Ok(KqueueAttacherSignal { pid, file: None })
     }
 
-    async fn signaled() -> Result<(), Box<dyn std::error::Error>> {
-        let attach_file_path = attach_file_path(std::process::id())?;
-        let parent = attach_file_path.parent().unwrap_or_else(|| Path::new("."));
-        let mut watcher = KqueueWatcherWrapper(Watcher::new()?);
-        watcher.add_filename(parent, EventFilter::EVFILT_VNODE, FilterFlag::NOTE_WRITE)?;
-        watcher.watch()?;
-        let async_kqueue = Async::new_nonblocking(watcher)?;
-        loop {
-            if std::fs::exists(&attach_file_path)? {
-                return Ok(());
+    fn signaled_as(
+        self_id: SelfId,
+    ) -> impl Future<Output = Result<SignalOutcome, Box<dyn std::error::Error>>> {
+        signaled_since_call(self_id, async move {
+            let attach_file_path = self_attach_file_path(self_id)?;
+            let parent = attach_file_path.parent().unwrap_or_else(|| Path::new("."));
+            let mut watcher = KqueueWatcherWrapper(Watcher::new()?);
+            watcher.add_filename(parent, EventFilter::EVFILT_VNODE, FilterFlag::NOTE_WRITE)?;
+            watcher.watch()?;
+            let async_kqueue = Async::new_nonblocking(watcher)?;
+            loop {
+                if std::fs::exists(&attach_file_path)? {
+                    return Ok(());
+                }
+                async_kqueue
+                    .read_with(|inner| match inner.poll(None) {
+                        Some(_) => Ok(()),
+                        None => Err(std::io::ErrorKind::WouldBlock.into()),
+                    })
+                    .await?;
             }
-            async_kqueue
-                .read_with(|inner| match inner.poll(None) {
-                    Some(_) => Ok(()),
-                    None => Err(std::io::ErrorKind::WouldBlock.into()),
-                })
-                .await?;
-        }
+        })
     }
 }
 
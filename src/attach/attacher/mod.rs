@@ -3,23 +3,56 @@
 //! The default attacher may vary from one platform to another.
 
 pub mod dummy;
+#[cfg(all(feature = "fanotify", target_os = "linux"))]
+pub mod fanotify;
 #[cfg(feature = "inotify")]
 pub mod inotify;
-#[cfg(target_os = "macos")]
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
 pub mod kqueue;
 #[cfg(unix)]
 pub mod unix;
+#[cfg(windows)]
+pub mod windows_dir;
+
+use std::{
+    future::Future,
+    pin::pin,
+    time::{Duration, Instant},
+};
+
+use async_io::Timer;
+use futures::future::{select, Either};
 
-use std::future::Future;
+use super::AttachError;
+use crate::internal::{random_u64, self_attach_file_path};
 
 // Decide which attacher is the default
 #[cfg(windows)]
 pub use dummy::DummyAttacher as DefaultAttacher;
 #[cfg(feature = "inotify")]
 pub use inotify::InotifyAttacher as DefaultAttacher;
-#[cfg(target_os = "macos")]
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
 pub use kqueue::KqueueAttacher as DefaultAttacher;
-#[cfg(all(unix, not(target_os = "macos"), not(feature = "inotify")))]
+#[cfg(all(
+    unix,
+    not(any(
+        target_os = "freebsd",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    )),
+    not(feature = "inotify")
+))]
 pub use unix::UnixAttacher as DefaultAttacher;
 
 /// Attacher abstraction.
@@ -27,33 +60,246 @@ pub trait Attacher {
     /// The type of signal returned by [signal](`Attacher::signal`).
     type Signal: AttacherSignal;
 
+    /// Retry options used by `connect` to wait for the target process, unless overridden.
+    ///
+    /// Attachers whose signal cannot be missed need not send it again, while others send it
+    /// periodically.
+    const DEFAULT_RETRY: RetryOpts = RetryOpts::DEFAULT;
+
     /// Returns a signal which can be sent multiple times to the target process.
     fn signal(pid: u32) -> Result<Self::Signal, Box<dyn std::error::Error>>;
 
     /// Waits asynchronously for the signal to be received by the process.
-    fn signaled() -> impl Future<Output = Result<(), Box<dyn std::error::Error>>>;
+    ///
+    /// The outcome tells whether the process has been signaled while waiting or whether the
+    /// signal was already there.
+    fn signaled() -> impl Future<Output = Result<SignalOutcome, Box<dyn std::error::Error>>> {
+        Self::signaled_as(SelfId::default())
+    }
+
+    /// Same as [`signaled`](Attacher::signaled) but waits for the signal sent to the passed ID
+    /// instead of the ID of the current process.
+    fn signaled_as(
+        self_id: SelfId,
+    ) -> impl Future<Output = Result<SignalOutcome, Box<dyn std::error::Error>>>;
+
+    /// Same as [`signaled`](Attacher::signaled) but gives up after the passed timeout.
+    ///
+    /// The future resolves to `true` if the process has been signaled, or to `false` if the
+    /// timeout elapsed first. This lets the process offer a time limited attach window.
+    fn signaled_timeout(
+        timeout: Duration,
+    ) -> impl Future<Output = Result<bool, Box<dyn std::error::Error>>> {
+        let signaled = Self::signaled();
+        async move {
+            match select(pin!(signaled), Timer::after(timeout)).await {
+                Either::Left((outcome, _)) => outcome.map(|_| true),
+                Either::Right(_) => Ok(false),
+            }
+        }
+    }
+}
+
+/// ID identifying the current process to the attachers and listeners.
+///
+/// It defaults to the ID of the current process. Tests can pass synthetic IDs to simulate
+/// distinct target processes within a single process, provided the signal reaches the current
+/// process: file based attachers only watch the attach file, but the UNIX attacher still expects
+/// a signal sent to the actual process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SelfId(pub u32);
+
+impl Default for SelfId {
+    fn default() -> Self {
+        Self(std::process::id())
+    }
+}
+
+/// How [`Attacher::signaled`] completed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignalOutcome {
+    /// The process has been signaled while waiting.
+    Freshly,
+    /// The attach file was already present when the process started waiting, it may be a stale
+    /// file left by a previous client.
+    PreExisting,
+}
+
+/// Runs the passed wait for the attach file of the passed ID, reporting
+/// [`SignalOutcome::PreExisting`] if the file is present right now.
+///
+/// The process starts waiting when [`Attacher::signaled_as`] is called, not when its future is
+/// first polled: an attachment in between is not stale. Errors are raised once awaited.
+#[cfg_attr(not(any(unix, windows)), allow(unused))]
+pub(crate) fn signaled_since_call<F>(
+    self_id: SelfId,
+    wait: F,
+) -> impl Future<Output = Result<SignalOutcome, Box<dyn std::error::Error>>>
+where
+    F: Future<Output = Result<(), Box<dyn std::error::Error>>>,
+{
+    let outcome = outcome_now(self_id);
+    async move {
+        let outcome = outcome?;
+        wait.await?;
+        Ok(outcome)
+    }
+}
+
+/// Outcome of a signal received from now on, depending on whether the attach file of the passed
+/// ID is already present.
+#[cfg_attr(not(any(unix, windows)), allow(unused))]
+pub(crate) fn outcome_now(self_id: SelfId) -> Result<SignalOutcome, Box<dyn std::error::Error>> {
+    Ok(if self_attach_file_path(self_id)?.exists() {
+        SignalOutcome::PreExisting
+    } else {
+        SignalOutcome::Freshly
+    })
 }
 
 /// Attachment signal abstraction.
 pub trait AttacherSignal {
     /// Sends the signal asynchronously once.
     fn send(&mut self) -> impl Future<Output = Result<(), Box<dyn std::error::Error>>>;
+
+    /// Sends the signal and polls until the predicate is satisfied.
+    ///
+    /// The signal is sent on the first attempt, and then every
+    /// [`RetryOpts::resignal_every`] attempts in case it was missed. The predicate is checked
+    /// before each attempt. The future resolves to `true` as soon as the
+    /// predicate is satisfied, or to `false` if it is still not satisfied after the last attempt.
+    ///
+    /// Up to [`RetryOpts::max_send_failures`] consecutive failures to send the signal are
+    /// tolerated. Beyond that, or if the last attempt failed to send the signal, the future fails
+    /// with [`AttachError::SignalFailed`] holding the last error.
+    fn wait_until(
+        &mut self,
+        predicate: impl Fn() -> bool,
+        opts: RetryOpts,
+    ) -> impl Future<Output = Result<bool, Box<dyn std::error::Error>>> {
+        self.wait_until_with_progress(predicate, opts, |_, _| {})
+    }
+
+    /// Same as [`wait_until`](AttacherSignal::wait_until) but calls `progress` on every attempt
+    /// with the number of the attempt, starting from 1, and the time elapsed since the first one.
+    fn wait_until_with_progress(
+        &mut self,
+        predicate: impl Fn() -> bool,
+        opts: RetryOpts,
+        mut progress: impl FnMut(u32, Duration),
+    ) -> impl Future<Output = Result<bool, Box<dyn std::error::Error>>> {
+        async move {
+            let started = Instant::now();
+            let mut attempts = 0;
+            let mut failures = 0;
+            let mut last_error = None;
+            while !predicate() {
+                if attempts >= opts.max_attempts {
+                    return match last_error {
+                        Some(source) => Err(AttachError::SignalFailed { failures, source }.into()),
+                        None => Ok(false),
+                    };
+                }
+                if attempts > 0 {
+                    Timer::after(opts.delay()).await;
+                }
+                let resignal = attempts == 0
+                    || (opts.resignal_every != 0 && attempts % opts.resignal_every == 0);
+                attempts += 1;
+                progress(attempts, started.elapsed());
+                if !resignal {
+                    continue;
+                }
+                match self.send().await {
+                    Ok(()) => {
+                        failures = 0;
+                        last_error = None;
+                    }
+                    Err(source) => {
+                        failures += 1;
+                        if failures > opts.max_send_failures {
+                            return Err(AttachError::SignalFailed { failures, source }.into());
+                        }
+                        last_error = Some(source);
+                    }
+                }
+            }
+            Ok(true)
+        }
+    }
+}
+
+/// Options of [`AttacherSignal::wait_until`].
+#[derive(Clone, Debug)]
+pub struct RetryOpts {
+    /// Delay between two attempts.
+    pub interval: Duration,
+    /// Maximum number of attempts.
+    pub max_attempts: u32,
+    /// Number of attempts after which the signal is sent again, `0` to send it only once.
+    pub resignal_every: u32,
+    /// Maximum number of consecutive failures to send the signal which are tolerated.
+    pub max_send_failures: u32,
+    /// Fraction of the interval by which each delay randomly varies, e.g. `0.2` for ±20%, so that
+    /// clients attaching to many processes at once do not signal them in lockstep.
+    ///
+    /// It is clamped between `0.0`, no jitter, and `1.0`.
+    pub jitter: f64,
+}
+
+impl RetryOpts {
+    /// Default options: the process is given 10 seconds to respond, and the signal is sent every
+    /// second.
+    pub const DEFAULT: RetryOpts = RetryOpts {
+        interval: Duration::from_millis(100),
+        max_attempts: 100,
+        resignal_every: 10,
+        max_send_failures: 3,
+        jitter: 0.0,
+    };
+
+    /// Same as [`DEFAULT`](Self::DEFAULT) but the signal is sent only once.
+    pub const SIGNAL_ONCE: RetryOpts = RetryOpts {
+        resignal_every: 0,
+        ..Self::DEFAULT
+    };
+
+    /// Delay before the next attempt, the interval varied by the jitter.
+    fn delay(&self) -> Duration {
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return self.interval;
+        }
+        // Uniformly distributed between -1 and 1
+        let factor = (random_u64() >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0;
+        self.interval.mul_f64(1.0 + jitter * factor)
+    }
+}
+
+impl Default for RetryOpts {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
 }
 
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
     use std::{
+        cell::Cell,
         future::Future,
         pin::pin,
+        rc::Rc,
         time::{Duration, Instant},
     };
 
+    use assert_matches::assert_matches;
     use async_io::Timer;
     use futures::{select, FutureExt};
 
-    use super::{Attacher, AttacherSignal};
-    use crate::tests::ATTACH_PROCESS_TEST_MUTEX;
+    use super::{Attacher, AttacherSignal, RetryOpts, SignalOutcome};
+    use crate::attach::AttachError;
+    use crate::internal::{set_attach_file_token, unique_attach_file_token};
 
     #[cfg_attr(windows, allow(unused))]
     pub(crate) fn test_attacher<A, W>(wrong_signal: W)
@@ -61,16 +307,25 @@ mod tests {
         A: Attacher,
         W: Future<Output = ()>,
     {
-        let _attacher_test = ATTACH_PROCESS_TEST_MUTEX.lock();
+        // Isolate the attach file from other tests signaling the current process
+        set_attach_file_token(Some(unique_attach_file_token()));
 
         let mut exec = futures::executor::LocalPool::new();
 
         let res = exec.run_until(async {
             let job = async {
+                assert!(!A::signaled_timeout(Duration::from_millis(100)).await?);
+
                 let signaled = A::signaled();
                 let mut signal = A::signal(std::process::id())?;
                 signal.send().await?;
-                signaled.await?;
+                // The signal is sent before the future is polled, but after waiting started
+                assert_eq!(signaled.await?, SignalOutcome::Freshly);
+
+                // The attach file is still there when waiting starts again
+                let signaled = A::signaled();
+                signal.send().await?;
+                assert_eq!(signaled.await?, SignalOutcome::PreExisting);
                 drop(signal);
 
                 let mut signaled = pin!(A::signaled().fuse());
@@ -94,7 +349,7 @@ mod tests {
 
                 let mut signal = A::signal(std::process::id())?;
                 signal.send().await?;
-                signaled.await?;
+                assert_eq!(signaled.await?, SignalOutcome::Freshly);
                 drop(signal);
 
                 Ok::<_, Box<dyn std::error::Error>>(())
@@ -113,4 +368,172 @@ mod tests {
 
         res.unwrap();
     }
+
+    struct CountingSignal {
+        sent: Rc<Cell<u32>>,
+    }
+
+    impl AttacherSignal for CountingSignal {
+        async fn send(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+            self.sent.set(self.sent.get() + 1);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_wait_until() {
+        let opts = RetryOpts {
+            interval: Duration::from_millis(1),
+            max_attempts: 5,
+            resignal_every: 1,
+            max_send_failures: 0,
+            jitter: 0.0,
+        };
+
+        let sent = Rc::new(Cell::new(0));
+        let mut signal = CountingSignal { sent: sent.clone() };
+        let res = futures::executor::block_on(signal.wait_until(|| sent.get() >= 3, opts.clone()));
+        assert!(res.unwrap());
+        assert_eq!(sent.get(), 3);
+
+        let sent = Rc::new(Cell::new(0));
+        let mut signal = CountingSignal { sent: sent.clone() };
+        let res = futures::executor::block_on(signal.wait_until(|| false, opts));
+        assert!(!res.unwrap());
+        assert_eq!(sent.get(), 5);
+    }
+
+    #[test]
+    fn test_wait_until_resignal() {
+        let polls = Cell::new(0);
+        let predicate = || {
+            polls.set(polls.get() + 1);
+            false
+        };
+
+        let opts = RetryOpts {
+            interval: Duration::from_millis(1),
+            max_attempts: 25,
+            resignal_every: 10,
+            max_send_failures: 0,
+            jitter: 0.0,
+        };
+        let sent = Rc::new(Cell::new(0));
+        let mut signal = CountingSignal { sent: sent.clone() };
+        let res = futures::executor::block_on(signal.wait_until(predicate, opts.clone()));
+        assert!(!res.unwrap());
+        assert_eq!(sent.get(), 3);
+        assert_eq!(polls.get(), 26);
+
+        let opts = RetryOpts {
+            resignal_every: 0,
+            ..opts
+        };
+        let sent = Rc::new(Cell::new(0));
+        let mut signal = CountingSignal { sent: sent.clone() };
+        let res = futures::executor::block_on(signal.wait_until(|| false, opts));
+        assert!(!res.unwrap());
+        assert_eq!(sent.get(), 1);
+    }
+
+    #[test]
+    fn test_retry_opts_jitter() {
+        let opts = RetryOpts {
+            jitter: 0.5,
+            ..RetryOpts::DEFAULT
+        };
+        let delays = (0..1000).map(|_| opts.delay()).collect::<Vec<_>>();
+
+        let min = *delays.iter().min().unwrap();
+        let max = *delays.iter().max().unwrap();
+        assert!(min >= Duration::from_millis(50), "{min:?}");
+        assert!(max <= Duration::from_millis(150), "{max:?}");
+        // Spread over the whole band
+        assert!(min < Duration::from_millis(60), "{min:?}");
+        assert!(max > Duration::from_millis(140), "{max:?}");
+
+        assert_eq!(RetryOpts::DEFAULT.delay(), RetryOpts::DEFAULT.interval);
+    }
+
+    struct FlakySignal {
+        sent: Rc<Cell<u32>>,
+        fails: fn(u32) -> bool,
+    }
+
+    impl AttacherSignal for FlakySignal {
+        async fn send(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+            let attempt = self.sent.get();
+            self.sent.set(attempt + 1);
+            if (self.fails)(attempt) {
+                Err(format!("failure {attempt}").into())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn test_wait_until_send_failures() {
+        let opts = RetryOpts {
+            interval: Duration::from_millis(1),
+            max_attempts: 6,
+            resignal_every: 1,
+            max_send_failures: 2,
+            jitter: 0.0,
+        };
+
+        // Intermittent failures are tolerated
+        let sent = Rc::new(Cell::new(0));
+        let mut signal = FlakySignal {
+            sent: sent.clone(),
+            fails: |attempt| attempt % 2 == 0,
+        };
+        let res = futures::executor::block_on(signal.wait_until(|| sent.get() >= 4, opts.clone()));
+        assert!(res.unwrap());
+
+        // Too many consecutive failures
+        let sent = Rc::new(Cell::new(0));
+        let mut signal = FlakySignal {
+            sent: sent.clone(),
+            fails: |attempt| attempt >= 1,
+        };
+        let res = futures::executor::block_on(signal.wait_until(|| false, opts.clone()));
+        let err = res.unwrap_err();
+        assert_matches!(
+            err.downcast_ref::<AttachError>(),
+            Some(AttachError::SignalFailed { failures: 3, source })
+                if source.to_string() == "failure 3"
+        );
+        assert_eq!(sent.get(), 4);
+
+        // The last attempt failed
+        let sent = Rc::new(Cell::new(0));
+        let mut signal = FlakySignal {
+            sent: sent.clone(),
+            fails: |attempt| attempt == 5,
+        };
+        let res = futures::executor::block_on(signal.wait_until(|| false, opts));
+        let err = res.unwrap_err();
+        assert_matches!(
+            err.downcast_ref::<AttachError>(),
+            Some(AttachError::SignalFailed { failures: 1, .. })
+        );
+    }
+
+    #[cfg(all(
+        any(
+            target_os = "freebsd",
+            target_os = "macos",
+            target_os = "netbsd",
+            target_os = "openbsd"
+        ),
+        not(feature = "inotify")
+    ))]
+    #[test]
+    fn test_default_attacher_is_kqueue() {
+        assert_eq!(
+            std::any::type_name::<super::DefaultAttacher>(),
+            std::any::type_name::<super::kqueue::KqueueAttacher>()
+        );
+    }
 }
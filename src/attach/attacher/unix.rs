@@ -1,28 +1,33 @@
 //! Unix attacher which creates a file in the process working directory and sends a `QUIT` signal
 //! to the process.
 //!
+//! Other signals can be used instead, see
+//! [`TeleopConfig::attach_signals`](crate::config::TeleopConfig::attach_signals).
+//!
 //! In this post-2025, there is no need to use this:
 //!
 //! * on `linux`, see `inotify` attacher instead (feature `inotify`)
-//! * on `macos`, see `kqueue` attacher instead
+//! * on `macos` and BSDs, see `kqueue` attacher instead
 
-use std::future::Future;
+use std::{borrow::Cow, future::Future};
 
-use async_signal::{Signal, Signals};
+pub use async_signal::Signal;
+use async_signal::Signals;
 use futures::StreamExt;
-use nix::{
-    sys::signal::{kill, Signal::SIGQUIT},
-    unistd::Pid,
-};
+use nix::{sys::signal::kill, unistd::Pid};
 
 use crate::{
-    attach::attacher::{Attacher, AttacherSignal},
-    internal::{attach_file_path, AutoDropFile},
+    attach::{
+        attacher::{signaled_since_call, Attacher, AttacherSignal, SelfId, SignalOutcome},
+        AttachError,
+    },
+    config::TeleopConfig,
+    internal::{attach_file_path, retry_on_eintr, self_attach_file_path, AutoDropFile},
 };
 
 /// UNIX attacher.
 ///
-/// It waits for the `QUIT` signal and checks the presence of the attach file in the working
+/// It waits for the attach signals and checks the presence of the attach file in the working
 /// directory.
 pub struct UnixAttacher;
 
@@ -30,41 +35,63 @@ impl Attacher for UnixAttacher {
     type Signal = UnixAttacherSignal;
 
     fn signal(pid: u32) -> Result<Self::Signal, Box<dyn std::error::Error>> {
-        Ok(UnixAttacherSignal { pid, file: None })
+        Ok(UnixAttacherSignal {
+            pid,
+            file: None,
+            signals: attach_signals()?,
+            next: 0,
+        })
     }
 
-    fn signaled() -> impl Future<Output = Result<(), Box<dyn std::error::Error>>> {
+    fn signaled_as(
+        self_id: SelfId,
+    ) -> impl Future<Output = Result<SignalOutcome, Box<dyn std::error::Error>>> {
         // It is important to keep this in the synchronous part in order to ensure the listening
         // process is ready to accept attachment requests even if the future is not awaited.
         //
         // Nevertheless, the error will only be raised if the future is awaited.
-        let signals = Signals::new([Signal::Quit]);
+        let signals = attach_signals()
+            .and_then(|signals| Ok(signal_handler(Signals::new(signals.iter().copied()))?));
 
-        async move {
+        signaled_since_call(self_id, async move {
             let mut signals = signals?;
 
+            let attach_file_path = self_attach_file_path(self_id)?;
             while let Some(signal) = signals.next().await {
-                if let Ok(signal) = signal {
-                    if signal == Signal::Quit {
-                        let attach_file_path = attach_file_path(std::process::id())?;
-                        if attach_file_path.exists() {
-                            break;
-                        }
-                    }
+                if signal.is_ok() && attach_file_path.exists() {
+                    break;
                 }
             }
 
             Ok(())
-        }
+        })
     }
 }
 
+fn attach_signals() -> Result<Cow<'static, [Signal]>, Box<dyn std::error::Error>> {
+    let signals = TeleopConfig::current().attach_signals;
+    if signals.is_empty() {
+        return Err("No attach signal configured".into());
+    }
+    Ok(signals)
+}
+
+/// Reports the failure to install the signal handler with a specific error.
+///
+/// This happens in restricted environments, e.g. when a seccomp policy denies `sigaction`.
+fn signal_handler(signals: std::io::Result<Signals>) -> Result<Signals, AttachError> {
+    signals.map_err(|source| AttachError::SignalHandlerUnavailable { source })
+}
+
 /// UNIX attacher signal.
 ///
-/// It creates the attach file and sends a `QUIT` signal to the target process.
+/// It creates the attach file and sends the next attach signal to the target process, cycling
+/// through them. Sending the signal is retried when interrupted by another signal (`EINTR`).
 pub struct UnixAttacherSignal {
     pid: u32,
     file: Option<AutoDropFile>,
+    signals: Cow<'static, [Signal]>,
+    next: usize,
 }
 
 impl AttacherSignal for UnixAttacherSignal {
@@ -79,7 +106,10 @@ impl AttacherSignal for UnixAttacherSignal {
         {
             self.file = Some(AutoDropFile::create(attach_file_path(self.pid)?)?);
         }
-        kill(Pid::from_raw(self.pid as _), SIGQUIT)?;
+        // Both enums use the signal numbers as discriminants
+        let signal = nix::sys::signal::Signal::try_from(self.signals[self.next] as i32)?;
+        self.next = (self.next + 1) % self.signals.len();
+        retry_on_eintr(|| kill(Pid::from_raw(self.pid as _), signal))?;
         Ok(())
     }
 }
@@ -87,11 +117,83 @@ impl AttacherSignal for UnixAttacherSignal {
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
-    use super::UnixAttacher;
-    use crate::attach::attacher::tests::test_attacher;
+    use std::time::Duration;
+
+    use assert_matches::assert_matches;
+    use async_io::Timer;
+    use futures::future::{select, Either};
+
+    use super::*;
+    use crate::{
+        attach::attacher::tests::test_attacher,
+        internal::{set_attach_file_token, unique_attach_file_token},
+    };
 
     #[test]
     fn test_unix_attacher() {
         test_attacher::<UnixAttacher, _>(async {});
     }
+
+    #[test]
+    fn test_unix_attacher_signal_fallback() {
+        // This test may not conflict with the other tests because
+        // * it uses its own attach file
+        // * SIGUSR1 and SIGUSR2 are not used anywhere else
+
+        set_attach_file_token(Some(unique_attach_file_token()));
+
+        // The application uses SIGUSR1 for its own purpose
+        let _reserved = Signals::new([Signal::Usr1]).unwrap();
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            TeleopConfig::install_for_thread(Some(TeleopConfig {
+                attach_signals: Cow::Borrowed(&[Signal::Usr2]),
+                ..TeleopConfig::default()
+            }));
+            let mut signaled = Box::pin(UnixAttacher::signaled());
+
+            TeleopConfig::install_for_thread(Some(TeleopConfig {
+                attach_signals: Cow::Borrowed(&[Signal::Usr1, Signal::Usr2]),
+                ..TeleopConfig::default()
+            }));
+            let mut signal = UnixAttacher::signal(std::process::id())?;
+
+            // SIGUSR1 is ignored
+            signal.send().await?;
+            let timeout = Timer::after(Duration::from_millis(100));
+            if let Either::Left((res, _)) = select(signaled.as_mut(), timeout).await {
+                res?;
+                panic!("Should not be signaled by SIGUSR1");
+            }
+
+            // SIGUSR2 is effective, the attach file was created after waiting started
+            signal.send().await?;
+            assert_eq!(signaled.await?, SignalOutcome::Freshly);
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        TeleopConfig::install_for_thread(None);
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_unix_attacher_signal_handler_unavailable() {
+        let err = signal_handler(Err(std::io::Error::from(
+            std::io::ErrorKind::PermissionDenied,
+        )))
+        .err()
+        .unwrap();
+        assert!(err
+            .to_string()
+            .starts_with("Could not install the attach signal handler"));
+        assert_matches!(
+            err,
+            AttachError::SignalHandlerUnavailable { source }
+                if source.kind() == std::io::ErrorKind::PermissionDenied
+        );
+    }
 }
@@ -0,0 +1,207 @@
+//! Directory change attacher which creates a file in the process working directory and waits for
+//! process to detect it.
+//!
+//! This is the Windows counterpart of the inotify and kqueue attachers.
+
+use std::{
+    fs::OpenOptions,
+    future::Future,
+    os::windows::{
+        fs::OpenOptionsExt,
+        io::{AsRawHandle, FromRawHandle, OwnedHandle},
+    },
+    path::Path,
+    ptr,
+    sync::Arc,
+};
+
+use blocking::unblock;
+use windows_sys::Win32::{
+    Foundation::{ERROR_IO_PENDING, FALSE, TRUE},
+    Storage::FileSystem::{
+        ReadDirectoryChangesW, FILE_FLAG_BACKUP_SEMANTICS, FILE_FLAG_OVERLAPPED,
+        FILE_LIST_DIRECTORY, FILE_NOTIFY_CHANGE_FILE_NAME, FILE_SHARE_DELETE, FILE_SHARE_READ,
+        FILE_SHARE_WRITE,
+    },
+    System::{
+        Threading::CreateEventW,
+        IO::{CancelIoEx, GetOverlappedResult, OVERLAPPED},
+    },
+};
+
+use crate::{
+    attach::attacher::{
+        signaled_since_call, Attacher, AttacherSignal, RetryOpts, SelfId, SignalOutcome,
+    },
+    internal::{attach_file_path, self_attach_file_path, AutoDropFile},
+};
+
+/// Size of the buffer receiving the change notifications, which are not inspected.
+const NOTIFY_BUFFER_SIZE: usize = 4 * 1024;
+
+/// Directory change attacher.
+///
+/// It waits for the attach file to be created in the working directory, using
+/// `ReadDirectoryChangesW` on a blocking thread.
+pub struct WindowsDirAttacher;
+
+impl Attacher for WindowsDirAttacher {
+    type Signal = WindowsDirAttacherSignal;
+
+    // The attach file stays until the process sees it
+    const DEFAULT_RETRY: RetryOpts = RetryOpts::SIGNAL_ONCE;
+
+    fn signal(pid: u32) -> Result<Self::Signal, Box<dyn std::error::Error>> {
+        Ok(WindowsDirAttacherSignal { pid, file: None })
+    }
+
+    fn signaled_as(
+        self_id: SelfId,
+    ) -> impl Future<Output = Result<SignalOutcome, Box<dyn std::error::Error>>> {
+        signaled_since_call(self_id, async move {
+            let attach_file_path = self_attach_file_path(self_id)?;
+            let parent = attach_file_path.parent().unwrap_or_else(|| Path::new("."));
+            let watcher = DirectoryWatcher(Arc::new(open_directory(parent)?));
+            let handle = watcher.0.clone();
+            Ok(unblock(move || wait_for_file(&handle, &attach_file_path)).await?)
+        })
+    }
+}
+
+/// Directory change attacher signal.
+///
+/// It creates the attach file.
+pub struct WindowsDirAttacherSignal {
+    pid: u32,
+    file: Option<AutoDropFile>,
+}
+
+impl AttacherSignal for WindowsDirAttacherSignal {
+    async fn send(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        // Recreate the file if necessary
+        if self
+            .file
+            .as_ref()
+            .map(|file| file.exists())
+            .transpose()?
+            .is_none_or(|exists| !exists)
+        {
+            self.file = Some(AutoDropFile::create(attach_file_path(self.pid)?)?);
+        }
+        Ok(())
+    }
+}
+
+/// Watched directory handle.
+///
+/// Dropping it aborts the pending notification request so that the blocking thread returns when
+/// [`Attacher::signaled`] is cancelled.
+struct DirectoryWatcher(Arc<OwnedHandle>);
+
+impl Drop for DirectoryWatcher {
+    fn drop(&mut self) {
+        // SAFETY: the handle is valid, failure only means there is nothing to cancel
+        unsafe {
+            CancelIoEx(self.0.as_raw_handle(), ptr::null());
+        }
+    }
+}
+
+fn open_directory(path: &Path) -> std::io::Result<OwnedHandle> {
+    let directory = OpenOptions::new()
+        .read(true)
+        .access_mode(FILE_LIST_DIRECTORY)
+        .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OVERLAPPED)
+        .open(path)?;
+    Ok(directory.into())
+}
+
+/// Waits for the attach file to exist, blocking the current thread.
+fn wait_for_file(directory: &OwnedHandle, attach_file_path: &Path) -> std::io::Result<()> {
+    // SAFETY: all pointer arguments are optional
+    let event = unsafe { CreateEventW(ptr::null(), TRUE, FALSE, ptr::null()) };
+    if event.is_null() {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: the event has just been created and nobody else owns it
+    let event = unsafe { OwnedHandle::from_raw_handle(event) };
+
+    // Notifications are DWORD aligned
+    let mut buffer = [0u32; NOTIFY_BUFFER_SIZE / 4];
+    loop {
+        let mut overlapped = OVERLAPPED {
+            hEvent: event.as_raw_handle(),
+            ..Default::default()
+        };
+        let handle = directory.as_raw_handle();
+        // The request is issued before checking the file so that a creation in between is not
+        // missed.
+        // SAFETY: the buffer and the OVERLAPPED structure are alive until the operation completes
+        if unsafe {
+            ReadDirectoryChangesW(
+                handle,
+                buffer.as_mut_ptr().cast(),
+                NOTIFY_BUFFER_SIZE as u32,
+                FALSE,
+                FILE_NOTIFY_CHANGE_FILE_NAME,
+                ptr::null_mut(),
+                &mut overlapped,
+                None,
+            )
+        } == FALSE
+        {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() != Some(ERROR_IO_PENDING as i32) {
+                return Err(err);
+            }
+        }
+
+        let exists = std::fs::exists(attach_file_path);
+        if !matches!(exists, Ok(false)) {
+            // SAFETY: the handle is valid, failure only means there is nothing to cancel
+            unsafe {
+                CancelIoEx(handle, &overlapped);
+            }
+        }
+
+        let mut transferred = 0;
+        // SAFETY: the OVERLAPPED structure is alive until the operation completes
+        let completed =
+            unsafe { GetOverlappedResult(handle, &overlapped, &mut transferred, TRUE) } != FALSE;
+        if exists? {
+            return Ok(());
+        }
+        if !completed {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::time::Duration;
+
+    use async_io::Timer;
+
+    use super::WindowsDirAttacher;
+    use crate::{
+        attach::attacher::tests::test_attacher,
+        internal::{attach_file_path, AutoDropFile},
+    };
+
+    #[test]
+    fn test_windows_dir_attacher() {
+        test_attacher::<WindowsDirAttacher, _>(async {
+            // Create a wrong file
+            let mut wrong_attach_file_path = attach_file_path(std::process::id()).unwrap();
+            let mut wrong_file_name = wrong_attach_file_path.file_name().unwrap().to_os_string();
+            wrong_file_name.push("_wrong");
+            wrong_attach_file_path.set_file_name(wrong_file_name);
+            let _file = AutoDropFile::create(wrong_attach_file_path);
+            // Wait to make sure the directory watcher sees the file
+            Timer::after(Duration::from_millis(200)).await;
+        });
+    }
+}
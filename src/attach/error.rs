@@ -0,0 +1,184 @@
+use std::{
+    fmt::{Display, Formatter},
+    path::PathBuf,
+    time::Duration,
+};
+
+/// Errors specific to attaching to a process.
+///
+/// Functions of the crate return boxed errors, this type can be recovered with
+/// `downcast_ref` on `dyn std::error::Error`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum AttachError {
+    /// The process behind the communication channel does not run a Teleop server.
+    NotTeleopServer {
+        /// Why the process is not considered as a Teleop server.
+        reason: String,
+    },
+    /// The process does not listen for connections and has not been signaled.
+    NotListening {
+        /// ID of the process.
+        pid: u32,
+    },
+    /// The peer of a connection stopped answering keepalive pings.
+    PeerUnresponsive {
+        /// Number of consecutive pings left unanswered.
+        missed: u32,
+    },
+    /// The client and the server do not speak the same version of the Teleop protocol.
+    VersionMismatch {
+        /// Protocol version of the client.
+        client: u32,
+        /// Protocol version of the server, `0` if it predates versioning.
+        server: u32,
+    },
+    /// The service registered in the server does not implement the version expected by the
+    /// client.
+    ServiceVersionMismatch {
+        /// Name of the service.
+        service: String,
+        /// Version of the service expected by the client.
+        client: u32,
+        /// Version of the service implemented by the server, `0` if it is not versioned.
+        server: u32,
+        /// Oldest version of the service the server is compatible with.
+        min_compatible: u32,
+    },
+    /// The server connection reached its maximum lifetime and has been closed.
+    SessionExpired {
+        /// Maximum lifetime of the connection.
+        max_lifetime: Duration,
+    },
+    /// The signal could not be sent to the process.
+    SignalFailed {
+        /// Number of consecutive failures.
+        failures: u32,
+        /// Last error.
+        source: Box<dyn std::error::Error>,
+    },
+    /// The handler of the attach signal could not be installed in the process to be teleoperated,
+    /// so that it cannot be attached with the signal based attacher.
+    ///
+    /// Another attacher which does not rely on signals, e.g. `inotify` or `kqueue`, may be used
+    /// instead.
+    SignalHandlerUnavailable {
+        /// Error raised while installing the handler.
+        source: std::io::Error,
+    },
+    /// The process listening on the socket is not run by the user running the target process, the
+    /// socket may have been planted by another user to intercept the connection.
+    SocketOwnershipMismatch {
+        /// Path of the socket file.
+        path: PathBuf,
+        /// ID of the user running the target process.
+        expected_uid: u32,
+        /// ID of the user running the process listening on the socket.
+        actual_uid: u32,
+    },
+    /// The user running the target process cannot be found, so the process listening on its
+    /// socket cannot be verified.
+    ///
+    /// See [`TeleopConfig::allow_unknown_target_user`](crate::config::TeleopConfig).
+    UnknownTargetUser {
+        /// ID of the process.
+        pid: u32,
+    },
+    /// The process did not create its socket file in time after being signaled.
+    Timeout {
+        /// Path of the socket file.
+        path: PathBuf,
+        /// ID of the process.
+        pid: u32,
+        /// Number of attempts made.
+        attempts: u32,
+        /// Total time spent waiting.
+        elapsed: Duration,
+    },
+}
+
+impl Display for AttachError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotTeleopServer { reason } => {
+                write!(f, "Target process is not a Teleop server: {reason}")
+            }
+            Self::NotListening { pid } => {
+                write!(f, "Target process {pid} is not listening")
+            }
+            Self::PeerUnresponsive { missed } => {
+                write!(f, "Peer is unresponsive ({missed} keepalive pings missed)")
+            }
+            Self::VersionMismatch { client, server } => {
+                write!(
+                    f,
+                    "Teleop protocol version mismatch: client {client}, server {server}"
+                )
+            }
+            Self::ServiceVersionMismatch {
+                service,
+                client,
+                server,
+                min_compatible,
+            } => {
+                write!(
+                    f,
+                    "Service {service} version mismatch: client {client}, server {server} \
+                     (compatible from {min_compatible})"
+                )
+            }
+            Self::SignalFailed { failures, source } => {
+                write!(
+                    f,
+                    "Could not signal the target process ({failures} consecutive failures): \
+                     {source}"
+                )
+            }
+            Self::SessionExpired { max_lifetime } => {
+                write!(f, "Session expired after {max_lifetime:?}")
+            }
+            Self::SignalHandlerUnavailable { source } => {
+                write!(
+                    f,
+                    "Could not install the attach signal handler, \
+                     use an attacher which does not rely on signals: {source}"
+                )
+            }
+            Self::SocketOwnershipMismatch {
+                path,
+                expected_uid,
+                actual_uid,
+            } => {
+                write!(
+                    f,
+                    "Socket {} is bound by user {actual_uid} instead of user {expected_uid} \
+                     running the target process",
+                    path.to_string_lossy()
+                )
+            }
+            Self::UnknownTargetUser { pid } => {
+                write!(
+                    f,
+                    "Cannot verify the socket of target process {pid}: its user is unknown"
+                )
+            }
+            Self::Timeout { path, pid, .. } => {
+                write!(
+                    f,
+                    "Unable to open socket file {}: target process {pid} doesn't respond",
+                    path.to_string_lossy()
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for AttachError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::SignalFailed { source, .. } => Some(source.as_ref()),
+            Self::SignalHandlerUnavailable { source } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -2,17 +2,226 @@
 //!
 //! See available sub-modules for your platform.
 //!
-//! The default communication channel may vary from one platform to another ([`listen`], [`connect`]).
+//! The default communication channel may vary from one platform to another ([`listen`],
+//! [`listen_as`], [`listen_eager`], [`listen_with_self_id`], [`connect`], [`connect_with_retry`],
+//! [`connect_no_signal`]).
+//!
+//! [`multi::listen_multi`] listens on several transports at once, e.g. the default one and TCP.
+//!
+//! On UNIX, [`is_attachable`] checks whether attaching to a process is plausible beforehand, and
+//! [`list_attachable`] lists the processes which already listen.
 
+#[cfg(any(unix, windows))]
+pub mod multi;
+#[cfg(windows)]
+pub mod named_pipe;
+pub mod reconnect;
 #[cfg(unix)]
 pub mod unix_socket;
 #[cfg(windows)]
 pub mod windows_unix_socket;
 
+#[cfg(unix)]
+mod attachability;
 pub mod attacher;
+mod error;
+mod target;
 
-// Decide which communication channel is the default
 #[cfg(unix)]
-pub use unix_socket::{connect, listen};
+pub use attachability::{is_attachable, AttachabilityReport};
+pub use error::AttachError;
+pub use target::Target;
+
+use std::{
+    future::Future,
+    io::{Error, ErrorKind},
+    pin::pin,
+    time::Duration,
+};
+
+use async_io::Timer;
+use async_stream::stream;
+use futures::{
+    future::{select, Either},
+    Stream,
+};
+
+use crate::{attach::attacher::SignalOutcome, cancellation::CancellationToken};
+
+// Decide which communication channel is the default
 #[cfg(windows)]
-pub use windows_unix_socket::{connect, listen};
+pub use named_pipe::{
+    connect, connect_no_signal, connect_with_retry, listen, listen_as, listen_eager,
+    listen_with_self_id,
+};
+#[cfg(unix)]
+pub use unix_socket::{
+    connect, connect_no_signal, connect_with_progress, connect_with_retry, list_attachable, listen,
+    listen_as, listen_eager, listen_with_self_id, resolve_socket_dir, AttachableProcess,
+};
+
+/// Handle returned by [`listen`] alongside the stream of incoming connections.
+#[derive(Clone)]
+pub struct ListenHandle {
+    token: CancellationToken,
+}
+
+impl ListenHandle {
+    #[cfg_attr(not(any(unix, windows)), allow(unused))]
+    pub(crate) fn new() -> Self {
+        Self {
+            token: CancellationToken::new(),
+        }
+    }
+
+    /// Stops listening.
+    ///
+    /// The stream of incoming connections terminates at the next poll and the socket is unbound.
+    /// If the attach signal has not been received yet, the socket is never bound.
+    pub fn shutdown(&self) {
+        self.token.cancel();
+    }
+
+    #[cfg_attr(not(any(unix, windows)), allow(unused))]
+    pub(crate) fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+}
+
+/// Reports attach files which were present before listening, they may be stale.
+#[cfg_attr(not(any(unix, windows)), allow(unused))]
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn trace_signal_outcome(outcome: SignalOutcome) {
+    #[cfg(feature = "tracing")]
+    if outcome == SignalOutcome::PreExisting {
+        tracing::warn!("The attach file was present before listening, it may be stale");
+    }
+}
+
+/// Waits for the attach signal, unless the token is cancelled first in which case it returns
+/// `None`.
+#[cfg_attr(not(any(unix, windows)), allow(unused))]
+pub(crate) async fn signaled_unless_cancelled<F>(
+    signaled: F,
+    token: &CancellationToken,
+) -> Result<Option<SignalOutcome>, Box<dyn std::error::Error>>
+where
+    F: Future<Output = Result<SignalOutcome, Box<dyn std::error::Error>>>,
+{
+    match select(pin!(signaled), token.cancelled()).await {
+        Either::Left((outcome, _)) => outcome.map(Some),
+        Either::Right(_) => Ok(None),
+    }
+}
+
+/// Delay before accepting connections again after running out of resources.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Accepts connections until the token is cancelled.
+///
+/// Transient errors are skipped, the stream terminates after yielding a fatal error.
+#[cfg_attr(not(any(unix, windows)), allow(unused))]
+pub(crate) fn accept_loop<'a, T, F, Fut>(
+    mut accept: F,
+    token: &'a CancellationToken,
+) -> impl Stream<Item = Result<T, Error>> + 'a
+where
+    T: 'a,
+    F: FnMut() -> Fut + 'a,
+    Fut: Future<Output = Result<T, Error>> + 'a,
+{
+    stream! {
+        while let Either::Left((conn, _)) = select(pin!(accept()), token.cancelled()).await {
+            match conn {
+                Ok(conn) => yield Ok(conn),
+                Err(err) if is_transient_accept_error(&err) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!("Skipping connection which could not be accepted: {err}");
+                    if is_resource_exhaustion(&err) {
+                        // Do not spin while resources are missing
+                        Timer::after(ACCEPT_BACKOFF).await;
+                    }
+                }
+                Err(err) => {
+                    yield Err(err);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Tells whether an error returned while accepting a connection only affects that connection, or
+/// is caused by a temporary shortage of resources.
+pub(crate) fn is_transient_accept_error(err: &Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionReset
+            | ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+    ) || is_resource_exhaustion(err)
+}
+
+fn is_resource_exhaustion(err: &Error) -> bool {
+    if err.kind() == ErrorKind::OutOfMemory {
+        return true;
+    }
+    #[cfg(unix)]
+    {
+        use nix::errno::Errno;
+        matches!(
+            err.raw_os_error().map(Errno::from_raw),
+            Some(Errno::EMFILE | Errno::ENFILE | Errno::ENOBUFS | Errno::ENOMEM)
+        )
+    }
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::Networking::WinSock::{WSAEMFILE, WSAENOBUFS};
+        matches!(err.raw_os_error(), Some(WSAEMFILE | WSAENOBUFS))
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        false
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::collections::VecDeque;
+
+    use assert_matches::assert_matches;
+    use futures::StreamExt;
+
+    use super::*;
+
+    #[test]
+    fn test_accept_loop_skips_transient_errors() {
+        let mut results = VecDeque::from([
+            Ok(1),
+            Err(Error::from(ErrorKind::ConnectionAborted)),
+            Ok(2),
+            Err(Error::from(ErrorKind::OutOfMemory)),
+            Ok(3),
+            Err(Error::from(ErrorKind::PermissionDenied)),
+            Ok(4),
+        ]);
+
+        let token = CancellationToken::new();
+        let connections = futures::executor::block_on(
+            accept_loop(|| std::future::ready(results.pop_front().unwrap()), &token)
+                .collect::<Vec<_>>(),
+        );
+
+        let mut connections = connections.into_iter();
+        assert_matches!(connections.next(), Some(Ok(1)));
+        assert_matches!(connections.next(), Some(Ok(2)));
+        assert_matches!(connections.next(), Some(Ok(3)));
+        assert_matches!(
+            connections.next(),
+            Some(Err(err)) if err.kind() == ErrorKind::PermissionDenied
+        );
+        assert_matches!(connections.next(), None);
+    }
+}
@@ -0,0 +1,173 @@
+//! Listening on several transports at once.
+//!
+//! [`listen_multi`] merges the connections of all the configured transports into a single stream,
+//! e.g. to be reachable both through the default local channel and through a TCP port forwarded
+//! by a tunnel for remote debugging. Each connection tells which transport it came from, and is
+//! already split into its input and output, ready to be passed to a server connection.
+
+use std::{net::SocketAddr, pin::pin};
+
+use async_net::TcpListener;
+use async_stream::try_stream;
+use futures::{
+    stream::{self, LocalBoxStream},
+    AsyncRead, AsyncReadExt, AsyncWrite, Stream, StreamExt,
+};
+
+use crate::attach::{accept_loop, attacher::Attacher, ListenHandle};
+
+/// Input half of a connection.
+pub type Input = Box<dyn AsyncRead + Unpin>;
+
+/// Output half of a connection.
+pub type Output = Box<dyn AsyncWrite + Unpin>;
+
+/// Transport to listen on with [`listen_multi`].
+pub enum TransportConfig {
+    /// Default communication channel of the platform, see [`listen`](crate::attach::listen).
+    Default,
+    /// TCP socket bound immediately to the passed address.
+    ///
+    /// Anybody who can reach the address can connect, bind it to a loopback address and let a
+    /// tunnel forward it.
+    Tcp(SocketAddr),
+    /// Connections provided by the caller, e.g. an in-memory transport in tests.
+    Custom(LocalBoxStream<'static, Result<(Input, Output), Box<dyn std::error::Error>>>),
+}
+
+/// Connection returned by [`listen_multi`].
+pub struct Connection {
+    /// Index of the transport in the configurations passed to [`listen_multi`].
+    pub transport: usize,
+    /// Input of the connection.
+    pub input: Input,
+    /// Output of the connection.
+    pub output: Output,
+}
+
+/// Starts listening on all the passed transports and returns the incoming connections of all of
+/// them as a single async `Stream`.
+///
+/// A transport which fails yields its error and stops, the other ones go on. In order to stop
+/// accepting connections on all of them, either stop polling the stream or call
+/// [`ListenHandle::shutdown`] on the returned handle.
+pub fn listen_multi<A>(
+    configs: Vec<TransportConfig>,
+) -> (
+    ListenHandle,
+    impl Stream<Item = Result<Connection, Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher + 'static,
+{
+    let handle = ListenHandle::new();
+
+    let streams = configs
+        .into_iter()
+        .enumerate()
+        .map(|(transport, config)| {
+            let connections = match config {
+                TransportConfig::Default => {
+                    // Dropping the stream is enough to stop it, the handle is not needed
+                    let (_handle, connections) = crate::attach::listen::<A>();
+                    connections
+                        .map(|conn| {
+                            let (input, output) = conn?.0.split();
+                            Ok((Box::new(input) as Input, Box::new(output) as Output))
+                        })
+                        .boxed_local()
+                }
+                TransportConfig::Tcp(addr) => listen_tcp(addr, handle.clone()).boxed_local(),
+                TransportConfig::Custom(connections) => connections,
+            };
+            connections.map(move |conn| {
+                conn.map(|(input, output)| Connection {
+                    transport,
+                    input,
+                    output,
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let stream = stream::select_all(streams).take_until(handle.token().cancelled());
+
+    (handle, stream)
+}
+
+fn listen_tcp(
+    addr: SocketAddr,
+    handle: ListenHandle,
+) -> impl Stream<Item = Result<(Input, Output), Box<dyn std::error::Error>>> {
+    try_stream! {
+        let listener = TcpListener::bind(addr).await?;
+        let mut connections = pin!(accept_loop(|| listener.accept(), handle.token()));
+        while let Some(conn) = connections.next().await {
+            let (input, output) = conn?.0.split();
+            yield (Box::new(input) as Input, Box::new(output) as Output);
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use futures::AsyncWriteExt;
+
+    use super::*;
+    use crate::{attach::attacher::dummy::DummyAttacher, config::TeleopConfig};
+
+    #[test]
+    fn test_listen_multi() {
+        // Isolate the socket from the other tests
+        TeleopConfig::install_for_thread(Some(TeleopConfig {
+            socket_prefix: ".teleop_multi_".into(),
+            ..TeleopConfig::default()
+        }));
+
+        let (memory_input, mut memory_client) = sluice::pipe::pipe();
+        let (_, memory_output) = sluice::pipe::pipe();
+        let memory = stream::iter([Ok((
+            Box::new(memory_input) as Input,
+            Box::new(memory_output) as Output,
+        ))])
+        .chain(stream::pending())
+        .boxed_local();
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (handle, connections) = listen_multi::<DummyAttacher>(vec![
+                TransportConfig::Default,
+                TransportConfig::Custom(memory),
+            ]);
+            let mut connections = pin!(connections);
+
+            let mut memory = connections.next().await.unwrap()?;
+            assert_eq!(memory.transport, 1);
+            memory_client.write_all(b"memory").await?;
+            let mut buf = [0; 6];
+            memory.input.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"memory");
+
+            let (accepted, connected) = futures::join!(
+                connections.next(),
+                crate::attach::connect::<DummyAttacher>(std::process::id())
+            );
+            let mut unix = accepted.unwrap()?;
+            assert_eq!(unix.transport, 0);
+            connected?.write_all(b"socket").await?;
+            unix.input.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"socket");
+
+            handle.shutdown();
+            assert!(connections.next().await.is_none());
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        TeleopConfig::install_for_thread(None);
+
+        res.unwrap();
+    }
+}
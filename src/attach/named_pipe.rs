@@ -0,0 +1,746 @@
+//! Communicate through a Windows named pipe.
+//!
+//! [`listen`] is the function to call in the process to be teleoperated.
+//!
+//! [`connect`] is the function to call in the client to initiate the teleoperation communication.
+//!
+//! The pipe of a process is named `\\.\pipe\teleop_{pid}`.
+
+use std::{
+    fs::OpenOptions,
+    io::{Read, Write},
+    iter::once,
+    os::windows::{
+        ffi::OsStrExt,
+        fs::OpenOptionsExt,
+        io::{AsRawHandle, FromRawHandle, OwnedHandle},
+    },
+    path::{Path, PathBuf},
+    pin::{pin, Pin},
+    ptr,
+    sync::Arc,
+};
+
+use async_stream::try_stream;
+use blocking::{unblock, Unblock};
+use futures::{
+    future::{select, Either},
+    task::{Context, Poll},
+    AsyncRead, AsyncWrite, Stream, StreamExt,
+};
+use windows_sys::{
+    core::BOOL,
+    Win32::{
+        Foundation::{
+            ERROR_BROKEN_PIPE, ERROR_IO_PENDING, ERROR_NO_DATA, ERROR_PIPE_BUSY,
+            ERROR_PIPE_CONNECTED, ERROR_SEM_TIMEOUT, FALSE, HANDLE, INVALID_HANDLE_VALUE, TRUE,
+        },
+        Storage::FileSystem::{
+            ReadFile, WriteFile, FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED,
+            PIPE_ACCESS_DUPLEX,
+        },
+        System::{
+            Pipes::{
+                ConnectNamedPipe, CreateNamedPipeW, WaitNamedPipeW, PIPE_READMODE_BYTE,
+                PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+            },
+            Threading::CreateEventW,
+            IO::{CancelIoEx, GetOverlappedResult, OVERLAPPED},
+        },
+    },
+};
+
+use crate::{
+    attach::{
+        attacher::{Attacher, AttacherSignal, RetryOpts, SelfId},
+        is_transient_accept_error, signaled_unless_cancelled, trace_signal_outcome, AttachError,
+        ListenHandle, Target,
+    },
+    cancellation::CancellationToken,
+};
+
+const PIPE_BUFFER_SIZE: u32 = 8 * 1024;
+
+const PIPE_BUSY_TIMEOUT_MS: u32 = 1000;
+
+/// Connected named pipe, either on the server side or on the client side.
+///
+/// Reads and writes are performed by blocking threads so that they can run concurrently.
+#[derive(Debug)]
+pub struct NamedPipeStream {
+    handle: Arc<OwnedHandle>,
+    reader: Unblock<PipeIo>,
+    writer: Unblock<PipeIo>,
+}
+
+impl NamedPipeStream {
+    fn new(handle: Arc<OwnedHandle>) -> Self {
+        Self {
+            reader: Unblock::new(PipeIo(handle.clone())),
+            writer: Unblock::new(PipeIo(handle.clone())),
+            handle,
+        }
+    }
+}
+
+impl Drop for NamedPipeStream {
+    fn drop(&mut self) {
+        // Abort pending operations so that the blocking threads release the handle and the pipe
+        // gets closed.
+        cancel_io(&self.handle);
+    }
+}
+
+impl AsyncRead for NamedPipeStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        Pin::new(&mut self.get_mut().reader).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for NamedPipeStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        Pin::new(&mut self.get_mut().writer).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.get_mut().writer).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.get_mut().writer).poll_close(cx)
+    }
+}
+
+/// Blocking side of [`NamedPipeStream`].
+#[derive(Debug)]
+struct PipeIo(Arc<OwnedHandle>);
+
+impl Read for PipeIo {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(u32::MAX as usize) as u32;
+        // SAFETY: the buffer outlives the operation since overlapped_io waits for its completion
+        match overlapped_io(&self.0, |handle, overlapped| unsafe {
+            ReadFile(handle, buf.as_mut_ptr(), len, ptr::null_mut(), overlapped)
+        }) {
+            Ok(read) => Ok(read as usize),
+            // The other end closed the pipe
+            Err(err) if err.raw_os_error() == Some(ERROR_BROKEN_PIPE as i32) => Ok(0),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl Write for PipeIo {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(u32::MAX as usize) as u32;
+        // SAFETY: the buffer outlives the operation since overlapped_io waits for its completion
+        overlapped_io(&self.0, |handle, overlapped| unsafe {
+            WriteFile(handle, buf.as_ptr(), len, ptr::null_mut(), overlapped)
+        })
+        .map(|written| written as usize)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Starts listening for attach signals and return incoming connections as a async `Stream`.
+///
+/// Every connection comes with the name of the pipe.
+///
+/// In order to stop accepting connections, either stop polling the stream or call
+/// [`ListenHandle::shutdown`] on the returned handle.
+#[allow(clippy::type_complexity)]
+pub fn listen<A>() -> (
+    ListenHandle,
+    impl Stream<Item = Result<(NamedPipeStream, PathBuf), Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher,
+{
+    listen_with_self_id::<A>(SelfId::default())
+}
+
+/// Same as [`listen`] but the process is identified by the passed ID, both by the attacher and
+/// to name the pipe.
+///
+/// This lets tests simulate a target process distinct from the current one, see [`SelfId`].
+#[allow(clippy::type_complexity)]
+pub fn listen_with_self_id<A>(
+    self_id: SelfId,
+) -> (
+    ListenHandle,
+    impl Stream<Item = Result<(NamedPipeStream, PathBuf), Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher,
+{
+    listen_on_pipe::<A>(self_id, pipe_name(self_id.0))
+}
+
+/// Same as [`listen`] but the pipe is named using the passed process ID instead of the ID of the
+/// current process.
+///
+/// This is useful when the process runs in a PID namespace (e.g. a container) and the client
+/// knows it by another ID (e.g. the host PID).
+///
+/// The attacher identifies the process by the advertised ID as well, so that file based
+/// attachers watch the attach file the client creates for that ID. This is the same as
+/// [`listen_with_self_id`] with the advertised ID.
+#[allow(clippy::type_complexity)]
+pub fn listen_as<A>(
+    advertised_pid: u32,
+) -> (
+    ListenHandle,
+    impl Stream<Item = Result<(NamedPipeStream, PathBuf), Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher,
+{
+    listen_with_self_id::<A>(SelfId(advertised_pid))
+}
+
+#[allow(clippy::type_complexity)]
+fn listen_on_pipe<A>(
+    self_id: SelfId,
+    pipe_name: PathBuf,
+) -> (
+    ListenHandle,
+    impl Stream<Item = Result<(NamedPipeStream, PathBuf), Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher,
+{
+    // It is important to keep this in the synchronous part in order to ensure the listening
+    // process is ready to accept attachment requests even if the future is not awaited.
+    //
+    // Nevertheless, the error will only be raised if the future is awaited.
+    let signaled = A::signaled_as(self_id);
+
+    let handle = ListenHandle::new();
+    let token = handle.token().clone();
+
+    let stream = try_stream! {
+
+        let Some(outcome) = signaled_unless_cancelled(signaled, &token).await? else {
+            // Shut down before being signaled
+            return;
+        };
+        trace_signal_outcome(outcome);
+
+        let pipe = create_pipe_instance(&pipe_name, true)?;
+
+        let mut connections = pin!(accept_on_pipe(pipe_name, pipe, token));
+        while let Some(conn) = connections.next().await {
+            yield conn?;
+        }
+    };
+
+    (handle, stream)
+}
+
+/// Same as [`listen`] but the pipe is created immediately instead of waiting for the attach
+/// signal.
+///
+/// The pipe exists as long as the stream is alive, so that clients can connect right away, e.g.
+/// with [`connect_no_signal`]. This suits daemons which are always ready to be teleoperated.
+/// Clients signaling the process connect as well since the pipe already exists.
+#[allow(clippy::type_complexity)]
+pub fn listen_eager() -> (
+    ListenHandle,
+    impl Stream<Item = Result<(NamedPipeStream, PathBuf), Box<dyn std::error::Error>>>,
+) {
+    let pipe_name = pipe_name(std::process::id());
+    // The error will only be raised if the stream is polled
+    let pipe = create_pipe_instance(&pipe_name, true);
+
+    let handle = ListenHandle::new();
+    let token = handle.token().clone();
+
+    let stream = try_stream! {
+        let pipe = pipe?;
+
+        let mut connections = pin!(accept_on_pipe(pipe_name, pipe, token));
+        while let Some(conn) = connections.next().await {
+            yield conn?;
+        }
+    };
+
+    (handle, stream)
+}
+
+/// Accepts connections on the pipe, starting with the passed first instance.
+fn accept_on_pipe(
+    pipe_name: PathBuf,
+    pipe: OwnedHandle,
+    token: CancellationToken,
+) -> impl Stream<Item = Result<(NamedPipeStream, PathBuf), Box<dyn std::error::Error>>> {
+    try_stream! {
+        // The pipe disappears as soon as all its instances are closed, there is nothing to clean
+        // up when the stream terminates.
+        let mut pipe = Arc::new(pipe);
+
+        loop {
+            let accept = unblock({
+                let pipe = pipe.clone();
+                move || connect_pipe_instance(&pipe)
+            });
+            match select(pin!(accept), token.cancelled()).await {
+                Either::Left((Err(err), _)) if is_transient_accept_error(&err) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!("Skipping connection which could not be accepted: {err}");
+                    // The instance may be left in any state, start over with a new one
+                    pipe = Arc::new(create_pipe_instance(&pipe_name, false)?);
+                }
+                Either::Left((res, _)) => {
+                    res?;
+                    // Create the next instance before handing over the connected one so that
+                    // clients never observe a missing pipe.
+                    let connected = std::mem::replace(
+                        &mut pipe,
+                        Arc::new(create_pipe_instance(&pipe_name, false)?),
+                    );
+                    yield (NamedPipeStream::new(connected), pipe_name.clone());
+                }
+                Either::Right(_) => {
+                    // Unblock the pending connection so that the blocking thread releases the
+                    // pipe instance.
+                    cancel_io(&pipe);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Connects to a target process, usually identified by its ID.
+///
+/// Returns the opened pipe on success.
+pub async fn connect<A>(
+    target: impl Into<Target>,
+) -> Result<NamedPipeStream, Box<dyn std::error::Error>>
+where
+    A: Attacher,
+{
+    connect_with_retry::<A>(target, A::DEFAULT_RETRY).await
+}
+
+/// Same as [`connect`] but waits for the process with the passed retry options instead of the
+/// [default ones of the attacher](Attacher::DEFAULT_RETRY).
+pub async fn connect_with_retry<A>(
+    target: impl Into<Target>,
+    opts: RetryOpts,
+) -> Result<NamedPipeStream, Box<dyn std::error::Error>>
+where
+    A: Attacher,
+{
+    let pid = target.into().resolve_pid()?;
+    let pipe_name = pipe_name(pid);
+    connect_to_pipe::<A>(pid, &pipe_name, opts).await
+}
+
+/// Connects to a target process, without signaling it.
+///
+/// Fails immediately with [`AttachError::NotListening`] if the process does not listen, which is
+/// useful to reconnect to a process known to listen without disturbing it again.
+pub async fn connect_no_signal(
+    target: impl Into<Target>,
+) -> Result<NamedPipeStream, Box<dyn std::error::Error>> {
+    let pid = target.into().resolve_pid()?;
+    let pipe_name = pipe_name(pid);
+
+    if !pipe_exists(&pipe_name) {
+        return Err(AttachError::NotListening { pid }.into());
+    }
+
+    let handle = unblock(move || open_pipe(&pipe_name)).await?;
+
+    Ok(NamedPipeStream::new(Arc::new(handle)))
+}
+
+async fn connect_to_pipe<A>(
+    pid: u32,
+    pipe_name: impl AsRef<Path>,
+    opts: RetryOpts,
+) -> Result<NamedPipeStream, Box<dyn std::error::Error>>
+where
+    A: Attacher,
+{
+    let pipe_name = pipe_name.as_ref();
+
+    if !pipe_exists(pipe_name) {
+        let mut signal = A::signal(pid)?;
+
+        if !signal.wait_until(|| pipe_exists(pipe_name), opts).await? {
+            return Err(format!(
+                "Unable to open named pipe {}: target process {} doesn't respond",
+                pipe_name.to_string_lossy(),
+                pid
+            )
+            .into());
+        }
+    }
+
+    let handle = unblock({
+        let pipe_name = pipe_name.to_owned();
+        move || open_pipe(&pipe_name)
+    })
+    .await?;
+
+    Ok(NamedPipeStream::new(Arc::new(handle)))
+}
+
+fn pipe_name(pid: u32) -> PathBuf {
+    PathBuf::from(format!(r"\\.\pipe\teleop_{pid}"))
+}
+
+fn wide_name(pipe_name: &Path) -> Vec<u16> {
+    pipe_name.as_os_str().encode_wide().chain(once(0)).collect()
+}
+
+fn create_pipe_instance(pipe_name: &Path, first: bool) -> std::io::Result<OwnedHandle> {
+    let name = wide_name(pipe_name);
+    let mut open_mode = PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED;
+    if first {
+        // Fail if another process already owns the pipe
+        open_mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+    }
+    // SAFETY: the name is NUL terminated
+    let handle = unsafe {
+        CreateNamedPipeW(
+            name.as_ptr(),
+            open_mode,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_UNLIMITED_INSTANCES,
+            PIPE_BUFFER_SIZE,
+            PIPE_BUFFER_SIZE,
+            0,
+            ptr::null(),
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: the handle has just been created and nobody else owns it
+    Ok(unsafe { OwnedHandle::from_raw_handle(handle) })
+}
+
+/// Waits for a client to connect to the pipe instance, blocking the current thread.
+fn connect_pipe_instance(pipe: &OwnedHandle) -> std::io::Result<()> {
+    // SAFETY: overlapped_io waits for the completion of the operation
+    match overlapped_io(pipe, |handle, overlapped| unsafe {
+        ConnectNamedPipe(handle, overlapped)
+    }) {
+        Ok(_) => Ok(()),
+        // The client connected before the call
+        Err(err) if err.raw_os_error() == Some(ERROR_PIPE_CONNECTED as i32) => Ok(()),
+        // The client closed its end before the call
+        Err(err) if err.raw_os_error() == Some(ERROR_NO_DATA as i32) => Err(std::io::Error::new(
+            std::io::ErrorKind::ConnectionAborted,
+            err,
+        )),
+        Err(err) => Err(err),
+    }
+}
+
+/// Opens the client end of the pipe, blocking the current thread while all instances are busy.
+fn open_pipe(pipe_name: &Path) -> std::io::Result<OwnedHandle> {
+    loop {
+        match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(FILE_FLAG_OVERLAPPED)
+            .open(pipe_name)
+        {
+            Ok(file) => return Ok(file.into()),
+            Err(err) if err.raw_os_error() == Some(ERROR_PIPE_BUSY as i32) => {
+                let name = wide_name(pipe_name);
+                // SAFETY: the name is NUL terminated
+                if unsafe { WaitNamedPipeW(name.as_ptr(), PIPE_BUSY_TIMEOUT_MS) } == FALSE {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Returns `true` if the server created the pipe.
+///
+/// Opening the pipe to check its existence would use up a server instance, the pipe is waited for
+/// instead.
+fn pipe_exists(pipe_name: &Path) -> bool {
+    let name = wide_name(pipe_name);
+    // SAFETY: the name is NUL terminated
+    if unsafe { WaitNamedPipeW(name.as_ptr(), 1) } != FALSE {
+        return true;
+    }
+    // All instances are busy, but the pipe exists
+    std::io::Error::last_os_error().raw_os_error() == Some(ERROR_SEM_TIMEOUT as i32)
+}
+
+fn cancel_io(handle: &OwnedHandle) {
+    // SAFETY: the handle is valid, failure only means there is nothing to cancel
+    unsafe {
+        CancelIoEx(handle.as_raw_handle(), ptr::null());
+    }
+}
+
+/// Runs an overlapped operation and blocks the current thread until it completes.
+///
+/// Every operation has its own event so that reads and writes can run concurrently on the same
+/// handle.
+fn overlapped_io(
+    handle: &OwnedHandle,
+    op: impl FnOnce(HANDLE, *mut OVERLAPPED) -> BOOL,
+) -> std::io::Result<u32> {
+    // SAFETY: all pointer arguments are optional
+    let event = unsafe { CreateEventW(ptr::null(), TRUE, FALSE, ptr::null()) };
+    if event.is_null() {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: the event has just been created and nobody else owns it
+    let event = unsafe { OwnedHandle::from_raw_handle(event) };
+
+    let mut overlapped = OVERLAPPED {
+        hEvent: event.as_raw_handle(),
+        ..Default::default()
+    };
+
+    let handle = handle.as_raw_handle();
+    if op(handle, &mut overlapped) == FALSE {
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() != Some(ERROR_IO_PENDING as i32) {
+            return Err(err);
+        }
+    }
+
+    let mut transferred = 0;
+    // SAFETY: the OVERLAPPED structure is alive until the operation completes
+    if unsafe { GetOverlappedResult(handle, &overlapped, &mut transferred, TRUE) } == FALSE {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(transferred)
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::time::Duration;
+
+    use assert_matches::assert_matches;
+    use futures::{
+        channel::oneshot,
+        io::{BufReader, BufWriter},
+        AsyncReadExt, AsyncWriteExt, StreamExt,
+    };
+
+    use super::*;
+    use crate::{
+        attach::attacher::{dummy::DummyAttacher, DefaultAttacher},
+        internal::{set_attach_file_token, unique_attach_file_token},
+        operate::raw::read_line,
+    };
+
+    fn pipe_name_for_failure(pid: u32) -> PathBuf {
+        PathBuf::from(format!(r"\\.\pipe\teleop_{pid}_fail"))
+    }
+
+    fn pipe_name_for_shutdown(pid: u32) -> PathBuf {
+        PathBuf::from(format!(r"\\.\pipe\teleop_{pid}_shutdown"))
+    }
+
+    #[test]
+    fn test_named_pipe_attachment() {
+        // Isolate the attach file from attacher tests, both threads must share the same token
+        let client_token = unique_attach_file_token();
+        let server_token = client_token.clone();
+
+        let (sender, receiver) = oneshot::channel::<()>();
+
+        let server = || -> Result<(), Box<dyn std::error::Error>> {
+            set_attach_file_token(Some(server_token));
+
+            let mut exec = futures::executor::LocalPool::new();
+
+            let res = exec.run_until(async {
+                let (_handle, conn_stream) = listen::<DefaultAttacher>();
+                let mut conn_stream = pin!(conn_stream);
+                println!("server is listening");
+                sender.send(()).unwrap();
+                if let Some(stream) = conn_stream.next().await {
+                    println!("server received connection");
+                    let (stream, _name) = stream?;
+                    let (input, output) = stream.split();
+                    let mut input = BufReader::new(input);
+                    let mut output = BufWriter::new(output);
+
+                    let read = read_line(&mut input).await?;
+                    assert_eq!(read.as_deref(), Some("ping\n"));
+                    println!("server received ping");
+
+                    output.write_all("pong\n".as_bytes()).await?;
+                    output.flush().await?;
+                    println!("server wrote pong");
+                }
+
+                Ok::<_, Box<dyn std::error::Error>>(())
+            });
+
+            exec.run();
+
+            res?;
+
+            Ok(())
+        };
+
+        let client = || -> Result<(), Box<dyn std::error::Error>> {
+            set_attach_file_token(Some(client_token));
+
+            let pid = std::process::id();
+
+            let mut exec = futures::executor::LocalPool::new();
+
+            let res = exec.run_until(async move {
+                let () = receiver.await?;
+                println!("client is initiating connection");
+                let stream = connect::<DefaultAttacher>(pid).await?;
+                let (input, output) = stream.split();
+                let mut input = BufReader::new(input);
+                let mut output = BufWriter::new(output);
+                println!("client is connected");
+                output.write_all("ping\n".as_bytes()).await?;
+                output.flush().await?;
+                println!("client wrote ping");
+
+                let read = read_line(&mut input).await?;
+                assert_eq!(read.as_deref(), Some("pong\n"));
+                println!("client received pong");
+
+                Ok::<_, Box<dyn std::error::Error>>(())
+            });
+
+            exec.run();
+
+            res?;
+
+            Ok(())
+        };
+
+        let s = std::thread::spawn(|| server().unwrap());
+        // Improve code coverage by letting the server avoid early returns
+        std::thread::sleep(Duration::from_secs(2));
+        let c = std::thread::spawn(|| client().unwrap());
+        c.join().unwrap();
+        s.join().unwrap();
+    }
+
+    #[test]
+    fn test_named_pipe_attachment_failure() {
+        // This test may not conflict with the other tests because
+        // * it uses the dummy attacher
+        // * it uses a special pipe name
+
+        let pid = std::process::id();
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async move {
+            let result = connect_to_pipe::<DummyAttacher>(
+                pid,
+                pipe_name_for_failure(pid),
+                RetryOpts::default(),
+            )
+            .await;
+            let err = assert_matches!(result, Err(err) => err);
+            assert!(
+                err.to_string().starts_with("Unable to open named pipe"),
+                "Expected error `{err}` to start with `Unable to open named pipe`."
+            );
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        exec.run();
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_named_pipe_shutdown() {
+        // This test may not conflict with the other tests because
+        // * it uses the dummy attacher
+        // * it uses a special pipe name
+
+        let pid = std::process::id();
+        let pipe_name = pipe_name_for_shutdown(pid);
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (handle, conn_stream) =
+                listen_on_pipe::<DummyAttacher>(SelfId::default(), pipe_name.clone());
+            let mut conn_stream = pin!(conn_stream);
+
+            let (conn, client) = futures::join!(
+                conn_stream.next(),
+                connect_to_pipe::<DummyAttacher>(pid, &pipe_name, RetryOpts::default())
+            );
+            assert_matches!(conn, Some(Ok(_)));
+            client?;
+            assert!(pipe_exists(&pipe_name));
+
+            handle.shutdown();
+
+            assert_matches!(conn_stream.next().await, None);
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        exec.run();
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_named_pipe_connect_no_signal() {
+        // This test may not conflict with the other tests because
+        // * it uses the dummy attacher
+        // * it uses a PID which is not the PID of the current process
+
+        let advertised_pid = u32::MAX - 2 - std::process::id();
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let result = connect_no_signal(advertised_pid).await;
+            let err = assert_matches!(result, Err(err) => err);
+            assert_matches!(
+                err.downcast_ref::<AttachError>(),
+                Some(AttachError::NotListening { pid }) if *pid == advertised_pid
+            );
+
+            let (_handle, conn_stream) = listen_as::<DummyAttacher>(advertised_pid);
+            let mut conn_stream = pin!(conn_stream);
+
+            let (conn, client) =
+                futures::join!(conn_stream.next(), connect_no_signal(advertised_pid));
+            assert_matches!(conn, Some(Ok(_)));
+            client?;
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        exec.run();
+
+        res.unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+//! Reconnection with exponential backoff.
+//!
+//! Reconnecting in a tight loop to a process which is down would spin the CPU and flood the
+//! process with attach signals. [`reconnect`] waits longer and longer between attempts instead.
+
+use std::{future::Future, time::Duration};
+
+use async_io::Timer;
+
+use crate::internal::random_u64;
+
+/// Exponential backoff policy of [`reconnect`].
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    /// Delay before the first retry.
+    pub base: Duration,
+    /// Upper bound of the delay between two attempts.
+    pub max: Duration,
+    /// Maximum number of attempts, including the first one.
+    pub max_attempts: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            base: Duration::from_millis(100),
+            max: Duration::from_secs(10),
+            max_attempts: 10,
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay after the passed number of failed attempts.
+    ///
+    /// The delay doubles after each failure, up to [`max`](Self::max), and half of it is random so
+    /// that several clients do not retry in lockstep.
+    fn delay(&self, failures: u32) -> Duration {
+        let exp = self
+            .base
+            .saturating_mul(1 << (failures - 1).min(31))
+            .min(self.max);
+        let half = exp / 2;
+        let jitter = Duration::from_nanos(random_u64() % (half.as_nanos() as u64 + 1));
+        half + jitter
+    }
+}
+
+/// Progress of [`reconnect`], passed to the callback before waiting for the next attempt.
+#[derive(Debug)]
+pub struct Reconnection<'a> {
+    /// Number of failed attempts so far.
+    pub attempt: u32,
+    /// Delay before the next attempt.
+    pub delay: Duration,
+    /// Error of the last attempt.
+    pub error: &'a dyn std::error::Error,
+}
+
+/// Calls `connect` until it succeeds, waiting between attempts according to the backoff policy.
+///
+/// `on_retry` is called after each failed attempt but the last one. The error of the last attempt
+/// is returned if the maximum number of attempts is reached.
+pub async fn reconnect<T, C, F, R>(
+    mut connect: C,
+    backoff: &Backoff,
+    mut on_retry: R,
+) -> Result<T, Box<dyn std::error::Error>>
+where
+    C: FnMut() -> F,
+    F: Future<Output = Result<T, Box<dyn std::error::Error>>>,
+    R: FnMut(&Reconnection),
+{
+    let mut attempt = 0;
+    loop {
+        let error = match connect().await {
+            Ok(conn) => return Ok(conn),
+            Err(error) => error,
+        };
+        attempt += 1;
+        if attempt >= backoff.max_attempts {
+            return Err(error);
+        }
+        let delay = backoff.delay(attempt);
+        on_retry(&Reconnection {
+            attempt,
+            delay,
+            error: error.as_ref(),
+        });
+        Timer::after(delay).await;
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn test_backoff_delay() {
+        let backoff = Backoff {
+            base: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+            max_attempts: 10,
+        };
+        for (failures, exp) in [
+            (1, 100),
+            (2, 200),
+            (3, 400),
+            (4, 800),
+            (5, 1000),
+            (40, 1000),
+        ] {
+            let delay = backoff.delay(failures);
+            let exp = Duration::from_millis(exp);
+            assert!(
+                delay >= exp / 2 && delay <= exp,
+                "{delay:?} not within {exp:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let backoff = Backoff {
+            base: Duration::from_millis(1),
+            max: Duration::from_millis(5),
+            max_attempts: 5,
+        };
+
+        let calls = Cell::new(0);
+        let mut retries = Vec::new();
+        let res = futures::executor::block_on(reconnect(
+            || {
+                calls.set(calls.get() + 1);
+                let call = calls.get();
+                async move {
+                    if call < 4 {
+                        Err(format!("failure {call}").into())
+                    } else {
+                        Ok(call)
+                    }
+                }
+            },
+            &backoff,
+            |reconnection| retries.push((reconnection.attempt, reconnection.error.to_string())),
+        ));
+        assert_eq!(res.unwrap(), 4);
+        assert_eq!(
+            retries,
+            [
+                (1, "failure 1".to_owned()),
+                (2, "failure 2".to_owned()),
+                (3, "failure 3".to_owned())
+            ]
+        );
+
+        let calls = Cell::new(0);
+        let res = futures::executor::block_on(reconnect(
+            || {
+                calls.set(calls.get() + 1);
+                async { Err::<(), _>("down".into()) }
+            },
+            &backoff,
+            |_| {},
+        ));
+        assert_eq!(res.unwrap_err().to_string(), "down");
+        assert_eq!(calls.get(), 5);
+    }
+}
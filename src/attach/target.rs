@@ -0,0 +1,72 @@
+//! Identification of the process to attach to.
+
+use std::path::PathBuf;
+
+/// Process to attach to.
+///
+/// Supervisors do not always address processes by their ID, a target is resolved to the ID of the
+/// process right before attaching to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Target {
+    /// Process identified by its ID.
+    Pid(u32),
+    /// First process of a control group, identified by the path of the group in the cgroup file
+    /// system, e.g. `/sys/fs/cgroup/system.slice/foo.service`.
+    Cgroup(PathBuf),
+}
+
+impl Target {
+    /// Resolves the ID of the target process.
+    pub fn resolve_pid(&self) -> Result<u32, Box<dyn std::error::Error>> {
+        match self {
+            Self::Pid(pid) => Ok(*pid),
+            Self::Cgroup(path) => {
+                let procs = std::fs::read_to_string(path.join("cgroup.procs"))?;
+                let first = procs.lines().next().ok_or_else(|| {
+                    format!("Control group {} has no process", path.to_string_lossy())
+                })?;
+                Ok(first.trim().parse()?)
+            }
+        }
+    }
+}
+
+impl From<u32> for Target {
+    fn from(pid: u32) -> Self {
+        Self::Pid(pid)
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::*;
+    use crate::internal::unique_attach_file_token;
+
+    #[test]
+    fn test_target_pid() {
+        assert_eq!(Target::from(42).resolve_pid().unwrap(), 42);
+    }
+
+    #[test]
+    fn test_target_cgroup() {
+        let mut cgroup = std::env::temp_dir();
+        cgroup.push(format!(".teleop_cgroup_{}", unique_attach_file_token()));
+        std::fs::create_dir_all(&cgroup).unwrap();
+
+        let target = Target::Cgroup(cgroup.clone());
+
+        std::fs::write(cgroup.join("cgroup.procs"), "1234\n5678\n").unwrap();
+        assert_eq!(target.resolve_pid().unwrap(), 1234);
+
+        std::fs::write(cgroup.join("cgroup.procs"), "").unwrap();
+        assert!(target
+            .resolve_pid()
+            .unwrap_err()
+            .to_string()
+            .ends_with("has no process"));
+
+        std::fs::remove_dir_all(&cgroup).unwrap();
+    }
+}
@@ -3,25 +3,309 @@
 //! [`listen`] is the function to call in the process to be teleoperated.
 //!
 //! [`connect`] is the function to call in the client to initiate the teleoperation communication.
+//!
+//! Both are built on `async-net`, the `async_std` sub-module provides the same
+//! functions built on `async-std` when the `async-std` feature is enabled.
+//!
+//! The socket lives in the temporary directory of the teleoperated process, which
+//! [`resolve_socket_dir`] finds from the client even if both processes have different `TMPDIR`.
+//!
+//! On Linux, [`listen_abstract_cookie`] and [`connect_abstract_cookie`] use an abstract socket
+//! instead, named after a random cookie shared through a file next to the attach file.
+//!
+//! Binding and connecting the socket are retried when interrupted by a signal (`EINTR`), which is
+//! likely in a process teleoperated through signals.
 
 use std::{
-    os::unix::net::SocketAddr,
+    collections::HashSet,
+    os::{
+        fd::AsFd,
+        unix::{
+            fs::{DirBuilderExt, PermissionsExt},
+            net::SocketAddr,
+        },
+    },
     path::{Path, PathBuf},
-    time::Duration,
+    pin::pin,
+    time::{Duration, Instant},
 };
 
-use async_io::Timer;
 use async_net::unix::{UnixListener, UnixStream};
 use async_stream::try_stream;
-use futures::Stream;
+use futures::{
+    future::{select, Either},
+    Stream, StreamExt,
+};
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use nix::sys::socket::{getsockopt, sockopt};
+#[cfg(any(
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "ios",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+use nix::unistd::getpeereid;
+use nix::unistd::{chown, geteuid, Gid, Uid};
+
+use crate::{
+    attach::{
+        accept_loop,
+        attacher::{Attacher, AttacherSignal, RetryOpts, SelfId},
+        signaled_unless_cancelled, trace_signal_outcome, AttachError, ListenHandle, Target,
+    },
+    config::TeleopConfig,
+    internal::{
+        attach_file_path, process_exists, process_name, process_uid, random_u64, retry_on_eintr,
+        retry_on_eintr_async, AutoDropDir, AutoDropFile,
+    },
+    operate::capnp::registry::PeerCredentials,
+};
 
-use crate::attach::attacher::{Attacher, AttacherSignal};
+#[cfg(any(target_os = "android", target_os = "linux"))]
+mod abstract_cookie;
+#[cfg(feature = "async-std")]
+pub mod async_std;
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub use abstract_cookie::{
+    connect_abstract_cookie, listen_abstract_cookie, listen_abstract_cookie_with_auth_policy,
+};
 
 /// Starts listening for attach signals and return incoming connections as a async `Stream`.
 ///
-/// In order to stop accepting connections, it is enough to stop polling the stream.
-pub fn listen<A>(
-) -> impl Stream<Item = Result<(UnixStream, SocketAddr), Box<dyn std::error::Error>>>
+/// In order to stop accepting connections, either stop polling the stream or call
+/// [`ListenHandle::shutdown`] on the returned handle.
+#[allow(clippy::type_complexity)]
+pub fn listen<A>() -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UnixStream, SocketAddr), Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher,
+{
+    listen_with_self_id::<A>(SelfId::default())
+}
+
+/// Same as [`listen`] but the process is identified by the passed ID, both by the attacher and
+/// to bind the socket.
+///
+/// This lets tests simulate a target process distinct from the current one, see [`SelfId`].
+#[allow(clippy::type_complexity)]
+pub fn listen_with_self_id<A>(
+    self_id: SelfId,
+) -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UnixStream, SocketAddr), Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher,
+{
+    listen_with_options::<A>(ListenOptions {
+        self_id,
+        ..ListenOptions::default()
+    })
+}
+
+/// Same as [`listen`] but the socket is bound using the passed process ID instead of the ID of
+/// the current process.
+///
+/// This is useful when the process runs in a PID namespace (e.g. a container) and the client
+/// knows it by another ID (e.g. the host PID).
+///
+/// The attacher identifies the process by the advertised ID as well, so that file based
+/// attachers watch the attach file the client creates for that ID. This is the same as
+/// [`listen_with_self_id`] with the advertised ID.
+#[allow(clippy::type_complexity)]
+pub fn listen_as<A>(
+    advertised_pid: u32,
+) -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UnixStream, SocketAddr), Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher,
+{
+    listen_with_self_id::<A>(SelfId(advertised_pid))
+}
+
+/// Same as [`listen`] but the socket is bound immediately instead of waiting for the attach
+/// signal.
+///
+/// The socket is present as long as the stream is alive, so that clients can connect right away,
+/// e.g. with [`connect_no_signal`]. This suits daemons which are always ready to be
+/// teleoperated. Clients signaling the process connect as well since the socket already exists.
+#[allow(clippy::type_complexity)]
+pub fn listen_eager() -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UnixStream, SocketAddr), Box<dyn std::error::Error>>>,
+) {
+    listen_eager_on_socket(socket_file_path(std::process::id()))
+}
+
+/// Same as [`listen`] but the socket is bound at the passed path.
+///
+/// The client must use [`connect_at`] with the same path. Together with an attacher which does
+/// not need to find the working directory of the target process, this works without the
+/// `discover` feature.
+#[allow(clippy::type_complexity)]
+pub fn listen_at<A>(
+    socket_file_path: PathBuf,
+) -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UnixStream, SocketAddr), Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher,
+{
+    listen_with_options::<A>(ListenOptions {
+        path: Some(socket_file_path),
+        ..ListenOptions::default()
+    })
+}
+
+/// Same as [`listen`] but access to the socket is controlled by the passed security settings.
+///
+/// By default the socket is created according to the umask of the process.
+#[allow(clippy::type_complexity)]
+pub fn listen_with_security<A>(
+    security: SocketSecurity,
+) -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UnixStream, SocketAddr), Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher,
+{
+    listen_with_options::<A>(ListenOptions {
+        security: Some(security),
+        ..ListenOptions::default()
+    })
+}
+
+/// Options of [`listen_with_options`].
+///
+/// The default options are the ones of [`listen`].
+#[derive(Clone, Debug, Default)]
+pub struct ListenOptions {
+    /// ID identifying the process, both by the attacher and to bind the socket, see
+    /// [`listen_with_self_id`].
+    pub self_id: SelfId,
+    /// Path of the socket file, see [`listen_at`]. It is derived from the ID if `None`.
+    pub path: Option<PathBuf>,
+    /// Access control of the socket file, see [`listen_with_security`]. The socket is created
+    /// according to the umask of the process if `None`.
+    pub security: Option<SocketSecurity>,
+    /// Peers allowed to connect. Connections from other peers are closed as soon as they are
+    /// accepted, before any protocol sees them.
+    ///
+    /// The credentials of the peer are read from the socket, see [`peer_credentials`].
+    /// Connections whose credentials cannot be read are closed as well, unless the policy is
+    /// [`AuthPolicy::Any`].
+    pub auth_policy: AuthPolicy,
+}
+
+/// Access control of the socket file.
+///
+/// For instance, mode `0o660` with the group of the operators allows users of that group to
+/// teleoperate the process while excluding others.
+#[derive(Clone, Debug)]
+pub struct SocketSecurity {
+    /// Permissions of the socket file.
+    pub mode: u32,
+    /// Group owning the socket file, unchanged if `None`.
+    pub group: Option<Gid>,
+}
+
+impl SocketSecurity {
+    /// Binds a socket at the passed path, which is only reachable once secured.
+    ///
+    /// The socket is bound in a private directory, where nobody else can connect whatever the
+    /// umask, then linked into place once its mode and group are set. Changing the mode of the
+    /// socket itself with `fchmod` would not affect its file.
+    fn bind(&self, path: &Path) -> Result<UnixListener, Box<dyn std::error::Error>> {
+        let mut private_dir_name = path.file_name().unwrap_or_default().to_owned();
+        private_dir_name.push(format!(".{:016x}", random_u64()));
+        let private_dir = path.with_file_name(private_dir_name);
+        std::fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&private_dir)?;
+        let _private_dir = AutoDropDir::adopt(private_dir.clone());
+
+        let private_path = private_dir.join("socket");
+        let listener = retry_on_eintr(|| UnixListener::bind(&private_path))?;
+        let _private_file = AutoDropFile::adopt(private_path.clone());
+        if let Some(group) = self.group {
+            chown(&private_path, None, Some(group))?;
+        }
+        std::fs::set_permissions(&private_path, std::fs::Permissions::from_mode(self.mode))?;
+        // Unlike a rename, linking does not replace an existing file
+        std::fs::hard_link(&private_path, path)?;
+        Ok(listener)
+    }
+}
+
+/// Peers allowed to connect, see [`ListenOptions::auth_policy`].
+///
+/// It complements [`SocketSecurity`], which controls who can reach the socket file, by checking
+/// who is actually at the other end of every connection.
+#[derive(Clone, Debug, Default)]
+pub enum AuthPolicy {
+    /// Only processes running as the effective user of the current process.
+    SameUser,
+    /// Only processes running as one of the passed users.
+    Users(HashSet<Uid>),
+    /// Only processes running as one of the passed groups.
+    Groups(HashSet<Gid>),
+    /// Any process which can reach the socket.
+    #[default]
+    Any,
+}
+
+impl AuthPolicy {
+    /// Whether the passed peer satisfies the policy.
+    pub fn allows(&self, peer: &PeerCredentials) -> bool {
+        match self {
+            Self::SameUser => peer.uid == geteuid().as_raw(),
+            Self::Users(users) => users.contains(&Uid::from_raw(peer.uid)),
+            Self::Groups(groups) => groups.contains(&Gid::from_raw(peer.gid)),
+            Self::Any => true,
+        }
+    }
+
+    fn allows_socket(&self, socket: impl AsFd) -> bool {
+        if let Self::Any = self {
+            // Do not even require credentials, which are not supported on all platforms
+            return true;
+        }
+        match peer_credentials(socket) {
+            Ok(peer) => {
+                let allowed = self.allows(&peer);
+                #[cfg(feature = "tracing")]
+                if !allowed {
+                    tracing::warn!(?peer, "Connection rejected by the authorization policy");
+                }
+                allowed
+            }
+            #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+            Err(err) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(%err, "Connection rejected, the peer credentials are unknown");
+                false
+            }
+        }
+    }
+}
+
+/// Same as [`listen`] but with the passed options, which the other variants are shorthands for.
+#[allow(clippy::type_complexity)]
+pub fn listen_with_options<A>(
+    options: ListenOptions,
+) -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UnixStream, SocketAddr), Box<dyn std::error::Error>>>,
+)
 where
     A: Attacher,
 {
@@ -29,111 +313,576 @@ where
     // process is ready to accept attachment requests even if the future is not awaited.
     //
     // Nevertheless, the error will only be raised if the future is awaited.
-    let signaled = A::signaled();
+    let ListenOptions {
+        self_id,
+        path,
+        security,
+        auth_policy,
+    } = options;
+    let signaled = A::signaled_as(self_id);
+    let socket_file_path = path.unwrap_or_else(|| socket_file_path(self_id.0));
+
+    let handle = ListenHandle::new();
+    let token = handle.token().clone();
+
+    let stream = try_stream! {
+
+        let Some(outcome) = signaled_unless_cancelled(signaled, &token).await? else {
+            // Shut down before being signaled
+            return;
+        };
+        trace_signal_outcome(outcome);
 
-    try_stream! {
+        let listener = match &security {
+            Some(security) => security.bind(&socket_file_path)?,
+            None => retry_on_eintr(|| UnixListener::bind(&socket_file_path))?,
+        };
+        // Unbind the socket when the stream terminates
+        let _socket_file = AutoDropFile::adopt(socket_file_path.clone());
+
+        let mut connections = pin!(accept_loop(|| listener.accept(), &token));
+        while let Some(conn) = connections.next().await {
+            let (stream, addr) = conn?;
+            // Dropping the stream closes the connection
+            if auth_policy.allows_socket(&stream) {
+                yield (stream, addr);
+            }
+        }
+    };
+
+    (handle, stream)
+}
+
+#[allow(clippy::type_complexity)]
+fn listen_eager_on_socket(
+    socket_file_path: PathBuf,
+) -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UnixStream, SocketAddr), Box<dyn std::error::Error>>>,
+) {
+    // The error will only be raised if the stream is polled, but the socket is unbound as soon as
+    // the stream is dropped, even if it is never polled.
+    let listener = retry_on_eintr(|| UnixListener::bind(&socket_file_path));
+    let socket_file = listener
+        .is_ok()
+        .then(|| AutoDropFile::adopt(socket_file_path));
 
-        signaled.await?;
+    let handle = ListenHandle::new();
+    let token = handle.token().clone();
 
-        let listener = UnixListener::bind(socket_file_path(std::process::id()))?;
+    let stream = try_stream! {
+        let _socket_file = socket_file;
+        let listener = listener?;
 
-        loop {
-            let conn = listener.accept().await?;
-            yield conn;
+        let mut connections = pin!(accept_loop(|| listener.accept(), &token));
+        while let Some(conn) = connections.next().await {
+            yield conn?;
         }
+    };
+
+    (handle, stream)
+}
+
+/// Waits for the attach signal and accepts exactly one connection.
+///
+/// The socket is unbound as soon as the connection is accepted. This suits short-lived tools
+/// which serve a single client, where [`listen`] and a loop over the connections are overkill.
+pub async fn accept_one<A>() -> Result<UnixStream, Box<dyn std::error::Error>>
+where
+    A: Attacher,
+{
+    accept_one_on_socket::<A>(socket_file_path(std::process::id())).await
+}
+
+async fn accept_one_on_socket<A>(
+    socket_file_path: PathBuf,
+) -> Result<UnixStream, Box<dyn std::error::Error>>
+where
+    A: Attacher,
+{
+    let (_handle, connections) = listen_at::<A>(socket_file_path);
+    let mut connections = pin!(connections);
+    match connections.next().await {
+        Some(conn) => Ok(conn?.0),
+        None => Err("Stopped listening before accepting a connection".into()),
     }
 }
 
-/// Connects to a process identified by its ID.
+/// Connects to a target process, usually identified by its ID.
 ///
 /// Returns the opened socket on success.
-pub async fn connect<A>(pid: u32) -> Result<UnixStream, Box<dyn std::error::Error>>
+pub async fn connect<A>(target: impl Into<Target>) -> Result<UnixStream, Box<dyn std::error::Error>>
 where
     A: Attacher,
 {
-    let socket_file_path = socket_file_path(pid);
-    connect_to_socket::<A>(pid, &socket_file_path).await
+    connect_with_retry::<A>(target, A::DEFAULT_RETRY).await
+}
+
+/// Same as [`connect`] but waits for the process with the passed retry options instead of the
+/// [default ones of the attacher](Attacher::DEFAULT_RETRY).
+pub async fn connect_with_retry<A>(
+    target: impl Into<Target>,
+    opts: RetryOpts,
+) -> Result<UnixStream, Box<dyn std::error::Error>>
+where
+    A: Attacher,
+{
+    let pid = target.into().resolve_pid()?;
+    let socket_file_path = target_socket_file_path(pid);
+    connect_to_socket::<A>(pid, &socket_file_path, opts).await
+}
+
+/// Same as [`connect`] but calls `progress` on every attempt to find the socket of the process,
+/// with the number of the attempt, starting from 1, and the time elapsed since the first one.
+///
+/// It lets interactive clients report slow attachments, e.g. `attaching… attempt 5/100`, the
+/// maximum being given by [`Attacher::DEFAULT_RETRY`].
+pub async fn connect_with_progress<A>(
+    target: impl Into<Target>,
+    progress: impl FnMut(u32, Duration),
+) -> Result<UnixStream, Box<dyn std::error::Error>>
+where
+    A: Attacher,
+{
+    let pid = target.into().resolve_pid()?;
+    let socket_file_path = target_socket_file_path(pid);
+    wait_for_socket_with_progress::<A>(pid, &socket_file_path, A::DEFAULT_RETRY, progress).await?;
+    let stream = retry_on_eintr_async(|| UnixStream::connect(&socket_file_path)).await?;
+    verify_peer_user(pid, &socket_file_path, &stream)?;
+    Ok(stream)
+}
+
+/// Connects to a process identified by its ID, through the socket at the passed path.
+///
+/// See [`listen_at`].
+pub async fn connect_at<A>(
+    pid: u32,
+    socket_file_path: impl AsRef<Path>,
+) -> Result<UnixStream, Box<dyn std::error::Error>>
+where
+    A: Attacher,
+{
+    connect_to_socket::<A>(pid, socket_file_path, A::DEFAULT_RETRY).await
+}
+
+/// Connects to a target process, without signaling it.
+///
+/// Fails immediately with [`AttachError::NotListening`] if the process does not listen, which is
+/// useful to reconnect to a process known to listen without disturbing it again.
+pub async fn connect_no_signal(
+    target: impl Into<Target>,
+) -> Result<UnixStream, Box<dyn std::error::Error>> {
+    let pid = target.into().resolve_pid()?;
+    let socket_file_path = target_socket_file_path(pid);
+
+    if !socket_file_path.exists() {
+        return Err(AttachError::NotListening { pid }.into());
+    }
+
+    let stream = retry_on_eintr_async(|| UnixStream::connect(&socket_file_path)).await?;
+    verify_peer_user(pid, &socket_file_path, &stream)?;
+    Ok(stream)
+}
+
+/// What [`connect`] would do to attach to a process, see [`connect_dry_run`].
+#[derive(Clone, Debug)]
+pub struct ConnectPlan {
+    /// ID of the target process.
+    pub pid: u32,
+    /// Whether the target process is running.
+    pub target_exists: bool,
+    /// Path of the socket to connect to.
+    pub socket_file_path: PathBuf,
+    /// Whether the socket already exists, in which case the process is not signaled.
+    pub socket_exists: bool,
+    /// Path of the attach file created by file based attachers, `None` if it cannot be
+    /// determined, e.g. because the working directory of the process cannot be found.
+    pub attach_file_path: Option<PathBuf>,
+}
+
+/// Reports what [`connect`] would do to attach to the passed process, without signaling it nor
+/// connecting to it.
+pub fn connect_dry_run(pid: u32) -> ConnectPlan {
+    let socket_file_path = target_socket_file_path(pid);
+    ConnectPlan {
+        pid,
+        target_exists: process_exists(pid),
+        socket_exists: socket_file_path.exists(),
+        socket_file_path,
+        attach_file_path: attach_file_path(pid).ok(),
+    }
+}
+
+/// Process found by [`list_attachable`].
+#[derive(Clone, Debug)]
+pub struct AttachableProcess {
+    /// ID of the process.
+    pub pid: u32,
+    /// Name of the process, `None` if it is not running or without the `discover` feature.
+    pub name: Option<String>,
+    /// Path of the socket of the process.
+    pub socket_file_path: PathBuf,
+    /// Whether the process is running and the socket bound, as opposed to a socket left behind
+    /// by a process which did not terminate gracefully.
+    pub socket_bound: bool,
+}
+
+/// Lists the processes which listen for connections, ordered by ID.
+///
+/// Sockets are found in the temporary directory of the current process, so processes running
+/// with another `TMPDIR` are not listed. Processes which have not been signaled yet do not listen
+/// either.
+pub fn list_attachable() -> Result<Vec<AttachableProcess>, Box<dyn std::error::Error>> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let socket_prefix = TeleopConfig::current().socket_prefix;
+    let mut processes = Vec::new();
+    for entry in std::fs::read_dir(std::env::temp_dir())? {
+        let entry = entry?;
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix(&*socket_prefix))
+            .and_then(|pid| pid.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        let is_socket = entry.file_type()?.is_socket();
+        let name = process_name(pid);
+        processes.push(AttachableProcess {
+            pid,
+            socket_bound: is_socket && process_exists(pid),
+            name,
+            socket_file_path: entry.path(),
+        });
+    }
+    processes.sort_by_key(|process| process.pid);
+    Ok(processes)
+}
+
+/// Connects through a socket forwarded from a remote host, without signaling any process.
+///
+/// The process behind the socket is remote, so it can neither be signaled nor found by ID
+/// locally: it must already listen, e.g. thanks to [`listen_eager`], or be signaled remotely.
+///
+/// With OpenSSH, the socket of the remote process is forwarded to a local path this way:
+///
+/// ```text
+/// ssh -N -L /tmp/remote.sock:/path/to/remote/cwd/.teleop_pid_1234 user@host
+/// ```
+///
+/// `/tmp/remote.sock` is then the path to pass to this function.
+pub async fn connect_forwarded(
+    socket_file_path: impl AsRef<Path>,
+) -> Result<UnixStream, Box<dyn std::error::Error>> {
+    Ok(retry_on_eintr_async(|| UnixStream::connect(&socket_file_path)).await?)
 }
 
 async fn connect_to_socket<A>(
     pid: u32,
     socket_file_path: impl AsRef<Path>,
+    opts: RetryOpts,
 ) -> Result<UnixStream, Box<dyn std::error::Error>>
 where
     A: Attacher,
 {
     let socket_file_path = socket_file_path.as_ref();
+    wait_for_socket::<A>(pid, socket_file_path, opts).await?;
+    let stream = retry_on_eintr_async(|| UnixStream::connect(&socket_file_path)).await?;
+    verify_peer_user(pid, socket_file_path, &stream)?;
+    Ok(stream)
+}
+
+/// Signals the process until the socket file exists.
+async fn wait_for_socket<A>(
+    pid: u32,
+    socket_file_path: &Path,
+    opts: RetryOpts,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    A: Attacher,
+{
+    wait_for_socket_with_progress::<A>(pid, socket_file_path, opts, |_, _| {}).await
+}
 
+async fn wait_for_socket_with_progress<A>(
+    pid: u32,
+    socket_file_path: &Path,
+    opts: RetryOpts,
+    progress: impl FnMut(u32, Duration),
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    A: Attacher,
+{
     if !socket_file_path.exists() {
         let mut signal = A::signal(pid)?;
 
-        signal.send().await?;
+        let attempts = opts.max_attempts;
+        let started = Instant::now();
+        let polled = signal.wait_until_with_progress(|| socket_file_path.exists(), opts, progress);
+        let created = match select(pin!(polled), pin!(socket_created(socket_file_path))).await {
+            Either::Left((created, _)) => created?,
+            Either::Right(((), _)) => true,
+        };
+        if !created {
+            return Err(AttachError::Timeout {
+                path: socket_file_path.to_owned(),
+                pid,
+                attempts,
+                elapsed: started.elapsed(),
+            }
+            .into());
+        }
+    }
 
-        let mut attempts = 1;
+    Ok(())
+}
 
-        while !socket_file_path.exists() && attempts < 100 {
-            Timer::after(Duration::from_millis(100)).await;
+/// Resolves as soon as the socket file is created, without waiting for the next poll.
+///
+/// It never resolves if the creation cannot be watched, polling takes over then.
+#[cfg(feature = "inotify")]
+async fn socket_created(socket_file_path: &Path) {
+    use crate::attach::attacher::inotify::wait_for_file;
+
+    if wait_for_file(socket_file_path).await.is_err() {
+        futures::future::pending().await
+    }
+}
 
-            signal.send().await?;
+#[cfg(not(feature = "inotify"))]
+async fn socket_created(_socket_file_path: &Path) {
+    futures::future::pending().await
+}
+
+/// Verifies that the process at the other end of the connected socket is run by the user running
+/// the target process, so that a socket planted by another local user cannot intercept the
+/// connection.
+///
+/// The credentials are the ones of the process which bound the socket, so replacing the socket
+/// file between the check and the connection is not possible. If the user running the target
+/// process cannot be found, e.g. for another process without the `discover` feature outside of
+/// Linux, the connection is refused with [`AttachError::UnknownTargetUser`] unless
+/// [`TeleopConfig::allow_unknown_target_user`] is set.
+fn verify_peer_user(
+    pid: u32,
+    socket_file_path: &Path,
+    socket: impl AsFd,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match process_uid(pid) {
+        Some(expected_uid) => check_peer_user(socket_file_path, socket, expected_uid),
+        None if TeleopConfig::current().allow_unknown_target_user => Ok(()),
+        None => Err(AttachError::UnknownTargetUser { pid }.into()),
+    }
+}
 
-            attempts += 1;
+fn check_peer_user(
+    socket_file_path: &Path,
+    socket: impl AsFd,
+    expected_uid: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let actual_uid = peer_credentials(socket)?.uid;
+    if actual_uid != expected_uid {
+        return Err(AttachError::SocketOwnershipMismatch {
+            path: socket_file_path.to_owned(),
+            expected_uid,
+            actual_uid,
         }
+        .into());
+    }
+    Ok(())
+}
 
-        if !socket_file_path.exists() {
-            return Err(format!(
-                "Unable to open socket file {}: target process {} doesn't respond",
-                socket_file_path.to_string_lossy(),
-                pid
-            )
-            .into());
+/// Returns the credentials of the process at the other end of the passed socket.
+///
+/// They can be reported by a
+/// [`ConnectionRegistry`](crate::operate::capnp::registry::ConnectionRegistry).
+pub fn peer_credentials(socket: impl AsFd) -> Result<PeerCredentials, Box<dyn std::error::Error>> {
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    {
+        let credentials = getsockopt(&socket, sockopt::PeerCredentials)?;
+        Ok(PeerCredentials {
+            pid: u32::try_from(credentials.pid()).ok(),
+            uid: credentials.uid(),
+            gid: credentials.gid(),
+        })
+    }
+    #[cfg(any(
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "ios",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    ))]
+    {
+        let (uid, gid) = getpeereid(socket)?;
+        Ok(PeerCredentials {
+            pid: None,
+            uid: uid.as_raw(),
+            gid: gid.as_raw(),
+        })
+    }
+    #[cfg(not(any(
+        target_os = "android",
+        target_os = "linux",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "ios",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    )))]
+    {
+        let _ = socket;
+        Err("Peer credentials are not supported on this platform".into())
+    }
+}
+
+/// Returns the directory where the passed process creates its socket, i.e. its temporary
+/// directory.
+///
+/// On Linux, the `TMPDIR` variable of the process is read from `/proc/<pid>/environ`, so that
+/// clients find the socket even if their own `TMPDIR` differs. When the environment cannot be
+/// read, e.g. the process belongs to another user, or on other platforms, the temporary directory
+/// of the current process is returned.
+pub fn resolve_socket_dir(pid: u32) -> PathBuf {
+    if pid == std::process::id() {
+        return std::env::temp_dir();
+    }
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    match std::fs::read(format!("/proc/{pid}/environ")) {
+        // Empty while the process is being executed, or once it is a zombie
+        Ok(environ) if !environ.is_empty() => return temp_dir_from_environ(&environ),
+        Ok(_) => {}
+        Err(_err) => {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(pid, error = %_err, "Cannot read the environment of the process");
         }
     }
+    std::env::temp_dir()
+}
+
+/// Mimics [`std::env::temp_dir`] with the passed NUL separated environment.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn temp_dir_from_environ(environ: &[u8]) -> PathBuf {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
 
-    Ok(UnixStream::connect(socket_file_path).await?)
+    environ
+        .split(|byte| *byte == 0)
+        .find_map(|entry| entry.strip_prefix(b"TMPDIR="))
+        .map(|dir| PathBuf::from(OsStr::from_bytes(dir)))
+        .unwrap_or_else(|| {
+            PathBuf::from(if cfg!(target_os = "android") {
+                "/data/local/tmp"
+            } else {
+                "/tmp"
+            })
+        })
 }
 
+/// Path of the socket the current process listens on when identified by the passed ID.
 fn socket_file_path(pid: u32) -> PathBuf {
-    let mut path = std::env::temp_dir();
-    path.push(format!(".teleop_pid_{pid}"));
-    path
+    socket_file_path_in(std::env::temp_dir(), pid)
+}
+
+/// Path of the socket the passed process listens on.
+fn target_socket_file_path(pid: u32) -> PathBuf {
+    socket_file_path_in(resolve_socket_dir(pid), pid)
+}
+
+fn socket_file_path_in(mut dir: PathBuf, pid: u32) -> PathBuf {
+    dir.push(format!("{}{pid}", TeleopConfig::current().socket_prefix));
+    dir
 }
 
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
-    use std::pin::pin;
+    use std::{os::unix::fs::MetadataExt, time::Duration};
 
     use assert_matches::assert_matches;
     use futures::{
         channel::oneshot,
         io::{BufReader, BufWriter},
-        AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, StreamExt,
+        task::LocalSpawnExt,
+        AsyncReadExt, AsyncWriteExt, StreamExt,
     };
 
     use super::*;
     use crate::{
-        attach::attacher::{dummy::DummyAttacher, DefaultAttacher},
-        tests::ATTACH_PROCESS_TEST_MUTEX,
+        attach::attacher::{dummy::DummyAttacher, DefaultAttacher, SignalOutcome},
+        internal::{set_attach_file_token, unique_attach_file_token},
+        operate::raw::read_line,
     };
 
-    fn socket_file_path_for_failure(pid: u32) -> PathBuf {
+    fn test_socket_path(pid: u32, tag: &str) -> PathBuf {
         let mut path = std::env::temp_dir();
-        path.push(format!(".teleop_pid_{pid}_fail"));
+        path.push(format!(".teleop_pid_{pid}_{tag}"));
         path
     }
 
+    #[test]
+    fn test_list_attachable() {
+        // Isolate the socket from the other tests
+        TeleopConfig::install_for_thread(Some(TeleopConfig {
+            socket_prefix: ".teleop_list_".into(),
+            ..TeleopConfig::default()
+        }));
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (_handle, connections) = listen::<DummyAttacher>();
+            let mut connections = pin!(connections);
+            // Bind the socket
+            assert!(futures::poll!(connections.next()).is_pending());
+
+            let processes = list_attachable()?;
+            let process = processes
+                .iter()
+                .find(|process| process.pid == std::process::id())
+                .unwrap();
+            assert!(process.socket_bound);
+            assert_eq!(
+                process.socket_file_path,
+                socket_file_path(std::process::id())
+            );
+            #[cfg(feature = "discover")]
+            assert!(process.name.as_ref().is_some_and(|name| !name.is_empty()));
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        // The socket is unbound once the stream is dropped
+        let unlisted = list_attachable().map(|processes| {
+            processes
+                .iter()
+                .all(|process| process.pid != std::process::id())
+        });
+
+        TeleopConfig::install_for_thread(None);
+
+        res.unwrap();
+        assert!(unlisted.unwrap());
+    }
+
     #[test]
     fn test_unix_socket_attachment() {
-        // This test may conflict with attacher tests
-        let _attacher_test = ATTACH_PROCESS_TEST_MUTEX.lock();
+        // Isolate the attach file from attacher tests, both threads must share the same token
+        let client_token = unique_attach_file_token();
+        let server_token = client_token.clone();
 
         let (sender, receiver) = oneshot::channel::<()>();
 
         let server = || -> Result<(), Box<dyn std::error::Error>> {
+            set_attach_file_token(Some(server_token));
+
             let mut exec = futures::executor::LocalPool::new();
 
             let res = exec.run_until(async {
-                let mut conn_stream = pin!(listen::<DefaultAttacher>());
+                let (_handle, conn_stream) = listen::<DefaultAttacher>();
+                let mut conn_stream = pin!(conn_stream);
                 println!("server is listening");
                 sender.send(()).unwrap();
                 if let Some(stream) = conn_stream.next().await {
@@ -143,9 +892,8 @@ mod tests {
                     let mut input = BufReader::new(input);
                     let mut output = BufWriter::new(output);
 
-                    let mut read = String::new();
-                    while input.read_line(&mut read).await? == 0 {}
-                    assert_eq!(read, "ping\n");
+                    let read = read_line(&mut input).await?;
+                    assert_eq!(read.as_deref(), Some("ping\n"));
                     println!("server received ping");
 
                     output.write_all("pong\n".as_bytes()).await?;
@@ -164,6 +912,8 @@ mod tests {
         };
 
         let client = || -> Result<(), Box<dyn std::error::Error>> {
+            set_attach_file_token(Some(client_token));
+
             let pid = std::process::id();
 
             let mut exec = futures::executor::LocalPool::new();
@@ -180,9 +930,8 @@ mod tests {
                 output.flush().await?;
                 println!("client wrote ping");
 
-                let mut read = String::new();
-                while input.read_line(&mut read).await? == 0 {}
-                assert_eq!(read, "pong\n");
+                let read = read_line(&mut input).await?;
+                assert_eq!(read.as_deref(), Some("pong\n"));
                 println!("client received pong");
 
                 Ok::<_, Box<dyn std::error::Error>>(())
@@ -215,14 +964,25 @@ mod tests {
             let mut exec = futures::executor::LocalPool::new();
 
             let res = exec.run_until(async move {
-                let result =
-                    connect_to_socket::<DummyAttacher>(pid, socket_file_path_for_failure(pid))
-                        .await;
+                let result = connect_to_socket::<DummyAttacher>(
+                    pid,
+                    test_socket_path(pid, "fail"),
+                    RetryOpts::default(),
+                )
+                .await;
                 let err = assert_matches!(result, Err(err) => err);
                 assert!(
                     err.to_string().starts_with("Unable to open socket file"),
                     "Expected error `{err}` to start with `Unable to open socket file`."
                 );
+                assert_matches!(
+                    err.downcast_ref::<AttachError>(),
+                    Some(AttachError::Timeout { path, pid: err_pid, attempts, elapsed })
+                        if *path == test_socket_path(pid, "fail")
+                            && *err_pid == pid
+                            && *attempts == RetryOpts::default().max_attempts
+                            && *elapsed >= Duration::from_secs(9)
+                );
                 Ok::<_, Box<dyn std::error::Error>>(())
             });
 
@@ -235,4 +995,766 @@ mod tests {
 
         client().unwrap();
     }
+
+    #[test]
+    fn test_unix_socket_attachment_failure_with_retry() {
+        // This test may not conflict with the other tests because
+        // * it uses the dummy attacher
+        // * it uses a special socket path
+
+        let pid = std::process::id();
+        let opts = RetryOpts {
+            interval: Duration::from_millis(10),
+            max_attempts: 3,
+            ..DummyAttacher::DEFAULT_RETRY
+        };
+
+        let result = futures::executor::block_on(connect_to_socket::<DummyAttacher>(
+            pid,
+            test_socket_path(pid, "fail"),
+            opts,
+        ));
+        let err = assert_matches!(result, Err(err) => err);
+        assert_matches!(
+            err.downcast_ref::<AttachError>(),
+            Some(AttachError::Timeout { attempts: 3, elapsed, .. })
+                if *elapsed < Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn test_unix_socket_accept_one() {
+        // This test may not conflict with the other tests because
+        // * it uses the dummy attacher
+        // * it uses a special socket path
+
+        let pid = std::process::id();
+        let socket_file_path = test_socket_path(pid, "accept_one");
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (conn, client) = futures::join!(
+                accept_one_on_socket::<DummyAttacher>(socket_file_path.clone()),
+                connect_to_socket::<DummyAttacher>(pid, &socket_file_path, RetryOpts::default())
+            );
+            let mut conn = conn?;
+            let mut client = client?;
+            assert!(!socket_file_path.exists());
+
+            client.write_all(b"ping").await?;
+            let mut read = [0; 4];
+            conn.read_exact(&mut read).await?;
+            assert_eq!(&read, b"ping");
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        exec.run();
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_unix_socket_listen_eager() {
+        // This test may not conflict with the other tests because
+        // * it uses no attacher
+        // * it uses a special socket path
+
+        let pid = std::process::id();
+        let socket_file_path = test_socket_path(pid, "eager");
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (_handle, conn_stream) = listen_eager_on_socket(socket_file_path.clone());
+            // Bound before the stream is polled
+            assert!(socket_file_path.exists());
+            let mut conn_stream = pin!(conn_stream);
+
+            // The client connects without signaling
+            let (conn, client) =
+                futures::join!(conn_stream.next(), UnixStream::connect(&socket_file_path));
+            assert_matches!(conn, Some(Ok(_)));
+            client?;
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        exec.run();
+
+        res.unwrap();
+        assert!(!socket_file_path.exists());
+    }
+
+    #[test]
+    fn test_unix_socket_connect_forwarded() {
+        // This test may not conflict with the other tests because
+        // * it uses no attacher
+        // * it uses special socket paths
+
+        let pid = std::process::id();
+        let remote_path = test_socket_path(pid, "forward_remote");
+        let local_path = test_socket_path(pid, "forward_local");
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        // Stand-in for `ssh -L`: forwards the local socket to the remote one
+        let forwarder = UnixListener::bind(&local_path).unwrap();
+        let _local_file = AutoDropFile::adopt(local_path.clone());
+        exec.spawner()
+            .spawn_local({
+                let remote_path = remote_path.clone();
+                async move {
+                    let (local, _) = forwarder.accept().await.unwrap();
+                    let remote = UnixStream::connect(&remote_path).await.unwrap();
+                    let (mut local_writer, mut remote_writer) = (local.clone(), remote.clone());
+                    let _ = futures::join!(
+                        futures::io::copy(local, &mut remote_writer),
+                        futures::io::copy(remote, &mut local_writer),
+                    );
+                }
+            })
+            .unwrap();
+
+        let res = exec.run_until(async {
+            let (_handle, conn_stream) = listen_eager_on_socket(remote_path.clone());
+            let mut conn_stream = pin!(conn_stream);
+
+            let (conn, client) = futures::join!(conn_stream.next(), connect_forwarded(&local_path));
+            let (mut conn, _) = assert_matches!(conn, Some(Ok(conn)) => conn);
+            let mut client = client?;
+
+            client.write_all(b"ping").await?;
+            let mut read = [0; 4];
+            conn.read_exact(&mut read).await?;
+            assert_eq!(&read, b"ping");
+
+            conn.write_all(b"pong").await?;
+            client.read_exact(&mut read).await?;
+            assert_eq!(&read, b"pong");
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_unix_socket_connect_with_progress() {
+        // This test may not conflict with the other tests because
+        // * it uses an attacher which does nothing
+        // * the custom prefix only applies to the current thread
+
+        struct QuickAttacher;
+
+        impl Attacher for QuickAttacher {
+            type Signal = <DummyAttacher as Attacher>::Signal;
+
+            const DEFAULT_RETRY: RetryOpts = RetryOpts {
+                interval: Duration::from_millis(10),
+                max_attempts: 5,
+                ..RetryOpts::DEFAULT
+            };
+
+            fn signal(pid: u32) -> Result<Self::Signal, Box<dyn std::error::Error>> {
+                DummyAttacher::signal(pid)
+            }
+
+            async fn signaled_as(
+                self_id: SelfId,
+            ) -> Result<SignalOutcome, Box<dyn std::error::Error>> {
+                DummyAttacher::signaled_as(self_id).await
+            }
+        }
+
+        TeleopConfig::install_for_thread(Some(TeleopConfig {
+            socket_prefix: ".teleop_progress_".into(),
+            ..TeleopConfig::default()
+        }));
+
+        let mut progress = Vec::new();
+        let result = futures::executor::block_on(connect_with_progress::<QuickAttacher>(
+            std::process::id(),
+            |attempt, elapsed| progress.push((attempt, elapsed)),
+        ));
+
+        TeleopConfig::install_for_thread(None);
+
+        let err = assert_matches!(result, Err(err) => err);
+        let attempts = assert_matches!(
+            err.downcast_ref::<AttachError>(),
+            Some(AttachError::Timeout { attempts, .. }) => *attempts
+        );
+        assert_eq!(progress.len(), attempts as usize);
+        assert!(progress
+            .iter()
+            .enumerate()
+            .all(|(i, (attempt, _))| *attempt as usize == i + 1));
+        assert!(progress.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+    }
+
+    #[test]
+    fn test_unix_socket_prefix() {
+        // This test may not conflict with the other tests because
+        // * it uses no attacher
+        // * the custom prefix only applies to the current thread
+
+        TeleopConfig::install_for_thread(Some(TeleopConfig {
+            socket_prefix: ".teleop_custom_".into(),
+            ..TeleopConfig::default()
+        }));
+
+        let pid = std::process::id();
+        let socket_file_path = socket_file_path(pid);
+        assert_eq!(
+            socket_file_path.file_name().unwrap().to_string_lossy(),
+            format!(".teleop_custom_{pid}")
+        );
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (_handle, conn_stream) = listen_eager();
+            assert!(socket_file_path.exists());
+            let mut conn_stream = pin!(conn_stream);
+
+            let (conn, client) = futures::join!(conn_stream.next(), connect_no_signal(pid));
+            assert_matches!(conn, Some(Ok(_)));
+            client?;
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        TeleopConfig::install_for_thread(None);
+
+        res.unwrap();
+        assert!(!socket_file_path.exists());
+    }
+
+    #[test]
+    fn test_unix_socket_ownership() {
+        // This test may not conflict with the other tests because
+        // * it uses no attacher
+        // * it uses a special socket path
+
+        let pid = std::process::id();
+        let socket_file_path = test_socket_path(pid, "ownership");
+
+        let _listener = std::os::unix::net::UnixListener::bind(&socket_file_path).unwrap();
+        let _socket_file = AutoDropFile::adopt(socket_file_path.clone());
+        let stream = std::os::unix::net::UnixStream::connect(&socket_file_path).unwrap();
+
+        let uid = nix::unistd::geteuid().as_raw();
+        verify_peer_user(pid, &socket_file_path, &stream).unwrap();
+        check_peer_user(&socket_file_path, &stream, uid).unwrap();
+
+        // As if the socket had been planted by another user
+        let err = check_peer_user(&socket_file_path, &stream, uid + 1).unwrap_err();
+        assert_matches!(
+            err.downcast_ref::<AttachError>(),
+            Some(AttachError::SocketOwnershipMismatch { path, expected_uid, actual_uid })
+                if *path == socket_file_path && *expected_uid == uid + 1 && *actual_uid == uid
+        );
+
+        // The user running a process which does not exist cannot be found
+        let unknown_pid = u32::MAX - 5 - pid;
+        let err = verify_peer_user(unknown_pid, &socket_file_path, &stream).unwrap_err();
+        assert_matches!(
+            err.downcast_ref::<AttachError>(),
+            Some(AttachError::UnknownTargetUser { pid }) if *pid == unknown_pid
+        );
+
+        TeleopConfig::install_for_thread(Some(TeleopConfig {
+            allow_unknown_target_user: true,
+            ..TeleopConfig::default()
+        }));
+        let res = verify_peer_user(unknown_pid, &socket_file_path, &stream);
+        TeleopConfig::install_for_thread(None);
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_unix_socket_connect_dry_run() {
+        // This test may not conflict with the other tests because
+        // * it uses no attacher
+        // * the custom prefix only applies to the current thread
+
+        TeleopConfig::install_for_thread(Some(TeleopConfig {
+            socket_prefix: ".teleop_dry_run_".into(),
+            ..TeleopConfig::default()
+        }));
+
+        let pid = std::process::id();
+        let plan = connect_dry_run(pid);
+        assert_eq!(plan.pid, pid);
+        assert!(plan.target_exists);
+        assert_eq!(plan.socket_file_path, socket_file_path(pid));
+        assert!(!plan.socket_exists);
+        assert_eq!(plan.attach_file_path, Some(attach_file_path(pid).unwrap()));
+
+        let _listener = UnixListener::bind(&plan.socket_file_path).unwrap();
+        let _socket_file = AutoDropFile::adopt(plan.socket_file_path.clone());
+        assert!(connect_dry_run(pid).socket_exists);
+
+        let missing_pid = u32::MAX - 8 - pid;
+        assert!(!connect_dry_run(missing_pid).target_exists);
+
+        TeleopConfig::install_for_thread(None);
+    }
+
+    #[test]
+    fn test_resolve_socket_dir() {
+        assert_eq!(resolve_socket_dir(std::process::id()), std::env::temp_dir());
+
+        let custom_dir = std::env::temp_dir().join("teleop_custom_tmpdir");
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .env("TMPDIR", &custom_dir)
+            .spawn()
+            .unwrap();
+        // Wait for the environment to be set up
+        #[cfg(any(target_os = "android", target_os = "linux"))]
+        while std::fs::read(format!("/proc/{}/environ", child.id()))
+            .unwrap()
+            .is_empty()
+        {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let resolved = resolve_socket_dir(child.id());
+        let resolved_socket = target_socket_file_path(child.id());
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        if cfg!(any(target_os = "android", target_os = "linux")) {
+            assert_eq!(resolved, custom_dir);
+            assert_eq!(
+                resolved_socket,
+                custom_dir.join(format!(".teleop_pid_{}", child.id()))
+            );
+        } else {
+            assert_eq!(resolved, std::env::temp_dir());
+        }
+
+        // The environment of a process which is gone cannot be read
+        assert_eq!(resolve_socket_dir(child.id()), std::env::temp_dir());
+    }
+
+    #[test]
+    fn test_unix_socket_shutdown() {
+        // This test may not conflict with the other tests because
+        // * it uses the dummy attacher
+        // * it uses a special socket path
+
+        let pid = std::process::id();
+        let socket_file_path = test_socket_path(pid, "shutdown");
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (handle, conn_stream) = listen_at::<DummyAttacher>(socket_file_path.clone());
+            let mut conn_stream = pin!(conn_stream);
+
+            let (conn, client) = futures::join!(
+                conn_stream.next(),
+                connect_at::<DummyAttacher>(pid, &socket_file_path)
+            );
+            assert_matches!(conn, Some(Ok(_)));
+            client?;
+            assert!(socket_file_path.exists());
+
+            handle.shutdown();
+
+            assert_matches!(conn_stream.next().await, None);
+            assert!(!socket_file_path.exists());
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        exec.run();
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_unix_socket_shutdown_before_signal() {
+        /// Attacher which is never signaled.
+        struct PendingAttacher;
+
+        impl Attacher for PendingAttacher {
+            type Signal = <DummyAttacher as Attacher>::Signal;
+
+            fn signal(pid: u32) -> Result<Self::Signal, Box<dyn std::error::Error>> {
+                DummyAttacher::signal(pid)
+            }
+
+            async fn signaled_as(
+                _self_id: SelfId,
+            ) -> Result<SignalOutcome, Box<dyn std::error::Error>> {
+                futures::future::pending().await
+            }
+        }
+
+        let pid = std::process::id();
+        let socket_file_path = test_socket_path(pid, "shutdown").with_extension("pending");
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (handle, conn_stream) = listen_at::<PendingAttacher>(socket_file_path.clone());
+            let mut conn_stream = pin!(conn_stream);
+
+            assert!(futures::poll!(conn_stream.next()).is_pending());
+
+            handle.shutdown();
+
+            assert_matches!(conn_stream.next().await, None);
+            assert!(!socket_file_path.exists());
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[cfg(feature = "inotify")]
+    #[test]
+    fn test_unix_socket_connect_woken_by_inotify() {
+        // This test may not conflict with the other tests because
+        // * it uses the dummy attacher
+        // * it uses a special socket path
+
+        let pid = std::process::id();
+        let socket_file_path = test_socket_path(pid, "shutdown").with_extension("inotify");
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (_handle, conn_stream) = listen_at::<DummyAttacher>(socket_file_path.clone());
+            let mut conn_stream = pin!(conn_stream);
+
+            let start = Instant::now();
+            let (conn, client) = futures::join!(
+                async {
+                    // Bind the socket while the client waits for the next poll
+                    async_io::Timer::after(Duration::from_millis(20)).await;
+                    conn_stream.next().await
+                },
+                async {
+                    let client = connect_at::<DummyAttacher>(pid, &socket_file_path).await;
+                    (client, start.elapsed())
+                }
+            );
+            assert_matches!(conn, Some(Ok(_)));
+            let (client, elapsed) = client;
+            client?;
+            assert!(elapsed < RetryOpts::DEFAULT.interval, "{elapsed:?}");
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_unix_socket_security() {
+        // This test may not conflict with the other tests because
+        // * it uses the dummy attacher
+        // * it uses a special socket path
+
+        let pid = std::process::id();
+        let socket_file_path = test_socket_path(pid, "security");
+        let group = nix::unistd::getegid();
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (_handle, conn_stream) = listen_with_options::<DummyAttacher>(ListenOptions {
+                path: Some(socket_file_path.clone()),
+                security: Some(SocketSecurity {
+                    mode: 0o660,
+                    group: Some(group),
+                }),
+                ..ListenOptions::default()
+            });
+            let mut conn_stream = pin!(conn_stream);
+
+            let (conn, client) = futures::join!(
+                conn_stream.next(),
+                connect_to_socket::<DummyAttacher>(pid, &socket_file_path, RetryOpts::default())
+            );
+            assert_matches!(conn, Some(Ok(_)));
+            client?;
+
+            let metadata = std::fs::metadata(&socket_file_path)?;
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o660);
+            assert_eq!(metadata.gid(), group.as_raw());
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        exec.run();
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_unix_socket_security_bind() {
+        // This test may not conflict with the other tests because
+        // * it uses no attacher
+        // * it uses a special socket path
+
+        let pid = std::process::id();
+        let socket_file_path = test_socket_path(pid, "security_bind");
+        let mode = 0o600;
+
+        let security = SocketSecurity { mode, group: None };
+        let _listener = security.bind(&socket_file_path).unwrap();
+        let _socket_file = AutoDropFile::adopt(socket_file_path.clone());
+
+        // The socket appears with the requested mode, never a wider one
+        let actual_mode = std::fs::metadata(&socket_file_path)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(actual_mode & 0o777, mode);
+
+        // The private directory is gone
+        let file_name = socket_file_path.file_name().unwrap().to_str().unwrap();
+        let leftovers = std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                name.starts_with(file_name) && name != file_name
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+
+        // An existing socket is not replaced
+        assert!(security.bind(&socket_file_path).is_err());
+    }
+
+    fn test_auth_policy(
+        policy: AuthPolicy,
+        name: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let pid = std::process::id();
+        let socket_file_path = test_socket_path(pid, &format!("auth_{name}"));
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        exec.run_until(async {
+            let (_handle, conn_stream) = listen_with_options::<DummyAttacher>(ListenOptions {
+                path: Some(socket_file_path.clone()),
+                auth_policy: policy,
+                ..ListenOptions::default()
+            });
+            let mut conn_stream = pin!(conn_stream);
+
+            let (conn, client) = futures::join!(
+                async {
+                    // Accept the connection, if allowed, or reject it
+                    select(
+                        conn_stream.next(),
+                        async_io::Timer::after(Duration::from_millis(200)),
+                    )
+                    .await
+                },
+                connect_to_socket::<DummyAttacher>(pid, &socket_file_path, RetryOpts::default())
+            );
+            let mut client = client?;
+
+            match conn {
+                Either::Left((conn, _)) => {
+                    assert_matches!(conn, Some(Ok(_)));
+                    Ok(true)
+                }
+                Either::Right(_) => {
+                    // The connection was closed by the server
+                    let mut buf = [0; 1];
+                    assert_eq!(client.read(&mut buf).await?, 0);
+                    Ok(false)
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn test_unix_socket_auth_policy() {
+        // This test may not conflict with the other tests because
+        // * it uses the dummy attacher
+        // * it uses special socket paths
+
+        assert!(test_auth_policy(AuthPolicy::SameUser, "same_user").unwrap());
+        assert!(!test_auth_policy(AuthPolicy::Users(HashSet::new()), "no_users").unwrap());
+        let groups = HashSet::from([nix::unistd::getegid()]);
+        assert!(test_auth_policy(AuthPolicy::Groups(groups), "groups").unwrap());
+    }
+
+    #[test]
+    fn test_unix_socket_peer_credentials() {
+        let (socket, _peer) = std::os::unix::net::UnixStream::pair().unwrap();
+        let credentials = peer_credentials(&socket).unwrap();
+        assert_eq!(credentials.uid, nix::unistd::geteuid().as_raw());
+        assert_eq!(credentials.gid, nix::unistd::getegid().as_raw());
+        #[cfg(target_os = "linux")]
+        assert_eq!(credentials.pid, Some(std::process::id()));
+    }
+
+    #[test]
+    fn test_unix_socket_listen_as() {
+        // This test may not conflict with the other tests because
+        // * it uses the dummy attacher
+        // * it uses a PID which is not the PID of the current process
+
+        let advertised_pid = u32::MAX - std::process::id();
+
+        // No user runs the synthetic process, do not verify the one listening on the socket
+        TeleopConfig::install_for_thread(Some(TeleopConfig {
+            allow_unknown_target_user: true,
+            ..TeleopConfig::default()
+        }));
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (_handle, conn_stream) = listen_as::<DummyAttacher>(advertised_pid);
+            let mut conn_stream = pin!(conn_stream);
+
+            let (conn, client) =
+                futures::join!(conn_stream.next(), connect::<DummyAttacher>(advertised_pid));
+            assert_matches!(conn, Some(Ok(_)));
+            client?;
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        exec.run();
+
+        TeleopConfig::install_for_thread(None);
+
+        res.unwrap();
+    }
+
+    #[cfg(feature = "inotify")]
+    #[test]
+    fn test_unix_socket_listen_as_inotify() {
+        use crate::{
+            attach::attacher::inotify::InotifyAttacher,
+            config::{AttachFileLocation, TeleopConfig},
+        };
+
+        // The attach file of a PID which is not the one of the current process is in the
+        // temporary directory, since no such process has a working directory
+        TeleopConfig::install_for_thread(Some(TeleopConfig {
+            attach_file_location: AttachFileLocation::TempDir,
+            socket_prefix: ".teleop_listen_as_".into(),
+            // No user runs the advertised process
+            allow_unknown_target_user: true,
+            ..TeleopConfig::default()
+        }));
+        set_attach_file_token(Some(unique_attach_file_token()));
+
+        let advertised_pid = u32::MAX - 6 - std::process::id();
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (_handle, conn_stream) = listen_as::<InotifyAttacher>(advertised_pid);
+            let mut conn_stream = pin!(conn_stream);
+
+            // The attach file created by the client is named after the advertised PID
+            let (conn, client) = futures::join!(
+                conn_stream.next(),
+                connect::<InotifyAttacher>(advertised_pid)
+            );
+            assert_matches!(conn, Some(Ok(_)));
+            client?;
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        TeleopConfig::install_for_thread(None);
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_unix_socket_listen_with_self_id() {
+        // This test may not conflict with the other tests because
+        // * it uses the dummy attacher
+        // * it uses a synthetic ID which is not the PID of the current process
+
+        let self_id = SelfId(u32::MAX - 4 - std::process::id());
+
+        // No user runs the synthetic process, do not verify the one listening on the socket
+        TeleopConfig::install_for_thread(Some(TeleopConfig {
+            allow_unknown_target_user: true,
+            ..TeleopConfig::default()
+        }));
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (_handle, conn_stream) = listen_with_self_id::<DummyAttacher>(self_id);
+            let mut conn_stream = pin!(conn_stream);
+
+            let (conn, client) =
+                futures::join!(conn_stream.next(), connect::<DummyAttacher>(self_id.0));
+            assert_matches!(conn, Some(Ok(_)));
+            client?;
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        exec.run();
+
+        TeleopConfig::install_for_thread(None);
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_unix_socket_connect_no_signal() {
+        // This test may not conflict with the other tests because
+        // * it uses the dummy attacher
+        // * it uses a PID which is not the PID of the current process
+
+        let advertised_pid = u32::MAX - 2 - std::process::id();
+
+        // No user runs the synthetic process, do not verify the one listening on the socket
+        TeleopConfig::install_for_thread(Some(TeleopConfig {
+            allow_unknown_target_user: true,
+            ..TeleopConfig::default()
+        }));
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let result = connect_no_signal(advertised_pid).await;
+            let err = assert_matches!(result, Err(err) => err);
+            assert_matches!(
+                err.downcast_ref::<AttachError>(),
+                Some(AttachError::NotListening { pid }) if *pid == advertised_pid
+            );
+
+            let (_handle, conn_stream) = listen_as::<DummyAttacher>(advertised_pid);
+            let mut conn_stream = pin!(conn_stream);
+
+            let (conn, client) =
+                futures::join!(conn_stream.next(), connect_no_signal(advertised_pid));
+            assert_matches!(conn, Some(Ok(_)));
+            client?;
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        exec.run();
+
+        TeleopConfig::install_for_thread(None);
+
+        res.unwrap();
+    }
 }
@@ -0,0 +1,284 @@
+//! Linux abstract sockets named after a random cookie.
+//!
+//! Abstract sockets have no file, so they do not depend on a writable temporary directory and
+//! are never left behind. Neither are they subject to file permissions: their names are listed in
+//! `/proc/net/unix` and any local process can connect to them.
+//!
+//! Access is therefore controlled by the credentials of the peers. By default, connections from
+//! users other than the one running the process are closed as soon as they are accepted, see
+//! [`listen_abstract_cookie_with_auth_policy`] to allow others.
+//!
+//! The name is derived from a random cookie, `teleop-{cookie}`, so that it does not clash with
+//! the names of other processes. The server writes the cookie in a file next to the attach file,
+//! where clients read it before connecting.
+
+use std::{
+    cell::RefCell,
+    fs::OpenOptions,
+    io::{Read, Write},
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            fs::OpenOptionsExt,
+            net::{self, SocketAddr},
+        },
+    },
+    path::{Path, PathBuf},
+    pin::pin,
+    time::Instant,
+};
+
+use async_net::unix::{UnixListener, UnixStream};
+use async_stream::try_stream;
+use futures::{Stream, StreamExt};
+
+use super::{verify_peer_user, AuthPolicy};
+use crate::{
+    attach::{
+        accept_loop,
+        attacher::{Attacher, AttacherSignal, SelfId},
+        signaled_unless_cancelled, trace_signal_outcome, AttachError, ListenHandle, Target,
+    },
+    internal::{attach_file_path, retry_on_eintr, self_attach_file_path, AutoDropFile},
+};
+
+/// Number of random bytes of a cookie, hex encoded in the cookie file.
+const COOKIE_SIZE: usize = 16;
+
+/// Starts listening for attach signals and binds an abstract socket named after a fresh cookie
+/// once signaled, returning incoming connections as an async `Stream`.
+///
+/// The cookie file is removed when the stream terminates. Only connections from the user running
+/// the process are accepted, i.e. [`AuthPolicy::SameUser`].
+#[allow(clippy::type_complexity)]
+pub fn listen_abstract_cookie<A>() -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UnixStream, SocketAddr), Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher,
+{
+    listen_abstract_cookie_with_auth_policy::<A>(AuthPolicy::SameUser)
+}
+
+/// Same as [`listen_abstract_cookie`] but connections from peers which do not satisfy the passed
+/// policy are closed as soon as they are accepted.
+///
+/// Connections whose credentials cannot be read are closed as well, unless the policy is
+/// [`AuthPolicy::Any`].
+#[allow(clippy::type_complexity)]
+pub fn listen_abstract_cookie_with_auth_policy<A>(
+    auth_policy: AuthPolicy,
+) -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UnixStream, SocketAddr), Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher,
+{
+    // Same as `listen`, be ready to accept attachment requests even if the stream is not polled
+    let signaled = A::signaled_as(SelfId::default());
+
+    let handle = ListenHandle::new();
+    let token = handle.token().clone();
+
+    let stream = try_stream! {
+
+        let Some(outcome) = signaled_unless_cancelled(signaled, &token).await? else {
+            // Shut down before being signaled
+            return;
+        };
+        trace_signal_outcome(outcome);
+
+        let cookie = random_cookie()?;
+        let addr = SocketAddr::from_abstract_name(abstract_name(&cookie))?;
+        let listener = retry_on_eintr(|| net::UnixListener::bind_addr(&addr))?;
+        let listener = UnixListener::try_from(listener)?;
+
+        let cookie_file_path = cookie_file_path(self_attach_file_path(SelfId::default())?);
+        write_cookie_file(&cookie_file_path, &cookie)?;
+        let _cookie_file = AutoDropFile::adopt(cookie_file_path);
+
+        let mut connections = pin!(accept_loop(|| listener.accept(), &token));
+        while let Some(conn) = connections.next().await {
+            let (stream, addr) = conn?;
+            // Dropping the stream closes the connection
+            if auth_policy.allows_socket(&stream) {
+                yield (stream, addr);
+            }
+        }
+    };
+
+    (handle, stream)
+}
+
+/// Connects to a target process listening with [`listen_abstract_cookie`].
+///
+/// The process is signaled unless its cookie file already leads to its socket, then the cookie
+/// file is read until it does.
+pub async fn connect_abstract_cookie<A>(
+    target: impl Into<Target>,
+) -> Result<UnixStream, Box<dyn std::error::Error>>
+where
+    A: Attacher,
+{
+    let pid = target.into().resolve_pid()?;
+    let cookie_file_path = cookie_file_path(attach_file_path(pid)?);
+
+    let connected = RefCell::new(None);
+    // A stale cookie file, e.g. left by a previous server which crashed, does not lead anywhere
+    let try_connect = || match connect_with_cookie_file(&cookie_file_path) {
+        Ok(stream) => {
+            connected.replace(Some(stream));
+            true
+        }
+        Err(_) => false,
+    };
+
+    if !try_connect() {
+        let mut signal = A::signal(pid)?;
+
+        let opts = A::DEFAULT_RETRY;
+        let attempts = opts.max_attempts;
+        let started = Instant::now();
+        if !signal.wait_until(try_connect, opts).await? {
+            return Err(AttachError::Timeout {
+                path: cookie_file_path,
+                pid,
+                attempts,
+                elapsed: started.elapsed(),
+            }
+            .into());
+        }
+    }
+
+    let stream = connected.into_inner().expect("connected stream");
+    // Do not trust a cookie planted by another local user
+    verify_peer_user(pid, &cookie_file_path, &stream)?;
+    Ok(UnixStream::try_from(stream)?)
+}
+
+fn connect_with_cookie_file(
+    cookie_file_path: &Path,
+) -> Result<net::UnixStream, Box<dyn std::error::Error>> {
+    let cookie = std::fs::read_to_string(cookie_file_path)?;
+    let addr = SocketAddr::from_abstract_name(abstract_name(cookie.trim()))?;
+    Ok(retry_on_eintr(|| net::UnixStream::connect_addr(&addr))?)
+}
+
+fn write_cookie_file(cookie_file_path: &Path, cookie: &str) -> std::io::Result<()> {
+    // Never write the cookie in a file created by someone else, who could read it
+    match std::fs::remove_file(cookie_file_path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(cookie_file_path)?;
+    file.write_all(cookie.as_bytes())
+}
+
+fn random_cookie() -> std::io::Result<String> {
+    let mut bytes = [0; COOKIE_SIZE];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+fn abstract_name(cookie: &str) -> String {
+    format!("teleop-{cookie}")
+}
+
+fn cookie_file_path(attach_file_path: PathBuf) -> PathBuf {
+    let mut file_name = attach_file_path.file_name().unwrap_or_default().to_owned();
+    file_name.push(".cookie");
+    attach_file_path.with_file_name(file_name)
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::{collections::HashSet, os::unix::fs::PermissionsExt, time::Duration};
+
+    use futures::{
+        future::{select, Either},
+        AsyncReadExt, AsyncWriteExt,
+    };
+
+    use super::*;
+    use crate::{
+        attach::attacher::dummy::DummyAttacher,
+        internal::{set_attach_file_token, unique_attach_file_token},
+    };
+
+    #[test]
+    fn test_abstract_cookie() {
+        set_attach_file_token(Some(unique_attach_file_token()));
+
+        let cookie_file_path = cookie_file_path(attach_file_path(std::process::id()).unwrap());
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (_handle, connections) = listen_abstract_cookie::<DummyAttacher>();
+            let mut connections = pin!(connections);
+
+            let (accepted, connected) = futures::join!(
+                connections.next(),
+                connect_abstract_cookie::<DummyAttacher>(std::process::id())
+            );
+            let (mut server, _) = accepted.unwrap()?;
+            let mut client = connected?;
+
+            let cookie = std::fs::read_to_string(&cookie_file_path)?;
+            assert_eq!(cookie.len(), 2 * COOKIE_SIZE);
+            assert_eq!(
+                std::fs::metadata(&cookie_file_path)?.permissions().mode() & 0o777,
+                0o600
+            );
+
+            client.write_all(b"ping").await?;
+            let mut buf = [0; 4];
+            server.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"ping");
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+        assert!(!cookie_file_path.exists());
+    }
+
+    #[test]
+    fn test_abstract_cookie_auth_policy() {
+        set_attach_file_token(Some(unique_attach_file_token()));
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (_handle, connections) = listen_abstract_cookie_with_auth_policy::<DummyAttacher>(
+                AuthPolicy::Users(HashSet::new()),
+            );
+            let mut connections = pin!(connections);
+
+            let (accepted, connected) = futures::join!(
+                select(
+                    connections.next(),
+                    async_io::Timer::after(Duration::from_millis(200)),
+                ),
+                connect_abstract_cookie::<DummyAttacher>(std::process::id())
+            );
+            assert!(matches!(accepted, Either::Right(_)));
+
+            // The connection was closed by the server
+            let mut client = connected?;
+            let mut buf = [0; 1];
+            assert_eq!(client.read(&mut buf).await?, 0);
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+}
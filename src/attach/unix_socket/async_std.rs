@@ -0,0 +1,223 @@
+//! Same as the parent module but built on `async-std`.
+//!
+//! The attachment protocol is the same, a client using one runtime can attach to a process using
+//! the other one.
+
+use std::{path::PathBuf, pin::pin};
+
+use ::async_std::os::unix::net::{SocketAddr, UnixListener, UnixStream};
+use async_stream::try_stream;
+use futures::{Stream, StreamExt};
+
+use super::{socket_file_path, verify_peer_user, wait_for_socket};
+use crate::{
+    attach::{
+        accept_loop,
+        attacher::{Attacher, RetryOpts, SelfId},
+        signaled_unless_cancelled, trace_signal_outcome, ListenHandle, Target,
+    },
+    internal::{retry_on_eintr, retry_on_eintr_async, AutoDropFile},
+};
+
+/// Starts listening for attach signals and return incoming connections as a async `Stream`.
+///
+/// See [`listen`](super::listen).
+#[allow(clippy::type_complexity)]
+pub fn listen<A>() -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UnixStream, SocketAddr), Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher,
+{
+    listen_with_self_id::<A>(SelfId::default())
+}
+
+/// Same as [`listen`] but the process is identified by the passed ID.
+///
+/// See [`listen_with_self_id`](super::listen_with_self_id).
+#[allow(clippy::type_complexity)]
+pub fn listen_with_self_id<A>(
+    self_id: SelfId,
+) -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UnixStream, SocketAddr), Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher,
+{
+    listen_on_socket::<A>(self_id, socket_file_path(self_id.0))
+}
+
+/// Same as [`listen`] but the socket is bound using the passed process ID instead of the ID of
+/// the current process.
+///
+/// See [`listen_as`](super::listen_as).
+#[allow(clippy::type_complexity)]
+pub fn listen_as<A>(
+    advertised_pid: u32,
+) -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UnixStream, SocketAddr), Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher,
+{
+    listen_with_self_id::<A>(SelfId(advertised_pid))
+}
+
+/// Same as [`listen`] but the socket is bound immediately instead of waiting for the attach
+/// signal.
+///
+/// See [`listen_eager`](super::listen_eager).
+#[allow(clippy::type_complexity)]
+pub fn listen_eager() -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UnixStream, SocketAddr), Box<dyn std::error::Error>>>,
+) {
+    let socket_file_path = socket_file_path(std::process::id());
+    // Binding is asynchronous with `async-std`, bind with the standard library instead
+    let listener = retry_on_eintr(|| std::os::unix::net::UnixListener::bind(&socket_file_path))
+        .map(UnixListener::from);
+    let socket_file = listener
+        .is_ok()
+        .then(|| AutoDropFile::adopt(socket_file_path));
+
+    let handle = ListenHandle::new();
+    let token = handle.token().clone();
+
+    let stream = try_stream! {
+        let _socket_file = socket_file;
+        let listener = listener?;
+
+        let mut connections = pin!(accept_loop(|| listener.accept(), &token));
+        while let Some(conn) = connections.next().await {
+            yield conn?;
+        }
+    };
+
+    (handle, stream)
+}
+
+#[allow(clippy::type_complexity)]
+fn listen_on_socket<A>(
+    self_id: SelfId,
+    socket_file_path: PathBuf,
+) -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UnixStream, SocketAddr), Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher,
+{
+    // See the parent module, the process must be ready to accept attachment requests even if the
+    // future is not awaited.
+    let signaled = A::signaled_as(self_id);
+
+    let handle = ListenHandle::new();
+    let token = handle.token().clone();
+
+    let stream = try_stream! {
+
+        let Some(outcome) = signaled_unless_cancelled(signaled, &token).await? else {
+            // Shut down before being signaled
+            return;
+        };
+        trace_signal_outcome(outcome);
+
+        let listener = retry_on_eintr_async(|| UnixListener::bind(&socket_file_path)).await?;
+        // Unbind the socket when the stream terminates
+        let _socket_file = AutoDropFile::adopt(socket_file_path);
+
+        let mut connections = pin!(accept_loop(|| listener.accept(), &token));
+        while let Some(conn) = connections.next().await {
+            yield conn?;
+        }
+    };
+
+    (handle, stream)
+}
+
+/// Connects to a target process, usually identified by its ID.
+///
+/// Returns the opened socket on success.
+pub async fn connect<A>(target: impl Into<Target>) -> Result<UnixStream, Box<dyn std::error::Error>>
+where
+    A: Attacher,
+{
+    connect_with_retry::<A>(target, A::DEFAULT_RETRY).await
+}
+
+/// Same as [`connect`] but waits for the process with the passed retry options.
+///
+/// See [`connect_with_retry`](super::connect_with_retry).
+pub async fn connect_with_retry<A>(
+    target: impl Into<Target>,
+    opts: RetryOpts,
+) -> Result<UnixStream, Box<dyn std::error::Error>>
+where
+    A: Attacher,
+{
+    let pid = target.into().resolve_pid()?;
+    let socket_file_path = socket_file_path(pid);
+    wait_for_socket::<A>(pid, &socket_file_path, opts).await?;
+    // The credentials of the peer cannot be read from `async-std` streams, connect with the
+    // standard library instead
+    let stream = retry_on_eintr(|| std::os::unix::net::UnixStream::connect(&socket_file_path))?;
+    verify_peer_user(pid, &socket_file_path, &stream)?;
+    Ok(UnixStream::from(stream))
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use assert_matches::assert_matches;
+    use futures::{AsyncReadExt, AsyncWriteExt, StreamExt};
+
+    use super::*;
+    use crate::{attach::attacher::dummy::DummyAttacher, config::TeleopConfig};
+
+    #[test]
+    fn test_async_std_unix_socket_attachment() {
+        // This test may not conflict with the other tests because
+        // * it uses the dummy attacher
+        // * it uses a PID which is not the PID of the current process
+
+        let advertised_pid = u32::MAX - 3 - std::process::id();
+
+        // No user runs the synthetic process, do not verify the one listening on the socket
+        TeleopConfig::install_for_thread(Some(TeleopConfig {
+            allow_unknown_target_user: true,
+            ..TeleopConfig::default()
+        }));
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (handle, conn_stream) = listen_as::<DummyAttacher>(advertised_pid);
+            let mut conn_stream = pin!(conn_stream);
+
+            let (conn, client) =
+                futures::join!(conn_stream.next(), connect::<DummyAttacher>(advertised_pid));
+            let (mut conn, _addr) = assert_matches!(conn, Some(Ok(conn)) => conn);
+            let mut client = client?;
+
+            client.write_all(b"ping").await?;
+            let mut read = [0; 4];
+            conn.read_exact(&mut read).await?;
+            assert_eq!(&read, b"ping");
+
+            handle.shutdown();
+            assert_matches!(conn_stream.next().await, None);
+            assert!(!socket_file_path(advertised_pid).exists());
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        exec.run();
+
+        TeleopConfig::install_for_thread(None);
+
+        res.unwrap();
+    }
+}
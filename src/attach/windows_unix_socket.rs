@@ -11,19 +11,28 @@ use std::{
         prelude::{AsSocket, BorrowedSocket},
     },
     path::{Path, PathBuf},
-    pin::Pin,
-    time::Duration,
+    pin::{pin, Pin},
+    time::{Duration, Instant},
 };
 
 use async_io::{Async, Timer};
 use async_stream::try_stream;
 use futures::{
+    future::{select, Either},
     task::{Context, Poll},
-    AsyncRead, AsyncWrite, Stream,
+    AsyncRead, AsyncWrite, Stream, StreamExt,
 };
 use uds_windows::{SocketAddr, UnixListener, UnixStream};
 
-use crate::attach::attacher::{Attacher, AttacherSignal};
+use crate::{
+    attach::{
+        accept_loop,
+        attacher::{Attacher, AttacherSignal, RetryOpts, SelfId},
+        signaled_unless_cancelled, trace_signal_outcome, AttachError, ListenHandle, Target,
+    },
+    config::TeleopConfig,
+    internal::AutoDropFile,
+};
 
 #[derive(Debug)]
 struct UdsListenerWrapper(UnixListener);
@@ -79,8 +88,126 @@ impl AsyncWrite for UdsStream {
 
 /// Starts listening for attach signals and return incoming connections as a async `Stream`.
 ///
-/// In order to stop accepting connections, it is enough to stop polling the stream.
-pub fn listen<A>() -> impl Stream<Item = Result<(UdsStream, SocketAddr), Box<dyn std::error::Error>>>
+/// In order to stop accepting connections, either stop polling the stream or call
+/// [`ListenHandle::shutdown`] on the returned handle.
+#[allow(clippy::type_complexity)]
+pub fn listen<A>() -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UdsStream, SocketAddr), Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher,
+{
+    listen_with_self_id::<A>(SelfId::default())
+}
+
+/// Same as [`listen`] but the process is identified by the passed ID, both by the attacher and
+/// to bind the socket.
+///
+/// This lets tests simulate a target process distinct from the current one, see [`SelfId`].
+#[allow(clippy::type_complexity)]
+pub fn listen_with_self_id<A>(
+    self_id: SelfId,
+) -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UdsStream, SocketAddr), Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher,
+{
+    listen_on_socket::<A>(self_id, socket_file_path(self_id.0), None)
+}
+
+/// Same as [`listen`] but gives up waiting for the attach signal after the passed timeout.
+///
+/// If the timeout elapses first, the stream terminates without binding the socket. This lets the
+/// process offer a time limited attach window, see also
+/// [`Attacher::signaled_timeout`]. Once signaled, connections are accepted without time limit.
+#[allow(clippy::type_complexity)]
+pub fn listen_with_signal_timeout<A>(
+    timeout: Duration,
+) -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UdsStream, SocketAddr), Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher,
+{
+    listen_on_socket::<A>(
+        SelfId::default(),
+        socket_file_path(std::process::id()),
+        Some(timeout),
+    )
+}
+
+/// Same as [`listen`] but the socket is bound using the passed process ID instead of the ID of
+/// the current process.
+///
+/// This is useful when the process runs in a PID namespace (e.g. a container) and the client
+/// knows it by another ID (e.g. the host PID).
+///
+/// The attacher identifies the process by the advertised ID as well, so that file based
+/// attachers watch the attach file the client creates for that ID. This is the same as
+/// [`listen_with_self_id`] with the advertised ID.
+#[allow(clippy::type_complexity)]
+pub fn listen_as<A>(
+    advertised_pid: u32,
+) -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UdsStream, SocketAddr), Box<dyn std::error::Error>>>,
+)
+where
+    A: Attacher,
+{
+    listen_with_self_id::<A>(SelfId(advertised_pid))
+}
+
+/// Same as [`listen`] but the socket is bound immediately instead of waiting for the attach
+/// signal.
+///
+/// The socket is present as long as the stream is alive, so that clients can connect right away,
+/// e.g. with [`connect_no_signal`]. This suits daemons which are always ready to be
+/// teleoperated. Clients signaling the process connect as well since the socket already exists.
+#[allow(clippy::type_complexity)]
+pub fn listen_eager() -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UdsStream, SocketAddr), Box<dyn std::error::Error>>>,
+) {
+    let socket_file_path = socket_file_path(std::process::id());
+    // The error will only be raised if the stream is polled, but the socket is unbound as soon as
+    // the stream is dropped, even if it is never polled.
+    let listener = UnixListener::bind(&socket_file_path)
+        .and_then(|listener| Async::new(UdsListenerWrapper(listener)));
+    let socket_file = listener
+        .is_ok()
+        .then(|| AutoDropFile::adopt(socket_file_path));
+
+    let handle = ListenHandle::new();
+    let token = handle.token().clone();
+
+    let stream = try_stream! {
+        let _socket_file = socket_file;
+        let listener = listener?;
+
+        let mut connections = pin!(accept_loop(|| listener.read_with(|l| l.accept()), &token));
+        while let Some(conn) = connections.next().await {
+            let (stream, addr) = conn?;
+            yield (UdsStream(Async::new(stream)?), addr);
+        }
+    };
+
+    (handle, stream)
+}
+
+#[allow(clippy::type_complexity)]
+fn listen_on_socket<A>(
+    self_id: SelfId,
+    socket_file_path: PathBuf,
+    signal_timeout: Option<Duration>,
+) -> (
+    ListenHandle,
+    impl Stream<Item = Result<(UdsStream, SocketAddr), Box<dyn std::error::Error>>>,
+)
 where
     A: Attacher,
 {
@@ -88,40 +215,133 @@ where
     // process is ready to accept attachment requests even if the future is not awaited.
     //
     // Nevertheless, the error will only be raised if the future is awaited.
-    let signaled = A::signaled();
+    let signaled = A::signaled_as(self_id);
 
-    try_stream! {
+    let handle = ListenHandle::new();
+    let token = handle.token().clone();
 
-        signaled.await?;
+    let stream = try_stream! {
+
+        let signaled = signaled_unless_cancelled(signaled, &token);
+        let outcome = match signal_timeout {
+            Some(timeout) => match select(pin!(signaled), Timer::after(timeout)).await {
+                Either::Left((outcome, _)) => outcome?,
+                // Not signaled in time
+                Either::Right(_) => None,
+            },
+            None => signaled.await?,
+        };
+        let Some(outcome) = outcome else {
+            // Shut down before being signaled
+            return;
+        };
+        trace_signal_outcome(outcome);
 
         let listener = Async::new(
             UdsListenerWrapper(
-                UnixListener::bind(socket_file_path(std::process::id()))?
+                UnixListener::bind(&socket_file_path)?
             )
         )?;
+        // Unbind the socket when the stream terminates
+        let _socket_file = AutoDropFile::adopt(socket_file_path);
 
-        loop {
-            let (stream, addr) = listener.read_with(|l| l.accept()).await?;
+        let mut connections = pin!(accept_loop(|| listener.read_with(|l| l.accept()), &token));
+        while let Some(conn) = connections.next().await {
+            let (stream, addr) = conn?;
             yield (UdsStream(Async::new(stream)?), addr);
         }
+    };
+
+    (handle, stream)
+}
+
+/// Waits for the attach signal and accepts exactly one connection.
+///
+/// The socket is unbound as soon as the connection is accepted. This suits short-lived tools
+/// which serve a single client, where [`listen`] and a loop over the connections are overkill.
+pub async fn accept_one<A>() -> Result<UdsStream, Box<dyn std::error::Error>>
+where
+    A: Attacher,
+{
+    accept_one_on_socket::<A>(socket_file_path(std::process::id())).await
+}
+
+async fn accept_one_on_socket<A>(
+    socket_file_path: PathBuf,
+) -> Result<UdsStream, Box<dyn std::error::Error>>
+where
+    A: Attacher,
+{
+    let (_handle, connections) = listen_on_socket::<A>(SelfId::default(), socket_file_path, None);
+    let mut connections = pin!(connections);
+    match connections.next().await {
+        Some(conn) => Ok(conn?.0),
+        None => Err("Stopped listening before accepting a connection".into()),
     }
 }
 
-/// Connects to a process identified by its ID.
+/// Connects to a target process, usually identified by its ID.
 ///
 /// Returns the opened socket on success.
-pub async fn connect<A>(pid: u32) -> Result<UdsStream, Box<dyn std::error::Error>>
+pub async fn connect<A>(target: impl Into<Target>) -> Result<UdsStream, Box<dyn std::error::Error>>
 where
     A: Attacher,
 {
+    connect_with_retry::<A>(target, A::DEFAULT_RETRY).await
+}
+
+/// Same as [`connect`] but waits for the process with the passed retry options instead of the
+/// [default ones of the attacher](Attacher::DEFAULT_RETRY).
+pub async fn connect_with_retry<A>(
+    target: impl Into<Target>,
+    opts: RetryOpts,
+) -> Result<UdsStream, Box<dyn std::error::Error>>
+where
+    A: Attacher,
+{
+    let pid = target.into().resolve_pid()?;
     let socket_file_path = socket_file_path(pid);
-    connect_to_socket::<A>(pid, &socket_file_path).await
+    connect_to_socket_with_retry::<A>(pid, &socket_file_path, opts).await
 }
 
+/// Connects to a target process, without signaling it.
+///
+/// Fails immediately with [`AttachError::NotListening`] if the process does not listen, which is
+/// useful to reconnect to a process known to listen without disturbing it again.
+pub async fn connect_no_signal(
+    target: impl Into<Target>,
+) -> Result<UdsStream, Box<dyn std::error::Error>> {
+    let pid = target.into().resolve_pid()?;
+    let socket_file_path = socket_file_path(pid);
+
+    if !socket_file_path.exists() {
+        return Err(AttachError::NotListening { pid }.into());
+    }
+
+    Ok(UdsStream(Async::new(UnixStream::connect(
+        socket_file_path,
+    )?)?))
+}
+
+/// Connects to a process identified by its ID, through the socket at the passed path.
+///
+/// The process is signaled with the [default retry options of the
+/// attacher](Attacher::DEFAULT_RETRY) unless the socket already exists.
 pub async fn connect_to_socket<A>(
     pid: u32,
     socket_file_path: impl AsRef<Path>,
 ) -> Result<UdsStream, Box<dyn std::error::Error>>
+where
+    A: Attacher,
+{
+    connect_to_socket_with_retry::<A>(pid, socket_file_path, A::DEFAULT_RETRY).await
+}
+
+async fn connect_to_socket_with_retry<A>(
+    pid: u32,
+    socket_file_path: impl AsRef<Path>,
+    opts: RetryOpts,
+) -> Result<UdsStream, Box<dyn std::error::Error>>
 where
     A: Attacher,
 {
@@ -130,24 +350,18 @@ where
     if !socket_file_path.exists() {
         let mut signal = A::signal(pid)?;
 
-        signal.send().await?;
-
-        let mut attempts = 1;
-
-        while !socket_file_path.exists() && attempts < 100 {
-            Timer::after(Duration::from_millis(100)).await;
-
-            signal.send().await?;
-
-            attempts += 1;
-        }
-
-        if !socket_file_path.exists() {
-            return Err(format!(
-                "Unable to open socket file {}: target process {} doesn't respond",
-                socket_file_path.to_string_lossy(),
-                pid
-            )
+        let attempts = opts.max_attempts;
+        let started = Instant::now();
+        if !signal
+            .wait_until(|| socket_file_path.exists(), opts)
+            .await?
+        {
+            return Err(AttachError::Timeout {
+                path: socket_file_path.to_owned(),
+                pid,
+                attempts,
+                elapsed: started.elapsed(),
+            }
             .into());
         }
     }
@@ -159,26 +373,27 @@ where
 
 fn socket_file_path(pid: u32) -> PathBuf {
     let mut path = std::env::temp_dir();
-    path.push(format!(".teleop_pid_{pid}"));
+    path.push(format!("{}{pid}", TeleopConfig::current().socket_prefix));
     path
 }
 
 #[cfg(test)]
 #[cfg_attr(coverage_nightly, coverage(off))]
 mod tests {
-    use std::pin::pin;
+    use std::time::Duration;
 
     use assert_matches::assert_matches;
     use futures::{
         channel::oneshot,
         io::{BufReader, BufWriter},
-        AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, StreamExt,
+        AsyncReadExt, AsyncWriteExt, StreamExt,
     };
 
     use super::*;
     use crate::{
-        attach::attacher::{dummy::DummyAttacher, DefaultAttacher},
-        tests::ATTACH_PROCESS_TEST_MUTEX,
+        attach::attacher::{dummy::DummyAttacher, DefaultAttacher, SignalOutcome},
+        internal::{set_attach_file_token, unique_attach_file_token},
+        operate::raw::read_line,
     };
 
     fn socket_file_path_for_failure(pid: u32) -> PathBuf {
@@ -187,18 +402,34 @@ mod tests {
         path
     }
 
+    fn socket_file_path_for_accept_one(pid: u32) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!(".teleop_pid_{pid}_accept_one"));
+        path
+    }
+
+    fn socket_file_path_for_shutdown(pid: u32) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!(".teleop_pid_{pid}_shutdown"));
+        path
+    }
+
     #[test]
     fn test_unix_socket_attachment() {
-        // This test may conflict with attacher tests
-        let _attacher_test = ATTACH_PROCESS_TEST_MUTEX.lock();
+        // Isolate the attach file from attacher tests, both threads must share the same token
+        let client_token = unique_attach_file_token();
+        let server_token = client_token.clone();
 
         let (sender, receiver) = oneshot::channel::<()>();
 
         let server = || -> Result<(), Box<dyn std::error::Error>> {
+            set_attach_file_token(Some(server_token));
+
             let mut exec = futures::executor::LocalPool::new();
 
             let res = exec.run_until(async {
-                let mut conn_stream = pin!(listen::<DefaultAttacher>());
+                let (_handle, conn_stream) = listen::<DefaultAttacher>();
+                let mut conn_stream = pin!(conn_stream);
                 println!("server is listening");
                 sender.send(()).unwrap();
                 if let Some(stream) = conn_stream.next().await {
@@ -208,9 +439,8 @@ mod tests {
                     let mut input = BufReader::new(input);
                     let mut output = BufWriter::new(output);
 
-                    let mut read = String::new();
-                    while input.read_line(&mut read).await? == 0 {}
-                    assert_eq!(read, "ping\n");
+                    let read = read_line(&mut input).await?;
+                    assert_eq!(read.as_deref(), Some("ping\n"));
                     println!("server received ping");
 
                     output.write_all("pong\n".as_bytes()).await?;
@@ -229,6 +459,8 @@ mod tests {
         };
 
         let client = || -> Result<(), Box<dyn std::error::Error>> {
+            set_attach_file_token(Some(client_token));
+
             let pid = std::process::id();
 
             let mut exec = futures::executor::LocalPool::new();
@@ -245,9 +477,8 @@ mod tests {
                 output.flush().await?;
                 println!("client wrote ping");
 
-                let mut read = String::new();
-                while input.read_line(&mut read).await? == 0 {}
-                assert_eq!(read, "pong\n");
+                let read = read_line(&mut input).await?;
+                assert_eq!(read.as_deref(), Some("pong\n"));
                 println!("client received pong");
 
                 Ok::<_, Box<dyn std::error::Error>>(())
@@ -280,14 +511,25 @@ mod tests {
             let mut exec = futures::executor::LocalPool::new();
 
             let res = exec.run_until(async move {
-                let result =
-                    connect_to_socket::<DummyAttacher>(pid, socket_file_path_for_failure(pid))
-                        .await;
+                let result = connect_to_socket_with_retry::<DummyAttacher>(
+                    pid,
+                    socket_file_path_for_failure(pid),
+                    RetryOpts::default(),
+                )
+                .await;
                 let err = assert_matches!(result, Err(err) => err);
                 assert!(
                     err.to_string().starts_with("Unable to open socket file"),
                     "Expected error `{err}` to start with `Unable to open socket file`."
                 );
+                assert_matches!(
+                    err.downcast_ref::<AttachError>(),
+                    Some(AttachError::Timeout { path, pid: err_pid, attempts, elapsed })
+                        if *path == socket_file_path_for_failure(pid)
+                            && *err_pid == pid
+                            && *attempts == RetryOpts::default().max_attempts
+                            && *elapsed >= Duration::from_secs(9)
+                );
                 Ok::<_, Box<dyn std::error::Error>>(())
             });
 
@@ -300,4 +542,189 @@ mod tests {
 
         client().unwrap();
     }
+
+    #[test]
+    fn test_unix_socket_accept_one() {
+        // This test may not conflict with the other tests because
+        // * it uses the dummy attacher
+        // * it uses a special socket path
+
+        let pid = std::process::id();
+        let socket_file_path = socket_file_path_for_accept_one(pid);
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (conn, client) = futures::join!(
+                accept_one_on_socket::<DummyAttacher>(socket_file_path.clone()),
+                connect_to_socket_with_retry::<DummyAttacher>(
+                    pid,
+                    &socket_file_path,
+                    RetryOpts::default()
+                )
+            );
+            let mut conn = conn?;
+            let mut client = client?;
+            assert!(!socket_file_path.exists());
+
+            client.write_all(b"ping").await?;
+            let mut read = [0; 4];
+            conn.read_exact(&mut read).await?;
+            assert_eq!(&read, b"ping");
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        exec.run();
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_unix_socket_shutdown() {
+        // This test may not conflict with the other tests because
+        // * it uses the dummy attacher
+        // * it uses a special socket path
+
+        let pid = std::process::id();
+        let socket_file_path = socket_file_path_for_shutdown(pid);
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (handle, conn_stream) = listen_on_socket::<DummyAttacher>(
+                SelfId::default(),
+                socket_file_path.clone(),
+                None,
+            );
+            let mut conn_stream = pin!(conn_stream);
+
+            let (conn, client) = futures::join!(
+                conn_stream.next(),
+                connect_to_socket_with_retry::<DummyAttacher>(
+                    pid,
+                    &socket_file_path,
+                    RetryOpts::default()
+                )
+            );
+            assert_matches!(conn, Some(Ok(_)));
+            client?;
+            assert!(socket_file_path.exists());
+
+            handle.shutdown();
+
+            assert_matches!(conn_stream.next().await, None);
+            assert!(!socket_file_path.exists());
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        exec.run();
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_unix_socket_signal_timeout() {
+        /// Attacher which is never signaled.
+        struct PendingAttacher;
+
+        impl Attacher for PendingAttacher {
+            type Signal = <DummyAttacher as Attacher>::Signal;
+
+            fn signal(pid: u32) -> Result<Self::Signal, Box<dyn std::error::Error>> {
+                DummyAttacher::signal(pid)
+            }
+
+            async fn signaled_as(
+                _self_id: SelfId,
+            ) -> Result<SignalOutcome, Box<dyn std::error::Error>> {
+                futures::future::pending().await
+            }
+        }
+
+        let pid = std::process::id();
+        let socket_file_path = socket_file_path_for_shutdown(pid).with_extension("timeout");
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (_handle, conn_stream) = listen_on_socket::<PendingAttacher>(
+                SelfId::default(),
+                socket_file_path.clone(),
+                Some(Duration::from_millis(100)),
+            );
+            let mut conn_stream = pin!(conn_stream);
+
+            let start = Instant::now();
+            assert_matches!(conn_stream.next().await, None);
+            assert!(start.elapsed() >= Duration::from_millis(100));
+            assert!(!socket_file_path.exists());
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_unix_socket_listen_as() {
+        // This test may not conflict with the other tests because
+        // * it uses the dummy attacher
+        // * it uses a PID which is not the PID of the current process
+
+        let advertised_pid = u32::MAX - std::process::id();
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let (_handle, conn_stream) = listen_as::<DummyAttacher>(advertised_pid);
+            let mut conn_stream = pin!(conn_stream);
+
+            let (conn, client) =
+                futures::join!(conn_stream.next(), connect::<DummyAttacher>(advertised_pid));
+            assert_matches!(conn, Some(Ok(_)));
+            client?;
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        exec.run();
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_unix_socket_connect_no_signal() {
+        // This test may not conflict with the other tests because
+        // * it uses the dummy attacher
+        // * it uses a PID which is not the PID of the current process
+
+        let advertised_pid = u32::MAX - 2 - std::process::id();
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        let res = exec.run_until(async {
+            let result = connect_no_signal(advertised_pid).await;
+            let err = assert_matches!(result, Err(err) => err);
+            assert_matches!(
+                err.downcast_ref::<AttachError>(),
+                Some(AttachError::NotListening { pid }) if *pid == advertised_pid
+            );
+
+            let (_handle, conn_stream) = listen_as::<DummyAttacher>(advertised_pid);
+            let mut conn_stream = pin!(conn_stream);
+
+            let (conn, client) =
+                futures::join!(conn_stream.next(), connect_no_signal(advertised_pid));
+            assert_matches!(conn, Some(Ok(_)));
+            client?;
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        exec.run();
+
+        res.unwrap();
+    }
 }
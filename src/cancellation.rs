@@ -0,0 +1,203 @@
+//! Runtime agnostic cancellation primitive.
+//!
+//! [`CancellationToken`] is shared between the party which requests the cancellation and the
+//! parties which need to stop what they are doing.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::{pin, Pin},
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use async_io::Timer;
+use futures::future::{select, Either};
+
+#[derive(Default)]
+struct State {
+    cancelled: bool,
+    // Keyed by the waiting futures, which remove their waker when dropped
+    wakers: HashMap<u64, Waker>,
+    next_key: u64,
+}
+
+/// Cancellation token which can be cloned and sent across threads.
+///
+/// All clones share the same state: cancelling one of them cancels all of them.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<Mutex<State>>);
+
+impl CancellationToken {
+    /// Creates a new token which is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the token and wakes up all the tasks waiting for it.
+    ///
+    /// Returns `true` if this call cancelled the token, `false` if it was already cancelled, so
+    /// that one time shutdown actions can be performed by a single caller.
+    pub fn cancel(&self) -> bool {
+        let wakers = {
+            let mut state = self.0.lock().unwrap();
+            if state.cancelled {
+                return false;
+            }
+            state.cancelled = true;
+            std::mem::take(&mut state.wakers)
+        };
+        for waker in wakers.into_values() {
+            waker.wake();
+        }
+        true
+    }
+
+    /// Returns `true` if the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.lock().unwrap().cancelled
+    }
+
+    /// Returns a future which completes when the token is cancelled.
+    pub fn cancelled(&self) -> Cancelled {
+        Cancelled {
+            token: self.clone(),
+            key: None,
+        }
+    }
+
+    /// Returns a future which completes when the token is cancelled or when the timeout elapses,
+    /// whichever comes first.
+    pub fn cancelled_or_timeout(&self, timeout: Duration) -> impl Future<Output = CancelOutcome> {
+        let cancelled = self.cancelled();
+        async move {
+            match select(cancelled, pin!(Timer::after(timeout))).await {
+                Either::Left(_) => CancelOutcome::Cancelled,
+                Either::Right(_) => CancelOutcome::TimedOut,
+            }
+        }
+    }
+}
+
+/// Outcome of [`CancellationToken::cancelled_or_timeout`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CancelOutcome {
+    /// The token was cancelled before the timeout elapsed.
+    Cancelled,
+    /// The timeout elapsed before the token was cancelled.
+    TimedOut,
+}
+
+/// Future returned by [`CancellationToken::cancelled`].
+pub struct Cancelled {
+    token: CancellationToken,
+    /// Key of the waker registered in the token, once polled.
+    key: Option<u64>,
+}
+
+impl Future for Cancelled {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        let mut state = this.token.0.lock().unwrap();
+        if state.cancelled {
+            return Poll::Ready(());
+        }
+        let key = *this.key.get_or_insert_with(|| {
+            let key = state.next_key;
+            state.next_key += 1;
+            key
+        });
+        match state.wakers.get_mut(&key) {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            Some(waker) => waker.clone_from(cx.waker()),
+            None => {
+                state.wakers.insert(key, cx.waker().clone());
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Cancelled {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            self.token.0.lock().unwrap().wakers.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use futures::FutureExt;
+
+    use super::{CancelOutcome, CancellationToken};
+
+    #[test]
+    fn test_cancellation_token() {
+        let token = CancellationToken::new();
+        assert!(!token.is_cancelled());
+
+        let mut cancelled = token.cancelled();
+        assert_eq!((&mut cancelled).now_or_never(), None);
+
+        let other = token.clone();
+        let thread = std::thread::spawn(move || other.cancel());
+
+        futures::executor::block_on(cancelled);
+        thread.join().unwrap();
+
+        assert!(token.is_cancelled());
+        assert_eq!(token.cancelled().now_or_never(), Some(()));
+    }
+
+    #[test]
+    fn test_cancellation_token_dropped_waiters() {
+        let token = CancellationToken::new();
+
+        // Every waiting future registers its waker, then forgets it when dropped
+        for _ in 0..100 {
+            assert_eq!(token.cancelled().now_or_never(), None);
+        }
+        assert!(token.0.lock().unwrap().wakers.is_empty());
+
+        let mut cancelled = token.cancelled();
+        assert_eq!((&mut cancelled).now_or_never(), None);
+        assert_eq!(token.0.lock().unwrap().wakers.len(), 1);
+        assert!(token.cancel());
+        assert_eq!(cancelled.now_or_never(), Some(()));
+    }
+
+    #[test]
+    fn test_cancellation_token_cancel_once() {
+        let token = CancellationToken::new();
+        let other = token.clone();
+
+        assert!(token.cancel());
+        assert!(!token.cancel());
+        assert!(!other.cancel());
+        assert!(other.is_cancelled());
+    }
+
+    #[test]
+    fn test_cancellation_token_cancelled_or_timeout() {
+        let token = CancellationToken::new();
+
+        let started = Instant::now();
+        let outcome =
+            futures::executor::block_on(token.cancelled_or_timeout(Duration::from_millis(50)));
+        assert_eq!(outcome, CancelOutcome::TimedOut);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        let other = token.clone();
+        let thread = std::thread::spawn(move || other.cancel());
+        let outcome = futures::executor::block_on(token.cancelled_or_timeout(Duration::MAX));
+        thread.join().unwrap();
+        assert_eq!(outcome, CancelOutcome::Cancelled);
+    }
+}
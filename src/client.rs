@@ -0,0 +1,120 @@
+//! Client side in one call.
+//!
+//! Clients usually [`connect`](crate::attach::connect) to the target process, split the stream,
+//! create the [client connection](crate::operate::capnp::client_connection_with_options), then
+//! spawn the RPC system and use the `Teleop` client. [`attach_client`] does all but spawning, the
+//! lower level functions remain available for other channels or options.
+
+use capnp_rpc::{rpc_twoparty_capnp, RpcSystem};
+use futures::AsyncReadExt;
+
+use crate::{
+    attach::{attacher::Attacher, connect, Target},
+    operate::capnp::{
+        client_connection_with_options, disconnect::GracefulDisconnector, teleop_capnp,
+        ConnectedStream, ConnectionOptions,
+    },
+};
+
+/// Connects to a target process with the default communication channel and sets up a Cap'n Proto
+/// RPC client connection with the default options.
+///
+/// The returned RPC system must be run by the async runtime for the client to make progress. The
+/// returned disconnector closes the connection gracefully once the client is done.
+#[allow(clippy::type_complexity)]
+pub async fn attach_client<A>(
+    target: impl Into<Target>,
+) -> Result<
+    (
+        RpcSystem<rpc_twoparty_capnp::Side>,
+        teleop_capnp::teleop::Client,
+        GracefulDisconnector,
+    ),
+    Box<dyn std::error::Error>,
+>
+where
+    A: Attacher,
+{
+    let (input, output) = connect::<A>(target).await?.split();
+    let connected =
+        client_connection_with_options(input, output, ConnectionOptions::default()).await?;
+    let disconnector = connected.graceful_disconnector();
+    let ConnectedStream {
+        rpc_system, teleop, ..
+    } = connected;
+    Ok((rpc_system, teleop, disconnector))
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::pin::pin;
+
+    use futures::{task::LocalSpawnExt, StreamExt};
+
+    use super::*;
+    use crate::{
+        attach::{attacher::dummy::DummyAttacher, listen},
+        config::TeleopConfig,
+        operate::capnp::{
+            echo::{echo_capnp, EchoServer},
+            run_server_connection, TeleopServer,
+        },
+    };
+
+    #[test]
+    fn test_attach_client() {
+        // Isolate the socket from the other tests
+        TeleopConfig::install_for_thread(Some(TeleopConfig {
+            socket_prefix: ".teleop_client_".into(),
+            ..TeleopConfig::default()
+        }));
+
+        let mut server = TeleopServer::new();
+        server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+        let client = server.into_client();
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawn = exec.spawner();
+
+        let res = exec.run_until(async move {
+            let (_handle, connections) = listen::<DummyAttacher>();
+            let mut connections = pin!(connections);
+
+            let (accepted, attached) = futures::join!(
+                connections.next(),
+                attach_client::<DummyAttacher>(std::process::id())
+            );
+            let (input, output) = accepted.unwrap()?.0.split();
+            spawn.spawn_local(async move {
+                if let Err(e) = run_server_connection(input, output, client.client.hook).await {
+                    eprintln!("Server connection interrupted {e}");
+                }
+            })?;
+
+            let (rpc_system, teleop, disconnector) = attached?;
+            spawn.spawn_local(async {
+                if let Err(e) = rpc_system.await {
+                    eprintln!("Connection interrupted {e}");
+                }
+            })?;
+
+            let mut req = teleop.service_request();
+            req.get().set_name("echo");
+            let echo = req.send().promise.await?;
+            let echo: echo_capnp::echo::Client = echo.get()?.get_service().get_as()?;
+            let mut req = echo.echo_request();
+            req.get().set_message("attached");
+            let reply = req.send().promise.await?;
+            assert_eq!(reply.get()?.get_reply()?.to_str()?, "attached");
+
+            disconnector.disconnect().await?;
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        TeleopConfig::install_for_thread(None);
+
+        res.unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+//! Process wide configuration.
+//!
+//! [`TeleopConfig`] is installed once, before listening or connecting, and applies to the whole
+//! process. The process to be teleoperated and its clients must agree on it.
+
+#[cfg(test)]
+use std::cell::RefCell;
+use std::{borrow::Cow, path::PathBuf, sync::RwLock};
+
+#[cfg(unix)]
+use crate::attach::attacher::unix::Signal;
+
+static CONFIG: RwLock<TeleopConfig> = RwLock::new(TeleopConfig::DEFAULT);
+
+#[cfg(test)]
+thread_local! {
+    // Configuration overriding the one of the process in the current thread, so that tests
+    // running concurrently do not see each other's configuration.
+    static THREAD_CONFIG: RefCell<Option<TeleopConfig>> = const { RefCell::new(None) };
+}
+
+/// Configuration of Teleop.
+#[derive(Clone, Debug)]
+pub struct TeleopConfig {
+    /// Directory where file based attachers create the attach file.
+    pub attach_file_location: AttachFileLocation,
+    /// Prefix of the name of the UNIX socket files, followed by the process ID.
+    ///
+    /// Independent applications built on Teleop can pick their own prefix so that their clients
+    /// cannot be mistaken for each other's. Named pipes are not affected.
+    pub socket_prefix: Cow<'static, str>,
+    /// Signals sent by the [UNIX attacher](crate::attach::attacher::unix), `QUIT` by default.
+    ///
+    /// The process to be teleoperated listens to all of them, while clients send them in turn
+    /// until the process responds. Applications which use some signal for their own purpose
+    /// should leave it out.
+    #[cfg(unix)]
+    pub attach_signals: Cow<'static, [Signal]>,
+    /// Size in bytes of the buffer the [inotify attacher](crate::attach::attacher::inotify) reads
+    /// events into, 1024 by default.
+    ///
+    /// Only the process to be teleoperated uses it. A larger buffer drains the events of busy
+    /// directories in fewer reads. It is raised to the size of the largest event if smaller.
+    #[cfg(feature = "inotify")]
+    pub inotify_buffer_size: usize,
+    /// Whether clients connect to a UNIX socket when the user running the target process cannot
+    /// be found, `false` by default.
+    ///
+    /// Clients verify that the process listening on the socket is run by that user, which is not
+    /// possible for other processes without the `discover` feature outside of Linux. Connecting
+    /// then fails with [`AttachError::UnknownTargetUser`](crate::attach::AttachError) unless this
+    /// is set, in which case any local user may intercept the connection by planting the socket.
+    #[cfg(unix)]
+    pub allow_unknown_target_user: bool,
+}
+
+impl TeleopConfig {
+    const DEFAULT: Self = Self {
+        attach_file_location: AttachFileLocation::WorkingDirectory,
+        socket_prefix: Cow::Borrowed(".teleop_pid_"),
+        #[cfg(unix)]
+        attach_signals: Cow::Borrowed(&[Signal::Quit]),
+        #[cfg(feature = "inotify")]
+        inotify_buffer_size: 1024,
+        #[cfg(unix)]
+        allow_unknown_target_user: false,
+    };
+
+    /// Returns the configuration of the process.
+    pub fn current() -> Self {
+        #[cfg(test)]
+        if let Some(config) = THREAD_CONFIG.with_borrow(Clone::clone) {
+            return config;
+        }
+        CONFIG.read().unwrap().clone()
+    }
+
+    /// Makes this configuration the configuration of the process.
+    ///
+    /// Listeners and clients started before keep the previous one.
+    pub fn install(self) {
+        *CONFIG.write().unwrap() = self;
+    }
+
+    /// Makes this configuration the configuration of the current thread, or restores the one of
+    /// the process.
+    #[cfg(test)]
+    #[cfg_attr(windows, allow(unused))]
+    pub(crate) fn install_for_thread(config: Option<Self>) {
+        THREAD_CONFIG.set(config);
+    }
+}
+
+impl Default for TeleopConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Directory of the attach file.
+///
+/// The client creates the attach file and the process to be teleoperated watches its directory,
+/// so the client must be allowed to write into it.
+///
+/// # Security
+///
+/// In the working directory of the process, only the users allowed to write there can signal the
+/// process. This usually restricts attaching to the user running the process, but fails when the
+/// client runs as another user or when the directory is read-only.
+///
+/// In a shared directory, any local user can signal any process since attach file names are
+/// predictable. The process then binds its socket, so access control must be enforced on the
+/// socket, e.g. with [`SocketSecurity`](crate::attach::unix_socket::SocketSecurity). The shared
+/// directory must have the sticky bit set, like `/tmp`, so that users cannot remove or replace
+/// attach files of others. Attach files are never created through symbolic links, which prevents
+/// other users from redirecting them to existing files.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AttachFileLocation {
+    /// Working directory of the process to be teleoperated.
+    ///
+    /// Finding the working directory of another process requires the `discover` feature.
+    #[default]
+    WorkingDirectory,
+    /// Temporary directory, where sockets are also bound.
+    ///
+    /// Both sides must see the same temporary directory, e.g. the same `TMPDIR` on UNIX.
+    TempDir,
+    /// Arbitrary shared directory.
+    Directory(PathBuf),
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::*;
+    use crate::internal::attach_file_path_in;
+
+    #[test]
+    fn test_attach_file_location() {
+        let pid = std::process::id();
+
+        let path = attach_file_path_in(&AttachFileLocation::WorkingDirectory, pid).unwrap();
+        assert_eq!(
+            path.parent(),
+            Some(std::env::current_dir().unwrap().as_path())
+        );
+
+        let path = attach_file_path_in(&AttachFileLocation::TempDir, pid).unwrap();
+        assert_eq!(path.parent(), Some(std::env::temp_dir().as_path()));
+
+        // Other processes are found without the `discover` feature
+        let dir = PathBuf::from("/shared");
+        let path =
+            attach_file_path_in(&AttachFileLocation::Directory(dir.clone()), pid + 1).unwrap();
+        assert_eq!(path.parent(), Some(dir.as_path()));
+        assert!(path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .contains(&(pid + 1).to_string()));
+    }
+}
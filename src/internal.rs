@@ -1,17 +1,46 @@
-use std::{fs::File, path::PathBuf};
+#[cfg(test)]
+use std::{
+    cell::RefCell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use std::{
+    collections::hash_map::RandomState,
+    fs::OpenOptions,
+    hash::{BuildHasher, Hasher},
+    path::PathBuf,
+    time::SystemTime,
+};
+#[cfg(unix)]
+use std::{future::Future, os::unix::fs::OpenOptionsExt};
 
+#[cfg(feature = "discover")]
 use sysinfo::{Pid, System};
 
+use crate::{
+    attach::attacher::SelfId,
+    config::{AttachFileLocation, TeleopConfig},
+};
+
 #[cfg_attr(windows, allow(unused))]
 pub struct AutoDropFile(PathBuf);
 
 impl AutoDropFile {
     #[cfg_attr(windows, allow(unused))]
     pub fn create(path: PathBuf) -> std::io::Result<Self> {
-        File::create(&path)?;
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        // The attach file may live in a shared directory where other users could plant a link
+        #[cfg(unix)]
+        options.custom_flags(nix::libc::O_NOFOLLOW);
+        options.open(&path)?;
         Ok(Self(path))
     }
 
+    /// Takes ownership of a file which has been created by other means.
+    pub fn adopt(path: PathBuf) -> Self {
+        Self(path)
+    }
+
     #[cfg_attr(windows, allow(unused))]
     pub fn exists(&self) -> Result<bool, std::io::Error> {
         std::fs::exists(&self.0)
@@ -26,8 +55,59 @@ impl Drop for AutoDropFile {
     }
 }
 
+/// Removes a directory when dropped, provided it is empty by then.
+#[cfg(unix)]
+pub struct AutoDropDir(PathBuf);
+
+#[cfg(unix)]
+impl AutoDropDir {
+    /// Takes ownership of a directory which has been created by other means.
+    pub fn adopt(path: PathBuf) -> Self {
+        Self(path)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for AutoDropDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir(&self.0);
+    }
+}
+
 #[cfg_attr(windows, allow(unused))]
 pub fn attach_file_path(pid: u32) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    attach_file_path_in(&TeleopConfig::current().attach_file_location, pid)
+}
+
+/// Returns the path of the attach file watched by the current process when identified by the
+/// passed ID.
+///
+/// The directory is always the one of the current process, even for a synthetic ID.
+#[cfg_attr(windows, allow(unused))]
+pub fn self_attach_file_path(self_id: SelfId) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    Ok(attach_file_path(std::process::id())?.with_file_name(attach_file_name(self_id.0)))
+}
+
+#[cfg_attr(windows, allow(unused))]
+pub fn attach_file_path_in(
+    location: &AttachFileLocation,
+    pid: u32,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let dir = match location {
+        AttachFileLocation::WorkingDirectory => process_cwd(pid)?,
+        AttachFileLocation::TempDir => std::env::temp_dir(),
+        AttachFileLocation::Directory(dir) => dir.clone(),
+    };
+    Ok(dir.join(attach_file_name(pid)))
+}
+
+#[cfg(feature = "discover")]
+#[cfg_attr(windows, allow(unused))]
+fn process_cwd(pid: u32) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    // Scanning all processes is slow, and useless for the current one
+    if pid == std::process::id() {
+        return Ok(std::env::current_dir()?);
+    }
     let sysinfo_pid = if let Ok(pid) = usize::try_from(pid) {
         Pid::from(pid)
     } else {
@@ -35,13 +115,240 @@ pub fn attach_file_path(pid: u32) -> Result<PathBuf, Box<dyn std::error::Error>>
     };
     let s = System::new_all();
     if let Some(process) = s.process(sysinfo_pid) {
-        let cwd = process.cwd();
-        Ok(cwd
+        Ok(process
+            .cwd()
             .ok_or_else(|| -> Box<dyn std::error::Error> {
                 "Cannot find process working directory".into()
             })?
-            .join(format!(".teleop_attach_{pid}")))
+            .to_path_buf())
     } else {
         Err("Cannot find process working directory".into())
     }
 }
+
+#[cfg(not(feature = "discover"))]
+#[cfg_attr(windows, allow(unused))]
+fn process_cwd(pid: u32) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    if pid == std::process::id() {
+        Ok(std::env::current_dir()?)
+    } else {
+        Err(
+            "Finding the working directory of another process requires the `discover` feature"
+                .into(),
+        )
+    }
+}
+
+/// Returns the start time of the current process, in seconds since the UNIX epoch.
+#[cfg(feature = "discover")]
+pub fn process_start_time() -> Option<u64> {
+    let pid = Pid::from_u32(std::process::id());
+    let mut s = System::new();
+    s.refresh_processes(sysinfo::ProcessesToUpdate::Some(&[pid]), false);
+    s.process(pid).map(|process| process.start_time())
+}
+
+#[cfg(not(feature = "discover"))]
+pub fn process_start_time() -> Option<u64> {
+    None
+}
+
+/// Tells whether a process with the passed ID is running.
+#[cfg(all(unix, feature = "discover"))]
+pub fn process_exists(pid: u32) -> bool {
+    let pid = Pid::from_u32(pid);
+    let mut s = System::new();
+    s.refresh_processes_specifics(
+        sysinfo::ProcessesToUpdate::Some(&[pid]),
+        false,
+        sysinfo::ProcessRefreshKind::nothing(),
+    );
+    s.process(pid).is_some()
+}
+
+#[cfg(all(unix, not(feature = "discover")))]
+pub fn process_exists(pid: u32) -> bool {
+    use nix::{errno::Errno, sys::signal::kill, unistd};
+
+    // The null signal only checks that the process exists
+    i32::try_from(pid).is_ok_and(|pid| {
+        matches!(
+            kill(unistd::Pid::from_raw(pid), None),
+            Ok(()) | Err(Errno::EPERM)
+        )
+    })
+}
+
+/// Returns the ID of the user running the passed process, if it can be found.
+#[cfg(all(unix, feature = "discover"))]
+pub fn process_uid(pid: u32) -> Option<u32> {
+    let pid = Pid::from_u32(pid);
+    let mut s = System::new();
+    s.refresh_processes_specifics(
+        sysinfo::ProcessesToUpdate::Some(&[pid]),
+        false,
+        sysinfo::ProcessRefreshKind::nothing().with_user(sysinfo::UpdateKind::Always),
+    );
+    s.process(pid)
+        .and_then(|process| process.user_id())
+        .map(|uid| **uid)
+}
+
+#[cfg(all(unix, not(feature = "discover")))]
+pub fn process_uid(pid: u32) -> Option<u32> {
+    if pid == std::process::id() {
+        return Some(nix::unistd::getuid().as_raw());
+    }
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        // The process directory belongs to the user running the process
+        std::fs::metadata(format!("/proc/{pid}"))
+            .ok()
+            .map(|metadata| metadata.uid())
+    }
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    None
+}
+
+/// Returns the name of the passed process, if it can be found.
+#[cfg(all(unix, feature = "discover"))]
+pub fn process_name(pid: u32) -> Option<String> {
+    let pid = Pid::from_u32(pid);
+    let mut s = System::new();
+    s.refresh_processes_specifics(
+        sysinfo::ProcessesToUpdate::Some(&[pid]),
+        false,
+        sysinfo::ProcessRefreshKind::nothing(),
+    );
+    s.process(pid)
+        .map(|process| process.name().to_string_lossy().into_owned())
+}
+
+#[cfg(all(unix, not(feature = "discover")))]
+pub fn process_name(_pid: u32) -> Option<String> {
+    None
+}
+
+/// Error of a system call which may have been interrupted by a signal (`EINTR`).
+#[cfg(unix)]
+pub trait Interrupted {
+    fn is_interrupted(&self) -> bool;
+}
+
+#[cfg(unix)]
+impl Interrupted for std::io::Error {
+    fn is_interrupted(&self) -> bool {
+        self.kind() == std::io::ErrorKind::Interrupted
+    }
+}
+
+#[cfg(unix)]
+impl Interrupted for nix::errno::Errno {
+    fn is_interrupted(&self) -> bool {
+        *self == nix::errno::Errno::EINTR
+    }
+}
+
+/// Calls the passed function again as long as it fails with `EINTR`.
+///
+/// Teleop relies on signals, which interrupt system calls of the process being signaled.
+#[cfg(unix)]
+pub fn retry_on_eintr<T, E>(mut f: impl FnMut() -> Result<T, E>) -> Result<T, E>
+where
+    E: Interrupted,
+{
+    loop {
+        match f() {
+            Err(err) if err.is_interrupted() => continue,
+            res => return res,
+        }
+    }
+}
+
+/// Same as [`retry_on_eintr`] for asynchronous operations.
+#[cfg(unix)]
+pub async fn retry_on_eintr_async<T, E, F>(mut f: impl FnMut() -> F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Interrupted,
+{
+    loop {
+        match f().await {
+            Err(err) if err.is_interrupted() => continue,
+            res => return res,
+        }
+    }
+}
+
+/// Returns a random number, not suitable for cryptography.
+pub fn random_u64() -> u64 {
+    // Hashers are randomly seeded, which is good enough for identifiers and jitter
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(now.as_nanos());
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+thread_local! {
+    // Token appended to the attach file name so that tests running concurrently in the same
+    // process do not see each other's attach files.
+    static ATTACH_FILE_TOKEN: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+#[cfg_attr(windows, allow(unused))]
+fn attach_file_name(pid: u32) -> String {
+    #[cfg(test)]
+    if let Some(token) = ATTACH_FILE_TOKEN.with_borrow(Clone::clone) {
+        return format!(".teleop_attach_{pid}_{token}");
+    }
+    format!(".teleop_attach_{pid}")
+}
+
+/// Sets the attach file token of the current thread.
+#[cfg(test)]
+#[cfg_attr(windows, allow(unused))]
+pub fn set_attach_file_token(token: Option<String>) {
+    ATTACH_FILE_TOKEN.set(token);
+}
+
+/// Returns a token which is unique in the test process.
+#[cfg(test)]
+#[cfg_attr(windows, allow(unused))]
+pub fn unique_attach_file_token() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    format!("test{}", COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+#[cfg(all(test, unix))]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::io::{Error, ErrorKind};
+
+    use super::*;
+
+    #[test]
+    fn test_retry_on_eintr() {
+        let mut calls = 0;
+        let res = retry_on_eintr(|| {
+            calls += 1;
+            if calls < 3 {
+                Err(Error::from(ErrorKind::Interrupted))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(res.unwrap(), 3);
+
+        let mut calls = 0;
+        let res = retry_on_eintr(|| -> Result<(), _> {
+            calls += 1;
+            Err(nix::errno::Errno::EPERM)
+        });
+        assert_eq!(res, Err(nix::errno::Errno::EPERM));
+        assert_eq!(calls, 1);
+    }
+}
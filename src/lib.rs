@@ -19,6 +19,23 @@
 //! Teleop provides a root interface named `Teleop` (see `teleop.capnp`) which gives access to
 //! arbitrary services.
 //!
+//! ## Features
+//!
+//! * `async-std`: provides the UNIX socket functions built on `async-std` in
+//!   `attach::unix_socket::async_std`.
+//! * `discover` (default): finds the working directory of the target process, which file based
+//!   attachers need. Without it, `listen_at` and `connect_at` avoid the dependency on `sysinfo`.
+//! * `fanotify`: enables the fanotify attacher on Linux, which reports the process writing the
+//!   attach file but requires `CAP_SYS_ADMIN`.
+//! * `inotify`: enables the inotify attacher and makes it the default. UNIX socket clients also
+//!   watch for the socket instead of polling for it.
+//! * `jsonrpc`: enables JSON-RPC as an alternative to Cap'n Proto in `operate::jsonrpc`.
+//! * `testing`: enables helpers to test services without attaching to a process, or in a child
+//!   process to attach to.
+//! * `tower`: enables exposing any `tower::Service` as a Teleop service.
+//! * `tracing`: emits [tracing](https://docs.rs/tracing) events, e.g. when a client requests a
+//!   service which is not registered.
+//!
 //! ## Example
 //!
 //! See examples in the Git repository.
@@ -26,18 +43,16 @@
 //! * The server example shows how to setup the process to teleoperate, including an `echo` service
 //!   which will reply to a request by echoing the input.
 //! * The client example shows how to setup the client, initiate the attach process, request the
-//!   `echo` service, and send echo requests.
+//!   `echo` service, and send echo requests. `client::attach_client` attaches and bootstraps the
+//!   `Teleop` client in one call.
 
 #![cfg_attr(coverage_nightly, feature(coverage_attribute))]
 
 pub mod attach;
+pub mod cancellation;
+#[cfg(any(unix, windows))]
+pub mod client;
+pub mod config;
 pub mod operate;
 
 mod internal;
-
-#[cfg(test)]
-#[cfg_attr(coverage_nightly, coverage(off))]
-mod tests {
-    // The attacher tests need to run separately
-    pub(crate) static ATTACH_PROCESS_TEST_MUTEX: std::sync::Mutex<()> = std::sync::Mutex::new(());
-}
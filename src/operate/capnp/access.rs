@@ -0,0 +1,87 @@
+//! Access control of the methods of services handed out to clients.
+//!
+//! A service registered with [`ServiceOptions::policy`](super::ServiceOptions::policy) is handed
+//! out behind a forwarding capability which consults its [`AccessPolicy`] before every call.
+//! Denied calls fail without reaching the service, while the connection keeps running.
+//!
+//! Methods are identified by the ordinal given in the schema, e.g. `@1`, since method names are
+//! not available at run time.
+//!
+//! Capabilities returned by the calls themselves are not wrapped and therefore not controlled.
+
+use std::rc::Rc;
+
+use capnp::{
+    capability::Promise,
+    private::capability::{ClientHook, ParamsHook, ResultsHook},
+};
+
+use super::forwarding::CallInterceptor;
+
+/// Method call submitted to an [`AccessPolicy`].
+#[derive(Clone, Copy, Debug)]
+pub struct MethodCall<'a> {
+    /// Name under which the service is registered.
+    pub service: &'a str,
+    /// Cap'n Proto type ID of the interface declaring the method.
+    pub interface_id: u64,
+    /// Ordinal of the method in the interface.
+    pub method_id: u16,
+}
+
+/// Policy deciding which methods of a service clients are allowed to call.
+///
+/// It is implemented by closures taking a [`MethodCall`].
+pub trait AccessPolicy {
+    /// Returns `true` if the call is allowed.
+    fn allows(&self, call: &MethodCall<'_>) -> bool;
+}
+
+impl<F> AccessPolicy for F
+where
+    F: Fn(&MethodCall<'_>) -> bool,
+{
+    fn allows(&self, call: &MethodCall<'_>) -> bool {
+        self(call)
+    }
+}
+
+/// Interceptor which fails the calls denied by the policy.
+#[derive(Clone)]
+pub(crate) struct AccessControl {
+    /// Name under which the service is registered.
+    pub(crate) service: Rc<str>,
+    /// Policy consulted before every call.
+    pub(crate) policy: Rc<dyn AccessPolicy>,
+}
+
+impl CallInterceptor for AccessControl {
+    fn intercept_call(
+        &self,
+        inner: &dyn ClientHook,
+        interface_id: u64,
+        method_id: u16,
+        params: Box<dyn ParamsHook>,
+        results: Box<dyn ResultsHook>,
+    ) -> Promise<(), capnp::Error> {
+        let call = MethodCall {
+            service: &self.service,
+            interface_id,
+            method_id,
+        };
+        if !self.policy.allows(&call) {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                service = call.service,
+                interface_id,
+                method_id,
+                "Call denied by the access policy"
+            );
+            return Promise::err(capnp::Error::failed(format!(
+                "method @{method_id} of service {} denied by the access policy",
+                self.service
+            )));
+        }
+        inner.call(interface_id, method_id, params, results)
+    }
+}
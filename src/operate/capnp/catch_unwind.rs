@@ -0,0 +1,48 @@
+//! Isolation of panics raised by service handlers.
+
+use std::panic::AssertUnwindSafe;
+
+use capnp::{
+    capability::Promise,
+    private::capability::{ClientHook, ParamsHook, ResultsHook},
+};
+use futures::{FutureExt, TryFutureExt};
+
+use super::forwarding::CallInterceptor;
+
+/// Interceptor which turns panics of the wrapped capability into errors.
+///
+/// Only calls coming from remote clients are protected, a panic is reported to the caller as an
+/// internal server error and the connection keeps running.
+#[derive(Clone)]
+pub(crate) struct CatchUnwind;
+
+impl CallInterceptor for CatchUnwind {
+    fn intercept_call(
+        &self,
+        inner: &dyn ClientHook,
+        interface_id: u64,
+        method_id: u16,
+        params: Box<dyn ParamsHook>,
+        results: Box<dyn ResultsHook>,
+    ) -> Promise<(), capnp::Error> {
+        // The call may panic synchronously, or when the returned promise is polled
+        let call = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            inner.call(interface_id, method_id, params, results)
+        }));
+        match call {
+            Ok(promise) => Promise::from_future(
+                AssertUnwindSafe(promise)
+                    .catch_unwind()
+                    .unwrap_or_else(|_| Err(internal_server_error())),
+            ),
+            Err(_) => Promise::err(internal_server_error()),
+        }
+    }
+}
+
+fn internal_server_error() -> capnp::Error {
+    #[cfg(feature = "tracing")]
+    tracing::error!("Service handler panicked");
+    capnp::Error::failed("internal server error".to_owned())
+}
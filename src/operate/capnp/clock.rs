@@ -0,0 +1,131 @@
+//! Clock service pushing the time to its subscribers.
+//!
+//! It demonstrates server initiated messages with an [`EventChannel`].
+
+use std::time::{Duration, SystemTime};
+
+use async_io::Timer;
+use capnp::capability::Promise;
+use clock_capnp::clock::{Server, SubscribeParams, SubscribeResults};
+use futures::{
+    task::{LocalSpawn, LocalSpawnExt},
+    SinkExt, StreamExt, TryFutureExt,
+};
+
+use super::events::EventChannel;
+
+capnp::generated_code!(pub mod clock_capnp);
+
+/// Clock service, it pushes the time to every subscribed listener periodically.
+///
+/// Pushing runs in the background on the passed executor, until the listener fails.
+pub struct ClockServer<S> {
+    spawner: S,
+    interval: Duration,
+}
+
+impl<S> ClockServer<S>
+where
+    S: LocalSpawn,
+{
+    /// Creates a clock pushing the time every second.
+    pub fn new(spawner: S) -> Self {
+        Self::with_interval(spawner, Duration::from_secs(1))
+    }
+
+    /// Creates a clock pushing the time at the passed interval.
+    pub fn with_interval(spawner: S, interval: Duration) -> Self {
+        Self { spawner, interval }
+    }
+}
+
+impl<S> Server for ClockServer<S>
+where
+    S: LocalSpawn + 'static,
+{
+    async fn subscribe(
+        self: capnp::capability::Rc<Self>,
+        params: SubscribeParams,
+        _results: SubscribeResults,
+    ) -> Result<(), capnp::Error> {
+        let listener = params.get()?.get_listener()?;
+        let mut events = EventChannel::new(move |unix_millis: u64| {
+            let mut req = listener.tick_request();
+            req.get().set_unix_millis(unix_millis);
+            Promise::from_future(req.send().promise.map_ok(|_| ()))
+        });
+        let mut ticks = Timer::interval(self.interval);
+        self.spawner
+            .spawn_local(async move {
+                while ticks.next().await.is_some() {
+                    let now = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap_or_default();
+                    if events.send(now.as_millis() as u64).await.is_err() {
+                        // The listener is gone
+                        break;
+                    }
+                }
+            })
+            .map_err(|err| capnp::Error::failed(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use futures::channel::mpsc;
+
+    use super::*;
+    use crate::operate::capnp::{testing::connected_pair, TeleopServer};
+
+    /// Listener forwarding the ticks to a channel.
+    struct ChannelListener(mpsc::UnboundedSender<u64>);
+
+    impl clock_capnp::clock_listener::Server for ChannelListener {
+        async fn tick(
+            self: capnp::capability::Rc<Self>,
+            params: clock_capnp::clock_listener::TickParams,
+            _results: clock_capnp::clock_listener::TickResults,
+        ) -> Result<(), capnp::Error> {
+            self.0
+                .unbounded_send(params.get()?.get_unix_millis())
+                .map_err(|err| capnp::Error::failed(err.to_string()))
+        }
+    }
+
+    #[test]
+    fn test_capnp_clock() {
+        let mut exec = futures::executor::LocalPool::new();
+
+        let mut server = TeleopServer::new();
+        let spawner = exec.spawner();
+        server.register_service::<clock_capnp::clock::Client, _, _>("clock", || {
+            ClockServer::with_interval(spawner, Duration::from_millis(10))
+        });
+
+        let teleop = connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            let mut req = teleop.service_request();
+            req.get().set_name("clock");
+            let clock = req.send().promise.await?;
+            let clock: clock_capnp::clock::Client = clock.get()?.get_service().get_as()?;
+
+            let (sender, receiver) = mpsc::unbounded();
+            let listener: clock_capnp::clock_listener::Client =
+                capnp_rpc::new_client(ChannelListener(sender));
+            let mut req = clock.subscribe_request();
+            req.get().set_listener(listener);
+            req.send().promise.await?;
+
+            let ticks = receiver.take(3).collect::<Vec<_>>().await;
+            assert_eq!(ticks.len(), 3);
+            assert!(ticks.is_sorted());
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+}
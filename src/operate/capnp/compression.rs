@@ -0,0 +1,269 @@
+//! Compression of the byte stream below the RPC layer.
+//!
+//! Compression is negotiated by the connection handshake: both sides send their preferred
+//! [`Compression`] and fall back to [`Compression::None`] if they do not agree.
+//!
+//! Requesting compression implies the handshake, so both sides must opt in to it, e.g. with
+//! [`ConnectionOptions::handshake`](super::ConnectionOptions::handshake). A peer which does not
+//! exchange the handshake reads it as a RPC message and the connection fails.
+
+use std::{
+    io::{Error, ErrorKind},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use futures::{AsyncRead, AsyncWrite};
+use lz4_flex::block::{
+    compress_prepend_size, decompress_into, get_maximum_output_size, uncompressed_size,
+};
+
+/// Compression algorithm applied to the byte stream.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Compression {
+    /// No compression.
+    #[default]
+    None,
+    /// LZ4 block compression, fast and suitable for highly compressible messages.
+    Lz4,
+}
+
+impl Compression {
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Lz4 => 1,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Self {
+        match id {
+            1 => Self::Lz4,
+            // Unknown algorithms cannot be agreed on
+            _ => Self::None,
+        }
+    }
+}
+
+/// Maximum number of uncompressed bytes in a frame.
+const MAX_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Stream wrapper which compresses what is written and decompresses what is read.
+///
+/// Data is sent in frames made of the compressed length followed by the compressed bytes. A frame
+/// is emitted on every flush, or whenever enough data is buffered.
+pub struct CompressedStream<S> {
+    inner: S,
+    // Write side
+    pending: Vec<u8>,
+    frame: Vec<u8>,
+    frame_written: usize,
+    // Read side
+    header: [u8; 4],
+    header_read: usize,
+    body: Vec<u8>,
+    body_read: usize,
+    decoded: Vec<u8>,
+    decoded_read: usize,
+}
+
+impl<S> CompressedStream<S> {
+    /// Wraps the passed stream.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            pending: Vec::new(),
+            frame: Vec::new(),
+            frame_written: 0,
+            header: [0; 4],
+            header_read: 0,
+            body: Vec::new(),
+            body_read: 0,
+            decoded: Vec::new(),
+            decoded_read: 0,
+        }
+    }
+
+    /// Returns the wrapped stream.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S> CompressedStream<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    /// Compresses pending data and writes frames until nothing is pending.
+    fn poll_write_frames(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        loop {
+            while self.frame_written < self.frame.len() {
+                let written = ready!(
+                    Pin::new(&mut self.inner).poll_write(cx, &self.frame[self.frame_written..])
+                )?;
+                if written == 0 {
+                    return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+                }
+                self.frame_written += written;
+            }
+            self.frame.clear();
+            self.frame_written = 0;
+
+            if self.pending.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+
+            let compressed = compress_prepend_size(&self.pending);
+            self.pending.clear();
+            self.frame
+                .extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+            self.frame.extend_from_slice(&compressed);
+        }
+    }
+}
+
+impl<S> AsyncWrite for CompressedStream<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        if this.pending.len() >= MAX_CHUNK_SIZE {
+            ready!(this.poll_write_frames(cx))?;
+        }
+        let len = buf.len().min(MAX_CHUNK_SIZE - this.pending.len());
+        this.pending.extend_from_slice(&buf[..len]);
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_frames(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_frames(cx))?;
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+impl<S> CompressedStream<S>
+where
+    S: AsyncRead + Unpin,
+{
+    /// Reads and decompresses the next frame.
+    ///
+    /// Returns `false` if the stream terminated cleanly before the frame.
+    fn poll_read_frame(&mut self, cx: &mut Context<'_>) -> Poll<Result<bool, Error>> {
+        while self.header_read < self.header.len() {
+            let read = ready!(
+                Pin::new(&mut self.inner).poll_read(cx, &mut self.header[self.header_read..])
+            )?;
+            if read == 0 {
+                if self.header_read == 0 {
+                    return Poll::Ready(Ok(false));
+                }
+                return Poll::Ready(Err(ErrorKind::UnexpectedEof.into()));
+            }
+            self.header_read += read;
+        }
+
+        let frame_len = u32::from_le_bytes(self.header) as usize;
+        if frame_len > 4 + get_maximum_output_size(MAX_CHUNK_SIZE) {
+            return Poll::Ready(Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("compressed frame too large: {frame_len} bytes"),
+            )));
+        }
+        self.body.resize(frame_len, 0);
+
+        while self.body_read < frame_len {
+            let read =
+                ready!(Pin::new(&mut self.inner).poll_read(cx, &mut self.body[self.body_read..]))?;
+            if read == 0 {
+                return Poll::Ready(Err(ErrorKind::UnexpectedEof.into()));
+            }
+            self.body_read += read;
+        }
+
+        self.header_read = 0;
+        self.body_read = 0;
+
+        let invalid_data = |err| Error::new(ErrorKind::InvalidData, err);
+        let (size, compressed) = uncompressed_size(&self.body).map_err(invalid_data)?;
+        if size > MAX_CHUNK_SIZE {
+            return Poll::Ready(Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("decompressed frame too large: {size} bytes"),
+            )));
+        }
+        self.decoded.resize(size, 0);
+        let size = decompress_into(compressed, &mut self.decoded).map_err(invalid_data)?;
+        self.decoded.truncate(size);
+        self.decoded_read = 0;
+
+        Poll::Ready(Ok(true))
+    }
+}
+
+impl<S> AsyncRead for CompressedStream<S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        while this.decoded_read == this.decoded.len() {
+            if !ready!(this.poll_read_frame(cx))? {
+                return Poll::Ready(Ok(0));
+            }
+        }
+        let len = buf.len().min(this.decoded.len() - this.decoded_read);
+        buf[..len].copy_from_slice(&this.decoded[this.decoded_read..this.decoded_read + len]);
+        this.decoded_read += len;
+        Poll::Ready(Ok(len))
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use futures::{executor::block_on, future::join, AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[test]
+    fn test_compressed_stream() {
+        let (input, output) = sluice::pipe::pipe();
+        let mut input = CompressedStream::new(input);
+        let mut output = CompressedStream::new(output);
+
+        // Spans several frames
+        let data = "compress me! ".repeat(20_000);
+
+        let res = block_on(join(
+            async {
+                output.write_all(data.as_bytes()).await?;
+                output.close().await
+            },
+            async {
+                let mut read = String::new();
+                input.read_to_string(&mut read).await?;
+                Ok::<_, Error>(read)
+            },
+        ));
+
+        res.0.unwrap();
+        assert_eq!(res.1.unwrap(), data);
+    }
+}
@@ -0,0 +1,100 @@
+//! Diagnostics service dumping the threads of the running process.
+//!
+//! This is the counterpart of the thread dumps the JVM prints when it receives `SIGQUIT`, the
+//! signal the UNIX attacher sends. Rust has no portable way to capture the backtraces of other
+//! threads, so the [`DiagnosticsServer`] calls the function passed by the process, which produces
+//! the dump the way it sees fit, e.g. from `/proc/self/task` on Linux or with a backtrace crate.
+
+use diagnostics_capnp::diagnostics::{Server, ThreadDumpParams, ThreadDumpResults};
+
+capnp::generated_code!(pub mod diagnostics_capnp);
+
+type ThreadDump = Box<dyn Fn() -> Result<String, Box<dyn std::error::Error>>>;
+
+/// Diagnostics service, see
+/// [`register_diagnostics_service`](super::TeleopServer::register_diagnostics_service).
+pub struct DiagnosticsServer {
+    thread_dump: ThreadDump,
+}
+
+impl DiagnosticsServer {
+    /// Creates a service producing thread dumps with the passed function.
+    ///
+    /// Its errors are returned to the client.
+    pub fn new(
+        thread_dump: impl Fn() -> Result<String, Box<dyn std::error::Error>> + 'static,
+    ) -> Self {
+        Self {
+            thread_dump: Box::new(thread_dump),
+        }
+    }
+}
+
+impl Server for DiagnosticsServer {
+    async fn thread_dump(
+        self: capnp::capability::Rc<Self>,
+        _params: ThreadDumpParams,
+        mut results: ThreadDumpResults,
+    ) -> Result<(), capnp::Error> {
+        let dump = (self.thread_dump)().map_err(|err| capnp::Error::failed(err.to_string()))?;
+        results.get().set_dump(&dump);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+    use crate::operate::capnp::{testing::connected_pair, TeleopServer};
+
+    #[test]
+    fn test_capnp_diagnostics() {
+        let dumps = Rc::new(Cell::new(0));
+
+        let mut server = TeleopServer::new();
+        server.register_diagnostics_service({
+            let dumps = dumps.clone();
+            move || {
+                dumps.set(dumps.get() + 1);
+                if dumps.get() > 1 {
+                    return Err("dump already in progress".into());
+                }
+                Ok("thread 'main'\n  0: main\n".to_owned())
+            }
+        });
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            let mut req = teleop.service_request();
+            req.get().set_name("diagnostics");
+            let diagnostics = req.send().promise.await?;
+            let diagnostics: diagnostics_capnp::diagnostics::Client =
+                diagnostics.get()?.get_service().get_as()?;
+
+            let reply = diagnostics.thread_dump_request().send().promise.await?;
+            assert_eq!(
+                reply.get()?.get_dump()?.to_str()?,
+                "thread 'main'\n  0: main\n"
+            );
+
+            let err = diagnostics
+                .thread_dump_request()
+                .send()
+                .promise
+                .await
+                .err()
+                .unwrap();
+            assert!(err.extra.contains("dump already in progress"), "{err}");
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+        assert_eq!(dumps.get(), 2);
+    }
+}
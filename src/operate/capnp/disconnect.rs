@@ -0,0 +1,202 @@
+//! Graceful termination of client connections.
+//!
+//! Awaiting the [`Disconnector`] of the RPC system aborts the connection and drops the streams
+//! without closing them, while the peer may still be reading. A [`GracefulDisconnector`]
+//! additionally closes the output stream, which flushes it and signals the end of the stream to
+//! the peer, then waits for the peer to close its side.
+
+use std::{
+    cell::RefCell,
+    io::Error,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+use capnp_rpc::{rpc_twoparty_capnp, Disconnector};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Stream shared by the RPC system and a [`GracefulDisconnector`], which gets it back once the RPC
+/// system terminates.
+pub(crate) struct SharedStream<T>(Rc<RefCell<T>>);
+
+impl<T> SharedStream<T> {
+    pub(crate) fn new(inner: T) -> Self {
+        Self(Rc::new(RefCell::new(inner)))
+    }
+}
+
+impl<T> Clone for SharedStream<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> AsyncRead for SharedStream<T>
+where
+    T: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        Pin::new(&mut *self.0.borrow_mut()).poll_read(cx, buf)
+    }
+}
+
+impl<T> AsyncWrite for SharedStream<T>
+where
+    T: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        Pin::new(&mut *self.0.borrow_mut()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut *self.0.borrow_mut()).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut *self.0.borrow_mut()).poll_close(cx)
+    }
+}
+
+pub(crate) type SharedInput = SharedStream<Box<dyn AsyncRead + Unpin>>;
+pub(crate) type SharedOutput = SharedStream<Box<dyn AsyncWrite + Unpin>>;
+
+/// Disconnects a client connection gracefully, see
+/// [`ConnectedStream::graceful_disconnector`](super::ConnectedStream::graceful_disconnector).
+///
+/// The RPC system must keep running until [`disconnect`](Self::disconnect) returns.
+pub struct GracefulDisconnector {
+    disconnector: Disconnector<rpc_twoparty_capnp::Side>,
+    input: SharedInput,
+    output: SharedOutput,
+}
+
+impl GracefulDisconnector {
+    pub(crate) fn new(
+        disconnector: Disconnector<rpc_twoparty_capnp::Side>,
+        input: SharedInput,
+        output: SharedOutput,
+    ) -> Self {
+        Self {
+            disconnector,
+            input,
+            output,
+        }
+    }
+
+    /// Disconnects the RPC system, closes the output stream and waits for the peer to close the
+    /// input stream.
+    ///
+    /// Messages queued before the call, e.g. a final request, are fully written before the output
+    /// stream is closed. Incoming data is discarded until the end of the input stream.
+    pub async fn disconnect(self) -> Result<(), capnp::Error> {
+        let Self {
+            disconnector,
+            mut input,
+            mut output,
+        } = self;
+        disconnector.await?;
+        output.close().await?;
+        let mut buf = [0; 1024];
+        while input.read(&mut buf).await? > 0 {}
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::cell::RefCell;
+
+    use futures::task::LocalSpawnExt;
+
+    use super::*;
+    use crate::operate::capnp::{
+        client_connection_with_options,
+        echo::echo_capnp::echo::{self, EchoParams, EchoResults},
+        run_server_connection, ConnectionOptions, TeleopServer,
+    };
+
+    /// Echo service recording the received messages.
+    struct RecordingEchoServer(Rc<RefCell<Vec<String>>>);
+
+    impl echo::Server for RecordingEchoServer {
+        async fn echo(
+            self: capnp::capability::Rc<Self>,
+            params: EchoParams,
+            mut results: EchoResults,
+        ) -> Result<(), capnp::Error> {
+            let message = params.get()?.get_message()?.to_string()?;
+            results.get().set_reply(message.as_str());
+            self.0.borrow_mut().push(message);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_graceful_disconnect() {
+        let received = Rc::new(RefCell::new(Vec::new()));
+
+        let mut server = TeleopServer::new();
+        server.register_service::<echo::Client, _, _>("echo", {
+            let received = received.clone();
+            || RecordingEchoServer(received)
+        });
+
+        let (client_input, server_output) = sluice::pipe::pipe();
+        let (server_input, client_output) = sluice::pipe::pipe();
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawn = exec.spawner();
+        spawn
+            .spawn_local(async move {
+                let server = server.into_client();
+                let _ =
+                    run_server_connection(server_input, server_output, server.client.hook).await;
+            })
+            .unwrap();
+
+        let connected = exec
+            .run_until(client_connection_with_options(
+                client_input,
+                client_output,
+                ConnectionOptions::default(),
+            ))
+            .unwrap();
+        let graceful = connected.graceful_disconnector();
+        spawn
+            .spawn_local(async {
+                let _ = connected.rpc_system.await;
+            })
+            .unwrap();
+
+        let teleop = connected.teleop;
+        let res = exec.run_until(async move {
+            let mut req = teleop.service_request();
+            req.get().set_name("echo");
+            let echo = req.send().promise.await?;
+            let echo: echo::Client = echo.get()?.get_service().get_as()?;
+
+            // The final request is not awaited
+            let mut req = echo.echo_request();
+            req.get().set_message("last words");
+            let _reply = req.send();
+
+            graceful.disconnect().await?;
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+        exec.run();
+        assert_eq!(*received.borrow(), ["last words"]);
+    }
+}
@@ -0,0 +1,120 @@
+//! Service whose methods are defined at run time.
+//!
+//! The methods of a [`DynamicServer`] take raw bytes and return raw bytes, they are invoked by
+//! name through the generic `Dynamic` interface (see `dynamic.capnp`). It is an escape hatch for
+//! e.g. admin consoles, when compile time schemas are too rigid.
+
+use std::collections::BTreeMap;
+
+use dynamic_capnp::dynamic::{InvokeParams, InvokeResults, MethodsParams, MethodsResults, Server};
+
+capnp::generated_code!(pub mod dynamic_capnp);
+
+type Method = Box<dyn Fn(Vec<u8>) -> Vec<u8>>;
+
+/// Server dispatching invocations to the methods added at run time.
+#[derive(Default)]
+pub struct DynamicServer {
+    methods: BTreeMap<String, Method>,
+}
+
+impl DynamicServer {
+    /// Creates a server with no methods.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a method, replacing any method with the same name.
+    pub fn add_method(
+        &mut self,
+        name: impl Into<String>,
+        method: impl Fn(Vec<u8>) -> Vec<u8> + 'static,
+    ) {
+        self.methods.insert(name.into(), Box::new(method));
+    }
+}
+
+impl Server for DynamicServer {
+    async fn invoke(
+        self: capnp::capability::Rc<Self>,
+        params: InvokeParams,
+        mut results: InvokeResults,
+    ) -> Result<(), capnp::Error> {
+        let params = params.get()?;
+        let name = params.get_method()?.to_str()?;
+        let Some(method) = self.methods.get(name) else {
+            return Err(capnp::Error::unimplemented(format!(
+                "method {name} is not defined"
+            )));
+        };
+        let result = method(params.get_args()?.to_vec());
+        results.get().set_result(&result);
+        Ok(())
+    }
+
+    async fn methods(
+        self: capnp::capability::Rc<Self>,
+        _params: MethodsParams,
+        mut results: MethodsResults,
+    ) -> Result<(), capnp::Error> {
+        let mut names = results.get().init_names(self.methods.len() as u32);
+        for (i, name) in self.methods.keys().enumerate() {
+            names.set(i as u32, name);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::*;
+    use crate::operate::capnp::{testing::connected_pair, TeleopServer};
+
+    #[test]
+    fn test_capnp_dynamic() {
+        let mut dynamic = DynamicServer::new();
+        dynamic.add_method("reverse", |mut args| {
+            args.reverse();
+            args
+        });
+        dynamic.add_method("len", |args| args.len().to_string().into_bytes());
+
+        let mut server = TeleopServer::new();
+        server.register_service::<dynamic_capnp::dynamic::Client, _, _>("admin", || dynamic);
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            let mut req = teleop.service_request();
+            req.get().set_name("admin");
+            let admin = req.send().promise.await?;
+            let admin: dynamic_capnp::dynamic::Client = admin.get()?.get_service().get_as()?;
+
+            let reply = admin.methods_request().send().promise.await?;
+            let names = reply
+                .get()?
+                .get_names()?
+                .iter()
+                .map(|name| Ok(name?.to_string()?))
+                .collect::<Result<Vec<_>, capnp::Error>>()?;
+            assert_eq!(names, ["len", "reverse"]);
+
+            let mut req = admin.invoke_request();
+            req.get().set_method("reverse");
+            req.get().set_args(b"hello");
+            let reply = req.send().promise.await?;
+            assert_eq!(reply.get()?.get_result()?, b"olleh");
+
+            let mut req = admin.invoke_request();
+            req.get().set_method("unknown");
+            let err = req.send().promise.await.err().unwrap();
+            assert_eq!(err.kind, capnp::ErrorKind::Unimplemented);
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+}
@@ -1,8 +1,18 @@
+#[cfg(any(test, feature = "testing"))]
+use std::time::Duration;
+
+#[cfg(any(test, feature = "testing"))]
+use async_io::Timer;
+#[cfg(any(test, feature = "testing"))]
+use echo_capnp::echo::{EchoDelayedParams, EchoDelayedResults};
 use echo_capnp::echo::{EchoParams, EchoResults, Server};
 
 capnp::generated_code!(pub mod echo_capnp);
 
 /// Echo service used to test good communication between client and server.
+///
+/// With the `testing` feature, `echoDelayed` waits before replying, which gives tests a
+/// deterministic slow request.
 #[derive(Default)]
 pub struct EchoServer;
 
@@ -16,4 +26,161 @@ impl Server for EchoServer {
         results.get().set_reply(message);
         Ok(())
     }
+
+    #[cfg(any(test, feature = "testing"))]
+    async fn echo_delayed(
+        self: capnp::capability::Rc<Self>,
+        params: EchoDelayedParams,
+        mut results: EchoDelayedResults,
+    ) -> Result<(), capnp::Error> {
+        let params = params.get()?;
+        Timer::after(Duration::from_millis(params.get_delay_millis().into())).await;
+        results.get().set_reply(params.get_message()?.to_str()?);
+        Ok(())
+    }
+}
+
+/// Echo service streaming the message back in chunks, used to test framing and flow control of
+/// large replies.
+///
+/// Chunks are sent as streaming calls on the receiver, so that only a window of them is in flight
+/// at any time instead of buffering the whole reply.
+#[derive(Default)]
+pub struct EchoStreamServer;
+
+impl echo_capnp::echo_stream::Server for EchoStreamServer {
+    async fn echo_stream(
+        self: capnp::capability::Rc<Self>,
+        params: echo_capnp::echo_stream::EchoStreamParams,
+        _results: echo_capnp::echo_stream::EchoStreamResults,
+    ) -> Result<(), capnp::Error> {
+        let params = params.get()?;
+        let chunk_size = params.get_chunk_size() as usize;
+        if chunk_size == 0 {
+            return Err(capnp::Error::failed(
+                "chunk size must not be zero".to_owned(),
+            ));
+        }
+        let receiver = params.get_receiver()?;
+        for chunk in params.get_message()?.chunks(chunk_size) {
+            let mut req = receiver.chunk_request();
+            req.get().set_chunk(chunk);
+            req.send().await?;
+        }
+        receiver.done_request().send().promise.await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::{
+        cell::{Cell, RefCell},
+        rc::Rc,
+        time::Instant,
+    };
+
+    use super::*;
+    use crate::operate::capnp::{testing::connected_pair, TeleopServer};
+
+    #[test]
+    fn test_capnp_echo_delayed() {
+        let mut server = TeleopServer::new();
+        server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            let mut req = teleop.service_request();
+            req.get().set_name("echo");
+            let echo = req.send().promise.await?;
+            let echo: echo_capnp::echo::Client = echo.get()?.get_service().get_as()?;
+
+            let start = Instant::now();
+            let mut req = echo.echo_delayed_request();
+            req.get().set_message("hello!");
+            req.get().set_delay_millis(200);
+            let reply = req.send().promise.await?;
+            assert_eq!(reply.get()?.get_reply()?.to_str()?, "hello!");
+            assert!(start.elapsed() >= Duration::from_millis(200));
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    /// Receiver recording the streamed chunks.
+    #[derive(Default)]
+    struct RecordingReceiver {
+        chunks: RefCell<Vec<Vec<u8>>>,
+        done: Cell<bool>,
+    }
+
+    impl echo_capnp::echo_receiver::Server for Rc<RecordingReceiver> {
+        async fn chunk(
+            self: capnp::capability::Rc<Self>,
+            params: echo_capnp::echo_receiver::ChunkParams,
+        ) -> Result<(), capnp::Error> {
+            self.chunks
+                .borrow_mut()
+                .push(params.get()?.get_chunk()?.to_vec());
+            Ok(())
+        }
+
+        async fn done(
+            self: capnp::capability::Rc<Self>,
+            _params: echo_capnp::echo_receiver::DoneParams,
+            _results: echo_capnp::echo_receiver::DoneResults,
+        ) -> Result<(), capnp::Error> {
+            self.done.set(true);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_capnp_echo_stream() {
+        const CHUNK_SIZE: usize = 64 * 1024;
+
+        let mut server = TeleopServer::new();
+        server.register_service::<echo_capnp::echo_stream::Client, _, _>("echo_stream", || {
+            EchoStreamServer
+        });
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = connected_pair(server, &exec.spawner()).unwrap();
+
+        let message = (0..10 * 1024 * 1024)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        let receiver = Rc::new(RecordingReceiver::default());
+
+        let res = exec.run_until({
+            let receiver = receiver.clone();
+            let message = message.clone();
+            async move {
+                let mut req = teleop.service_request();
+                req.get().set_name("echo_stream");
+                let echo = req.send().promise.await?;
+                let echo: echo_capnp::echo_stream::Client = echo.get()?.get_service().get_as()?;
+
+                let mut req = echo.echo_stream_request();
+                req.get().set_message(&message);
+                req.get().set_chunk_size(CHUNK_SIZE as u32);
+                req.get().set_receiver(capnp_rpc::new_client(receiver));
+                req.send().promise.await?;
+
+                Ok::<_, Box<dyn std::error::Error>>(())
+            }
+        });
+
+        res.unwrap();
+        assert!(receiver.done.get());
+        let chunks = receiver.chunks.borrow();
+        assert_eq!(chunks.len(), message.len() / CHUNK_SIZE);
+        assert!(chunks.iter().all(|chunk| chunk.len() == CHUNK_SIZE));
+        assert_eq!(chunks.concat(), message);
+    }
 }
@@ -0,0 +1,70 @@
+//! Server initiated messages.
+//!
+//! Cap'n Proto has no notion of server push: the client passes a callback capability to a service,
+//! which calls it whenever an event occurs. An [`EventChannel`] wraps such a callback into a
+//! [`Sink`] so that the service can push events with the usual combinators, see
+//! [`ClockServer`](super::clock::ClockServer) for an example.
+
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use capnp::capability::Promise;
+use futures::{FutureExt, Sink};
+
+/// [`Sink`] pushing events to a client through a callback capability.
+///
+/// Events are pushed one at a time: the sink is ready again once the client has acknowledged the
+/// previous event, which provides back pressure. The sink fails as soon as a call fails, e.g.
+/// because the client disconnected.
+pub struct EventChannel<E> {
+    push: Box<dyn FnMut(E) -> Promise<(), capnp::Error>>,
+    in_flight: Option<Promise<(), capnp::Error>>,
+}
+
+impl<E> EventChannel<E> {
+    /// Creates a channel calling the passed function to push every event.
+    ///
+    /// The function typically builds a request on the callback capability and sends it.
+    pub fn new<F>(push: F) -> Self
+    where
+        F: FnMut(E) -> Promise<(), capnp::Error> + 'static,
+    {
+        Self {
+            push: Box::new(push),
+            in_flight: None,
+        }
+    }
+
+    fn poll_in_flight(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), capnp::Error>> {
+        if let Some(in_flight) = &mut self.in_flight {
+            let res = ready!(in_flight.poll_unpin(cx));
+            self.in_flight = None;
+            res?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<E> Sink<E> for EventChannel<E> {
+    type Error = capnp::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_in_flight(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, event: E) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        this.in_flight = Some((this.push)(event));
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_in_flight(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_in_flight(cx)
+    }
+}
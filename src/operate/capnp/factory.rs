@@ -0,0 +1,81 @@
+use factory_capnp::factory::{MakeEchoParams, MakeEchoResults, Server};
+
+// The generated code refers to the imported echo schema relatively to this module.
+use super::echo::echo_capnp;
+
+capnp::generated_code!(pub mod factory_capnp);
+
+/// Factory service used to test that services can create and return other capabilities.
+#[derive(Default)]
+pub struct FactoryServer;
+
+impl Server for FactoryServer {
+    async fn make_echo(
+        self: capnp::capability::Rc<Self>,
+        params: MakeEchoParams,
+        mut results: MakeEchoResults,
+    ) -> Result<(), capnp::Error> {
+        let name = params.get()?.get_name()?.to_string()?;
+        let echo: echo_capnp::echo::Client = capnp_rpc::new_client(NamedEchoServer { name });
+        results.get().set_echo(echo);
+        Ok(())
+    }
+}
+
+/// Echo service created by [`FactoryServer`], it prefixes replies with its name.
+struct NamedEchoServer {
+    name: String,
+}
+
+impl echo_capnp::echo::Server for NamedEchoServer {
+    async fn echo(
+        self: capnp::capability::Rc<Self>,
+        params: echo_capnp::echo::EchoParams,
+        mut results: echo_capnp::echo::EchoResults,
+    ) -> Result<(), capnp::Error> {
+        let message = params.get()?.get_message()?.to_str()?;
+        results
+            .get()
+            .set_reply(format!("{}: {}", self.name, message).as_str());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::*;
+    use crate::operate::capnp::{testing::connected_pair, TeleopServer};
+
+    #[test]
+    fn test_capnp_factory() {
+        let mut server = TeleopServer::new();
+        server
+            .register_service::<factory_capnp::factory::Client, _, _>("factory", || FactoryServer);
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            let mut req = teleop.service_request();
+            req.get().set_name("factory");
+            let factory = req.send().promise.await?;
+            let factory: factory_capnp::factory::Client = factory.get()?.get_service().get_as()?;
+
+            let mut req = factory.make_echo_request();
+            req.get().set_name("x");
+            let echo = req.send().promise.await?;
+            let echo = echo.get()?.get_echo()?;
+
+            let mut req = echo.echo_request();
+            req.get().set_message("hello!");
+            let reply = req.send().promise.await?;
+            let reply = reply.get()?.get_reply()?.to_str()?;
+            assert_eq!(reply, "x: hello!");
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+}
@@ -0,0 +1,153 @@
+//! File transfer service pulling files out of the process, e.g. a heap dump.
+//!
+//! The client opens a file and pulls it chunk by chunk, each chunk being a request, which gives
+//! natural backpressure.
+//!
+//! Only the files under the paths allowed by the [`FileTransferServer`] can be opened. Paths are
+//! resolved, including symbolic links, before being checked.
+
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use file_transfer_capnp::{file_reader, file_transfer};
+
+capnp::generated_code!(pub mod file_transfer_capnp);
+
+/// Maximum size of a chunk, larger reads are truncated.
+pub const MAX_CHUNK_SIZE: u32 = 1024 * 1024;
+
+/// File transfer service, it opens the files under the allowed paths.
+///
+/// Files are read synchronously on the executor, one chunk per request.
+pub struct FileTransferServer {
+    allowed: Vec<PathBuf>,
+}
+
+impl FileTransferServer {
+    /// Creates a service allowing the files under the passed paths, either files or directories.
+    pub fn new(allowed: impl IntoIterator<Item = impl Into<PathBuf>>) -> Self {
+        Self {
+            allowed: allowed.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    fn is_allowed(&self, path: &Path) -> bool {
+        self.allowed.iter().any(|allowed| {
+            allowed
+                .canonicalize()
+                .is_ok_and(|allowed| path.starts_with(allowed))
+        })
+    }
+}
+
+impl file_transfer::Server for FileTransferServer {
+    async fn open(
+        self: capnp::capability::Rc<Self>,
+        params: file_transfer::OpenParams,
+        mut results: file_transfer::OpenResults,
+    ) -> Result<(), capnp::Error> {
+        let path = params.get()?.get_path()?.to_str()?;
+        // Resolve `..` and symbolic links which could escape the allowed paths
+        let resolved = Path::new(path).canonicalize().ok();
+        let Some(resolved) = resolved.filter(|resolved| self.is_allowed(resolved)) else {
+            return Err(capnp::Error::failed(format!("path {path} is not allowed")));
+        };
+        let file = File::open(resolved)?;
+        results
+            .get()
+            .set_reader(capnp_rpc::new_client(FileReaderServer(RefCell::new(file))));
+        Ok(())
+    }
+}
+
+struct FileReaderServer(RefCell<File>);
+
+impl file_reader::Server for FileReaderServer {
+    async fn read(
+        self: capnp::capability::Rc<Self>,
+        params: file_reader::ReadParams,
+        mut results: file_reader::ReadResults,
+    ) -> Result<(), capnp::Error> {
+        let max_bytes = params.get()?.get_max_bytes().min(MAX_CHUNK_SIZE);
+        let mut chunk = Vec::new();
+        let mut file = self.0.borrow_mut();
+        (&mut *file)
+            .take(max_bytes.into())
+            .read_to_end(&mut chunk)?;
+        let mut results = results.get();
+        results.set_eof(chunk.len() < max_bytes as usize);
+        results.set_chunk(&chunk);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::*;
+    use crate::{
+        internal::unique_attach_file_token,
+        operate::capnp::{testing::connected_pair, TeleopServer},
+    };
+
+    #[test]
+    fn test_capnp_file_transfer() {
+        let dir = std::env::temp_dir().join(format!(
+            ".teleop_file_transfer_{}",
+            unique_attach_file_token()
+        ));
+        std::fs::create_dir(&dir).unwrap();
+        let content = (0..100_000).map(|i| i as u8).collect::<Vec<_>>();
+        std::fs::write(dir.join("dump"), &content).unwrap();
+
+        let mut server = TeleopServer::new();
+        let allowed = dir.clone();
+        server.register_service::<file_transfer::Client, _, _>("file_transfer", || {
+            FileTransferServer::new([allowed])
+        });
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async {
+            let mut req = teleop.service_request();
+            req.get().set_name("file_transfer");
+            let transfer = req.send().promise.await?;
+            let transfer: file_transfer::Client = transfer.get()?.get_service().get_as()?;
+
+            let mut req = transfer.open_request();
+            req.get().set_path(dir.join("dump").to_str().unwrap());
+            let reader = req.send().promise.await?.get()?.get_reader()?;
+
+            let mut transferred = Vec::new();
+            loop {
+                let mut req = reader.read_request();
+                req.get().set_max_bytes(4096);
+                let reply = req.send().promise.await?;
+                let reply = reply.get()?;
+                transferred.extend_from_slice(reply.get_chunk()?);
+                if reply.get_eof() {
+                    break;
+                }
+            }
+            assert_eq!(transferred, content);
+
+            // Escaping the allowed directory
+            let mut req = transfer.open_request();
+            req.get()
+                .set_path(dir.join("../../etc/passwd").to_str().unwrap());
+            let err = req.send().promise.await.err().unwrap();
+            assert!(err.extra.contains("is not allowed"), "{err}");
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        res.unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+//! Capabilities forwarding to another one, to add behaviour to its calls.
+
+use capnp::{
+    any_pointer,
+    capability::{Promise, Request},
+    private::capability::{ClientHook, ParamsHook, ResultsHook},
+    MessageSize,
+};
+use futures::TryFutureExt;
+
+/// Behaviour added to the calls of a capability wrapped by a [`ForwardingClientHook`].
+///
+/// It is cloned along with the wrapper, when references are added or the capability is resolved,
+/// so state shared by all the references must be behind a shared pointer.
+pub(crate) trait CallInterceptor: Clone + 'static {
+    /// Handles a call made to the wrapped capability, usually by forwarding it to `inner`.
+    fn intercept_call(
+        &self,
+        inner: &dyn ClientHook,
+        interface_id: u64,
+        method_id: u16,
+        params: Box<dyn ParamsHook>,
+        results: Box<dyn ResultsHook>,
+    ) -> Promise<(), capnp::Error>;
+}
+
+/// Capability wrapper which forwards everything to the wrapped capability, calls going through
+/// the interceptor first.
+pub(crate) struct ForwardingClientHook<I> {
+    inner: Box<dyn ClientHook>,
+    interceptor: I,
+}
+
+impl<I> ForwardingClientHook<I>
+where
+    I: CallInterceptor,
+{
+    pub(crate) fn new(inner: Box<dyn ClientHook>, interceptor: I) -> Self {
+        Self { inner, interceptor }
+    }
+
+    fn wrap(&self, inner: Box<dyn ClientHook>) -> Box<dyn ClientHook> {
+        Box::new(Self::new(inner, self.interceptor.clone()))
+    }
+}
+
+impl<I> ClientHook for ForwardingClientHook<I>
+where
+    I: CallInterceptor,
+{
+    fn add_ref(&self) -> Box<dyn ClientHook> {
+        self.wrap(self.inner.add_ref())
+    }
+
+    fn new_call(
+        &self,
+        interface_id: u64,
+        method_id: u16,
+        size_hint: Option<MessageSize>,
+    ) -> Request<any_pointer::Owned, any_pointer::Owned> {
+        self.inner.new_call(interface_id, method_id, size_hint)
+    }
+
+    fn call(
+        &self,
+        interface_id: u64,
+        method_id: u16,
+        params: Box<dyn ParamsHook>,
+        results: Box<dyn ResultsHook>,
+    ) -> Promise<(), capnp::Error> {
+        self.interceptor
+            .intercept_call(&*self.inner, interface_id, method_id, params, results)
+    }
+
+    fn get_brand(&self) -> usize {
+        self.inner.get_brand()
+    }
+
+    fn get_ptr(&self) -> usize {
+        self.inner.get_ptr()
+    }
+
+    fn get_resolved(&self) -> Option<Box<dyn ClientHook>> {
+        self.inner
+            .get_resolved()
+            .map(|resolved| self.wrap(resolved))
+    }
+
+    fn when_more_resolved(&self) -> Option<Promise<Box<dyn ClientHook>, capnp::Error>> {
+        let interceptor = self.interceptor.clone();
+        self.inner.when_more_resolved().map(|promise| {
+            Promise::from_future(promise.map_ok(move |resolved| {
+                Box::new(Self::new(resolved, interceptor)) as Box<dyn ClientHook>
+            }))
+        })
+    }
+
+    fn when_resolved(&self) -> Promise<(), capnp::Error> {
+        self.inner.when_resolved()
+    }
+}
@@ -0,0 +1,173 @@
+//! Handoff of capabilities between clients of the same process.
+//!
+//! Cap'n Proto defines three-party handoff (level 3 of the RPC protocol) to let a client pass a
+//! capability it received to another client, which then talks directly to the capability owner.
+//! `capnp-rpc` only implements the two-party network, so this is not available: a capability can
+//! only travel back and forth between the two ends of a connection.
+//!
+//! The [`HandoffServer`] service works around it with forwarding. A client offers a capability
+//! and gets a token, which it passes to another client by any means. That client claims the
+//! capability with the token and gets a promise forwarded by the process to be teleoperated.
+//! Calls therefore take one more hop than with a real handoff, and the capability breaks when
+//! either connection terminates.
+//!
+//! Since the service is shared by all the connections, it must be registered once:
+//!
+//! ```
+//! use teleop::operate::capnp::{
+//!     handoff::{handoff_capnp, HandoffServer},
+//!     TeleopServer,
+//! };
+//!
+//! let mut server = TeleopServer::new();
+//! server.register_service::<handoff_capnp::handoff::Client, _, _>("handoff", HandoffServer::new);
+//! ```
+
+use std::{cell::RefCell, collections::HashMap};
+
+use capnp::{capability::FromClientHook, private::capability::ClientHook};
+use handoff_capnp::handoff::{ClaimParams, ClaimResults, OfferParams, OfferResults, Server};
+
+use crate::internal::random_u64;
+
+capnp::generated_code!(pub mod handoff_capnp);
+
+/// Handoff service, holding offered capabilities until they are claimed.
+#[derive(Default)]
+pub struct HandoffServer {
+    offers: RefCell<HashMap<u64, Box<dyn ClientHook>>>,
+}
+
+impl HandoffServer {
+    /// Creates a new handoff service with no offered capability.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Server for HandoffServer {
+    async fn offer(
+        self: capnp::capability::Rc<Self>,
+        params: OfferParams,
+        mut results: OfferResults,
+    ) -> Result<(), capnp::Error> {
+        let cap: capnp::capability::Client = params.get()?.get_cap().get_as_capability()?;
+        let mut offers = self.offers.borrow_mut();
+        // Tokens are random so that they cannot be guessed by other clients
+        let token = loop {
+            let token = random_u64();
+            if !offers.contains_key(&token) {
+                break token;
+            }
+        };
+        offers.insert(token, cap.into_client_hook());
+        results.get().set_token(token);
+        Ok(())
+    }
+
+    async fn claim(
+        self: capnp::capability::Rc<Self>,
+        params: ClaimParams,
+        mut results: ClaimResults,
+    ) -> Result<(), capnp::Error> {
+        let token = params.get()?.get_token();
+        let cap = self
+            .offers
+            .borrow_mut()
+            .remove(&token)
+            .ok_or_else(|| capnp::Error::failed(format!("unknown handoff token {token}")))?;
+        results.get().init_cap().set_as_capability(cap);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use futures::{
+        io::{BufReader, BufWriter},
+        task::LocalSpawnExt,
+    };
+
+    use super::*;
+    use crate::operate::{
+        capnp::{
+            client_network,
+            echo::{echo_capnp, EchoServer},
+            teleop_capnp, CapnpProtocol, TeleopServer,
+        },
+        Protocol,
+    };
+
+    #[test]
+    fn test_capnp_handoff() {
+        let mut server = TeleopServer::new();
+        server.register_service::<handoff_capnp::handoff::Client, _, _>(
+            "handoff",
+            HandoffServer::new,
+        );
+        let server = server.into_client();
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawner = exec.spawner();
+
+        // Two clients of the same server
+        let mut clients = Vec::<teleop_capnp::teleop::Client>::new();
+        for _ in 0..2 {
+            let (client_input, server_output) = sluice::pipe::pipe();
+            let (server_input, client_output) = sluice::pipe::pipe();
+            let serve = CapnpProtocol::default().serve(&server, server_input, server_output);
+            spawner
+                .spawn_local(async move {
+                    let _ = serve.await;
+                })
+                .unwrap();
+            let (rpc_system, teleop) =
+                client_network(BufReader::new(client_input), BufWriter::new(client_output));
+            spawner
+                .spawn_local(async {
+                    let _ = rpc_system.await;
+                })
+                .unwrap();
+            clients.push(teleop);
+        }
+
+        let res = exec.run_until(async move {
+            let mut handoffs = Vec::new();
+            for teleop in &clients {
+                let mut req = teleop.service_request();
+                req.get().set_name("handoff");
+                let handoff = req.send().promise.await?;
+                let handoff: handoff_capnp::handoff::Client =
+                    handoff.get()?.get_service().get_as()?;
+                handoffs.push(handoff);
+            }
+
+            // The first client offers a capability it hosts
+            let echo: echo_capnp::echo::Client = capnp_rpc::new_client(EchoServer);
+            let mut req = handoffs[0].offer_request();
+            req.get().init_cap().set_as_capability(echo.client.hook);
+            let token = req.send().promise.await?.get()?.get_token();
+
+            // The second client claims it
+            let mut req = handoffs[1].claim_request();
+            req.get().set_token(token);
+            let claimed = req.send().promise.await?;
+            let echo: echo_capnp::echo::Client = claimed.get()?.get_cap().get_as_capability()?;
+
+            let mut req = echo.echo_request();
+            req.get().set_message("hello!");
+            let reply = req.send().promise.await?;
+            assert_eq!(reply.get()?.get_reply()?.to_str()?, "hello!");
+
+            // Tokens can be claimed only once
+            let mut req = handoffs[1].claim_request();
+            req.get().set_token(token);
+            assert!(req.send().promise.await.is_err());
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+//! Handshake exchanged by both sides before the RPC messages.
+//!
+//! Each side sends the magic bytes, its preferred [`Compression`], its [`Role`] and a random nonce.
+//! Both sides agree on the compression if they prefer the same one, otherwise they fall back to
+//! [`Compression::None`]. The connection is in the observer role if either side asks for it. The
+//! connection ID is derived from both nonces so that both sides know it without further exchange.
+//!
+//! Peers which do not request the handshake skip it entirely. Both sides must therefore agree on
+//! exchanging it: a peer unaware of it reads it as a RPC message and the connection fails, so
+//! options implying it cannot be enabled on one side only.
+
+use std::io::{Error, ErrorKind};
+
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::{compression::Compression, observer::Role};
+use crate::internal::random_u64;
+
+const HANDSHAKE_MAGIC: [u8; 3] = *b"TLP";
+
+const HANDSHAKE_SIZE: usize = HANDSHAKE_MAGIC.len() + 1 + 1 + 8;
+
+/// Result of the handshake.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Handshake {
+    pub compression: Compression,
+    pub role: Role,
+    pub connection_id: u64,
+}
+
+/// Exchanges the handshake with the peer.
+pub(crate) async fn handshake<R, W>(
+    input: &mut R,
+    output: &mut W,
+    compression: Compression,
+    role: Role,
+) -> Result<Handshake, Error>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let nonce = random_u64();
+
+    let mut message = [0; HANDSHAKE_SIZE];
+    message[..3].copy_from_slice(&HANDSHAKE_MAGIC);
+    message[3] = compression.id();
+    message[4] = role.id();
+    message[5..].copy_from_slice(&nonce.to_le_bytes());
+    output.write_all(&message).await?;
+    output.flush().await?;
+
+    input.read_exact(&mut message).await?;
+    if message[..3] != HANDSHAKE_MAGIC {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "peer did not send the handshake",
+        ));
+    }
+
+    let peer_compression = Compression::from_id(message[3]);
+    let peer_role = Role::from_id(message[4]);
+    let peer_nonce = u64::from_le_bytes(message[5..].try_into().unwrap());
+
+    Ok(Handshake {
+        compression: if peer_compression == compression {
+            compression
+        } else {
+            Compression::None
+        },
+        role: if peer_role == Role::Observer {
+            peer_role
+        } else {
+            role
+        },
+        connection_id: nonce ^ peer_nonce,
+    })
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use futures::{executor::block_on, future::join};
+
+    use super::*;
+
+    #[test]
+    fn test_handshake() {
+        let (mut a_input, mut b_output) = sluice::pipe::pipe();
+        let (mut b_input, mut a_output) = sluice::pipe::pipe();
+        let (a, b) = block_on(join(
+            handshake(&mut a_input, &mut a_output, Compression::Lz4, Role::Full),
+            handshake(
+                &mut b_input,
+                &mut b_output,
+                Compression::Lz4,
+                Role::Observer,
+            ),
+        ));
+        let (a, b) = (a.unwrap(), b.unwrap());
+        assert_eq!(a.compression, Compression::Lz4);
+        assert_eq!(a.role, Role::Observer);
+        assert_eq!(a, b);
+
+        // Unknown algorithm
+        let (mut input, mut peer_output) = sluice::pipe::pipe();
+        let (_peer_input, mut output) = sluice::pipe::pipe();
+        let res = block_on(async {
+            peer_output.write_all(b"TLP\x7f\x0001234567").await?;
+            handshake(&mut input, &mut output, Compression::Lz4, Role::Full).await
+        });
+        assert_eq!(res.unwrap().compression, Compression::None);
+    }
+}
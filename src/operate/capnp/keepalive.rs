@@ -0,0 +1,121 @@
+//! Application level liveness of connections.
+//!
+//! The client pings the server periodically with the `ping` method of `Teleop`, the server
+//! watches the incoming traffic. Either side tears the connection down if the peer stays silent.
+
+use std::{
+    cell::Cell,
+    future::Future,
+    io::Error,
+    pin::{pin, Pin},
+    rc::Rc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use async_io::Timer;
+use futures::{
+    future::{select, Either, LocalBoxFuture},
+    AsyncRead,
+};
+
+use super::teleop_capnp;
+use crate::attach::AttachError;
+
+/// Keepalive of a client connection, see [`ConnectedStream`](super::ConnectedStream).
+///
+/// It must be polled alongside the RPC system. It resolves once the connection is closed, or
+/// fails with [`AttachError::PeerUnresponsive`] after disconnecting the RPC system if too many
+/// pings are left unanswered.
+#[must_use = "the keepalive does nothing unless polled"]
+pub struct Keepalive(LocalBoxFuture<'static, Result<(), Box<dyn std::error::Error>>>);
+
+impl Keepalive {
+    pub(crate) fn new(
+        teleop: teleop_capnp::teleop::Client,
+        disconnect: impl Future<Output = Result<(), capnp::Error>> + 'static,
+        interval: Duration,
+        max_missed: u32,
+    ) -> Self {
+        Self(Box::pin(async move {
+            let mut missed = 0;
+            loop {
+                Timer::after(interval).await;
+                let ping = teleop.ping_request().send().promise;
+                match select(pin!(ping), Timer::after(interval)).await {
+                    Either::Left((Err(err), _)) if err.kind == capnp::ErrorKind::Disconnected => {
+                        return Ok(());
+                    }
+                    // Any answer, even an error, proves the peer is alive
+                    Either::Left(_) => missed = 0,
+                    Either::Right(_) => {
+                        missed += 1;
+                        if missed >= max_missed {
+                            // The RPC system is torn down no matter what
+                            let _ = disconnect.await;
+                            return Err(AttachError::PeerUnresponsive { missed }.into());
+                        }
+                    }
+                }
+            }
+        }))
+    }
+}
+
+impl Future for Keepalive {
+    type Output = Result<(), Box<dyn std::error::Error>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.0.as_mut().poll(cx)
+    }
+}
+
+/// Reader which records the last time some data was received.
+pub(crate) struct ActivityReader<R> {
+    inner: R,
+    last_activity: Rc<Cell<Instant>>,
+}
+
+impl<R> ActivityReader<R> {
+    pub(crate) fn new(inner: R) -> (Self, Rc<Cell<Instant>>) {
+        let last_activity = Rc::new(Cell::new(Instant::now()));
+        (
+            Self {
+                inner,
+                last_activity: last_activity.clone(),
+            },
+            last_activity,
+        )
+    }
+}
+
+impl<R> AsyncRead for ActivityReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        let read = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(len)) = read {
+            if len > 0 {
+                this.last_activity.set(Instant::now());
+            }
+        }
+        read
+    }
+}
+
+/// Resolves once nothing has been received for the passed timeout.
+pub(crate) async fn watchdog(last_activity: Rc<Cell<Instant>>, timeout: Duration) {
+    loop {
+        let deadline = last_activity.get() + timeout;
+        if Instant::now() >= deadline {
+            return;
+        }
+        Timer::at(deadline).await;
+    }
+}
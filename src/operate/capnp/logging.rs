@@ -0,0 +1,146 @@
+//! Logging service adjusting the log levels of the running process.
+//!
+//! Teleop does not depend on any logging framework: the [`LoggingServer`] calls the passed
+//! functions, which typically update the dynamic filter of `tracing` or `log`. Targets and levels
+//! are plain text, interpreted by those functions.
+
+use logging_capnp::logging::{
+    GetLevelParams, GetLevelResults, Server, SetLevelParams, SetLevelResults,
+};
+
+capnp::generated_code!(pub mod logging_capnp);
+
+type SetLevel = Box<dyn Fn(&str, &str) -> Result<(), Box<dyn std::error::Error>>>;
+type GetLevel = Box<dyn Fn(&str) -> Result<String, Box<dyn std::error::Error>>>;
+
+/// Logging service, see
+/// [`TeleopServer::register_logging_service`](super::TeleopServer::register_logging_service).
+pub struct LoggingServer {
+    set_level: SetLevel,
+    get_level: GetLevel,
+}
+
+impl LoggingServer {
+    /// Creates a service setting and getting levels with the passed functions, which take the
+    /// target and the level.
+    ///
+    /// Their errors, e.g. an unknown level, are returned to the client.
+    pub fn new(
+        set_level: impl Fn(&str, &str) -> Result<(), Box<dyn std::error::Error>> + 'static,
+        get_level: impl Fn(&str) -> Result<String, Box<dyn std::error::Error>> + 'static,
+    ) -> Self {
+        Self {
+            set_level: Box::new(set_level),
+            get_level: Box::new(get_level),
+        }
+    }
+}
+
+impl Server for LoggingServer {
+    async fn set_level(
+        self: capnp::capability::Rc<Self>,
+        params: SetLevelParams,
+        _results: SetLevelResults,
+    ) -> Result<(), capnp::Error> {
+        let params = params.get()?;
+        let target = params.get_target()?.to_str()?;
+        let level = params.get_level()?.to_str()?;
+        (self.set_level)(target, level).map_err(|err| capnp::Error::failed(err.to_string()))
+    }
+
+    async fn get_level(
+        self: capnp::capability::Rc<Self>,
+        params: GetLevelParams,
+        mut results: GetLevelResults,
+    ) -> Result<(), capnp::Error> {
+        let target = params.get()?.get_target()?.to_str()?;
+        let level =
+            (self.get_level)(target).map_err(|err| capnp::Error::failed(err.to_string()))?;
+        results.get().set_level(&level);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+    use super::*;
+    use crate::operate::capnp::{testing::connected_pair, TeleopServer};
+
+    #[test]
+    fn test_capnp_logging() {
+        let levels = Rc::new(RefCell::new(HashMap::from([(
+            "app".to_owned(),
+            "info".to_owned(),
+        )])));
+
+        let mut server = TeleopServer::new();
+        server.register_logging_service(
+            {
+                let levels = levels.clone();
+                move |target, level| {
+                    if !["error", "warn", "info", "debug", "trace"].contains(&level) {
+                        return Err(format!("unknown level {level}").into());
+                    }
+                    levels
+                        .borrow_mut()
+                        .insert(target.to_owned(), level.to_owned());
+                    Ok(())
+                }
+            },
+            {
+                let levels = levels.clone();
+                move |target| {
+                    levels
+                        .borrow()
+                        .get(target)
+                        .cloned()
+                        .ok_or_else(|| format!("unknown target {target}").into())
+                }
+            },
+        );
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            let mut req = teleop.service_request();
+            req.get().set_name("logging");
+            let logging = req.send().promise.await?;
+            let logging: logging_capnp::logging::Client = logging.get()?.get_service().get_as()?;
+
+            let get_level = |target: &str| {
+                let mut req = logging.get_level_request();
+                req.get().set_target(target);
+                req.send().promise
+            };
+
+            let reply = get_level("app").await?;
+            assert_eq!(reply.get()?.get_level()?.to_str()?, "info");
+
+            let mut req = logging.set_level_request();
+            req.get().set_target("app");
+            req.get().set_level("debug");
+            req.send().promise.await?;
+
+            let reply = get_level("app").await?;
+            assert_eq!(reply.get()?.get_level()?.to_str()?, "debug");
+
+            let mut req = logging.set_level_request();
+            req.get().set_target("app");
+            req.get().set_level("verbose");
+            let err = req.send().promise.await.err().unwrap();
+            assert!(err.extra.contains("unknown level verbose"), "{err}");
+
+            let err = get_level("other").await.err().unwrap();
+            assert!(err.extra.contains("unknown target other"), "{err}");
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+        assert_eq!(levels.borrow()["app"], "debug");
+    }
+}
@@ -1,36 +1,146 @@
 //! Cap'n Proto RPC capabilities.
 //!
 //! [`TeleopServer`] is the structure to create the main Teleop server and set it up with
-//! predefined services.
+//! predefined services. [`TeleopServerBuilder`] additionally enables optional features.
 //!
 //! [`run_server_connection`] is called to wire some communication streams with a [`TeleopServer`]
 //! and operate the entire stack.
 //!
+//! [`serve`] does it for every process attaching to the current one, and
+//! [`serve_with_error_handler`] reports the errors of the connections. [`serve_with_handle`] can be
+//! drained gracefully, e.g. for rolling restarts.
+//!
 //! [`client_connection`] is called to wire some communication streams and expose a `Teleop` client
 //! endpoint.
+//!
+//! [`run_server_connection_packed`] and [`client_connection_packed`] do the same using the packed
+//! encoding on the wire. Both sides must agree on the encoding.
+//!
+//! [`run_server_connection_with_tap`] and [`client_connection_with_tap`] report the bytes on the
+//! wire to a [`tap`] sink, to debug the protocol.
+//!
+//! On UNIX, [`run_server_connection_from_fd`] and [`client_connection_from_fd`] skip attachment
+//! entirely and use an already connected socket, e.g. inherited from a supervisor.
+//!
+//! The `_with_options` variants accept [`ConnectionOptions`] to fine tune the connection,
+//! including the [`compression`] of the byte stream and [`keepalive`] pings. Their clients can be
+//! [`disconnect`]ed gracefully.
+//!
+//! [`CapnpProtocol`] implements the generic [`Protocol`] on top of these functions.
+//!
+//! [`verify_teleop`] checks that the peer of a client connection is actually a Teleop server, and
+//! [`check_compatible`] that it speaks the same [`PROTOCOL_VERSION`]. Services evolve
+//! independently, [`check_service_compatible`] checks their [`ServiceVersion`].
+//!
+//! A [`ConnectionPool`](pool::ConnectionPool) reuses client connections across requests.
+//!
+//! Services can be revoked, see [`revocation`], the methods clients may call restricted, see
+//! [`access`], and the calls timed, see [`timing`]. Connections in the [`observer`] role cannot
+//! call the methods classified as mutating.
+//!
+//! [`handoff`] forwards capabilities from one client to another.
+//!
+//! [`events`] lets services push messages to their clients, see the [`clock`] service.
+//!
+//! [`file_transfer`] lets clients pull files out of the process at their own pace.
+//!
+//! [`dynamic`] exposes methods defined at run time, without any schema.
+//!
+//! The opt-in [`logging`] and [`diagnostics`] services adjust the log levels and dump the threads
+//! of the process, through functions it passes.
 
-use std::{collections::BTreeMap, sync::LazyLock};
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    future::Future,
+    pin::pin,
+    rc::Rc,
+    sync::LazyLock,
+    time::{Duration, Instant, SystemTime},
+};
 
+use async_io::Timer;
 use capnp::{
     capability::{Client, FromClientHook, FromServer},
     private::capability::ClientHook,
+    traits::HasTypeId,
 };
+use capnp_futures::serialize_packed::{PackedRead, PackedWrite};
 use capnp_rpc::{rpc_twoparty_capnp, twoparty, RpcSystem};
+#[cfg(any(unix, windows))]
+use futures::task::{LocalSpawn, LocalSpawnExt};
 use futures::{
+    future::{select, Either},
     io::{BufReader, BufWriter},
-    AsyncRead, AsyncWrite,
+    AsyncRead, AsyncWrite, FutureExt,
+};
+
+use self::{
+    access::{AccessControl, AccessPolicy, MethodCall},
+    catch_unwind::CatchUnwind,
+    compression::{CompressedStream, Compression},
+    disconnect::{GracefulDisconnector, SharedInput, SharedOutput, SharedStream},
+    forwarding::ForwardingClientHook,
+    handshake::{handshake, Handshake},
+    keepalive::{watchdog, ActivityReader, Keepalive},
+    observer::{ObserverRole, Role},
+    registry::{ActiveConnection, CloseReason, ConnectionRegistry, PeerCredentials, Registration},
+    revocation::RevocationHandle,
+    service_limit::ServiceLimit,
+    tap::{Tap, TapSink},
+    termination::RecordingNetwork,
+    timing::CallTimings,
+};
+use super::Protocol;
+#[cfg(any(unix, windows))]
+use crate::{attach::attacher::Attacher, cancellation::CancellationToken};
+use crate::{
+    attach::{AttachError, ListenHandle},
+    internal::process_start_time,
 };
 
+pub mod access;
+mod catch_unwind;
+pub mod clock;
+pub mod compression;
+pub mod diagnostics;
+pub mod disconnect;
+pub mod dynamic;
 pub mod echo;
+pub mod events;
+pub mod factory;
+pub mod file_transfer;
+mod forwarding;
+pub mod handoff;
+mod handshake;
+pub mod keepalive;
+pub mod logging;
+pub mod observer;
+pub mod pool;
+pub mod registry;
+pub mod revocation;
+mod service_limit;
+pub mod tap;
+mod termination;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod timing;
+#[cfg(feature = "tower")]
+pub mod tower;
 
 capnp::generated_code!(pub mod teleop_capnp);
 
+/// Version of the Teleop protocol, bumped on breaking changes of the schema.
+pub const PROTOCOL_VERSION: u32 = 1;
+
 /// Main structure to start teleoperations with Cap'n Proto RPC.
+///
+/// Clients can introspect registered services to learn the Cap'n Proto type ID of their interface
+/// and the Rust path of its generated client.
 #[derive(Default)]
 pub struct TeleopServer {
-    #[allow(clippy::type_complexity)]
-    services:
-        BTreeMap<String, LazyLock<Box<dyn ClientHook>, Box<dyn FnOnce() -> Box<dyn ClientHook>>>>,
+    services: BTreeMap<String, Service>,
+    remote_shutdown: Option<ListenHandle>,
 }
 
 impl TeleopServer {
@@ -39,195 +149,3156 @@ impl TeleopServer {
         Self::default()
     }
 
+    /// Creates a builder to set up a server with optional features.
+    pub fn builder() -> TeleopServerBuilder {
+        TeleopServerBuilder::default()
+    }
+
     /// Registers a new service, lazily initialized via the passed callback.
     ///
     /// The service is not initialized until it is requested by a client.
     pub fn register_service<Client, Server, F>(&mut self, name: impl Into<String>, f: F)
     where
-        Client: FromClientHook + FromServer<Server>,
+        Client: FromClientHook + FromServer<Server> + HasTypeId,
+        F: FnOnce() -> Server + 'static,
+    {
+        self.register_service_with::<Client, Server, F>(name, ServiceOptions::default(), f);
+    }
+
+    /// Same as [`register_service`](`Self::register_service`) but the service is set up with the
+    /// passed options, e.g. to limit its rate or control access to its methods.
+    pub fn register_service_with<Client, Server, F>(
+        &mut self,
+        name: impl Into<String>,
+        options: ServiceOptions,
+        f: F,
+    ) where
+        Client: FromClientHook + FromServer<Server> + HasTypeId,
         F: FnOnce() -> Server + 'static,
     {
+        let name = name.into();
+        let service_name = Rc::<str>::from(name.as_str());
+        let ServiceOptions {
+            rate_limit,
+            revocation,
+            policy,
+            timings,
+            is_mutating,
+            version,
+        } = options;
         self.services.insert(
-            name.into(),
-            LazyLock::new(Box::new(|| {
-                let client: Client = capnp_rpc::new_client(f());
-                Box::<dyn ClientHook>::new(client.into_client_hook())
-            })),
+            name,
+            Service {
+                client: LazyLock::new(Box::new(|| {
+                    let client: Client = capnp_rpc::new_client(f());
+                    let mut hook: Box<dyn ClientHook> = Box::new(ForwardingClientHook::new(
+                        client.into_client_hook(),
+                        CatchUnwind,
+                    ));
+                    if let Some(timings) = timings {
+                        hook = Box::new(ForwardingClientHook::new(hook, timings));
+                    }
+                    if let Some(policy) = policy {
+                        let access = AccessControl {
+                            service: service_name,
+                            policy,
+                        };
+                        hook = Box::new(ForwardingClientHook::new(hook, access));
+                    }
+                    match revocation {
+                        Some(handle) => Box::new(ForwardingClientHook::new(hook, handle)),
+                        None => hook,
+                    }
+                })),
+                rate_limiter: rate_limit.map(RateLimiter::new),
+                is_mutating,
+                version,
+                type_id: Client::TYPE_ID,
+                type_name: std::any::type_name::<Client>(),
+            },
         );
     }
-}
 
-impl teleop_capnp::teleop::Server for TeleopServer {
-    async fn service(
-        self: capnp::capability::Rc<Self>,
-        params: teleop_capnp::teleop::ServiceParams,
-        mut results: teleop_capnp::teleop::ServiceResults,
-    ) -> Result<(), capnp::Error> {
-        let name = params.get()?.get_name()?.to_str()?;
-        let service = self.services.get(name);
-        if let Some(service) = service {
-            results
-                .get()
-                .init_service()
-                .set_as_capability((*service).clone());
-            Ok(())
-        } else {
-            Err(capnp::Error::failed(format!("service {name} not found")))
-        }
+    /// Same as [`register_service`](`Self::register_service`) but the service can be revoked.
+    ///
+    /// Once the returned handle is revoked, calls made through the capabilities already handed
+    /// out fail with a disconnected error, see [`revocation`].
+    pub fn register_revocable_service<Client, Server, F>(
+        &mut self,
+        name: impl Into<String>,
+        f: F,
+    ) -> RevocationHandle
+    where
+        Client: FromClientHook + FromServer<Server> + HasTypeId,
+        F: FnOnce() -> Server + 'static,
+    {
+        let handle = RevocationHandle::default();
+        let options = ServiceOptions::new().revocable(handle.clone());
+        self.register_service_with::<Client, Server, F>(name, options, f);
+        handle
     }
-}
 
-/// Runs a new RPC server connection.
-///
-/// The communication goes through the passed input and output.
-///
-/// The Cap'n Proto main service is passed as an abstract `capnp` client.
-pub async fn run_server_connection<R, W>(
-    input: R,
-    output: W,
-    client: Box<dyn ClientHook>,
-) -> Result<(), capnp::Error>
-where
-    R: AsyncRead + Unpin + 'static,
-    W: AsyncWrite + Unpin + 'static,
-{
-    let network = twoparty::VatNetwork::new(
-        BufReader::new(input),
-        BufWriter::new(output),
-        rpc_twoparty_capnp::Side::Server,
-        Default::default(),
-    );
-    let rpc_system = RpcSystem::new(Box::new(network), Some(Client { hook: client }));
+    /// Same as [`register_service`](`Self::register_service`) but every call is timed.
+    ///
+    /// The returned handle reports the number of calls and the time spent in them, see
+    /// [`timing`].
+    pub fn register_service_timed<Client, Server, F>(
+        &mut self,
+        name: impl Into<String>,
+        f: F,
+    ) -> CallTimings
+    where
+        Client: FromClientHook + FromServer<Server> + HasTypeId,
+        F: FnOnce() -> Server + 'static,
+    {
+        let timings = CallTimings::default();
+        let options = ServiceOptions::new().timed(timings.clone());
+        self.register_service_with::<Client, Server, F>(name, options, f);
+        timings
+    }
 
-    rpc_system.await
-}
+    /// Registers a nested server, so that clients can navigate a tree of services.
+    ///
+    /// Requesting the passed name returns the `Teleop` capability of the nested server, and a
+    /// dotted name such as `name.service` directly returns the service registered in the nested
+    /// server, provided the dotted name is not registered as such.
+    pub fn register_nested_server(&mut self, name: impl Into<String>, server: TeleopServer) {
+        self.register_service::<teleop_capnp::teleop::Client, _, _>(name, || server);
+    }
 
-/// Creates a RPC client connection.
-///
-/// The communication goes through the passed input and output.
-///
-/// The returned value is made of a system to be run by the async runtime and the client interface
-/// to initiate RPC requests.
-pub async fn client_connection<R, W>(
-    input: R,
-    output: W,
-) -> (
-    RpcSystem<rpc_twoparty_capnp::Side>,
-    teleop_capnp::teleop::Client,
-)
-where
-    R: AsyncRead + Unpin + 'static,
-    W: AsyncWrite + Unpin + 'static,
-{
-    let network = twoparty::VatNetwork::new(
-        BufReader::new(input),
-        BufWriter::new(output),
-        rpc_twoparty_capnp::Side::Client,
-        Default::default(),
-    );
-    let mut rpc_system = RpcSystem::new(Box::new(network), None);
-    let teleop: teleop_capnp::teleop::Client =
-        rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
-    (rpc_system, teleop)
+    /// Registers the opt-in `diagnostics` service, which lets clients dump the threads of the
+    /// process with the passed function, see [`diagnostics`].
+    pub fn register_diagnostics_service(
+        &mut self,
+        thread_dump: impl Fn() -> Result<String, Box<dyn std::error::Error>> + 'static,
+    ) {
+        self.register_service::<diagnostics::diagnostics_capnp::diagnostics::Client, _, _>(
+            "diagnostics",
+            || diagnostics::DiagnosticsServer::new(thread_dump),
+        );
+    }
+
+    /// Registers the opt-in `logging` service, which lets clients adjust the log levels of the
+    /// process through the passed functions, see [`logging`].
+    pub fn register_logging_service(
+        &mut self,
+        set_level: impl Fn(&str, &str) -> Result<(), Box<dyn std::error::Error>> + 'static,
+        get_level: impl Fn(&str) -> Result<String, Box<dyn std::error::Error>> + 'static,
+    ) {
+        self.register_service::<logging::logging_capnp::logging::Client, _, _>("logging", || {
+            logging::LoggingServer::new(set_level, get_level)
+        });
+    }
+
+    /// Registers a [`tower::Service`](::tower::Service) taking and returning raw bytes.
+    ///
+    /// The service is exposed with the `TowerService` interface, see [`tower`](self::tower).
+    #[cfg(feature = "tower")]
+    pub fn register_tower_service<S>(&mut self, name: impl Into<String>, service: S)
+    where
+        S: ::tower::Service<bytes::Bytes, Response = bytes::Bytes> + 'static,
+        S::Error: Into<::tower::BoxError>,
+    {
+        self.register_service::<tower::tower_capnp::tower_service::Client, _, _>(name, || {
+            tower::TowerServer::new(service)
+        });
+    }
+
+    /// Turns the server into its shared form, once all services are registered.
+    ///
+    /// The server is immutable from then on. The returned client is cheap to clone: each
+    /// connection gets its own clone, and service lookups of all connections only borrow the
+    /// same server.
+    pub fn into_client(self) -> teleop_capnp::teleop::Client {
+        capnp_rpc::new_client(self)
+    }
+
+    /// Unregisters all services.
+    ///
+    /// Services which have never been requested are dropped without being initialized.
+    pub fn clear(&mut self) {
+        self.services.clear();
+    }
+
+    /// Returns the number of registered services.
+    pub fn len(&self) -> usize {
+        self.services.len()
+    }
+
+    /// Returns `true` if no service is registered.
+    pub fn is_empty(&self) -> bool {
+        self.services.is_empty()
+    }
 }
 
-#[cfg(test)]
-#[cfg_attr(coverage_nightly, coverage(off))]
-mod tests {
+struct Service {
+    #[allow(clippy::type_complexity)]
+    client: LazyLock<Box<dyn ClientHook>, Box<dyn FnOnce() -> Box<dyn ClientHook>>>,
+    rate_limiter: Option<RateLimiter>,
+    #[allow(clippy::type_complexity)]
+    is_mutating: Option<Rc<dyn Fn(&MethodCall<'_>) -> bool>>,
+    version: ServiceVersion,
+    type_id: u64,
+    type_name: &'static str,
+}
 
-    use futures::task::LocalSpawnExt;
+/// Options of a service, see [`TeleopServer::register_service_with`].
+///
+/// The default options are the ones of [`TeleopServer::register_service`].
+#[derive(Clone, Default)]
+pub struct ServiceOptions {
+    rate_limit: Option<RateLimit>,
+    revocation: Option<RevocationHandle>,
+    policy: Option<Rc<dyn AccessPolicy>>,
+    timings: Option<CallTimings>,
+    #[allow(clippy::type_complexity)]
+    is_mutating: Option<Rc<dyn Fn(&MethodCall<'_>) -> bool>>,
+    version: ServiceVersion,
+}
 
-    use super::{
-        echo::{echo_capnp, EchoServer},
-        *,
-    };
+impl ServiceOptions {
+    /// Creates the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    #[test]
-    fn test_capnp_teleop() {
-        let (client_input, server_output) = sluice::pipe::pipe();
-        let (server_input, client_output) = sluice::pipe::pipe();
+    /// Limits the number of times the service can be requested.
+    ///
+    /// Requests exceeding the limit fail with an overloaded error.
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
 
-        let server = || -> Result<(), Box<dyn std::error::Error>> {
-            let mut server = TeleopServer::new();
-            server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
-            let client = capnp_rpc::new_client::<teleop_capnp::teleop::Client, _>(server);
+    /// Makes the service revocable through the passed handle.
+    ///
+    /// Once the handle is revoked, calls made through the capabilities already handed out fail
+    /// with a disconnected error, see [`revocation`].
+    pub fn revocable(mut self, handle: RevocationHandle) -> Self {
+        self.revocation = Some(handle);
+        self
+    }
 
-            let mut exec = futures::executor::LocalPool::new();
+    /// Checks every call against the passed policy.
+    ///
+    /// Denied calls fail without reaching the service, see [`access`].
+    pub fn policy(mut self, policy: impl AccessPolicy + 'static) -> Self {
+        self.policy = Some(Rc::new(policy));
+        self
+    }
 
-            let res = exec.run_until(run_server_connection(
-                server_input,
-                server_output,
-                client.client.hook,
-            ));
+    /// Times every call.
+    ///
+    /// The passed handle reports the number of calls and the time spent in them, see
+    /// [`timing`].
+    pub fn timed(mut self, timings: CallTimings) -> Self {
+        self.timings = Some(timings);
+        self
+    }
 
-            exec.run();
+    /// Classifies the methods as mutating or not.
+    ///
+    /// Connections in the observer role cannot call the mutating methods, see [`observer`].
+    pub fn mutability(mut self, is_mutating: impl Fn(&MethodCall<'_>) -> bool + 'static) -> Self {
+        self.is_mutating = Some(Rc::new(is_mutating));
+        self
+    }
 
-            res?;
+    /// Versions the service, so that clients can check it with [`check_service_compatible`]
+    /// before using it.
+    pub fn version(mut self, version: ServiceVersion) -> Self {
+        self.version = version;
+        self
+    }
+}
 
-            Ok(())
-        };
+/// Version of a service, which evolves independently of the Teleop protocol.
+///
+/// A service is compatible with the clients expecting any version from
+/// [`min_compatible`](Self::min_compatible) up to [`version`](Self::version), e.g. a client
+/// relying on a method added in version 2 cannot use a service in version 1.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ServiceVersion {
+    /// Version implemented by the service.
+    pub version: u32,
+    /// Oldest version the service is still compatible with.
+    pub min_compatible: u32,
+}
 
-        let client = || -> Result<(), Box<dyn std::error::Error>> {
-            let mut exec = futures::executor::LocalPool::new();
-            let spawn = exec.spawner();
+impl ServiceVersion {
+    /// Returns `true` if a client expecting the passed version can use the service.
+    pub fn is_compatible_with(&self, client: u32) -> bool {
+        (self.min_compatible..=self.version).contains(&client)
+    }
+}
 
-            let res = exec.run_until(async move {
-                let (rpc_system, teleop) = client_connection(client_input, client_output).await;
-                let rpc_disconnect = rpc_system.get_disconnector();
+/// Limit of the number of times a service can be requested.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    /// Maximum number of requests per second.
+    pub max_per_sec: u32,
+}
 
-                spawn.spawn_local(async {
-                    if let Err(e) = rpc_system.await {
-                        eprintln!("Connection interrupted {e}");
-                    }
-                })?;
+/// Counts requests in one second windows.
+struct RateLimiter {
+    limit: RateLimit,
+    window: Cell<(Instant, u32)>,
+}
 
-                let res = async {
-                    let mut req = teleop.service_request();
-                    req.get().set_name("echo");
-                    let echo = req.send().promise.await?;
-                    let echo = echo.get()?.get_service();
-                    let echo: echo_capnp::echo::Client = echo.get_as()?;
+impl RateLimiter {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            window: Cell::new((Instant::now(), 0)),
+        }
+    }
 
-                    println!("got echo service");
+    /// Counts a new request, returns `false` if the limit is exceeded.
+    fn acquire(&self) -> bool {
+        let now = Instant::now();
+        let (mut start, mut count) = self.window.get();
+        if now.duration_since(start) >= Duration::from_secs(1) {
+            start = now;
+            count = 0;
+        }
+        let acquired = count < self.limit.max_per_sec;
+        if acquired {
+            count += 1;
+        }
+        self.window.set((start, count));
+        acquired
+    }
+}
 
-                    let mut req = echo.echo_request();
-                    req.get().set_message("hello!");
-                    let reply = req.send().promise.await?;
-                    let reply = reply.get()?.get_reply()?.to_str()?;
+/// Maximum edit distance for a registered service name to be suggested.
+const MAX_SUGGESTION_DISTANCE: usize = 2;
 
-                    println!("{}", reply);
+/// Error returned when a client requests a service which is not registered.
+///
+/// It suggests the closest registered name, if close enough, to help with typos.
+#[derive(Debug)]
+struct ServiceNotFound {
+    name: String,
+    suggestion: Option<String>,
+}
 
-                    let mut req = teleop.service_request();
-                    req.get().set_name("tango");
-                    let tango_res = req.send().promise.await;
-                    assert!(tango_res.is_err());
-                    let tango_err = tango_res.err().unwrap();
-                    assert_eq!(tango_err.kind, capnp::ErrorKind::Failed);
-                    assert!(tango_err.extra.contains("service tango not found"));
+impl ServiceNotFound {
+    fn new<'a>(name: &str, registered: impl IntoIterator<Item = &'a String>) -> Self {
+        let suggestion = registered
+            .into_iter()
+            .map(|candidate| (levenshtein(name, candidate), candidate))
+            // Replacing the whole name is not a typo
+            .filter(|(distance, _)| {
+                *distance <= MAX_SUGGESTION_DISTANCE && *distance < name.chars().count()
+            })
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, candidate)| candidate.clone());
+        Self {
+            name: name.to_owned(),
+            suggestion,
+        }
+    }
+}
 
-                    Ok::<_, Box<dyn std::error::Error>>(())
-                }
-                .await;
+impl std::fmt::Display for ServiceNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "service {} not found", self.name)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, ", did you mean {suggestion}?")?;
+        }
+        Ok(())
+    }
+}
 
-                let res2 = rpc_disconnect.await;
+impl std::error::Error for ServiceNotFound {}
 
-                res?;
+impl From<ServiceNotFound> for capnp::Error {
+    fn from(err: ServiceNotFound) -> Self {
+        capnp::Error::failed(err.to_string())
+    }
+}
 
-                res2?;
+/// Returns the number of single character edits to turn one string into the other.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
 
-                Ok::<_, Box<dyn std::error::Error>>(())
+impl teleop_capnp::teleop::Server for TeleopServer {
+    async fn service(
+        self: capnp::capability::Rc<Self>,
+        params: teleop_capnp::teleop::ServiceParams,
+        mut results: teleop_capnp::teleop::ServiceResults,
+    ) -> Result<(), capnp::Error> {
+        let params = params.get()?;
+        let name = params.get_name()?.to_str()?;
+        let observer = params.get_observer();
+        // A dotted name not registered as such is resolved by the nested server registered under
+        // its first segment
+        let found = self
+            .services
+            .get(name)
+            .map(|service| (service, None))
+            .or_else(|| {
+                let (prefix, rest) = name.split_once('.')?;
+                let service = self.services.get(prefix)?;
+                (service.type_id == teleop_capnp::teleop::Client::TYPE_ID)
+                    .then_some((service, Some(rest)))
             });
+        let Some((service, rest)) = found else {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                service = name,
+                available = ?self.services.keys().collect::<Vec<_>>(),
+                "Client requested a service which is not registered"
+            );
+            return Err(ServiceNotFound::new(name, self.services.keys()).into());
+        };
+        if let Some(rate_limiter) = &service.rate_limiter {
+            if !rate_limiter.acquire() {
+                return Err(capnp::Error::overloaded("rate limit exceeded".to_owned()));
+            }
+        }
+        let mut hook = (*service.client).clone();
+        if let Some(rest) = rest {
+            let nested = teleop_capnp::teleop::Client::new(hook);
+            let mut req = nested.service_request();
+            req.get().set_name(rest);
+            req.get().set_observer(observer);
+            let reply = req.send().promise.await?;
+            hook = reply
+                .get()?
+                .get_service()
+                .get_as_capability::<Client>()?
+                .hook;
+        } else if observer {
+            if let Some(is_mutating) = &service.is_mutating {
+                let is_mutating = is_mutating.clone();
+                let access = AccessControl {
+                    service: Rc::from(name),
+                    policy: Rc::new(move |call: &MethodCall<'_>| !is_mutating(call)),
+                };
+                hook = Box::new(ForwardingClientHook::new(hook, access));
+            }
+            if service.type_id == teleop_capnp::teleop::Client::TYPE_ID {
+                // The services of a nested server are requested as an observer as well
+                hook = Box::new(ForwardingClientHook::new(hook, ObserverRole));
+            }
+        }
+        results.get().init_service().set_as_capability(hook);
+        Ok(())
+    }
 
-            exec.run();
-
-            res?;
+    async fn list_services(
+        self: capnp::capability::Rc<Self>,
+        _params: teleop_capnp::teleop::ListServicesParams,
+        mut results: teleop_capnp::teleop::ListServicesResults,
+    ) -> Result<(), capnp::Error> {
+        let mut names = results.get().init_names(self.services.len() as u32);
+        for (i, name) in self.services.keys().enumerate() {
+            names.set(i as u32, name.as_str());
+        }
+        Ok(())
+    }
 
-            Ok(())
+    async fn introspect(
+        self: capnp::capability::Rc<Self>,
+        params: teleop_capnp::teleop::IntrospectParams,
+        mut results: teleop_capnp::teleop::IntrospectResults,
+    ) -> Result<(), capnp::Error> {
+        let name = params.get()?.get_name()?.to_str()?;
+        let Some(service) = self.services.get(name) else {
+            return Err(ServiceNotFound::new(name, self.services.keys()).into());
         };
+        let mut results = results.get();
+        results.set_type_id(service.type_id);
+        results.set_type_name(service.type_name);
+        results.set_version(service.version.version);
+        results.set_min_compatible_version(service.version.min_compatible);
+        Ok(())
+    }
 
-        let s = std::thread::spawn(|| server().unwrap());
-        let c = std::thread::spawn(|| client().unwrap());
-        c.join().unwrap();
-        s.join().unwrap();
+    async fn version(
+        self: capnp::capability::Rc<Self>,
+        _params: teleop_capnp::teleop::VersionParams,
+        mut results: teleop_capnp::teleop::VersionResults,
+    ) -> Result<(), capnp::Error> {
+        results.get().set_version(PROTOCOL_VERSION);
+        Ok(())
+    }
+
+    async fn process_info(
+        self: capnp::capability::Rc<Self>,
+        _params: teleop_capnp::teleop::ProcessInfoParams,
+        mut results: teleop_capnp::teleop::ProcessInfoResults,
+    ) -> Result<(), capnp::Error> {
+        let mut info = results.get().init_info();
+        info.set_pid(std::process::id());
+        if let Ok(executable) = std::env::current_exe() {
+            info.set_executable(executable.to_string_lossy().as_ref());
+        }
+        info.set_start_time(process_start_time().unwrap_or_default());
+        info.set_teleop_version(env!("CARGO_PKG_VERSION"));
+        Ok(())
+    }
+
+    async fn ping(
+        self: capnp::capability::Rc<Self>,
+        _params: teleop_capnp::teleop::PingParams,
+        _results: teleop_capnp::teleop::PingResults,
+    ) -> Result<(), capnp::Error> {
+        Ok(())
+    }
+
+    async fn shutdown(
+        self: capnp::capability::Rc<Self>,
+        _params: teleop_capnp::teleop::ShutdownParams,
+        _results: teleop_capnp::teleop::ShutdownResults,
+    ) -> Result<(), capnp::Error> {
+        if let Some(handle) = &self.remote_shutdown {
+            handle.shutdown();
+            Ok(())
+        } else {
+            Err(capnp::Error::failed(
+                "remote shutdown is not allowed".to_owned(),
+            ))
+        }
+    }
+}
+
+/// Builder of [`TeleopServer`].
+#[derive(Default)]
+pub struct TeleopServerBuilder {
+    server: TeleopServer,
+}
+
+impl TeleopServerBuilder {
+    /// Registers a new service, see [`TeleopServer::register_service`].
+    pub fn register_service<Client, Server, F>(mut self, name: impl Into<String>, f: F) -> Self
+    where
+        Client: FromClientHook + FromServer<Server> + HasTypeId,
+        F: FnOnce() -> Server + 'static,
+    {
+        self.server.register_service::<Client, Server, F>(name, f);
+        self
+    }
+
+    /// Registers a new service set up with the passed options, see
+    /// [`TeleopServer::register_service_with`].
+    pub fn register_service_with<Client, Server, F>(
+        mut self,
+        name: impl Into<String>,
+        options: ServiceOptions,
+        f: F,
+    ) -> Self
+    where
+        Client: FromClientHook + FromServer<Server> + HasTypeId,
+        F: FnOnce() -> Server + 'static,
+    {
+        self.server
+            .register_service_with::<Client, Server, F>(name, options, f);
+        self
+    }
+
+    /// Registers a nested server, see [`TeleopServer::register_nested_server`].
+    pub fn register_nested_server(mut self, name: impl Into<String>, server: TeleopServer) -> Self {
+        self.server.register_nested_server(name, server);
+        self
+    }
+
+    /// Registers the diagnostics service, see [`TeleopServer::register_diagnostics_service`].
+    pub fn register_diagnostics_service(
+        mut self,
+        thread_dump: impl Fn() -> Result<String, Box<dyn std::error::Error>> + 'static,
+    ) -> Self {
+        self.server.register_diagnostics_service(thread_dump);
+        self
+    }
+
+    /// Registers the logging service, see [`TeleopServer::register_logging_service`].
+    pub fn register_logging_service(
+        mut self,
+        set_level: impl Fn(&str, &str) -> Result<(), Box<dyn std::error::Error>> + 'static,
+        get_level: impl Fn(&str) -> Result<String, Box<dyn std::error::Error>> + 'static,
+    ) -> Self {
+        self.server.register_logging_service(set_level, get_level);
+        self
+    }
+
+    /// Registers a new tower service, see [`TeleopServer::register_tower_service`].
+    #[cfg(feature = "tower")]
+    pub fn register_tower_service<S>(mut self, name: impl Into<String>, service: S) -> Self
+    where
+        S: ::tower::Service<bytes::Bytes, Response = bytes::Bytes> + 'static,
+        S::Error: Into<::tower::BoxError>,
+    {
+        self.server.register_tower_service(name, service);
+        self
+    }
+
+    /// Allows clients to stop the listener behind the passed handle by calling `shutdown`.
+    ///
+    /// This is dangerous since any client can then prevent further teleoperations of the process.
+    /// Without it, `shutdown` fails.
+    pub fn allow_remote_shutdown(mut self, handle: &ListenHandle) -> Self {
+        self.server.remote_shutdown = Some(handle.clone());
+        self
+    }
+
+    /// Builds the server.
+    pub fn build(self) -> TeleopServer {
+        self.server
+    }
+}
+
+/// Options of RPC connections.
+///
+/// The default options are used by [`run_server_connection`] and [`client_connection`].
+#[derive(Clone, Debug)]
+pub struct ConnectionOptions {
+    /// Capacity of the buffer wrapping the input.
+    pub read_buffer_capacity: usize,
+    /// Capacity of the buffer wrapping the output.
+    pub write_buffer_capacity: usize,
+    /// Whether messages are packed on the wire. Both sides must agree on the encoding.
+    pub packed: bool,
+    /// Compression of the byte stream, negotiated with the peer. Requesting it implies the
+    /// handshake, so the peer must exchange it as well, see [`handshake`](Self::handshake).
+    pub compression: Compression,
+    /// Whether a handshake is exchanged with the peer before the RPC messages. It assigns an ID to
+    /// the connection. Both sides must agree on it.
+    pub handshake: bool,
+    /// Interval of the keepalive pings sent by the client. The server tears the connection down if
+    /// it receives nothing for that long, [`keepalive_max_missed`](Self::keepalive_max_missed)
+    /// times in a row.
+    pub keepalive: Option<Duration>,
+    /// Number of consecutive keepalive pings which may be left unanswered.
+    pub keepalive_max_missed: u32,
+    /// Maximum duration of the server connection, regardless of its activity. Once elapsed, the
+    /// connection is closed, even in the middle of a call.
+    pub max_lifetime: Option<Duration>,
+    /// Registry tracking the server connection while it runs.
+    pub registry: Option<ConnectionRegistry>,
+    /// Credentials of the peer of the server connection, reported by the registry.
+    pub peer: Option<PeerCredentials>,
+    /// Maximum number of distinct services the client of the server connection can request.
+    /// Further requests of other services fail with an overloaded error.
+    ///
+    /// It is a connection option rather than a server setting because the server is shared by all
+    /// connections, see [`TeleopServer::into_client`].
+    pub max_services_per_connection: Option<usize>,
+    /// Role of the connection, negotiated with the peer: it is in the observer role if either
+    /// side asks for it, see [`observer`]. Requesting the observer role implies the handshake.
+    pub role: Role,
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        Self {
+            read_buffer_capacity: DEFAULT_BUFFER_CAPACITY,
+            write_buffer_capacity: DEFAULT_BUFFER_CAPACITY,
+            packed: false,
+            compression: Compression::None,
+            handshake: false,
+            keepalive: None,
+            keepalive_max_missed: 3,
+            max_lifetime: None,
+            registry: None,
+            peer: None,
+            max_services_per_connection: None,
+            role: Role::Full,
+        }
+    }
+}
+
+impl ConnectionOptions {
+    fn needs_handshake(&self) -> bool {
+        self.handshake || self.compression != Compression::None || self.role != Role::Full
+    }
+
+    fn register(&self, connection_id: Option<u64>) -> Option<Registration> {
+        self.registry.as_ref().map(|registry| {
+            registry.register(ActiveConnection {
+                connection_id,
+                peer: self.peer,
+                connected_at: SystemTime::now(),
+            })
+        })
+    }
+}
+
+// Same as `futures::io::BufReader::new` and `futures::io::BufWriter::new`
+const DEFAULT_BUFFER_CAPACITY: usize = 8 * 1024;
+
+/// Listens for attachment and serves every incoming connection with the passed server until the
+/// token is cancelled.
+///
+/// Each connection is run with [`run_server_connection`] in a task spawned on the passed executor,
+/// so that any executor implementing [`LocalSpawn`] can be used. Connections which are still
+/// running when the token is cancelled are not interrupted.
+///
+/// Thread pools, which only implement [`Spawn`](futures::task::Spawn), cannot run the connections
+/// since Cap'n Proto RPC systems are not `Send`. Run a `LocalPool` on a dedicated thread instead.
+///
+/// This is the whole server side of Teleop:
+///
+/// ```no_run
+/// use teleop::{
+///     attach::attacher::DefaultAttacher,
+///     cancellation::CancellationToken,
+///     operate::capnp::{
+///         echo::{echo_capnp, EchoServer},
+///         serve, TeleopServer,
+///     },
+/// };
+///
+/// let mut server = TeleopServer::new();
+/// server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+///
+/// let mut exec = futures::executor::LocalPool::new();
+/// let spawner = exec.spawner();
+/// exec.run_until(serve::<DefaultAttacher, _>(server, CancellationToken::new(), &spawner))
+///     .unwrap();
+/// ```
+#[cfg(any(unix, windows))]
+pub async fn serve<A, S>(
+    server: TeleopServer,
+    token: CancellationToken,
+    spawner: &S,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    A: Attacher,
+    S: LocalSpawn,
+{
+    serve_with_error_handler::<A, S>(server, token, spawner, |_err| {
+        #[cfg(feature = "tracing")]
+        tracing::warn!("Server connection interrupted: {_err}");
+    })
+    .await
+}
+
+/// Same as [`serve`] but the errors terminating connections are passed to the handler.
+///
+/// Connections closed by the client are not reported, see [`run_server_connection`].
+#[cfg(any(unix, windows))]
+pub async fn serve_with_error_handler<A, S>(
+    server: TeleopServer,
+    token: CancellationToken,
+    spawner: &S,
+    on_error: impl Fn(capnp::Error) + 'static,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    A: Attacher,
+    S: LocalSpawn,
+{
+    // Nobody waits for the connections to finish
+    let (tracker, _connections) = futures::channel::mpsc::channel(0);
+    serve_tracked::<A, S>(server, token, spawner, tracker, on_error).await
+}
+
+#[cfg(any(unix, windows))]
+async fn serve_tracked<A, S>(
+    server: TeleopServer,
+    token: CancellationToken,
+    spawner: &S,
+    tracker: futures::channel::mpsc::Sender<std::convert::Infallible>,
+    on_error: impl Fn(capnp::Error) + 'static,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    A: Attacher,
+    S: LocalSpawn,
+{
+    use futures::{AsyncReadExt, StreamExt};
+
+    let on_error = Rc::new(on_error);
+
+    let (_handle, connections) = crate::attach::listen::<A>();
+    let mut connections = pin!(connections.take_until(token.cancelled()));
+    let client = server.into_client();
+    while let Some(connection) = connections.next().await {
+        let (stream, _addr) = connection?;
+        let (input, output) = stream.split();
+        let client = client.client.hook.clone();
+        let on_error = on_error.clone();
+        let tracker = tracker.clone();
+        spawner.spawn_local(async move {
+            // Dropped when the connection terminates
+            let _tracker = tracker;
+            if let Err(err) = run_server_connection(input, output, client).await {
+                on_error(err);
+            }
+        })?;
+    }
+    Ok(())
+}
+
+/// Same as [`serve`] but returns a [`ServeHandle`] alongside the future serving the connections,
+/// which stops it gracefully with [`ServeHandle::drain`].
+#[cfg(any(unix, windows))]
+pub fn serve_with_handle<A, S>(
+    server: TeleopServer,
+    spawner: &S,
+) -> (
+    ServeHandle,
+    impl Future<Output = Result<(), Box<dyn std::error::Error>>> + use<'_, A, S>,
+)
+where
+    A: Attacher,
+    S: LocalSpawn,
+{
+    let token = CancellationToken::new();
+    let (tracker, connections) = futures::channel::mpsc::channel(0);
+    let handle = ServeHandle {
+        token: token.clone(),
+        connections,
+    };
+    let serving = serve_tracked::<A, S>(server, token, spawner, tracker, |_err| {
+        #[cfg(feature = "tracing")]
+        tracing::warn!("Server connection interrupted: {_err}");
+    });
+    (handle, serving)
+}
+
+/// Handle returned by [`serve_with_handle`] alongside the future serving the connections.
+#[cfg(any(unix, windows))]
+pub struct ServeHandle {
+    token: CancellationToken,
+    // Terminates once all the senders, held by the serve loop and the connections, are dropped
+    connections: futures::channel::mpsc::Receiver<std::convert::Infallible>,
+}
+
+#[cfg(any(unix, windows))]
+impl ServeHandle {
+    /// Stops accepting connections immediately, then waits for the running connections to finish
+    /// within the grace period, e.g. during a rolling restart.
+    ///
+    /// Returns `true` if all of them finished in time. Those which did not are left running.
+    pub async fn drain(mut self, grace: Duration) -> bool {
+        use futures::StreamExt;
+
+        self.token.cancel();
+        let finished = self.connections.next();
+        matches!(select(finished, Timer::after(grace)).await, Either::Left(_))
+    }
+}
+
+/// Runs a new RPC server connection.
+///
+/// The communication goes through the passed input and output.
+///
+/// It resolves successfully when the client disconnects, and fails with the error which
+/// terminated the connection otherwise, e.g. a malformed message.
+///
+/// The Cap'n Proto main service is passed as an abstract `capnp` client.
+pub async fn run_server_connection<R, W>(
+    input: R,
+    output: W,
+    client: Box<dyn ClientHook>,
+) -> Result<(), capnp::Error>
+where
+    R: AsyncRead + Unpin + 'static,
+    W: AsyncWrite + Unpin + 'static,
+{
+    run_server_connection_with_options(input, output, client, ConnectionOptions::default()).await
+}
+
+/// Runs a new RPC server connection over an already connected stream socket, e.g. inherited from
+/// a supervisor, without attaching to any process.
+///
+/// See [`run_server_connection`].
+#[cfg(unix)]
+pub async fn run_server_connection_from_fd(
+    fd: std::os::fd::OwnedFd,
+    client: Box<dyn ClientHook>,
+) -> Result<(), capnp::Error> {
+    use futures::AsyncReadExt;
+
+    let (input, output) = stream_from_fd(fd)?.split();
+    run_server_connection(input, output, client).await
+}
+
+/// Runs a new RPC server connection using the packed encoding.
+///
+/// Same as [`run_server_connection`] but messages are packed on the wire, which reduces the
+/// number of bytes exchanged for sparse messages. The client must use
+/// [`client_connection_packed`].
+pub async fn run_server_connection_packed<R, W>(
+    input: R,
+    output: W,
+    client: Box<dyn ClientHook>,
+) -> Result<(), capnp::Error>
+where
+    R: AsyncRead + Unpin + 'static,
+    W: AsyncWrite + Unpin + 'static,
+{
+    let options = ConnectionOptions {
+        packed: true,
+        ..Default::default()
+    };
+    run_server_connection_with_options(input, output, client, options).await
+}
+
+/// Same as [`run_server_connection`] but the bytes exchanged on the wire are reported to the
+/// passed sink, see [`tap`].
+pub async fn run_server_connection_with_tap<R, W>(
+    input: R,
+    output: W,
+    client: Box<dyn ClientHook>,
+    sink: TapSink,
+) -> Result<(), capnp::Error>
+where
+    R: AsyncRead + Unpin + 'static,
+    W: AsyncWrite + Unpin + 'static,
+{
+    run_server_connection(
+        Tap::new(input, sink.clone()),
+        Tap::new(output, sink),
+        client,
+    )
+    .await
+}
+
+/// Runs a new RPC server connection with the passed options.
+///
+/// See [`run_server_connection`]. On top of it, the connection fails with
+/// [`AttachError::PeerUnresponsive`] or [`AttachError::SessionExpired`] when the
+/// [`keepalive`](ConnectionOptions::keepalive) or the
+/// [`max_lifetime`](ConnectionOptions::max_lifetime) are exceeded.
+pub async fn run_server_connection_with_options<R, W>(
+    input: R,
+    output: W,
+    client: Box<dyn ClientHook>,
+    options: ConnectionOptions,
+) -> Result<(), capnp::Error>
+where
+    R: AsyncRead + Unpin + 'static,
+    W: AsyncWrite + Unpin + 'static,
+{
+    let client = match options.max_services_per_connection {
+        Some(max) => Box::new(ForwardingClientHook::new(client, ServiceLimit::new(max))),
+        None => client,
+    };
+
+    // Filled once the connection ID is known, the registration reports why the connection closed
+    let registration = RefCell::new(None);
+    let max_lifetime = options.max_lifetime;
+    let run = run_server_kept_alive(input, output, client, options, &registration);
+    let (res, reason) = match max_lifetime {
+        None => run.await,
+        Some(max_lifetime) => match select(pin!(run), pin!(Timer::after(max_lifetime))).await {
+            Either::Left((closed, _)) => closed,
+            Either::Right((_, _)) => (
+                Err(capnp::Error::disconnected(
+                    AttachError::SessionExpired { max_lifetime }.to_string(),
+                )),
+                CloseReason::SessionExpired,
+            ),
+        },
+    };
+    if let Some(registration) = registration.into_inner() {
+        registration.close(reason);
+    }
+    res
+}
+
+async fn run_server_kept_alive<R, W>(
+    input: R,
+    output: W,
+    client: Box<dyn ClientHook>,
+    options: ConnectionOptions,
+    registration: &RefCell<Option<Registration>>,
+) -> (Result<(), capnp::Error>, CloseReason)
+where
+    R: AsyncRead + Unpin + 'static,
+    W: AsyncWrite + Unpin + 'static,
+{
+    let Some(interval) = options.keepalive else {
+        return closed(run_server_negotiated(input, output, client, options, registration).await);
+    };
+
+    let (input, last_activity) = ActivityReader::new(input);
+    let max_missed = options.keepalive_max_missed;
+    let timeout = interval * (max_missed + 1);
+    match select(
+        pin!(run_server_negotiated(
+            input,
+            output,
+            client,
+            options,
+            registration
+        )),
+        pin!(watchdog(last_activity, timeout)),
+    )
+    .await
+    {
+        Either::Left((res, _)) => closed(res),
+        Either::Right(((), _)) => (
+            Err(capnp::Error::disconnected(
+                AttachError::PeerUnresponsive { missed: max_missed }.to_string(),
+            )),
+            CloseReason::PeerUnresponsive,
+        ),
+    }
+}
+
+/// Pairs the result of a connection which terminated by itself with the reason why it closed.
+fn closed(res: Result<(), capnp::Error>) -> (Result<(), capnp::Error>, CloseReason) {
+    let reason = match &res {
+        Ok(()) => CloseReason::ClientDisconnected,
+        Err(err) => CloseReason::Error(err.clone()),
+    };
+    (res, reason)
+}
+
+async fn run_server_negotiated<R, W>(
+    input: R,
+    output: W,
+    client: Box<dyn ClientHook>,
+    options: ConnectionOptions,
+    registration: &RefCell<Option<Registration>>,
+) -> Result<(), capnp::Error>
+where
+    R: AsyncRead + Unpin + 'static,
+    W: AsyncWrite + Unpin + 'static,
+{
+    if !options.needs_handshake() {
+        *registration.borrow_mut() = options.register(None);
+        return run_server_buffered(input, output, client, &options).await;
+    }
+
+    let (mut input, mut output) = (input, output);
+    let Handshake {
+        compression,
+        role,
+        connection_id,
+    } = handshake(&mut input, &mut output, options.compression, options.role).await?;
+    *registration.borrow_mut() = options.register(Some(connection_id));
+    let client = match role {
+        Role::Observer => Box::new(ForwardingClientHook::new(client, ObserverRole)),
+        _ => client,
+    };
+
+    let run = async {
+        #[cfg(feature = "tracing")]
+        tracing::info!(?compression, "Connection established");
+        match compression {
+            Compression::None => run_server_buffered(input, output, client, &options).await,
+            Compression::Lz4 => {
+                run_server_buffered(
+                    CompressedStream::new(input),
+                    CompressedStream::new(output),
+                    client,
+                    &options,
+                )
+                .await
+            }
+        }
+    };
+    #[cfg(feature = "tracing")]
+    let run = tracing::Instrument::instrument(
+        run,
+        tracing::info_span!(
+            "teleop_connection",
+            connection_id = %format_connection_id(connection_id)
+        ),
+    );
+    run.await.map_err(|mut err| {
+        err.extra = format!(
+            "connection {}: {}",
+            format_connection_id(connection_id),
+            err.extra
+        );
+        err
+    })
+}
+
+fn format_connection_id(connection_id: u64) -> String {
+    format!("{connection_id:016x}")
+}
+
+async fn run_server_buffered<R, W>(
+    input: R,
+    output: W,
+    client: Box<dyn ClientHook>,
+    options: &ConnectionOptions,
+) -> Result<(), capnp::Error>
+where
+    R: AsyncRead + Unpin + 'static,
+    W: AsyncWrite + Unpin + 'static,
+{
+    let input = BufReader::with_capacity(options.read_buffer_capacity, input);
+    let output = BufWriter::with_capacity(options.write_buffer_capacity, output);
+    if options.packed {
+        run_server_network(PackedRead::new(input), PackedWrite::new(output), client).await
+    } else {
+        run_server_network(input, output, client).await
+    }
+}
+
+async fn run_server_network<R, W>(
+    input: R,
+    output: W,
+    client: Box<dyn ClientHook>,
+) -> Result<(), capnp::Error>
+where
+    R: AsyncRead + Unpin + 'static,
+    W: AsyncWrite + Unpin + 'static,
+{
+    let network = twoparty::VatNetwork::new(
+        input,
+        output,
+        rpc_twoparty_capnp::Side::Server,
+        Default::default(),
+    );
+    let (network, termination) = RecordingNetwork::new(network);
+    // Panics of service handlers must not tear down the connection
+    let client = Box::new(ForwardingClientHook::new(client, CatchUnwind));
+    let rpc_system = RpcSystem::new(Box::new(network), Some(Client { hook: client }));
+
+    rpc_system.await?;
+    // The peer closing the connection is the normal termination
+    match termination.take() {
+        Some(err) if err.kind != capnp::ErrorKind::Disconnected => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Creates a RPC client connection.
+///
+/// The communication goes through the passed input and output.
+///
+/// The returned value is made of a system to be run by the async runtime and the client interface
+/// to initiate RPC requests.
+pub async fn client_connection<R, W>(
+    input: R,
+    output: W,
+) -> (
+    RpcSystem<rpc_twoparty_capnp::Side>,
+    teleop_capnp::teleop::Client,
+)
+where
+    R: AsyncRead + Unpin + 'static,
+    W: AsyncWrite + Unpin + 'static,
+{
+    client_buffered(input, output, &ConnectionOptions::default())
+}
+
+/// Creates a RPC client connection over an already connected stream socket, e.g. inherited from
+/// a supervisor, without attaching to any process.
+///
+/// See [`client_connection`].
+#[cfg(unix)]
+pub async fn client_connection_from_fd(
+    fd: std::os::fd::OwnedFd,
+) -> Result<
+    (
+        RpcSystem<rpc_twoparty_capnp::Side>,
+        teleop_capnp::teleop::Client,
+    ),
+    std::io::Error,
+> {
+    use futures::AsyncReadExt;
+
+    let (input, output) = stream_from_fd(fd)?.split();
+    Ok(client_connection(input, output).await)
+}
+
+#[cfg(unix)]
+fn stream_from_fd(fd: std::os::fd::OwnedFd) -> std::io::Result<async_net::unix::UnixStream> {
+    async_net::unix::UnixStream::try_from(std::os::unix::net::UnixStream::from(fd))
+}
+
+/// Creates a RPC client connection using the packed encoding.
+///
+/// Same as [`client_connection`] but messages are packed on the wire. The server must use
+/// [`run_server_connection_packed`].
+pub async fn client_connection_packed<R, W>(
+    input: R,
+    output: W,
+) -> (
+    RpcSystem<rpc_twoparty_capnp::Side>,
+    teleop_capnp::teleop::Client,
+)
+where
+    R: AsyncRead + Unpin + 'static,
+    W: AsyncWrite + Unpin + 'static,
+{
+    let options = ConnectionOptions {
+        packed: true,
+        ..Default::default()
+    };
+    client_buffered(input, output, &options)
+}
+
+/// Same as [`client_connection`] but the bytes exchanged on the wire are reported to the passed
+/// sink, see [`tap`].
+pub async fn client_connection_with_tap<R, W>(
+    input: R,
+    output: W,
+    sink: TapSink,
+) -> (
+    RpcSystem<rpc_twoparty_capnp::Side>,
+    teleop_capnp::teleop::Client,
+)
+where
+    R: AsyncRead + Unpin + 'static,
+    W: AsyncWrite + Unpin + 'static,
+{
+    client_connection(Tap::new(input, sink.clone()), Tap::new(output, sink)).await
+}
+
+/// Client connection created by [`client_connection_with_options`].
+pub struct ConnectedStream {
+    /// System to be run by the async runtime.
+    pub rpc_system: RpcSystem<rpc_twoparty_capnp::Side>,
+    /// Client interface to initiate RPC requests.
+    pub teleop: teleop_capnp::teleop::Client,
+    /// Keepalive to be run alongside the RPC system, if enabled by
+    /// [`ConnectionOptions::keepalive`].
+    pub keepalive: Option<Keepalive>,
+    connection_id: Option<u64>,
+    input: SharedInput,
+    output: SharedOutput,
+}
+
+impl ConnectedStream {
+    fn new(
+        rpc_system: RpcSystem<rpc_twoparty_capnp::Side>,
+        teleop: teleop_capnp::teleop::Client,
+        connection_id: Option<u64>,
+        (input, output): (SharedInput, SharedOutput),
+        options: &ConnectionOptions,
+    ) -> Self {
+        let keepalive = options.keepalive.map(|interval| {
+            Keepalive::new(
+                teleop.clone(),
+                rpc_system.get_disconnector(),
+                interval,
+                options.keepalive_max_missed,
+            )
+        });
+        Self {
+            rpc_system,
+            teleop,
+            keepalive,
+            connection_id,
+            input,
+            output,
+        }
+    }
+
+    /// ID of the connection, shared with the server, if the handshake was exchanged.
+    ///
+    /// It is meant to correlate the logs of both sides.
+    pub fn connection_id(&self) -> Option<u64> {
+        self.connection_id
+    }
+
+    /// Creates a [`GracefulDisconnector`], to be used instead of the [`Disconnector`] of the RPC
+    /// system to make sure the peer receives the final messages.
+    ///
+    /// [`Disconnector`]: capnp_rpc::Disconnector
+    pub fn graceful_disconnector(&self) -> GracefulDisconnector {
+        GracefulDisconnector::new(
+            self.rpc_system.get_disconnector(),
+            self.input.clone(),
+            self.output.clone(),
+        )
+    }
+}
+
+/// Creates a RPC client connection with the passed options.
+///
+/// See [`client_connection`]. Unlike it, this fails if the handshake fails.
+pub async fn client_connection_with_options<R, W>(
+    input: R,
+    output: W,
+    options: ConnectionOptions,
+) -> Result<ConnectedStream, capnp::Error>
+where
+    R: AsyncRead + Unpin + 'static,
+    W: AsyncWrite + Unpin + 'static,
+{
+    // Kept to close the streams gracefully
+    let shared = (
+        SharedStream::new(Box::new(input) as Box<dyn AsyncRead + Unpin>),
+        SharedStream::new(Box::new(output) as Box<dyn AsyncWrite + Unpin>),
+    );
+    let (input, output) = shared.clone();
+
+    if !options.needs_handshake() {
+        let (rpc_system, teleop) = client_buffered(input, output, &options);
+        return Ok(ConnectedStream::new(
+            rpc_system, teleop, None, shared, &options,
+        ));
+    }
+
+    let (mut input, mut output) = (input, output);
+    let Handshake {
+        compression,
+        connection_id,
+        ..
+    } = handshake(&mut input, &mut output, options.compression, options.role).await?;
+    #[cfg(feature = "tracing")]
+    tracing::info!(
+        connection_id = %format_connection_id(connection_id),
+        ?compression,
+        "Connection established"
+    );
+    let (rpc_system, teleop) = match compression {
+        Compression::None => client_buffered(input, output, &options),
+        Compression::Lz4 => client_buffered(
+            CompressedStream::new(input),
+            CompressedStream::new(output),
+            &options,
+        ),
+    };
+    Ok(ConnectedStream::new(
+        rpc_system,
+        teleop,
+        Some(connection_id),
+        shared,
+        &options,
+    ))
+}
+
+fn client_buffered<R, W>(
+    input: R,
+    output: W,
+    options: &ConnectionOptions,
+) -> (
+    RpcSystem<rpc_twoparty_capnp::Side>,
+    teleop_capnp::teleop::Client,
+)
+where
+    R: AsyncRead + Unpin + 'static,
+    W: AsyncWrite + Unpin + 'static,
+{
+    let input = BufReader::with_capacity(options.read_buffer_capacity, input);
+    let output = BufWriter::with_capacity(options.write_buffer_capacity, output);
+    if options.packed {
+        client_network(PackedRead::new(input), PackedWrite::new(output))
+    } else {
+        client_network(input, output)
+    }
+}
+
+/// Cap'n Proto implementation of [`Protocol`], serving the `Teleop` root interface.
+///
+/// It is equivalent to [`run_server_connection_with_options`] and
+/// [`client_connection_with_options`] with the protocol options.
+#[derive(Clone, Debug, Default)]
+pub struct CapnpProtocol {
+    /// Options of the connections.
+    pub options: ConnectionOptions,
+}
+
+impl CapnpProtocol {
+    /// Creates the protocol with the passed connection options.
+    pub fn new(options: ConnectionOptions) -> Self {
+        Self { options }
+    }
+}
+
+impl Protocol for CapnpProtocol {
+    type Server = teleop_capnp::teleop::Client;
+    type Client = ConnectedStream;
+    type Error = capnp::Error;
+
+    fn serve<R, W>(
+        &self,
+        server: &Self::Server,
+        input: R,
+        output: W,
+    ) -> impl Future<Output = Result<(), Self::Error>> + 'static
+    where
+        R: AsyncRead + Unpin + 'static,
+        W: AsyncWrite + Unpin + 'static,
+    {
+        run_server_connection_with_options(
+            input,
+            output,
+            server.client.hook.add_ref(),
+            self.options.clone(),
+        )
+    }
+
+    fn connect<R, W>(
+        &self,
+        input: R,
+        output: W,
+    ) -> impl Future<Output = Result<Self::Client, Self::Error>> + 'static
+    where
+        R: AsyncRead + Unpin + 'static,
+        W: AsyncWrite + Unpin + 'static,
+    {
+        client_connection_with_options(input, output, self.options.clone())
+    }
+}
+
+/// Verifies that the process behind a client connection runs a Teleop server.
+///
+/// A connection to a process which does not run a Teleop server otherwise only fails on the first
+/// request, with an error which is hard to attribute. The check is a quick probe which fails with
+/// [`AttachError::NotTeleopServer`] if the peer does not answer properly within the passed timeout.
+/// Older servers, which answer that the probed method is unimplemented, pass the check.
+pub async fn verify_teleop(
+    teleop: &teleop_capnp::teleop::Client,
+    timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let probe = async {
+        let reply = teleop.list_services_request().send().promise.await?;
+        reply.get()?.get_names()?;
+        Ok::<_, capnp::Error>(())
+    };
+    let timeout = Timer::after(timeout).map(|_| format!("no answer within {timeout:?}"));
+    match select(pin!(probe), timeout).await {
+        Either::Left((Ok(()), _)) => Ok(()),
+        // Servers predating the method answer properly nonetheless
+        Either::Left((Err(err), _)) if err.kind == capnp::ErrorKind::Unimplemented => Ok(()),
+        Either::Left((Err(err), _)) => Err(AttachError::NotTeleopServer {
+            reason: err.to_string(),
+        }
+        .into()),
+        Either::Right((reason, _)) => Err(AttachError::NotTeleopServer { reason }.into()),
+    }
+}
+
+/// Checks that the server speaks the same version of the Teleop protocol as the client.
+///
+/// Fails with [`AttachError::VersionMismatch`] otherwise.
+pub async fn check_compatible(
+    teleop: &teleop_capnp::teleop::Client,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let server = match teleop.version_request().send().promise.await {
+        Ok(reply) => reply.get()?.get_version(),
+        // Servers predating versioning do not implement the method
+        Err(err) if err.kind == capnp::ErrorKind::Unimplemented => 0,
+        Err(err) => return Err(err.into()),
+    };
+    if server == PROTOCOL_VERSION {
+        Ok(())
+    } else {
+        Err(AttachError::VersionMismatch {
+            client: PROTOCOL_VERSION,
+            server,
+        }
+        .into())
+    }
+}
+
+/// Checks that the named service of the server is compatible with the version expected by the
+/// client, e.g. before calling a method which older versions lack.
+///
+/// Fails with [`AttachError::ServiceVersionMismatch`] otherwise. Services which are not versioned,
+/// or registered by servers predating service versions, are in version `0`.
+pub async fn check_service_compatible(
+    teleop: &teleop_capnp::teleop::Client,
+    name: &str,
+    version: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut req = teleop.introspect_request();
+    req.get().set_name(name);
+    let reply = req.send().promise.await?;
+    let reply = reply.get()?;
+    let server = ServiceVersion {
+        version: reply.get_version(),
+        min_compatible: reply.get_min_compatible_version(),
+    };
+    if server.is_compatible_with(version) {
+        Ok(())
+    } else {
+        Err(AttachError::ServiceVersionMismatch {
+            service: name.to_owned(),
+            client: version,
+            server: server.version,
+            min_compatible: server.min_compatible,
+        }
+        .into())
+    }
+}
+
+fn client_network<R, W>(
+    input: R,
+    output: W,
+) -> (
+    RpcSystem<rpc_twoparty_capnp::Side>,
+    teleop_capnp::teleop::Client,
+)
+where
+    R: AsyncRead + Unpin + 'static,
+    W: AsyncWrite + Unpin + 'static,
+{
+    let network = twoparty::VatNetwork::new(
+        input,
+        output,
+        rpc_twoparty_capnp::Side::Client,
+        Default::default(),
+    );
+    let mut rpc_system = RpcSystem::new(Box::new(network), None);
+    let teleop: teleop_capnp::teleop::Client =
+        rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+    (rpc_system, teleop)
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+
+    use assert_matches::assert_matches;
+    use futures::task::LocalSpawnExt;
+
+    use super::{
+        echo::{echo_capnp, EchoServer},
+        *,
+    };
+
+    #[test]
+    fn test_capnp_teleop() {
+        let (client_input, server_output) = sluice::pipe::pipe();
+        let (server_input, client_output) = sluice::pipe::pipe();
+
+        let server = || -> Result<(), Box<dyn std::error::Error>> {
+            let mut server = TeleopServer::new();
+            server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+            let client = capnp_rpc::new_client::<teleop_capnp::teleop::Client, _>(server);
+
+            let mut exec = futures::executor::LocalPool::new();
+
+            let res = exec.run_until(run_server_connection(
+                server_input,
+                server_output,
+                client.client.hook,
+            ));
+
+            exec.run();
+
+            res?;
+
+            Ok(())
+        };
+
+        let client = || -> Result<(), Box<dyn std::error::Error>> {
+            let mut exec = futures::executor::LocalPool::new();
+            let spawn = exec.spawner();
+
+            let res = exec.run_until(async move {
+                let (rpc_system, teleop) = client_connection(client_input, client_output).await;
+                let rpc_disconnect = rpc_system.get_disconnector();
+
+                spawn.spawn_local(async {
+                    if let Err(e) = rpc_system.await {
+                        eprintln!("Connection interrupted {e}");
+                    }
+                })?;
+
+                let res = async {
+                    let mut req = teleop.service_request();
+                    req.get().set_name("echo");
+                    let echo = req.send().promise.await?;
+                    let echo = echo.get()?.get_service();
+                    let echo: echo_capnp::echo::Client = echo.get_as()?;
+
+                    println!("got echo service");
+
+                    let mut req = echo.echo_request();
+                    req.get().set_message("hello!");
+                    let reply = req.send().promise.await?;
+                    let reply = reply.get()?.get_reply()?.to_str()?;
+
+                    println!("{}", reply);
+
+                    let mut req = teleop.service_request();
+                    req.get().set_name("tango");
+                    let tango_res = req.send().promise.await;
+                    assert!(tango_res.is_err());
+                    let tango_err = tango_res.err().unwrap();
+                    assert_eq!(tango_err.kind, capnp::ErrorKind::Failed);
+                    assert!(tango_err.extra.contains("service tango not found"));
+
+                    Ok::<_, Box<dyn std::error::Error>>(())
+                }
+                .await;
+
+                let res2 = rpc_disconnect.await;
+
+                res?;
+
+                res2?;
+
+                Ok::<_, Box<dyn std::error::Error>>(())
+            });
+
+            exec.run();
+
+            res?;
+
+            Ok(())
+        };
+
+        let s = std::thread::spawn(|| server().unwrap());
+        let c = std::thread::spawn(|| client().unwrap());
+        c.join().unwrap();
+        s.join().unwrap();
+    }
+
+    #[test]
+    fn test_capnp_teleop_packed() {
+        let (client_input, server_output) = sluice::pipe::pipe();
+        let (server_input, client_output) = sluice::pipe::pipe();
+
+        let mut server = TeleopServer::new();
+        server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+        let client = capnp_rpc::new_client::<teleop_capnp::teleop::Client, _>(server);
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawn = exec.spawner();
+
+        spawn
+            .spawn_local(async move {
+                if let Err(e) =
+                    run_server_connection_packed(server_input, server_output, client.client.hook)
+                        .await
+                {
+                    eprintln!("Server connection interrupted {e}");
+                }
+            })
+            .unwrap();
+
+        let res = exec.run_until(async move {
+            let (rpc_system, teleop) = client_connection_packed(client_input, client_output).await;
+            let rpc_disconnect = rpc_system.get_disconnector();
+
+            spawn.spawn_local(async {
+                if let Err(e) = rpc_system.await {
+                    eprintln!("Connection interrupted {e}");
+                }
+            })?;
+
+            let res = async {
+                let mut req = teleop.service_request();
+                req.get().set_name("echo");
+                let echo = req.send().promise.await?;
+                let echo: echo_capnp::echo::Client = echo.get()?.get_service().get_as()?;
+
+                let mut req = echo.echo_request();
+                req.get().set_message("hello!");
+                let reply = req.send().promise.await?;
+                let reply = reply.get()?.get_reply()?.to_str()?;
+                assert_eq!(reply, "hello!");
+
+                Ok::<_, Box<dyn std::error::Error>>(())
+            }
+            .await;
+
+            let res2 = rpc_disconnect.await;
+
+            res?;
+
+            res2?;
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        exec.run();
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_teleop_compressed() {
+        let (client_input, server_output) = sluice::pipe::pipe();
+        let (server_input, client_output) = sluice::pipe::pipe();
+
+        let mut server = TeleopServer::new();
+        server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+        let client = capnp_rpc::new_client::<teleop_capnp::teleop::Client, _>(server);
+
+        let options = ConnectionOptions {
+            compression: Compression::Lz4,
+            ..Default::default()
+        };
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawn = exec.spawner();
+
+        spawn
+            .spawn_local({
+                let options = options.clone();
+                async move {
+                    if let Err(e) = run_server_connection_with_options(
+                        server_input,
+                        server_output,
+                        client.client.hook,
+                        options,
+                    )
+                    .await
+                    {
+                        eprintln!("Server connection interrupted {e}");
+                    }
+                }
+            })
+            .unwrap();
+
+        let res = exec.run_until(async move {
+            let connected =
+                client_connection_with_options(client_input, client_output, options).await?;
+            assert!(connected.connection_id().is_some());
+            let ConnectedStream {
+                rpc_system, teleop, ..
+            } = connected;
+
+            spawn.spawn_local(async {
+                if let Err(e) = rpc_system.await {
+                    eprintln!("Connection interrupted {e}");
+                }
+            })?;
+
+            let mut req = teleop.service_request();
+            req.get().set_name("echo");
+            let echo = req.send().promise.await?;
+            let echo: echo_capnp::echo::Client = echo.get()?.get_service().get_as()?;
+
+            let message = "hello! ".repeat(100_000);
+            let mut req = echo.echo_request();
+            req.get().set_message(message.as_str());
+            let reply = req.send().promise.await?;
+            assert_eq!(reply.get()?.get_reply()?.to_str()?, message);
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_teleop_compressed_without_peer_handshake() {
+        let (client_input, server_output) = sluice::pipe::pipe();
+        let (server_input, client_output) = sluice::pipe::pipe();
+
+        let client = TeleopServer::new().into_client();
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawn = exec.spawner();
+
+        // The server does not expect the handshake and reads it as a RPC message
+        spawn
+            .spawn_local(async move {
+                let res = run_server_connection(server_input, server_output, client.client.hook);
+                assert!(res.await.is_err());
+            })
+            .unwrap();
+
+        let res = exec.run_until(async move {
+            let options = ConnectionOptions {
+                compression: Compression::Lz4,
+                ..Default::default()
+            };
+            let connected =
+                client_connection_with_options(client_input, client_output, options).await;
+            assert!(connected.is_err());
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+        exec.run();
+    }
+
+    #[test]
+    fn test_capnp_protocol() {
+        let (client_input, server_output) = sluice::pipe::pipe();
+        let (server_input, client_output) = sluice::pipe::pipe();
+
+        let protocol = CapnpProtocol::new(ConnectionOptions {
+            compression: Compression::Lz4,
+            ..Default::default()
+        });
+        let server = TeleopServer::new().into_client();
+
+        let mut exec = futures::executor::LocalPool::new();
+        exec.spawner()
+            .spawn_local({
+                let serve = protocol.serve(&server, server_input, server_output);
+                async move {
+                    serve.await.unwrap();
+                }
+            })
+            .unwrap();
+
+        let res = exec.run_until(async {
+            let ConnectedStream {
+                rpc_system, teleop, ..
+            } = protocol.connect(client_input, client_output).await?;
+            let disconnector = rpc_system.get_disconnector();
+            futures::try_join!(rpc_system, async {
+                teleop.ping_request().send().promise.await?;
+                disconnector.await
+            })?;
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+        exec.run();
+    }
+
+    #[test]
+    fn test_capnp_max_services_per_connection() {
+        let (client_input, server_output) = sluice::pipe::pipe();
+        let (server_input, client_output) = sluice::pipe::pipe();
+
+        let mut server = TeleopServer::new();
+        for name in ["echo1", "echo2", "echo3"] {
+            server.register_service::<echo_capnp::echo::Client, _, _>(name, || EchoServer);
+        }
+        let server = server.into_client();
+        let protocol = CapnpProtocol::new(ConnectionOptions {
+            max_services_per_connection: Some(2),
+            ..Default::default()
+        });
+
+        let mut exec = futures::executor::LocalPool::new();
+        exec.spawner()
+            .spawn_local({
+                let serve = protocol.serve(&server, server_input, server_output);
+                async move {
+                    serve.await.unwrap();
+                }
+            })
+            .unwrap();
+
+        let res = exec.run_until(async {
+            let ConnectedStream {
+                rpc_system, teleop, ..
+            } = protocol.connect(client_input, client_output).await?;
+            let disconnector = rpc_system.get_disconnector();
+            futures::try_join!(rpc_system, async {
+                let service = |name| {
+                    let mut req = teleop.service_request();
+                    req.get().set_name(name);
+                    req.send().promise
+                };
+
+                // Unknown services and services requested again do not count
+                assert!(service("unknown").await.is_err());
+                service("echo1").await?;
+                service("echo1").await?;
+                service("echo2").await?;
+
+                let err = service("echo3").await.err().unwrap();
+                assert_eq!(err.kind, capnp::ErrorKind::Overloaded);
+
+                // Already resolved services are still available
+                service("echo2").await?;
+
+                disconnector.await
+            })?;
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+        exec.run();
+    }
+
+    #[test]
+    fn test_capnp_keepalive() {
+        let (client_input, server_output) = sluice::pipe::pipe();
+        let (server_input, client_output) = sluice::pipe::pipe();
+
+        let mut server = TeleopServer::new();
+        server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+        let client = capnp_rpc::new_client::<teleop_capnp::teleop::Client, _>(server);
+
+        let options = ConnectionOptions {
+            keepalive: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawn = exec.spawner();
+
+        spawn
+            .spawn_local({
+                let options = options.clone();
+                async move {
+                    if let Err(e) = run_server_connection_with_options(
+                        server_input,
+                        server_output,
+                        client.client.hook,
+                        options,
+                    )
+                    .await
+                    {
+                        eprintln!("Server connection interrupted {e}");
+                    }
+                }
+            })
+            .unwrap();
+
+        let res = exec.run_until(async move {
+            let ConnectedStream {
+                rpc_system,
+                teleop,
+                keepalive,
+                ..
+            } = client_connection_with_options(client_input, client_output, options).await?;
+
+            spawn.spawn_local(async {
+                if let Err(e) = rpc_system.await {
+                    eprintln!("Connection interrupted {e}");
+                }
+            })?;
+            let mut keepalive = keepalive.unwrap().fuse();
+
+            // Idle for several keepalive intervals
+            futures::select! {
+                res = keepalive => panic!("Keepalive terminated: {res:?}"),
+                _ = FutureExt::fuse(Timer::after(Duration::from_millis(200))) => {}
+            }
+
+            let mut req = teleop.service_request();
+            req.get().set_name("echo");
+            let echo = req.send().promise.await?;
+            let echo: echo_capnp::echo::Client = echo.get()?.get_service().get_as()?;
+
+            let mut req = echo.echo_request();
+            req.get().set_message("hello!");
+            let reply = req.send().promise.await?;
+            assert_eq!(reply.get()?.get_reply()?.to_str()?, "hello!");
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_keepalive_stalled_server() {
+        let (client_input, _server_output) = sluice::pipe::pipe();
+        let (_server_input, client_output) = sluice::pipe::pipe();
+
+        let options = ConnectionOptions {
+            keepalive: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawn = exec.spawner();
+
+        let res = exec.run_until(async move {
+            let ConnectedStream {
+                rpc_system,
+                keepalive,
+                ..
+            } = client_connection_with_options(client_input, client_output, options).await?;
+
+            spawn.spawn_local(async {
+                let _ = rpc_system.await;
+            })?;
+
+            let err = keepalive.unwrap().await.unwrap_err();
+            assert_matches!(
+                err.downcast_ref::<AttachError>(),
+                Some(AttachError::PeerUnresponsive { missed: 3 })
+            );
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_keepalive_stalled_client() {
+        let (_client_input, server_output) = sluice::pipe::pipe();
+        let (server_input, _client_output) = sluice::pipe::pipe();
+
+        let client = capnp_rpc::new_client::<teleop_capnp::teleop::Client, _>(TeleopServer::new());
+
+        let options = ConnectionOptions {
+            keepalive: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+
+        let res = futures::executor::block_on(run_server_connection_with_options(
+            server_input,
+            server_output,
+            client.client.hook,
+            options,
+        ));
+        let err = res.unwrap_err();
+        assert_eq!(err.kind, capnp::ErrorKind::Disconnected);
+        assert!(err.extra.contains("Peer is unresponsive"));
+    }
+
+    #[test]
+    fn test_capnp_max_lifetime() {
+        struct SlowReceiver;
+
+        impl echo_capnp::echo_receiver::Server for SlowReceiver {
+            async fn chunk(
+                self: capnp::capability::Rc<Self>,
+                _params: echo_capnp::echo_receiver::ChunkParams,
+            ) -> Result<(), capnp::Error> {
+                Timer::after(Duration::from_millis(10)).await;
+                Ok(())
+            }
+        }
+
+        let (client_input, server_output) = sluice::pipe::pipe();
+        let (server_input, client_output) = sluice::pipe::pipe();
+
+        let mut server = TeleopServer::new();
+        server.register_service::<echo_capnp::echo_stream::Client, _, _>("echo_stream", || {
+            echo::EchoStreamServer
+        });
+        let client = capnp_rpc::new_client::<teleop_capnp::teleop::Client, _>(server);
+
+        // Both limits apply, the keepalive one is not reached
+        let options = ConnectionOptions {
+            keepalive: Some(Duration::from_millis(100)),
+            max_lifetime: Some(Duration::from_millis(200)),
+            ..Default::default()
+        };
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawn = exec.spawner();
+
+        let server_res = spawn
+            .spawn_local_with_handle(run_server_connection_with_options(
+                server_input,
+                server_output,
+                client.client.hook,
+                options.clone(),
+            ))
+            .unwrap();
+
+        let res = exec.run_until(async move {
+            let ConnectedStream {
+                rpc_system, teleop, ..
+            } = client_connection_with_options(client_input, client_output, options).await?;
+
+            spawn.spawn_local(async {
+                let _ = rpc_system.await;
+            })?;
+
+            let mut req = teleop.service_request();
+            req.get().set_name("echo_stream");
+            let echo = req.send().promise.await?;
+            let echo: echo_capnp::echo_stream::Client = echo.get()?.get_service().get_as()?;
+
+            // Would take 10 seconds
+            let start = Instant::now();
+            let mut req = echo.echo_stream_request();
+            req.get().set_message(&[0; 1000]);
+            req.get().set_chunk_size(1);
+            req.get().set_receiver(capnp_rpc::new_client(SlowReceiver));
+            let err = req.send().promise.await.err().unwrap();
+            assert_eq!(err.kind, capnp::ErrorKind::Disconnected);
+            assert!(start.elapsed() < Duration::from_secs(5));
+
+            let err = server_res.await.unwrap_err();
+            assert_eq!(err.kind, capnp::ErrorKind::Disconnected);
+            assert!(err.extra.contains("Session expired"), "{err}");
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_registry() {
+        let registry = ConnectionRegistry::new();
+        let peer = PeerCredentials {
+            pid: Some(42),
+            uid: 1000,
+            gid: 1000,
+        };
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawn = exec.spawner();
+
+        let mut disconnects = Vec::new();
+        let mut connection_ids = Vec::new();
+        for peer in [Some(peer), None] {
+            let (client_input, server_output) = sluice::pipe::pipe();
+            let (server_input, client_output) = sluice::pipe::pipe();
+
+            let client =
+                capnp_rpc::new_client::<teleop_capnp::teleop::Client, _>(TeleopServer::new());
+            let options = ConnectionOptions {
+                handshake: true,
+                registry: Some(registry.clone()),
+                peer,
+                ..Default::default()
+            };
+            spawn
+                .spawn_local({
+                    let options = options.clone();
+                    async move {
+                        let _ = run_server_connection_with_options(
+                            server_input,
+                            server_output,
+                            client.client.hook,
+                            options,
+                        )
+                        .await;
+                    }
+                })
+                .unwrap();
+
+            let connected = exec
+                .run_until(client_connection_with_options(
+                    client_input,
+                    client_output,
+                    options,
+                ))
+                .unwrap();
+            connection_ids.push(connected.connection_id());
+            disconnects.push(connected.rpc_system.get_disconnector());
+            spawn
+                .spawn_local(async {
+                    let _ = connected.rpc_system.await;
+                })
+                .unwrap();
+        }
+
+        let active = registry.active_connections();
+        assert_eq!(active.len(), 2);
+        assert_eq!(active[0].connection_id, connection_ids[0]);
+        assert_eq!(active[0].peer, Some(peer));
+        assert_eq!(active[1].connection_id, connection_ids[1]);
+        assert_eq!(active[1].peer, None);
+
+        exec.run_until(disconnects.remove(0)).unwrap();
+        exec.run_until_stalled();
+        let active = registry.active_connections();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].connection_id, connection_ids[1]);
+    }
+
+    #[test]
+    fn test_capnp_close_reasons() {
+        use futures::{AsyncWriteExt, StreamExt};
+
+        let registry = ConnectionRegistry::new();
+        let mut events = registry.subscribe();
+
+        let options = ConnectionOptions {
+            registry: Some(registry.clone()),
+            ..Default::default()
+        };
+        let cases = [
+            (&[][..], options.clone()),
+            (&[0xff; 64][..], options.clone()),
+            (
+                &[][..],
+                ConnectionOptions {
+                    keepalive: Some(Duration::from_millis(10)),
+                    ..options.clone()
+                },
+            ),
+            (
+                &[][..],
+                ConnectionOptions {
+                    max_lifetime: Some(Duration::from_millis(10)),
+                    ..options
+                },
+            ),
+        ];
+        let mut reasons = Vec::new();
+        for (i, (garbage, options)) in cases.into_iter().enumerate() {
+            let (client_input, server_output) = sluice::pipe::pipe();
+            let (server_input, mut client_output) = sluice::pipe::pipe();
+            let server = TeleopServer::new().into_client().client.hook;
+
+            futures::executor::block_on(async move {
+                let client = async move {
+                    client_output.write_all(garbage).await.unwrap();
+                    // The timed out connections are stalled
+                    let _stalled = (i >= 2).then_some((client_input, client_output));
+                    futures::future::pending::<()>().await;
+                };
+                select(
+                    pin!(run_server_connection_with_options(
+                        server_input,
+                        server_output,
+                        server,
+                        options,
+                    )),
+                    pin!(client),
+                )
+                .await;
+            });
+
+            let opened = futures::executor::block_on(events.next());
+            let Some(registry::ConnectionEvent::Opened { id: opened_id, .. }) = opened else {
+                panic!("Unexpected event {opened:?}");
+            };
+            let closed = futures::executor::block_on(events.next());
+            let Some(registry::ConnectionEvent::Closed { id, reason }) = closed else {
+                panic!("Unexpected event {closed:?}");
+            };
+            assert_eq!(id, opened_id);
+            reasons.push(reason);
+        }
+
+        assert_matches!(
+            reasons[..],
+            [
+                CloseReason::ClientDisconnected,
+                CloseReason::Error(capnp::Error {
+                    kind: capnp::ErrorKind::Failed,
+                    ..
+                }),
+                CloseReason::PeerUnresponsive,
+                CloseReason::SessionExpired,
+            ]
+        );
+        assert!(registry.active_connections().is_empty());
+    }
+
+    #[test]
+    fn test_capnp_nested_server() {
+        let mut debug = TeleopServer::new();
+        debug.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+        let server = TeleopServer::builder()
+            .register_nested_server("debug", debug)
+            .build();
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = testing::connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            let mut req = teleop.service_request();
+            req.get().set_name("debug");
+            let debug = req.send().promise.await?;
+            let debug: teleop_capnp::teleop::Client = debug.get()?.get_service().get_as()?;
+
+            for (teleop, name) in [(&debug, "echo"), (&teleop, "debug.echo")] {
+                let mut req = teleop.service_request();
+                req.get().set_name(name);
+                let echo = req.send().promise.await?;
+                let echo: echo_capnp::echo::Client = echo.get()?.get_service().get_as()?;
+
+                let mut req = echo.echo_request();
+                req.get().set_message("hello!");
+                let reply = req.send().promise.await?;
+                assert_eq!(reply.get()?.get_reply()?.to_str()?, "hello!");
+            }
+
+            let mut req = teleop.service_request();
+            req.get().set_name("debug.tango");
+            let err = req.send().promise.await.err().unwrap();
+            assert!(err.extra.contains("service tango not found"), "{err}");
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_introspect() {
+        let mut server = TeleopServer::new();
+        server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = testing::connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            let mut req = teleop.introspect_request();
+            req.get().set_name("echo");
+            let reply = req.send().promise.await?;
+            let reply = reply.get()?;
+            assert_eq!(reply.get_type_id(), echo_capnp::echo::Client::TYPE_ID);
+            assert!(reply
+                .get_type_name()?
+                .to_str()?
+                .ends_with("echo_capnp::echo::Client"));
+
+            let mut req = teleop.introspect_request();
+            req.get().set_name("unknown");
+            let err = req.send().promise.await.err().unwrap();
+            assert!(err.extra.contains("service unknown not found"));
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_service_suggestion() {
+        assert_eq!(levenshtein("ech", "echo"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+
+        let mut server = TeleopServer::new();
+        server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = testing::connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            let mut req = teleop.service_request();
+            req.get().set_name("ech");
+            let err = req.send().promise.await.err().unwrap();
+            assert!(err
+                .extra
+                .contains("service ech not found, did you mean echo?"));
+
+            let mut req = teleop.service_request();
+            req.get().set_name("tango");
+            let err = req.send().promise.await.err().unwrap();
+            assert!(err.extra.contains("service tango not found"));
+            assert!(!err.extra.contains("did you mean"));
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_rate_limit() {
+        let mut server = TeleopServer::new();
+        server.register_service_with::<echo_capnp::echo::Client, _, _>(
+            "echo",
+            ServiceOptions::new().rate_limit(RateLimit { max_per_sec: 3 }),
+            || EchoServer,
+        );
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = testing::connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            let mut overloaded = 0;
+            for _ in 0..10 {
+                let mut req = teleop.service_request();
+                req.get().set_name("echo");
+                match req.send().promise.await {
+                    Ok(_) => {}
+                    Err(err) => {
+                        assert_eq!(err.kind, capnp::ErrorKind::Overloaded);
+                        assert!(err.extra.contains("rate limit exceeded"));
+                        overloaded += 1;
+                    }
+                }
+            }
+            assert!(overloaded > 0);
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_clear() {
+        let mut server = TeleopServer::new();
+        assert!(server.is_empty());
+
+        server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+        server.register_service::<echo_capnp::echo::Client, _, _>("never", || -> EchoServer {
+            panic!("Service should not be initialized")
+        });
+        assert_eq!(server.len(), 2);
+        assert!(!server.is_empty());
+
+        LazyLock::force(&server.services["echo"].client);
+
+        server.clear();
+        assert_eq!(server.len(), 0);
+        assert!(server.is_empty());
+    }
+
+    #[test]
+    fn test_capnp_revocable_service() {
+        let mut server = TeleopServer::new();
+        let revocation = RevocationHandle::default();
+        server.register_service_with::<echo_capnp::echo::Client, _, _>(
+            "echo",
+            ServiceOptions::new().revocable(revocation.clone()),
+            || EchoServer,
+        );
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = testing::connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            let mut req = teleop.service_request();
+            req.get().set_name("echo");
+            let echo = req.send().promise.await?;
+            let echo: echo_capnp::echo::Client = echo.get()?.get_service().get_as()?;
+
+            let mut req = echo.echo_request();
+            req.get().set_message("hello!");
+            let reply = req.send().promise.await?;
+            assert_eq!(reply.get()?.get_reply()?.to_str()?, "hello!");
+
+            revocation.revoke();
+            assert!(revocation.is_revoked());
+
+            let mut req = echo.echo_request();
+            req.get().set_message("hello!");
+            let err = req.send().promise.await.err().unwrap();
+            assert_eq!(err.kind, capnp::ErrorKind::Disconnected);
+            assert!(err.extra.contains("revoked"));
+
+            // The connection survived
+            teleop.ping_request().send().promise.await?;
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_service_timed() {
+        let mut server = TeleopServer::new();
+        let timings = CallTimings::default();
+        server.register_service_with::<echo_capnp::echo::Client, _, _>(
+            "echo",
+            ServiceOptions::new().timed(timings.clone()),
+            || EchoServer,
+        );
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = testing::connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            let mut req = teleop.service_request();
+            req.get().set_name("echo");
+            let echo = req.send().promise.await?;
+            let echo: echo_capnp::echo::Client = echo.get()?.get_service().get_as()?;
+            assert_eq!(timings.stats(), timing::CallStats::default());
+
+            for _ in 0..2 {
+                let mut req = echo.echo_request();
+                req.get().set_message("hello!");
+                req.send().promise.await?;
+            }
+
+            let stats = timings.stats();
+            assert_eq!(stats.calls, 2);
+            assert!(stats.total_time > Duration::ZERO);
+            assert!(stats.mean_time().unwrap() > Duration::ZERO);
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_register_revocable_service() {
+        let mut server = TeleopServer::new();
+        let revocation = server
+            .register_revocable_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = testing::connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            let mut req = teleop.service_request();
+            req.get().set_name("echo");
+            let echo = req.send().promise.await?;
+            let echo: echo_capnp::echo::Client = echo.get()?.get_service().get_as()?;
+
+            revocation.revoke();
+
+            let mut req = echo.echo_request();
+            req.get().set_message("hello!");
+            let err = req.send().promise.await.err().unwrap();
+            assert_eq!(err.kind, capnp::ErrorKind::Disconnected);
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_register_service_timed() {
+        let mut server = TeleopServer::new();
+        let timings =
+            server.register_service_timed::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = testing::connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            let mut req = teleop.service_request();
+            req.get().set_name("echo");
+            let echo = req.send().promise.await?;
+            let echo: echo_capnp::echo::Client = echo.get()?.get_service().get_as()?;
+
+            let mut req = echo.echo_request();
+            req.get().set_message("hello!");
+            req.send().promise.await?;
+
+            assert_eq!(timings.stats().calls, 1);
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_service_with_policy() {
+        let server = TeleopServer::builder()
+            .register_service_with::<echo_capnp::echo::Client, _, _>(
+                "echo",
+                // Deny echoDelayed @1
+                ServiceOptions::new().policy(|call: &access::MethodCall| {
+                    call.interface_id != echo_capnp::echo::Client::TYPE_ID || call.method_id != 1
+                }),
+                || EchoServer,
+            )
+            .build();
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = testing::connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            let mut req = teleop.service_request();
+            req.get().set_name("echo");
+            let echo = req.send().promise.await?;
+            let echo: echo_capnp::echo::Client = echo.get()?.get_service().get_as()?;
+
+            let mut req = echo.echo_request();
+            req.get().set_message("hello!");
+            let reply = req.send().promise.await?;
+            assert_eq!(reply.get()?.get_reply()?.to_str()?, "hello!");
+
+            let mut req = echo.echo_delayed_request();
+            req.get().set_message("hello!");
+            let err = req.send().promise.await.err().unwrap();
+            assert_eq!(err.kind, capnp::ErrorKind::Failed);
+            assert!(err.extra.contains("denied"), "{err}");
+
+            // The connection survived
+            teleop.ping_request().send().promise.await?;
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_service_panic() {
+        struct PanickingEchoServer;
+
+        impl echo_capnp::echo::Server for PanickingEchoServer {
+            async fn echo(
+                self: capnp::capability::Rc<Self>,
+                params: echo_capnp::echo::EchoParams,
+                mut results: echo_capnp::echo::EchoResults,
+            ) -> Result<(), capnp::Error> {
+                let message = params.get()?.get_message()?.to_str()?;
+                if message == "panic" {
+                    panic!("Handler panicked on purpose");
+                }
+                results.get().set_reply(message);
+                Ok(())
+            }
+        }
+
+        let mut server = TeleopServer::new();
+        server.register_service::<echo_capnp::echo::Client, _, _>("echo", || PanickingEchoServer);
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = testing::connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            let mut req = teleop.service_request();
+            req.get().set_name("echo");
+            let echo = req.send().promise.await?;
+            let echo: echo_capnp::echo::Client = echo.get()?.get_service().get_as()?;
+
+            let mut req = echo.echo_request();
+            req.get().set_message("panic");
+            let err = req.send().promise.await.err().unwrap();
+            assert_eq!(err.kind, capnp::ErrorKind::Failed);
+            assert!(err.extra.contains("internal server error"));
+
+            // The connection survived
+            let mut req = echo.echo_request();
+            req.get().set_message("hello!");
+            let reply = req.send().promise.await?;
+            assert_eq!(reply.get()?.get_reply()?.to_str()?, "hello!");
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_capnp_serve() {
+        use futures::AsyncReadExt;
+
+        use crate::{
+            attach::{attacher::dummy::DummyAttacher, connect},
+            config::TeleopConfig,
+        };
+
+        // Isolate the socket from the other tests
+        TeleopConfig::install_for_thread(Some(TeleopConfig {
+            socket_prefix: ".teleop_serve_".into(),
+            ..TeleopConfig::default()
+        }));
+
+        let mut server = TeleopServer::new();
+        server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+        let token = CancellationToken::new();
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawner = exec.spawner();
+
+        let res = exec.run_until(async {
+            let client = async {
+                let stream = connect::<DummyAttacher>(std::process::id()).await?;
+                let (input, output) = stream.split();
+                let (rpc_system, teleop) = client_connection(input, output).await;
+                spawner.spawn_local(async {
+                    let _ = rpc_system.await;
+                })?;
+
+                let mut req = teleop.service_request();
+                req.get().set_name("echo");
+                let echo = req.send().promise.await?;
+                let echo: echo_capnp::echo::Client = echo.get()?.get_service().get_as()?;
+
+                let mut req = echo.echo_request();
+                req.get().set_message("hello!");
+                let reply = req.send().promise.await?;
+                assert_eq!(reply.get()?.get_reply()?.to_str()?, "hello!");
+
+                token.cancel();
+
+                Ok::<_, Box<dyn std::error::Error>>(())
+            };
+
+            let (served, client) = futures::join!(
+                serve::<DummyAttacher, _>(server, token.clone(), &spawner),
+                client
+            );
+            served?;
+            client
+        });
+
+        TeleopConfig::install_for_thread(None);
+
+        res.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_capnp_serve_error_handler() {
+        use futures::{channel::mpsc, AsyncWriteExt, StreamExt};
+
+        use crate::{
+            attach::{attacher::dummy::DummyAttacher, connect},
+            config::TeleopConfig,
+        };
+
+        // Isolate the socket from the other tests
+        TeleopConfig::install_for_thread(Some(TeleopConfig {
+            socket_prefix: ".teleop_serve_errors_".into(),
+            ..TeleopConfig::default()
+        }));
+
+        let token = CancellationToken::new();
+        let (sender, mut errors) = mpsc::unbounded::<capnp::Error>();
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawner = exec.spawner();
+
+        let res = exec.run_until(async {
+            let client = async {
+                // Disconnecting is not an error
+                let stream = connect::<DummyAttacher>(std::process::id()).await?;
+                drop(stream);
+
+                let mut stream = connect::<DummyAttacher>(std::process::id()).await?;
+                stream.write_all(&[0xff; 64]).await?;
+                let err = errors.next().await.unwrap();
+                assert_eq!(err.kind, capnp::ErrorKind::Failed);
+
+                token.cancel();
+
+                Ok::<_, Box<dyn std::error::Error>>(())
+            };
+
+            let (served, client) = futures::join!(
+                serve_with_error_handler::<DummyAttacher, _>(
+                    TeleopServer::new(),
+                    token.clone(),
+                    &spawner,
+                    move |err| sender.unbounded_send(err).unwrap(),
+                ),
+                client
+            );
+            served?;
+            client
+        });
+
+        TeleopConfig::install_for_thread(None);
+
+        res.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_capnp_serve_drain() {
+        use futures::AsyncReadExt;
+
+        use crate::{
+            attach::{attacher::dummy::DummyAttacher, connect},
+            config::TeleopConfig,
+        };
+
+        // Isolate the socket from the other tests
+        TeleopConfig::install_for_thread(Some(TeleopConfig {
+            socket_prefix: ".teleop_serve_drain_".into(),
+            ..TeleopConfig::default()
+        }));
+
+        let mut server = TeleopServer::new();
+        server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawner = exec.spawner();
+
+        let (handle, serving) = serve_with_handle::<DummyAttacher, _>(server, &spawner);
+
+        let res = exec.run_until(async {
+            let client = async {
+                let stream = connect::<DummyAttacher>(std::process::id()).await?;
+                let (input, output) = stream.split();
+                let (rpc_system, teleop) = client_connection(input, output).await;
+                let rpc_system = spawner.spawn_local_with_handle(async {
+                    let _ = rpc_system.await;
+                })?;
+
+                let mut req = teleop.service_request();
+                req.get().set_name("echo");
+                req.send().promise.await?;
+
+                let disconnect = async {
+                    // The connection is in flight while draining
+                    Timer::after(Duration::from_millis(200)).await;
+                    drop(rpc_system);
+                };
+                let drain = async {
+                    let start = Instant::now();
+                    let drained = handle.drain(Duration::from_secs(10)).await;
+                    (drained, start.elapsed())
+                };
+                let ((drained, elapsed), ()) = futures::join!(drain, disconnect);
+                assert!(drained);
+                assert!(elapsed >= Duration::from_millis(200), "{elapsed:?}");
+
+                Ok::<_, Box<dyn std::error::Error>>(())
+            };
+
+            let (served, client) = futures::join!(serving, client);
+            served?;
+            client
+        });
+
+        TeleopConfig::install_for_thread(None);
+
+        res.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_capnp_connection_from_fd() {
+        use nix::sys::socket::{socketpair, AddressFamily, SockFlag, SockType};
+
+        let (client_fd, server_fd) = socketpair(
+            AddressFamily::Unix,
+            SockType::Stream,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )
+        .unwrap();
+
+        let mut server = TeleopServer::new();
+        server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawner = exec.spawner();
+
+        spawner
+            .spawn_local(async move {
+                let client = server.into_client().client.hook;
+                let _ = run_server_connection_from_fd(server_fd, client).await;
+            })
+            .unwrap();
+
+        let res = exec.run_until(async {
+            let (rpc_system, teleop) = client_connection_from_fd(client_fd).await?;
+            spawner.spawn_local(async {
+                let _ = rpc_system.await;
+            })?;
+
+            let mut req = teleop.service_request();
+            req.get().set_name("echo");
+            let echo = req.send().promise.await?;
+            let echo: echo_capnp::echo::Client = echo.get()?.get_service().get_as()?;
+
+            let mut req = echo.echo_request();
+            req.get().set_message("hello!");
+            let reply = req.send().promise.await?;
+            assert_eq!(reply.get()?.get_reply()?.to_str()?, "hello!");
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_protocol_error() {
+        use futures::AsyncWriteExt;
+
+        let mut exec = futures::executor::LocalPool::new();
+
+        for (garbage, expected) in [
+            (&[][..], None),
+            (&[0xff; 64][..], Some(capnp::ErrorKind::Failed)),
+        ] {
+            let (client_input, server_output) = sluice::pipe::pipe();
+            let (server_input, mut client_output) = sluice::pipe::pipe();
+            let server = TeleopServer::new().into_client().client.hook;
+
+            let res = exec.run_until(async move {
+                let client = async move {
+                    client_output.write_all(garbage).await?;
+                    drop((client_input, client_output));
+                    Ok::<_, std::io::Error>(())
+                };
+                let (served, client) = futures::join!(
+                    run_server_connection(server_input, server_output, server),
+                    client
+                );
+                client.unwrap();
+                served
+            });
+
+            assert_eq!(res.err().map(|err| err.kind), expected);
+        }
+    }
+
+    #[test]
+    fn test_capnp_process_info() {
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = testing::connected_pair(TeleopServer::new(), &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            let reply = teleop.process_info_request().send().promise.await?;
+            let info = reply.get()?.get_info()?;
+            assert_eq!(info.get_pid(), std::process::id());
+            assert_eq!(
+                info.get_executable()?.to_str()?,
+                std::env::current_exe()?.to_string_lossy()
+            );
+            #[cfg(feature = "discover")]
+            assert!(info.get_start_time() > 0);
+            assert_eq!(
+                info.get_teleop_version()?.to_str()?,
+                env!("CARGO_PKG_VERSION")
+            );
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_verify_teleop() {
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = testing::connected_pair(TeleopServer::new(), &exec.spawner()).unwrap();
+
+        let res = exec.run_until(verify_teleop(&teleop, Duration::from_secs(5)));
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_verify_teleop_without_list_services() {
+        /// Server only implementing `service`, like the ones of teleop 0.4.
+        struct PastServer;
+
+        impl teleop_capnp::teleop::Server for PastServer {
+            async fn service(
+                self: capnp::capability::Rc<Self>,
+                _params: teleop_capnp::teleop::ServiceParams,
+                _results: teleop_capnp::teleop::ServiceResults,
+            ) -> Result<(), capnp::Error> {
+                Err(capnp::Error::failed("no service".to_owned()))
+            }
+        }
+
+        let (client_input, server_output) = sluice::pipe::pipe();
+        let (server_input, client_output) = sluice::pipe::pipe();
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawn = exec.spawner();
+
+        let past = capnp_rpc::new_client::<teleop_capnp::teleop::Client, _>(PastServer);
+        spawn
+            .spawn_local(async move {
+                if let Err(e) =
+                    run_server_connection(server_input, server_output, past.client.hook).await
+                {
+                    eprintln!("Server connection interrupted {e}");
+                }
+            })
+            .unwrap();
+
+        let res = exec.run_until(async move {
+            let (rpc_system, teleop) = client_connection(client_input, client_output).await;
+            spawn.spawn_local(async {
+                if let Err(e) = rpc_system.await {
+                    eprintln!("Connection interrupted {e}");
+                }
+            })?;
+
+            verify_teleop(&teleop, Duration::from_secs(5)).await
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_verify_not_teleop() {
+        let (client_input, server_output) = sluice::pipe::pipe();
+        let (server_input, client_output) = sluice::pipe::pipe();
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawn = exec.spawner();
+
+        // Plain echo server which sends back whatever it receives
+        spawn
+            .spawn_local(async move {
+                let mut server_output = server_output;
+                futures::io::copy(server_input, &mut server_output)
+                    .await
+                    .unwrap();
+            })
+            .unwrap();
+
+        let res = exec.run_until(async move {
+            let (rpc_system, teleop) = client_connection(client_input, client_output).await;
+
+            spawn.spawn_local(async {
+                if let Err(e) = rpc_system.await {
+                    eprintln!("Connection interrupted {e}");
+                }
+            })?;
+
+            let result = verify_teleop(&teleop, Duration::from_secs(1)).await;
+            let err = assert_matches!(result, Err(err) => err);
+            assert_matches!(
+                err.downcast_ref::<AttachError>(),
+                Some(AttachError::NotTeleopServer { .. })
+            );
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_check_compatible() {
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = testing::connected_pair(TeleopServer::new(), &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move { check_compatible(&teleop).await });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_check_not_compatible() {
+        struct FutureServer;
+
+        impl teleop_capnp::teleop::Server for FutureServer {
+            async fn version(
+                self: capnp::capability::Rc<Self>,
+                _params: teleop_capnp::teleop::VersionParams,
+                mut results: teleop_capnp::teleop::VersionResults,
+            ) -> Result<(), capnp::Error> {
+                results.get().set_version(PROTOCOL_VERSION + 1);
+                Ok(())
+            }
+        }
+
+        struct PastServer;
+
+        impl teleop_capnp::teleop::Server for PastServer {}
+
+        let future = capnp_rpc::new_client::<teleop_capnp::teleop::Client, _>(FutureServer);
+        let past = capnp_rpc::new_client::<teleop_capnp::teleop::Client, _>(PastServer);
+        for (teleop, expected) in [(future, PROTOCOL_VERSION + 1), (past, 0)] {
+            let err = futures::executor::block_on(check_compatible(&teleop)).unwrap_err();
+            assert_matches!(
+                err.downcast_ref::<AttachError>(),
+                Some(AttachError::VersionMismatch { client, server })
+                    if *client == PROTOCOL_VERSION && *server == expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_capnp_check_service_compatible() {
+        let mut server = TeleopServer::new();
+        server.register_service_with::<echo_capnp::echo::Client, _, _>(
+            "echo",
+            ServiceOptions::new().version(ServiceVersion {
+                version: 3,
+                min_compatible: 2,
+            }),
+            || EchoServer,
+        );
+        server.register_service::<echo_capnp::echo::Client, _, _>("legacy", || EchoServer);
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = testing::connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            check_service_compatible(&teleop, "echo", 2).await?;
+            check_service_compatible(&teleop, "echo", 3).await?;
+            check_service_compatible(&teleop, "legacy", 0).await?;
+
+            for (name, client, server, min_compatible) in
+                [("echo", 1, 3, 2), ("echo", 4, 3, 2), ("legacy", 1, 0, 0)]
+            {
+                let err = check_service_compatible(&teleop, name, client)
+                    .await
+                    .unwrap_err();
+                assert_matches!(
+                    err.downcast_ref::<AttachError>(),
+                    Some(AttachError::ServiceVersionMismatch {
+                        service,
+                        client: c,
+                        server: s,
+                        min_compatible: m,
+                    }) if service == name && *c == client && *s == server && *m == min_compatible
+                );
+            }
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn test_capnp_remote_shutdown() {
+        use futures::StreamExt;
+
+        use crate::{
+            attach::{attacher::dummy::DummyAttacher, connect, listen_as},
+            config::TeleopConfig,
+        };
+
+        // Use a PID which is not the PID of the current process to avoid conflicts with other tests
+        let pid = u32::MAX - 1 - std::process::id();
+
+        // No user runs the synthetic process, do not verify the one listening on the socket
+        TeleopConfig::install_for_thread(Some(TeleopConfig {
+            #[cfg(unix)]
+            allow_unknown_target_user: true,
+            ..TeleopConfig::default()
+        }));
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawn = exec.spawner();
+
+        let res = exec.run_until(async move {
+            let (handle, conn_stream) = listen_as::<DummyAttacher>(pid);
+            let server = TeleopServer::builder()
+                .allow_remote_shutdown(&handle)
+                .build();
+            let server = capnp_rpc::new_client::<teleop_capnp::teleop::Client, _>(server);
+
+            let server = async {
+                let mut conn_stream = pin!(conn_stream);
+                while let Some(stream) = conn_stream.next().await {
+                    let (stream, _addr) = stream?;
+                    let (input, output) = futures::AsyncReadExt::split(stream);
+                    spawn.spawn_local({
+                        let client = server.client.hook.clone();
+                        async move {
+                            if let Err(e) = run_server_connection(input, output, client).await {
+                                eprintln!("Server connection interrupted {e}");
+                            }
+                        }
+                    })?;
+                }
+                Ok::<_, Box<dyn std::error::Error>>(())
+            };
+
+            let client = async {
+                let stream = connect::<DummyAttacher>(pid).await?;
+                let (input, output) = futures::AsyncReadExt::split(stream);
+                let (rpc_system, teleop) = client_connection(input, output).await;
+                spawn.spawn_local(async {
+                    if let Err(e) = rpc_system.await {
+                        eprintln!("Connection interrupted {e}");
+                    }
+                })?;
+
+                teleop.shutdown_request().send().promise.await?;
+
+                Ok::<_, Box<dyn std::error::Error>>(())
+            };
+
+            // The server only terminates if the listen stream ends
+            let (server, client) = futures::join!(server, client);
+            server?;
+            client?;
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        TeleopConfig::install_for_thread(None);
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_capnp_remote_shutdown_not_allowed() {
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = testing::connected_pair(TeleopServer::new(), &exec.spawner()).unwrap();
+
+        let res = exec.run_until(teleop.shutdown_request().send().promise);
+
+        let err = res.err().unwrap();
+        assert_eq!(err.kind, capnp::ErrorKind::Failed);
+        assert!(err.extra.contains("remote shutdown is not allowed"));
     }
 }
@@ -0,0 +1,239 @@
+//! Read-only connections.
+//!
+//! A client asks for the [`Role::Observer`] role in the connection handshake, see
+//! [`ConnectionOptions::role`](super::ConnectionOptions::role). On such a connection, the methods
+//! classified as mutating with [`ServiceOptions::mutability`](super::ServiceOptions::mutability)
+//! when the service was registered fail without reaching the service, and the process cannot be
+//! shut down remotely. Other connections have full access.
+//!
+//! Services registered without a classification have no mutating method, as far as observers are
+//! concerned. Capabilities returned by the calls themselves are not wrapped and therefore not
+//! controlled.
+
+use capnp::{
+    capability::{FromClientHook, Promise},
+    private::capability::{ClientHook, ParamsHook, ResultsHook},
+    traits::HasTypeId,
+};
+
+use super::{forwarding::CallInterceptor, teleop_capnp};
+
+/// Ordinal of the `service` method of `Teleop`.
+const SERVICE_METHOD_ID: u16 = 0;
+
+/// Ordinal of the `shutdown` method of `Teleop`.
+const SHUTDOWN_METHOD_ID: u16 = 2;
+
+/// Role of a connection, negotiated by the handshake.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Role {
+    /// Full access to the services.
+    #[default]
+    Full,
+    /// Read-only access: mutating methods are denied.
+    Observer,
+}
+
+impl Role {
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Self::Full => 0,
+            Self::Observer => 1,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Self {
+        match id {
+            0 => Self::Full,
+            // Unknown roles are at least as restricted as observers
+            _ => Self::Observer,
+        }
+    }
+}
+
+/// Interceptor of the root capability of an observer connection, which requests all services as
+/// an observer and denies `shutdown`.
+#[derive(Clone)]
+pub(crate) struct ObserverRole;
+
+impl CallInterceptor for ObserverRole {
+    fn intercept_call(
+        &self,
+        inner: &dyn ClientHook,
+        interface_id: u64,
+        method_id: u16,
+        params: Box<dyn ParamsHook>,
+        results: Box<dyn ResultsHook>,
+    ) -> Promise<(), capnp::Error> {
+        if interface_id != teleop_capnp::teleop::Client::TYPE_ID {
+            return inner.call(interface_id, method_id, params, results);
+        }
+        match method_id {
+            SERVICE_METHOD_ID => service_as_observer(inner, params, results),
+            SHUTDOWN_METHOD_ID => Promise::err(capnp::Error::failed(
+                "shutdown is not allowed to observers".to_owned(),
+            )),
+            _ => inner.call(interface_id, method_id, params, results),
+        }
+    }
+}
+
+fn service_as_observer(
+    inner: &dyn ClientHook,
+    params: Box<dyn ParamsHook>,
+    mut results: Box<dyn ResultsHook>,
+) -> Promise<(), capnp::Error> {
+    let name = match params
+        .get()
+        .and_then(|params| params.get_as::<teleop_capnp::teleop::service_params::Reader>())
+        .and_then(|params| Ok(params.get_name()?.to_str()?.to_owned()))
+    {
+        Ok(name) => name,
+        Err(err) => return Promise::err(err),
+    };
+    let teleop = teleop_capnp::teleop::Client::new(inner.add_ref());
+    let mut req = teleop.service_request();
+    req.get().set_name(&name);
+    req.get().set_observer(true);
+    Promise::from_future(async move {
+        let reply = req.send().promise.await?;
+        let service = reply
+            .get()?
+            .get_service()
+            .get_as_capability::<capnp::capability::Client>()?;
+        results
+            .get()?
+            .init_as::<teleop_capnp::teleop::service_results::Builder>()
+            .init_service()
+            .set_as_capability(service.hook);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use futures::task::LocalSpawnExt;
+
+    use super::*;
+    use crate::operate::capnp::{
+        client_connection_with_options,
+        dynamic::{dynamic_capnp, DynamicServer},
+        echo::{echo_capnp, EchoServer},
+        run_server_connection_with_options, ConnectedStream, ConnectionOptions, ServiceOptions,
+        TeleopServer,
+    };
+
+    /// Ordinal of the `invoke` method of `Dynamic`.
+    const INVOKE_METHOD_ID: u16 = 0;
+
+    fn test_role(role: Role) -> Result<u32, Box<dyn std::error::Error>> {
+        let (client_input, server_output) = sluice::pipe::pipe();
+        let (server_input, client_output) = sluice::pipe::pipe();
+
+        let count = Rc::new(Cell::new(0));
+        let mut counter = DynamicServer::new();
+        counter.add_method("increment", {
+            let count = count.clone();
+            move |_| {
+                count.set(count.get() + 1);
+                Vec::new()
+            }
+        });
+
+        let mut server = TeleopServer::new();
+        server.register_service_with::<echo_capnp::echo::Client, _, _>(
+            "echo",
+            ServiceOptions::new().mutability(|_| false),
+            || EchoServer,
+        );
+        server.register_service_with::<dynamic_capnp::dynamic::Client, _, _>(
+            "counter",
+            ServiceOptions::new().mutability(|call| call.method_id == INVOKE_METHOD_ID),
+            || counter,
+        );
+        let client = server.into_client();
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawn = exec.spawner();
+
+        spawn.spawn_local(async move {
+            if let Err(e) = run_server_connection_with_options(
+                server_input,
+                server_output,
+                client.client.hook,
+                ConnectionOptions {
+                    handshake: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            {
+                eprintln!("Server connection interrupted {e}");
+            }
+        })?;
+
+        exec.run_until(async move {
+            let options = ConnectionOptions {
+                handshake: true,
+                role,
+                ..Default::default()
+            };
+            let ConnectedStream {
+                rpc_system, teleop, ..
+            } = client_connection_with_options(client_input, client_output, options).await?;
+            spawn.spawn_local(async {
+                if let Err(e) = rpc_system.await {
+                    eprintln!("Connection interrupted {e}");
+                }
+            })?;
+
+            let mut req = teleop.service_request();
+            req.get().set_name("echo");
+            let echo = req.send().promise.await?;
+            let echo: echo_capnp::echo::Client = echo.get()?.get_service().get_as()?;
+            let mut req = echo.echo_request();
+            req.get().set_message("hello");
+            let reply = req.send().promise.await?;
+            assert_eq!(reply.get()?.get_reply()?.to_str()?, "hello");
+
+            let mut req = teleop.service_request();
+            req.get().set_name("counter");
+            let counter = req.send().promise.await?;
+            let counter: dynamic_capnp::dynamic::Client = counter.get()?.get_service().get_as()?;
+            counter.methods_request().send().promise.await?;
+            let mut req = counter.invoke_request();
+            req.get().set_method("increment");
+            let incremented = req.send().promise.await;
+
+            let shutdown = teleop.shutdown_request().send().promise.await;
+
+            match role {
+                Role::Observer => {
+                    let err = incremented.err().unwrap();
+                    assert!(err.extra.contains("method @0 of service counter"), "{err}");
+                    let err = shutdown.err().unwrap();
+                    assert!(err.extra.contains("not allowed to observers"), "{err}");
+                }
+                _ => {
+                    incremented?;
+                    // Not allowed by the server anyway
+                    assert!(shutdown.is_err());
+                }
+            }
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        })?;
+
+        Ok(count.get())
+    }
+
+    #[test]
+    fn test_capnp_observer() {
+        assert_eq!(test_role(Role::Full).unwrap(), 1);
+        assert_eq!(test_role(Role::Observer).unwrap(), 0);
+    }
+}
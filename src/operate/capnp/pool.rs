@@ -0,0 +1,505 @@
+//! Reuse of client connections.
+//!
+//! A client polling many processes repeatedly, e.g. a dashboard, would otherwise attach and
+//! bootstrap a new connection on every poll. A [`ConnectionPool`] keeps one live connection per
+//! process ID instead.
+//!
+//! A [`CachedService`] keeps a service resolved through the pool, and resolves it again when its
+//! connection dies.
+
+use std::{cell::RefCell, collections::HashMap, future::Future, rc::Rc};
+
+use capnp::capability::FromClientHook;
+use capnp_rpc::{rpc_twoparty_capnp, Disconnector};
+use futures::{
+    future::LocalBoxFuture,
+    task::{LocalSpawn, LocalSpawnExt},
+    AsyncRead, AsyncWrite, FutureExt,
+};
+
+use super::{client_connection_with_options, teleop_capnp, ConnectedStream, ConnectionOptions};
+
+type Connect = Box<
+    dyn Fn(
+        u32,
+    ) -> LocalBoxFuture<
+        'static,
+        Result<
+            (Box<dyn AsyncRead + Unpin>, Box<dyn AsyncWrite + Unpin>),
+            Box<dyn std::error::Error>,
+        >,
+    >,
+>;
+
+/// Pool of client connections, keyed by process ID.
+///
+/// The RPC system of every connection is spawned on the passed executor. A connection is evicted
+/// as soon as its RPC system terminates, e.g. because the target process exited. The next
+/// [`get`](Self::get) then attaches again, which transparently handles restarted processes.
+///
+/// Dropping the pool disconnects all its connections.
+pub struct ConnectionPool<S>
+where
+    S: LocalSpawn,
+{
+    spawner: S,
+    connect: Connect,
+    options: ConnectionOptions,
+    connections: RefCell<HashMap<u32, PooledConnection>>,
+}
+
+struct PooledConnection {
+    teleop: teleop_capnp::teleop::Client,
+    disconnector: Disconnector<rpc_twoparty_capnp::Side>,
+    alive: Rc<()>,
+}
+
+impl PooledConnection {
+    fn is_alive(&self) -> bool {
+        // The RPC system task holds the other reference until it terminates
+        Rc::strong_count(&self.alive) > 1
+    }
+}
+
+#[cfg(any(unix, windows))]
+impl<S> ConnectionPool<S>
+where
+    S: LocalSpawn,
+{
+    /// Creates a pool attaching with the default communication channel and attacher, see
+    /// [`connect`](crate::attach::connect).
+    pub fn new(spawner: S) -> Self {
+        use futures::AsyncReadExt;
+
+        use crate::attach::{attacher::DefaultAttacher, connect};
+
+        Self::with_connect(spawner, ConnectionOptions::default(), |pid| async move {
+            Ok(connect::<DefaultAttacher>(pid).await?.split())
+        })
+    }
+}
+
+impl<S> ConnectionPool<S>
+where
+    S: LocalSpawn,
+{
+    /// Creates a pool opening streams with the passed function, and setting up connections with
+    /// the passed options.
+    pub fn with_connect<F, Fut, R, W>(spawner: S, options: ConnectionOptions, connect: F) -> Self
+    where
+        F: Fn(u32) -> Fut + 'static,
+        Fut: Future<Output = Result<(R, W), Box<dyn std::error::Error>>> + 'static,
+        R: AsyncRead + Unpin + 'static,
+        W: AsyncWrite + Unpin + 'static,
+    {
+        let connect = move |pid| {
+            connect(pid)
+                .map(|res| {
+                    res.map(|(input, output)| {
+                        (
+                            Box::new(input) as Box<dyn AsyncRead + Unpin>,
+                            Box::new(output) as Box<dyn AsyncWrite + Unpin>,
+                        )
+                    })
+                })
+                .boxed_local()
+        };
+        Self {
+            spawner,
+            connect: Box::new(connect),
+            options,
+            connections: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Returns a client of the process, reusing the pooled connection if it is still alive.
+    pub async fn get(
+        &self,
+        pid: u32,
+    ) -> Result<teleop_capnp::teleop::Client, Box<dyn std::error::Error>> {
+        if let Some(connection) = self.connections.borrow().get(&pid) {
+            if connection.is_alive() {
+                return Ok(connection.teleop.clone());
+            }
+        }
+        self.evict(pid);
+
+        let (input, output) = (self.connect)(pid).await?;
+        let ConnectedStream {
+            rpc_system,
+            teleop,
+            keepalive,
+            ..
+        } = client_connection_with_options(input, output, self.options.clone()).await?;
+
+        let alive = Rc::new(());
+        let disconnector = rpc_system.get_disconnector();
+        self.spawner.spawn_local({
+            let alive = alive.clone();
+            #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+            async move {
+                let res = rpc_system.await;
+                #[cfg(feature = "tracing")]
+                if let Err(e) = res {
+                    tracing::debug!(pid, error = %e, "Pooled connection interrupted");
+                }
+                drop(alive);
+            }
+        })?;
+        if let Some(keepalive) = keepalive {
+            self.spawner.spawn_local(keepalive.map(|_| ()))?;
+        }
+
+        let replaced = self.connections.borrow_mut().insert(
+            pid,
+            PooledConnection {
+                teleop: teleop.clone(),
+                disconnector,
+                alive,
+            },
+        );
+        // Another caller connected to the process concurrently
+        if let Some(replaced) = replaced {
+            self.disconnect(replaced);
+        }
+        Ok(teleop)
+    }
+
+    /// Disconnects and forgets the connection to the process, if any.
+    pub fn evict(&self, pid: u32) {
+        let connection = self.connections.borrow_mut().remove(&pid);
+        if let Some(connection) = connection {
+            self.disconnect(connection);
+        }
+    }
+
+    /// Evicts the connection to the process if it is the one of the passed client, which is known
+    /// to be disconnected even if the RPC system has not terminated yet.
+    ///
+    /// A newer connection, e.g. set up by another user of the pool, is kept.
+    fn evict_connection_of(&self, pid: u32, teleop: &teleop_capnp::teleop::Client) {
+        let same = self
+            .connections
+            .borrow()
+            .get(&pid)
+            .is_some_and(|connection| {
+                connection.teleop.client.hook.get_ptr() == teleop.client.hook.get_ptr()
+            });
+        if same {
+            self.evict(pid);
+        }
+    }
+
+    /// Returns the number of pooled connections, including the ones which died but have not been
+    /// evicted yet.
+    pub fn len(&self) -> usize {
+        self.connections.borrow().len()
+    }
+
+    /// Returns `true` if no connection is pooled.
+    pub fn is_empty(&self) -> bool {
+        self.connections.borrow().is_empty()
+    }
+
+    fn disconnect(&self, connection: PooledConnection) {
+        if connection.is_alive() {
+            // Failure means the executor is shutting down, which terminates the RPC system anyway
+            let _ = self
+                .spawner
+                .spawn_local(connection.disconnector.map(|_| ()));
+        }
+    }
+}
+
+impl<S> Drop for ConnectionPool<S>
+where
+    S: LocalSpawn,
+{
+    fn drop(&mut self) {
+        for (_, connection) in self.connections.take() {
+            self.disconnect(connection);
+        }
+    }
+}
+
+/// Service of a process resolved through a [`ConnectionPool`], and resolved again once its
+/// connection died.
+///
+/// Capabilities do not survive their connection: once the connection dropped, e.g. because the
+/// process restarted, calls made through them fail with a disconnected error.
+/// [`call`](Self::call) then resolves the service again through a new connection and retries the
+/// call once, which makes reconnection transparent to the users of the service.
+pub struct CachedService<'a, C, S>
+where
+    S: LocalSpawn,
+{
+    pool: &'a ConnectionPool<S>,
+    pid: u32,
+    name: String,
+    // Along with the client of the connection it was resolved through
+    client: RefCell<Option<(C, teleop_capnp::teleop::Client)>>,
+}
+
+impl<'a, C, S> CachedService<'a, C, S>
+where
+    C: FromClientHook + Clone,
+    S: LocalSpawn,
+{
+    /// Creates a cached service, resolved lazily by name in the passed process.
+    pub fn new(pool: &'a ConnectionPool<S>, pid: u32, name: impl Into<String>) -> Self {
+        Self {
+            pool,
+            pid,
+            name: name.into(),
+            client: RefCell::new(None),
+        }
+    }
+
+    /// Returns the client of the service, resolving it if it is not cached.
+    pub async fn get(&self) -> Result<C, Box<dyn std::error::Error>> {
+        if let Some((client, _)) = self.client.borrow().as_ref() {
+            return Ok(client.clone());
+        }
+        let teleop = self.pool.get(self.pid).await?;
+        let mut req = teleop.service_request();
+        req.get().set_name(&self.name);
+        let reply = req.send().promise.await?;
+        let client: C = reply.get()?.get_service().get_as_capability()?;
+        *self.client.borrow_mut() = Some((client.clone(), teleop));
+        Ok(client)
+    }
+
+    /// Makes a call with the client of the service.
+    ///
+    /// If the call fails with a disconnected error, the service is resolved again and the call is
+    /// made a second time, so it must be safe to repeat.
+    pub async fn call<T, F, Fut>(&self, mut call: F) -> Result<T, Box<dyn std::error::Error>>
+    where
+        F: FnMut(C) -> Fut,
+        Fut: Future<Output = Result<T, capnp::Error>>,
+    {
+        match call(self.get().await?).await {
+            Err(err) if err.kind == capnp::ErrorKind::Disconnected => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(
+                    pid = self.pid,
+                    service = self.name,
+                    error = %err,
+                    "Cached service disconnected, resolving it again"
+                );
+                if let Some((_, teleop)) = self.client.take() {
+                    self.pool.evict_connection_of(self.pid, &teleop);
+                }
+                Ok(call(self.get().await?).await?)
+            }
+            res => Ok(res?),
+        }
+    }
+
+    /// Forgets the client of the service, the next call resolves it again.
+    pub fn invalidate(&self) {
+        self.client.take();
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::cell::Cell;
+
+    use futures::{
+        executor::LocalPool,
+        future::{poll_fn, AbortHandle, Abortable},
+        task::Poll,
+    };
+
+    use super::*;
+    use crate::operate::capnp::{
+        echo::{echo_capnp, EchoServer},
+        run_server_connection, TeleopServer,
+    };
+
+    /// Returns to the executor once.
+    async fn yield_now() {
+        let mut yielded = false;
+        poll_fn(|cx| {
+            if yielded {
+                return Poll::Ready(());
+            }
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        })
+        .await
+    }
+
+    #[test]
+    fn test_connection_pool() {
+        let mut exec = LocalPool::new();
+        let spawner = exec.spawner();
+
+        // Each connection gets its own server, which can be killed to simulate a process exit
+        let connects = Rc::new(Cell::new(0));
+        let servers = Rc::new(RefCell::new(Vec::<AbortHandle>::new()));
+
+        let pool = ConnectionPool::with_connect(exec.spawner(), ConnectionOptions::default(), {
+            let connects = connects.clone();
+            let servers = servers.clone();
+            move |_pid| {
+                connects.set(connects.get() + 1);
+                let (client_input, server_output) = sluice::pipe::pipe();
+                let (server_input, client_output) = sluice::pipe::pipe();
+                let (abort_handle, abort_registration) = AbortHandle::new_pair();
+                servers.borrow_mut().push(abort_handle);
+                let server = TeleopServer::new().into_client();
+                let serve = Abortable::new(
+                    run_server_connection(server_input, server_output, server.client.hook),
+                    abort_registration,
+                );
+                let res = spawner
+                    .spawn_local(serve.map(|_| ()))
+                    .map(|()| (client_input, client_output))
+                    .map_err(Into::into);
+                async move { res }
+            }
+        });
+
+        let pid = 42;
+
+        // Reuse
+        for _ in 0..2 {
+            exec.run_until(async {
+                let teleop = pool.get(pid).await?;
+                teleop.ping_request().send().promise.await?;
+                Ok::<_, Box<dyn std::error::Error>>(())
+            })
+            .unwrap();
+        }
+        assert_eq!(connects.get(), 1);
+        assert_eq!(pool.len(), 1);
+
+        // The process exits
+        servers.borrow()[0].abort();
+        exec.run_until_stalled();
+
+        // Eviction
+        exec.run_until(async {
+            let teleop = pool.get(pid).await?;
+            teleop.ping_request().send().promise.await?;
+            Ok::<_, Box<dyn std::error::Error>>(())
+        })
+        .unwrap();
+        assert_eq!(connects.get(), 2);
+        assert_eq!(pool.len(), 1);
+
+        pool.evict(pid);
+        assert!(pool.is_empty());
+        exec.run();
+    }
+
+    #[test]
+    fn test_connection_pool_concurrent_get() {
+        let mut exec = LocalPool::new();
+        let spawner = exec.spawner();
+
+        // Server connections which terminated, because their client disconnected
+        let closed = Rc::new(Cell::new(0));
+
+        let pool = ConnectionPool::with_connect(exec.spawner(), ConnectionOptions::default(), {
+            let closed = closed.clone();
+            move |_pid| {
+                let (client_input, server_output) = sluice::pipe::pipe();
+                let (server_input, client_output) = sluice::pipe::pipe();
+                let server = TeleopServer::new().into_client();
+                let closed = closed.clone();
+                let serve = run_server_connection(server_input, server_output, server.client.hook)
+                    .map(move |_| closed.set(closed.get() + 1));
+                let res = spawner
+                    .spawn_local(serve)
+                    .map(|()| (client_input, client_output))
+                    .map_err(Into::into);
+                async move {
+                    // Let the other caller miss as well
+                    yield_now().await;
+                    res
+                }
+            }
+        });
+
+        let pid = 42;
+
+        // Both calls miss and connect
+        let (first, second) =
+            exec.run_until(async { futures::join!(pool.get(pid), pool.get(pid)) });
+        first.unwrap();
+        second.unwrap();
+        assert_eq!(pool.len(), 1);
+
+        // The replaced connection is disconnected rather than left running
+        exec.run_until_stalled();
+        assert_eq!(closed.get(), 1);
+
+        pool.evict(pid);
+        exec.run();
+        assert_eq!(closed.get(), 2);
+    }
+
+    #[test]
+    fn test_cached_service() {
+        let mut exec = LocalPool::new();
+        let spawner = exec.spawner();
+
+        let connects = Rc::new(Cell::new(0));
+        let servers = Rc::new(RefCell::new(Vec::<AbortHandle>::new()));
+
+        let pool = ConnectionPool::with_connect(exec.spawner(), ConnectionOptions::default(), {
+            let connects = connects.clone();
+            let servers = servers.clone();
+            move |_pid| {
+                connects.set(connects.get() + 1);
+                let (client_input, server_output) = sluice::pipe::pipe();
+                let (server_input, client_output) = sluice::pipe::pipe();
+                let (abort_handle, abort_registration) = AbortHandle::new_pair();
+                servers.borrow_mut().push(abort_handle);
+                let mut server = TeleopServer::new();
+                server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+                let serve = Abortable::new(
+                    run_server_connection(
+                        server_input,
+                        server_output,
+                        server.into_client().client.hook,
+                    ),
+                    abort_registration,
+                );
+                let res = spawner
+                    .spawn_local(serve.map(|_| ()))
+                    .map(|()| (client_input, client_output))
+                    .map_err(Into::into);
+                async move { res }
+            }
+        });
+
+        let echo = CachedService::<echo_capnp::echo::Client, _>::new(&pool, 42, "echo");
+        let call = || {
+            echo.call(async |echo| {
+                let mut req = echo.echo_request();
+                req.get().set_message("hello!");
+                let reply = req.send().promise.await?;
+                Ok(reply.get()?.get_reply()?.to_string()?)
+            })
+        };
+
+        assert_eq!(exec.run_until(call()).unwrap(), "hello!");
+        assert_eq!(connects.get(), 1);
+
+        // The connection dies between two calls
+        servers.borrow()[0].abort();
+
+        assert_eq!(exec.run_until(call()).unwrap(), "hello!");
+        assert_eq!(connects.get(), 2);
+
+        drop(echo);
+        drop(pool);
+        exec.run();
+    }
+}
@@ -0,0 +1,172 @@
+//! Introspection of the connections currently served.
+//!
+//! A [`ConnectionRegistry`] passed to the server in [`ConnectionOptions`](super::ConnectionOptions)
+//! tracks the connections while they run, so that the process can tell who is teleoperating it.
+//!
+//! It also reports when connections open and close, and why they closed, see
+//! [`ConnectionRegistry::subscribe`].
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use futures::{channel::mpsc, Stream};
+
+/// Registry of active connections.
+///
+/// All clones share the same state, it can be queried from any thread.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionRegistry(Arc<Mutex<Registry>>);
+
+#[derive(Debug, Default)]
+struct Registry {
+    next_key: u64,
+    connections: BTreeMap<u64, ActiveConnection>,
+    subscribers: Vec<mpsc::UnboundedSender<ConnectionEvent>>,
+}
+
+impl Registry {
+    fn notify(&mut self, event: ConnectionEvent) {
+        // Dropped subscribers are forgotten
+        self.subscribers
+            .retain(|subscriber| subscriber.unbounded_send(event.clone()).is_ok());
+    }
+}
+
+impl ConnectionRegistry {
+    /// Creates a new empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the connections which are currently active, in connection order.
+    pub fn active_connections(&self) -> Vec<ActiveConnection> {
+        self.0
+            .lock()
+            .unwrap()
+            .connections
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Returns a stream of the events of the connections registered from now on.
+    ///
+    /// Connections already active when subscribing are only reported when they close.
+    pub fn subscribe(&self) -> impl Stream<Item = ConnectionEvent> + Send + Unpin {
+        let (sender, receiver) = mpsc::unbounded();
+        self.0.lock().unwrap().subscribers.push(sender);
+        receiver
+    }
+
+    /// Registers a connection until the returned guard is dropped.
+    pub(crate) fn register(&self, connection: ActiveConnection) -> Registration {
+        let mut registry = self.0.lock().unwrap();
+        let key = registry.next_key;
+        registry.next_key += 1;
+        registry.connections.insert(key, connection.clone());
+        registry.notify(ConnectionEvent::Opened {
+            id: key,
+            connection,
+        });
+        Registration {
+            registry: self.clone(),
+            key,
+            reason: Some(CloseReason::Dropped),
+        }
+    }
+}
+
+/// Guard removing a connection from the registry when dropped.
+pub(crate) struct Registration {
+    registry: ConnectionRegistry,
+    key: u64,
+    reason: Option<CloseReason>,
+}
+
+impl Registration {
+    /// Removes the connection from the registry, reporting why it closed.
+    pub(crate) fn close(mut self, reason: CloseReason) {
+        self.reason = Some(reason);
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut registry = self.registry.0.lock().unwrap();
+        registry.connections.remove(&self.key);
+        if let Some(reason) = self.reason.take() {
+            registry.notify(ConnectionEvent::Closed {
+                id: self.key,
+                reason,
+            });
+        }
+    }
+}
+
+/// Event of a connection reported by [`ConnectionRegistry::subscribe`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum ConnectionEvent {
+    /// A connection has been established.
+    Opened {
+        /// ID of the connection in the registry, not to be confused with the ID assigned by the
+        /// handshake.
+        id: u64,
+        /// The new connection.
+        connection: ActiveConnection,
+    },
+    /// A connection has been closed.
+    Closed {
+        /// ID of the connection in the registry.
+        id: u64,
+        /// Why the connection has been closed.
+        reason: CloseReason,
+    },
+}
+
+/// Why a connection has been closed.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum CloseReason {
+    /// The client closed the connection.
+    ///
+    /// A client which crashed is reported the same way, since its end of the connection is then
+    /// closed by the operating system.
+    ClientDisconnected,
+    /// The client stopped sending keepalive pings, see
+    /// [`ConnectionOptions::keepalive`](super::ConnectionOptions::keepalive).
+    PeerUnresponsive,
+    /// The connection reached its
+    /// [maximum lifetime](super::ConnectionOptions::max_lifetime).
+    SessionExpired,
+    /// The connection failed, e.g. the client sent a malformed message.
+    Error(capnp::Error),
+    /// The connection was dropped before terminating, e.g. the server is shutting down.
+    Dropped,
+}
+
+/// Connection tracked by a [`ConnectionRegistry`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ActiveConnection {
+    /// ID of the connection, if the handshake was exchanged.
+    pub connection_id: Option<u64>,
+    /// Credentials of the peer, if known.
+    pub peer: Option<PeerCredentials>,
+    /// When the connection was established.
+    pub connected_at: SystemTime,
+}
+
+/// Credentials of the process at the other end of a connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerCredentials {
+    /// ID of the process, not available on all platforms.
+    pub pid: Option<u32>,
+    /// Effective user ID of the process.
+    pub uid: u32,
+    /// Effective group ID of the process.
+    pub gid: u32,
+}
@@ -0,0 +1,60 @@
+//! Revocation of services handed out to clients.
+//!
+//! A service registered with
+//! [`register_revocable_service`](super::TeleopServer::register_revocable_service) is handed out
+//! behind a forwarding capability. Once its [`RevocationHandle`] is revoked, all calls made
+//! through capabilities previously obtained by clients fail with a disconnected error, while the
+//! connections keep running.
+//!
+//! Capabilities returned by the calls themselves are not wrapped and therefore not revoked.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use capnp::{
+    capability::Promise,
+    private::capability::{ClientHook, ParamsHook, ResultsHook},
+};
+
+use super::forwarding::CallInterceptor;
+
+/// Handle to revoke a service, it can be cloned and sent to other threads.
+#[derive(Clone, Debug, Default)]
+pub struct RevocationHandle(Arc<AtomicBool>);
+
+impl RevocationHandle {
+    /// Revokes the service.
+    ///
+    /// Revocation cannot be undone, the service must be registered again to be handed out anew.
+    pub fn revoke(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    /// Returns `true` if the service has been revoked.
+    pub fn is_revoked(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// Fails all calls once revoked.
+impl CallInterceptor for RevocationHandle {
+    fn intercept_call(
+        &self,
+        inner: &dyn ClientHook,
+        interface_id: u64,
+        method_id: u16,
+        params: Box<dyn ParamsHook>,
+        results: Box<dyn ResultsHook>,
+    ) -> Promise<(), capnp::Error> {
+        if self.is_revoked() {
+            return Promise::err(revoked_error());
+        }
+        inner.call(interface_id, method_id, params, results)
+    }
+}
+
+fn revoked_error() -> capnp::Error {
+    capnp::Error::disconnected("revoked".to_owned())
+}
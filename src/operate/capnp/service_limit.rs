@@ -0,0 +1,89 @@
+//! Limit of the number of distinct services requested on a connection.
+
+use std::{cell::RefCell, collections::BTreeSet, rc::Rc};
+
+use capnp::{
+    capability::Promise,
+    private::capability::{ClientHook, ParamsHook, ResultsHook},
+    traits::HasTypeId,
+};
+use futures::FutureExt;
+
+use super::{forwarding::CallInterceptor, teleop_capnp};
+
+/// Ordinal of the `service` method of `Teleop`.
+const SERVICE_METHOD_ID: u16 = 0;
+
+/// Interceptor of the root capability of a connection which fails `service` calls with an
+/// overloaded error once the connection resolved the maximum number of distinct services.
+///
+/// Services which could not be resolved, e.g. unknown ones, do not count.
+#[derive(Clone)]
+pub(crate) struct ServiceLimit {
+    max: usize,
+    // Shared by all the references of the capability on the connection
+    resolved: Rc<RefCell<BTreeSet<String>>>,
+}
+
+impl ServiceLimit {
+    pub(crate) fn new(max: usize) -> Self {
+        Self {
+            max,
+            resolved: Rc::default(),
+        }
+    }
+
+    /// Counts a new request of the named service, returns `true` if it was not counted yet.
+    fn acquire(&self, name: &str) -> Result<bool, capnp::Error> {
+        let mut resolved = self.resolved.borrow_mut();
+        if resolved.contains(name) {
+            return Ok(false);
+        }
+        if resolved.len() >= self.max {
+            return Err(capnp::Error::overloaded(format!(
+                "too many services requested on this connection (max {})",
+                self.max
+            )));
+        }
+        resolved.insert(name.to_owned());
+        Ok(true)
+    }
+}
+
+impl CallInterceptor for ServiceLimit {
+    fn intercept_call(
+        &self,
+        inner: &dyn ClientHook,
+        interface_id: u64,
+        method_id: u16,
+        params: Box<dyn ParamsHook>,
+        results: Box<dyn ResultsHook>,
+    ) -> Promise<(), capnp::Error> {
+        if interface_id != teleop_capnp::teleop::Client::TYPE_ID || method_id != SERVICE_METHOD_ID {
+            return inner.call(interface_id, method_id, params, results);
+        }
+        let name = match params
+            .get()
+            .and_then(|params| params.get_as::<teleop_capnp::teleop::service_params::Reader>())
+            .and_then(|params| Ok(params.get_name()?.to_str()?.to_owned()))
+        {
+            Ok(name) => name,
+            Err(err) => return Promise::err(err),
+        };
+        let counted = match self.acquire(&name) {
+            Ok(counted) => counted,
+            Err(err) => return Promise::err(err),
+        };
+        let call = inner.call(interface_id, method_id, params, results);
+        if !counted {
+            return call;
+        }
+        let resolved = self.resolved.clone();
+        Promise::from_future(call.map(move |res| {
+            if res.is_err() {
+                resolved.borrow_mut().remove(&name);
+            }
+            res
+        }))
+    }
+}
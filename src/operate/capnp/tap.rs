@@ -0,0 +1,232 @@
+//! Tap of the bytes exchanged on the wire, to debug the RPC protocol.
+//!
+//! A [`Tap`] wraps a stream and reports every chunk of bytes read or written to a [`TapSink`],
+//! before the Cap'n Proto network sees them, e.g. to diagnose a protocol mismatch between a client
+//! and a server. [`TapSink::hex_dump`] writes them in the classic hexadecimal dump format.
+//!
+//! See [`run_server_connection_with_tap`](super::run_server_connection_with_tap) and
+//! [`client_connection_with_tap`](super::client_connection_with_tap).
+
+use std::{
+    cell::RefCell,
+    fmt::Write as _,
+    io::{Error, Write},
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+use futures::{AsyncRead, AsyncWrite};
+
+/// Number of bytes per line of a hexadecimal dump.
+const HEX_DUMP_WIDTH: usize = 16;
+
+/// Direction of the bytes reported to a [`TapSink`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Bytes read from the peer.
+    Read,
+    /// Bytes written to the peer.
+    Written,
+}
+
+/// Function called with every chunk of bytes seen by a [`Tap`].
+type TapFn = dyn FnMut(Direction, &[u8]);
+
+/// Destination of the bytes seen by a [`Tap`], shared by the input and the output of a
+/// connection.
+#[derive(Clone)]
+pub struct TapSink(Rc<RefCell<TapFn>>);
+
+impl TapSink {
+    /// Creates a sink calling the passed function with every chunk of bytes.
+    pub fn new(f: impl FnMut(Direction, &[u8]) + 'static) -> Self {
+        Self(Rc::new(RefCell::new(f)))
+    }
+
+    /// Creates a sink writing a hexadecimal dump of every chunk of bytes to the passed writer.
+    ///
+    /// Failures to write the dump are ignored, they must not break the connection.
+    pub fn hex_dump(mut writer: impl Write + 'static) -> Self {
+        Self::new(move |direction, bytes| {
+            let _ = writer.write_all(hex_dump(direction, bytes).as_bytes());
+        })
+    }
+
+    fn report(&self, direction: Direction, bytes: &[u8]) {
+        if !bytes.is_empty() {
+            (self.0.borrow_mut())(direction, bytes);
+        }
+    }
+}
+
+/// Formats a chunk of bytes as a header followed by lines made of the offset, the bytes in
+/// hexadecimal and their printable characters.
+pub fn hex_dump(direction: Direction, bytes: &[u8]) -> String {
+    let arrow = match direction {
+        Direction::Read => "<<",
+        Direction::Written => ">>",
+    };
+    let mut dump = format!("{arrow} {} bytes\n", bytes.len());
+    for (i, line) in bytes.chunks(HEX_DUMP_WIDTH).enumerate() {
+        let _ = write!(dump, "{:08x} ", i * HEX_DUMP_WIDTH);
+        for byte in line {
+            let _ = write!(dump, " {byte:02x}");
+        }
+        dump.push_str(&"   ".repeat(HEX_DUMP_WIDTH - line.len()));
+        dump.push_str("  |");
+        dump.extend(line.iter().map(|byte| {
+            if byte.is_ascii_graphic() || *byte == b' ' {
+                *byte as char
+            } else {
+                '.'
+            }
+        }));
+        dump.push_str("|\n");
+    }
+    dump
+}
+
+/// Stream wrapper which reports the bytes read and written to a [`TapSink`].
+pub struct Tap<S> {
+    inner: S,
+    sink: TapSink,
+}
+
+impl<S> Tap<S> {
+    /// Wraps the passed stream.
+    pub fn new(inner: S, sink: TapSink) -> Self {
+        Self { inner, sink }
+    }
+}
+
+impl<S> AsyncRead for Tap<S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        let read = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(len)) = read {
+            this.sink.report(Direction::Read, &buf[..len]);
+        }
+        read
+    }
+}
+
+impl<S> AsyncWrite for Tap<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        let written = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(len)) = written {
+            this.sink.report(Direction::Written, &buf[..len]);
+        }
+        written
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use futures::task::LocalSpawnExt;
+
+    use super::*;
+    use crate::operate::capnp::{
+        client_connection_with_tap,
+        echo::{echo_capnp, EchoServer},
+        run_server_connection, TeleopServer,
+    };
+
+    #[test]
+    fn test_hex_dump() {
+        assert_eq!(
+            hex_dump(Direction::Written, b"hello, teleop!\x00\x01\xff"),
+            ">> 17 bytes\n\
+             00000000  68 65 6c 6c 6f 2c 20 74 65 6c 65 6f 70 21 00 01  |hello, teleop!..|\n\
+             00000010  ff                                               |.|\n"
+        );
+    }
+
+    #[test]
+    fn test_capnp_tap() {
+        let (client_input, server_output) = sluice::pipe::pipe();
+        let (server_input, client_output) = sluice::pipe::pipe();
+
+        let mut server = TeleopServer::new();
+        server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+        let client = server.into_client();
+
+        let captured = Rc::new(RefCell::new(Vec::new()));
+        let sink = TapSink::new({
+            let captured = captured.clone();
+            move |direction, bytes| captured.borrow_mut().push((direction, bytes.to_vec()))
+        });
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawn = exec.spawner();
+
+        spawn
+            .spawn_local(async move {
+                if let Err(e) =
+                    run_server_connection(server_input, server_output, client.client.hook).await
+                {
+                    eprintln!("Server connection interrupted {e}");
+                }
+            })
+            .unwrap();
+
+        let res = exec.run_until(async move {
+            let (rpc_system, teleop) =
+                client_connection_with_tap(client_input, client_output, sink).await;
+            spawn.spawn_local(async {
+                if let Err(e) = rpc_system.await {
+                    eprintln!("Connection interrupted {e}");
+                }
+            })?;
+
+            let mut req = teleop.service_request();
+            req.get().set_name("echo");
+            let echo = req.send().promise.await?;
+            let echo: echo_capnp::echo::Client = echo.get()?.get_service().get_as()?;
+            let mut req = echo.echo_request();
+            req.get().set_message("tapped!");
+            let reply = req.send().promise.await?;
+            assert_eq!(reply.get()?.get_reply()?.to_str()?, "tapped!");
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+
+        let bytes = |expected| {
+            captured
+                .borrow()
+                .iter()
+                .filter(|(direction, _)| *direction == expected)
+                .flat_map(|(_, bytes)| bytes.clone())
+                .collect::<Vec<_>>()
+        };
+        let contains = |bytes: &[u8], text: &[u8]| bytes.windows(text.len()).any(|w| w == text);
+        assert!(contains(&bytes(Direction::Written), b"tapped!"));
+        assert!(contains(&bytes(Direction::Read), b"tapped!"));
+    }
+}
@@ -0,0 +1,93 @@
+//! Recording of the error which terminates a connection.
+//!
+//! The RPC system aborts the connection when it fails, e.g. on a malformed message, but resolves
+//! successfully anyway. [`RecordingNetwork`] wraps the network in order to catch the error passed
+//! to the connection on shutdown.
+
+use std::{cell::RefCell, rc::Rc};
+
+use capnp::capability::Promise;
+use capnp_rpc::{
+    rpc_twoparty_capnp::Side, Connection, FlowController, IncomingMessage, OutgoingMessage,
+    VatNetwork,
+};
+use futures::TryFutureExt;
+
+/// Error which terminated a connection, shared by the network and its connections.
+pub(crate) type TerminationError = Rc<RefCell<Option<capnp::Error>>>;
+
+/// Network recording the error which terminates its connections.
+pub(crate) struct RecordingNetwork<N> {
+    inner: N,
+    error: TerminationError,
+}
+
+impl<N> RecordingNetwork<N> {
+    pub(crate) fn new(inner: N) -> (Self, TerminationError) {
+        let error = TerminationError::default();
+        (
+            Self {
+                inner,
+                error: error.clone(),
+            },
+            error,
+        )
+    }
+}
+
+impl<N> VatNetwork<Side> for RecordingNetwork<N>
+where
+    N: VatNetwork<Side>,
+{
+    fn connect(&mut self, host_id: Side) -> Option<Box<dyn Connection<Side>>> {
+        let error = self.error.clone();
+        self.inner
+            .connect(host_id)
+            .map(|inner| Box::new(RecordingConnection { inner, error }) as _)
+    }
+
+    fn accept(&mut self) -> Promise<Box<dyn Connection<Side>>, capnp::Error> {
+        let error = self.error.clone();
+        Promise::from_future(
+            self.inner
+                .accept()
+                .map_ok(|inner| Box::new(RecordingConnection { inner, error }) as _),
+        )
+    }
+
+    fn drive_until_shutdown(&mut self) -> Promise<(), capnp::Error> {
+        self.inner.drive_until_shutdown()
+    }
+}
+
+struct RecordingConnection {
+    inner: Box<dyn Connection<Side>>,
+    error: TerminationError,
+}
+
+impl Connection<Side> for RecordingConnection {
+    fn get_peer_vat_id(&self) -> Side {
+        self.inner.get_peer_vat_id()
+    }
+
+    fn new_outgoing_message(&mut self, first_segment_word_size: u32) -> Box<dyn OutgoingMessage> {
+        self.inner.new_outgoing_message(first_segment_word_size)
+    }
+
+    fn receive_incoming_message(
+        &mut self,
+    ) -> Promise<Option<Box<dyn IncomingMessage>>, capnp::Error> {
+        self.inner.receive_incoming_message()
+    }
+
+    fn new_stream(&mut self) -> (Box<dyn FlowController>, Promise<(), capnp::Error>) {
+        self.inner.new_stream()
+    }
+
+    fn shutdown(&mut self, result: capnp::Result<()>) -> Promise<(), capnp::Error> {
+        if let Err(err) = &result {
+            self.error.borrow_mut().get_or_insert_with(|| err.clone());
+        }
+        self.inner.shutdown(result)
+    }
+}
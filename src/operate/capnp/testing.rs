@@ -0,0 +1,230 @@
+//! Helpers to test services without attaching to a process.
+//!
+//! Enabled with the `testing` feature.
+//!
+//! [`spawn_teleop_target`] runs a server in a child process instead, for end to end tests which
+//! really attach to it.
+
+#[cfg(any(unix, windows))]
+use std::{
+    io::{BufRead, Write},
+    pin::pin,
+    process::{Child, Command, Stdio},
+    task::Poll,
+};
+
+use futures::{
+    io::{BufReader, BufWriter},
+    task::{LocalSpawn, LocalSpawnExt, SpawnError},
+};
+
+use super::{client_network, teleop_capnp, CapnpProtocol, TeleopServer};
+use crate::operate::Protocol;
+
+/// Environment variable holding the name of the test acting as a target in a child process.
+#[cfg(any(unix, windows))]
+const TARGET_TEST_VAR: &str = "TELEOP_TARGET_TEST";
+
+/// Line written by the child process once it is ready to be attached.
+#[cfg(any(unix, windows))]
+const TARGET_READY: &str = "teleop target ready";
+
+/// Connects a new client to the passed server through an in-memory pipe.
+///
+/// Both the server and the client RPC systems are spawned on the passed local executor. The
+/// returned client is ready to request services as soon as the executor runs.
+pub fn connected_pair<S>(
+    server: TeleopServer,
+    spawner: &S,
+) -> Result<teleop_capnp::teleop::Client, SpawnError>
+where
+    S: LocalSpawn,
+{
+    let (client_input, server_output) = sluice::pipe::pipe();
+    let (server_input, client_output) = sluice::pipe::pipe();
+
+    let serve = CapnpProtocol::default().serve(&server.into_client(), server_input, server_output);
+    spawner.spawn_local(async move {
+        if let Err(e) = serve.await {
+            eprintln!("Server connection interrupted {e}");
+        }
+    })?;
+
+    let (rpc_system, teleop) =
+        client_network(BufReader::new(client_input), BufWriter::new(client_output));
+    spawner.spawn_local(async {
+        if let Err(e) = rpc_system.await {
+            eprintln!("Client connection interrupted {e}");
+        }
+    })?;
+
+    Ok(teleop)
+}
+
+/// Spawns a child process serving the server built by `services` with the
+/// [default attacher](crate::attach::attacher::DefaultAttacher), and returns it with its ID so
+/// that the test can [`connect`](crate::attach::connect) to it.
+///
+/// The child process re-executes the current test binary, running only the test named
+/// `test_name`, e.g. `tests::test_attach` in a unit test or `test_attach` in an integration test.
+/// In the child process, this function serves forever instead of returning, so it must be called
+/// by that very test, before anything the child must not do.
+///
+/// It returns once the child process is ready to be attached. The test is responsible for
+/// killing it.
+#[cfg(any(unix, windows))]
+pub fn spawn_teleop_target(
+    test_name: &str,
+    services: impl FnOnce() -> TeleopServer,
+) -> Result<(Child, u32), Box<dyn std::error::Error>> {
+    if std::env::var_os(TARGET_TEST_VAR).is_some_and(|name| name == test_name) {
+        run_teleop_target(services());
+    }
+
+    let mut child = Command::new(std::env::current_exe()?)
+        .args([test_name, "--exact", "--test-threads=1"])
+        .env(TARGET_TEST_VAR, test_name)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let pid = child.id();
+
+    // The harness writes its own output before running the test
+    let mut stdout = std::io::BufReader::new(child.stdout.take().expect("piped stdout"));
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if stdout.read_line(&mut line)? == 0 {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("Target test {test_name} exited before being ready").into());
+        }
+        // The harness does not end the line naming the test before running it
+        if line.trim_end().ends_with(TARGET_READY) {
+            break;
+        }
+    }
+
+    Ok((child, pid))
+}
+
+#[cfg(any(unix, windows))]
+fn run_teleop_target(server: TeleopServer) -> ! {
+    use crate::{
+        attach::attacher::DefaultAttacher, cancellation::CancellationToken, operate::capnp::serve,
+    };
+
+    let mut exec = futures::executor::LocalPool::new();
+    let spawner = exec.spawner();
+    let mut serving = pin!(serve::<DefaultAttacher, _>(
+        server,
+        CancellationToken::new(),
+        &spawner
+    ));
+    // Listen for the attach signal before telling the parent
+    if let Poll::Ready(res) = exec.run_until(async { futures::poll!(serving.as_mut()) }) {
+        exit_teleop_target(res);
+    }
+
+    // Bypass the output capture of the harness
+    let mut stdout = std::io::stdout().lock();
+    let _ = writeln!(stdout, "{TARGET_READY}").and_then(|()| stdout.flush());
+    drop(stdout);
+
+    exit_teleop_target(exec.run_until(serving))
+}
+
+#[cfg(any(unix, windows))]
+fn exit_teleop_target(res: Result<(), Box<dyn std::error::Error>>) -> ! {
+    match res {
+        Ok(()) => std::process::exit(0),
+        Err(err) => {
+            eprintln!("Target server interrupted: {err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use super::*;
+    use crate::operate::capnp::echo::{echo_capnp, EchoServer};
+
+    #[test]
+    fn test_connected_pair() {
+        let mut server = TeleopServer::new();
+        server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            let mut req = teleop.service_request();
+            req.get().set_name("echo");
+            let echo = req.send().promise.await?;
+            let echo: echo_capnp::echo::Client = echo.get()?.get_service().get_as()?;
+
+            let mut req = echo.echo_request();
+            req.get().set_message("hello!");
+            let reply = req.send().promise.await?;
+            assert_eq!(reply.get()?.get_reply()?.to_str()?, "hello!");
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    // Attaching to another process requires finding its working directory
+    #[cfg(all(any(unix, windows), feature = "discover"))]
+    #[test]
+    fn test_spawn_teleop_target() {
+        use futures::AsyncReadExt;
+
+        use crate::{
+            attach::{attacher::DefaultAttacher, connect},
+            operate::capnp::client_connection,
+        };
+
+        let (mut child, pid) = spawn_teleop_target(
+            "operate::capnp::testing::tests::test_spawn_teleop_target",
+            || {
+                let mut server = TeleopServer::new();
+                server.register_service::<echo_capnp::echo::Client, _, _>("echo", || EchoServer);
+                server
+            },
+        )
+        .unwrap();
+        assert_ne!(pid, std::process::id());
+
+        let mut exec = futures::executor::LocalPool::new();
+        let spawner = exec.spawner();
+
+        let res = exec.run_until(async {
+            let stream = connect::<DefaultAttacher>(pid).await?;
+            let (input, output) = stream.split();
+            let (rpc_system, teleop) = client_connection(input, output).await;
+            spawner.spawn_local(async {
+                let _ = rpc_system.await;
+            })?;
+
+            let mut req = teleop.service_request();
+            req.get().set_name("echo");
+            let echo = req.send().promise.await?;
+            let echo: echo_capnp::echo::Client = echo.get()?.get_service().get_as()?;
+
+            let mut req = echo.echo_request();
+            req.get().set_message("hello!");
+            let reply = req.send().promise.await?;
+            assert_eq!(reply.get()?.get_reply()?.to_str()?, "hello!");
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        res.unwrap();
+    }
+}
@@ -0,0 +1,80 @@
+//! Timing of the calls made to services handed out to clients.
+//!
+//! A service registered with
+//! [`register_service_timed`](super::TeleopServer::register_service_timed) is handed out behind a
+//! forwarding capability which measures every call, successful or not, until its results are
+//! ready. The [`CallTimings`] handle reports how many calls were made and how long they took in
+//! total, e.g. to find out which service of a teleoperated process is slow.
+//!
+//! Calls are not told apart by method, since method names are not available at run time.
+//!
+//! Capabilities returned by the calls themselves are not wrapped and therefore not timed.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use capnp::{
+    capability::Promise,
+    private::capability::{ClientHook, ParamsHook, ResultsHook},
+};
+
+use super::forwarding::CallInterceptor;
+
+/// Statistics of the calls made to a service, see [`CallTimings::stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CallStats {
+    /// Number of completed calls.
+    pub calls: u64,
+    /// Total time spent in completed calls.
+    pub total_time: Duration,
+}
+
+impl CallStats {
+    /// Returns the average time of a call, `None` if no call completed yet.
+    pub fn mean_time(&self) -> Option<Duration> {
+        u32::try_from(self.calls)
+            .ok()
+            .filter(|calls| *calls > 0)
+            .map(|calls| self.total_time / calls)
+    }
+}
+
+/// Handle to the timings of a service, it can be cloned and sent to other threads.
+#[derive(Clone, Debug, Default)]
+pub struct CallTimings(Arc<Mutex<CallStats>>);
+
+impl CallTimings {
+    /// Returns a snapshot of the statistics of the calls completed so far.
+    pub fn stats(&self) -> CallStats {
+        *self.0.lock().unwrap()
+    }
+
+    fn record(&self, elapsed: Duration) {
+        let mut stats = self.0.lock().unwrap();
+        stats.calls += 1;
+        stats.total_time += elapsed;
+    }
+}
+
+/// Measures all calls.
+impl CallInterceptor for CallTimings {
+    fn intercept_call(
+        &self,
+        inner: &dyn ClientHook,
+        interface_id: u64,
+        method_id: u16,
+        params: Box<dyn ParamsHook>,
+        results: Box<dyn ResultsHook>,
+    ) -> Promise<(), capnp::Error> {
+        let timings = self.clone();
+        let start = Instant::now();
+        let call = inner.call(interface_id, method_id, params, results);
+        Promise::from_future(async move {
+            let res = call.await;
+            timings.record(start.elapsed());
+            res
+        })
+    }
+}
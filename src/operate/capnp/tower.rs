@@ -0,0 +1,117 @@
+//! Bridge exposing [`tower::Service`](::tower::Service)s over Teleop.
+//!
+//! The service is wrapped into a generic `TowerService` interface (see `tower.capnp`) which takes
+//! raw bytes and returns raw bytes, no Cap'n Proto schema is needed.
+
+use std::{cell::RefCell, future::poll_fn};
+
+use ::tower::{BoxError, Service};
+use bytes::Bytes;
+use tower_capnp::tower_service::{CallParams, CallResults, Server};
+
+capnp::generated_code!(pub mod tower_capnp);
+
+/// Server wrapping a [`tower::Service`](::tower::Service).
+///
+/// It is usually registered with
+/// [`TeleopServer::register_tower_service`](super::TeleopServer::register_tower_service).
+pub struct TowerServer<S> {
+    service: RefCell<S>,
+}
+
+impl<S> TowerServer<S> {
+    /// Wraps the passed service.
+    pub fn new(service: S) -> Self {
+        Self {
+            service: RefCell::new(service),
+        }
+    }
+}
+
+impl<S> Server for TowerServer<S>
+where
+    S: Service<Bytes, Response = Bytes> + 'static,
+    S::Error: Into<BoxError>,
+{
+    async fn call(
+        self: capnp::capability::Rc<Self>,
+        params: CallParams,
+        mut results: CallResults,
+    ) -> Result<(), capnp::Error> {
+        let request = Bytes::copy_from_slice(params.get()?.get_request()?);
+        poll_fn(|cx| self.service.borrow_mut().poll_ready(cx))
+            .await
+            .map_err(service_error)?;
+        // No await point between readiness and the call, other requests cannot steal readiness
+        let call = self.service.borrow_mut().call(request);
+        let response = call.await.map_err(service_error)?;
+        results.get().set_response(&response);
+        Ok(())
+    }
+}
+
+fn service_error(err: impl Into<BoxError>) -> capnp::Error {
+    capnp::Error::failed(err.into().to_string())
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use std::{
+        future::{ready, Ready},
+        task::{Context, Poll},
+    };
+
+    use super::*;
+    use crate::operate::capnp::{testing::connected_pair, TeleopServer};
+
+    struct EchoService;
+
+    impl Service<Bytes> for EchoService {
+        type Response = Bytes;
+        type Error = BoxError;
+        type Future = Ready<Result<Bytes, BoxError>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Bytes) -> Self::Future {
+            if request.is_empty() {
+                ready(Err("empty request".into()))
+            } else {
+                ready(Ok(request))
+            }
+        }
+    }
+
+    #[test]
+    fn test_capnp_tower() {
+        let mut server = TeleopServer::new();
+        server.register_tower_service("echo", EchoService);
+
+        let mut exec = futures::executor::LocalPool::new();
+        let teleop = connected_pair(server, &exec.spawner()).unwrap();
+
+        let res = exec.run_until(async move {
+            let mut req = teleop.service_request();
+            req.get().set_name("echo");
+            let echo = req.send().promise.await?;
+            let echo: tower_capnp::tower_service::Client = echo.get()?.get_service().get_as()?;
+
+            let mut req = echo.call_request();
+            req.get().set_request(b"hello!");
+            let reply = req.send().promise.await?;
+            assert_eq!(reply.get()?.get_response()?, b"hello!");
+
+            let mut req = echo.call_request();
+            req.get().set_request(b"");
+            let err = req.send().promise.await.err().unwrap();
+            assert!(err.extra.contains("empty request"));
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+}
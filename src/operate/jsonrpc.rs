@@ -0,0 +1,319 @@
+//! JSON-RPC 2.0 over newline-delimited JSON.
+//!
+//! This is an alternative to Cap'n Proto for clients which speak JSON more easily, e.g. debugging
+//! tools. It runs over the same streams, opened by the [`attach`](crate::attach) module.
+//!
+//! [`JsonRpcServer`] dispatches requests to registered methods, [`JsonRpcProtocol`] implements
+//! [`Protocol`] to run it on a connection and to set up a [`JsonRpcClient`].
+//!
+//! Every message is a single JSON object on its own line. Requests are handled one at a time, in
+//! order. Notifications, i.e. requests without ID, are handled but get no response.
+//!
+//! Enabled with the `jsonrpc` feature.
+
+use std::{collections::BTreeMap, future::Future, rc::Rc};
+
+use futures::{
+    future::LocalBoxFuture,
+    io::{BufReader, Lines},
+    AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, FutureExt, StreamExt,
+};
+use serde_json::{json, Value};
+
+use super::Protocol;
+
+/// Invalid JSON was received.
+const PARSE_ERROR: i64 = -32700;
+
+/// The JSON sent is not a valid request object.
+const INVALID_REQUEST: i64 = -32600;
+
+/// The method does not exist.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// The method failed.
+const SERVER_ERROR: i64 = -32000;
+
+type Method =
+    Box<dyn Fn(Value) -> LocalBoxFuture<'static, Result<Value, Box<dyn std::error::Error>>>>;
+
+/// Server dispatching JSON-RPC requests to registered methods.
+#[derive(Default)]
+pub struct JsonRpcServer {
+    methods: BTreeMap<String, Method>,
+}
+
+impl JsonRpcServer {
+    /// Creates a new server with no methods registered.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a new method.
+    ///
+    /// The handler receives the parameters of the request, `null` if there are none, and returns
+    /// the result of the call. Errors are reported to the client as server errors.
+    pub fn register_method<F, Fut>(&mut self, name: impl Into<String>, handler: F)
+    where
+        F: Fn(Value) -> Fut + 'static,
+        Fut: Future<Output = Result<Value, Box<dyn std::error::Error>>> + 'static,
+    {
+        self.methods.insert(
+            name.into(),
+            Box::new(move |params| handler(params).boxed_local()),
+        );
+    }
+
+    /// Handles one line received from a client, returns the response line if any.
+    async fn handle(&self, line: &str) -> Option<Value> {
+        let request = match serde_json::from_str::<Value>(line) {
+            Ok(request) => request,
+            Err(err) => return Some(error_response(Value::Null, PARSE_ERROR, err.to_string())),
+        };
+        let id = request.get("id").cloned();
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            return Some(error_response(
+                id.unwrap_or(Value::Null),
+                INVALID_REQUEST,
+                "missing method".to_owned(),
+            ));
+        };
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+        let result = match self.methods.get(method) {
+            Some(handler) => handler(params)
+                .await
+                .map_err(|err| (SERVER_ERROR, err.to_string())),
+            None => Err((METHOD_NOT_FOUND, format!("method {method} not found"))),
+        };
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => error_response(id, code, message),
+        })
+    }
+}
+
+fn error_response(id: Value, code: i64, message: String) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+/// Echo method, the JSON-RPC counterpart of the Cap'n Proto echo service.
+///
+/// It takes `{"message": ...}` and returns `{"reply": ...}` with the same message.
+pub async fn echo(params: Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let message = params
+        .get("message")
+        .and_then(Value::as_str)
+        .ok_or("missing message")?;
+    Ok(json!({ "reply": message }))
+}
+
+/// JSON-RPC implementation of [`Protocol`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonRpcProtocol;
+
+impl Protocol for JsonRpcProtocol {
+    type Server = Rc<JsonRpcServer>;
+    type Client = JsonRpcClient;
+    type Error = std::io::Error;
+
+    fn serve<R, W>(
+        &self,
+        server: &Self::Server,
+        input: R,
+        mut output: W,
+    ) -> impl Future<Output = Result<(), Self::Error>> + 'static
+    where
+        R: AsyncRead + Unpin + 'static,
+        W: AsyncWrite + Unpin + 'static,
+    {
+        let server = server.clone();
+        async move {
+            let mut lines = BufReader::new(input).lines();
+            while let Some(line) = lines.next().await {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                if let Some(response) = server.handle(&line).await {
+                    write_message(&mut output, &response).await?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn connect<R, W>(
+        &self,
+        input: R,
+        output: W,
+    ) -> impl Future<Output = Result<Self::Client, Self::Error>> + 'static
+    where
+        R: AsyncRead + Unpin + 'static,
+        W: AsyncWrite + Unpin + 'static,
+    {
+        let client = JsonRpcClient {
+            input: BufReader::new(Box::new(input) as Box<dyn AsyncRead + Unpin>).lines(),
+            output: Box::new(output),
+            next_id: 0,
+        };
+        futures::future::ok(client)
+    }
+}
+
+/// Client side of a JSON-RPC connection.
+pub struct JsonRpcClient {
+    input: Lines<BufReader<Box<dyn AsyncRead + Unpin>>>,
+    output: Box<dyn AsyncWrite + Unpin>,
+    next_id: u64,
+}
+
+impl JsonRpcClient {
+    /// Calls a method and waits for its result.
+    ///
+    /// An error response of the server is turned into an error.
+    pub async fn call(
+        &mut self,
+        method: &str,
+        params: Value,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        write_message(&mut self.output, &request).await?;
+
+        while let Some(line) = self.input.next().await {
+            let mut response = serde_json::from_str::<Value>(&line?)?;
+            if response.get("id") != Some(&Value::from(id)) {
+                // Not the answer to this request
+                continue;
+            }
+            if let Some(error) = response.get("error") {
+                return Err(format!(
+                    "JSON-RPC error {}: {}",
+                    error.get("code").unwrap_or(&Value::Null),
+                    error
+                        .get("message")
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                )
+                .into());
+            }
+            return Ok(response
+                .get_mut("result")
+                .map(Value::take)
+                .unwrap_or_default());
+        }
+        Err("Connection closed before the response".into())
+    }
+}
+
+async fn write_message<W>(output: &mut W, message: &Value) -> Result<(), std::io::Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    output.write_all(&line).await?;
+    output.flush().await
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use futures::{executor::LocalPool, task::LocalSpawnExt, AsyncReadExt};
+
+    use super::*;
+
+    fn echo_server() -> Rc<JsonRpcServer> {
+        let mut server = JsonRpcServer::new();
+        server.register_method("echo", echo);
+        Rc::new(server)
+    }
+
+    #[test]
+    fn test_jsonrpc_framing() {
+        let (input, mut client_output) = sluice::pipe::pipe();
+        let (mut client_input, output) = sluice::pipe::pipe();
+
+        let mut exec = LocalPool::new();
+        exec.spawner()
+            .spawn_local({
+                let serve = JsonRpcProtocol.serve(&echo_server(), input, output);
+                async move {
+                    serve.await.unwrap();
+                }
+            })
+            .unwrap();
+
+        let res = exec.run_until(async move {
+            let requests = [
+                r#"{"jsonrpc":"2.0","id":1,"method":"echo","params":{"message":"hello"}}"#,
+                // Notification
+                r#"{"jsonrpc":"2.0","method":"echo","params":{"message":"ignored"}}"#,
+                r#"{"jsonrpc":"2.0","id":2,"method":"unknown"}"#,
+                "not json",
+            ];
+            for request in requests {
+                client_output.write_all(request.as_bytes()).await?;
+                client_output.write_all(b"\n").await?;
+            }
+            client_output.close().await?;
+
+            let mut received = String::new();
+            client_input.read_to_string(&mut received).await?;
+            assert!(received.ends_with('\n'));
+            let responses = received
+                .lines()
+                .map(serde_json::from_str)
+                .collect::<Result<Vec<Value>, _>>()?;
+            assert_eq!(responses.len(), 3);
+            assert_eq!(
+                responses[0],
+                json!({ "jsonrpc": "2.0", "id": 1, "result": { "reply": "hello" } })
+            );
+            assert_eq!(responses[1]["id"], 2);
+            assert_eq!(responses[1]["error"]["code"], METHOD_NOT_FOUND);
+            assert_eq!(responses[1]["error"]["message"], "method unknown not found");
+            assert_eq!(responses[2]["id"], Value::Null);
+            assert_eq!(responses[2]["error"]["code"], PARSE_ERROR);
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_jsonrpc_client() {
+        let (input, client_output) = sluice::pipe::pipe();
+        let (client_input, output) = sluice::pipe::pipe();
+
+        let mut exec = LocalPool::new();
+        exec.spawner()
+            .spawn_local({
+                let serve = JsonRpcProtocol.serve(&echo_server(), input, output);
+                async move {
+                    serve.await.unwrap();
+                }
+            })
+            .unwrap();
+
+        let res = exec.run_until(async move {
+            let mut client = JsonRpcProtocol.connect(client_input, client_output).await?;
+            let reply = client.call("echo", json!({ "message": "hello" })).await?;
+            assert_eq!(reply, json!({ "reply": "hello" }));
+
+            let err = client.call("echo", Value::Null).await.unwrap_err();
+            assert_eq!(err.to_string(), "JSON-RPC error -32000: missing message");
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+}
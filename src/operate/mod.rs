@@ -1,5 +1,118 @@
 //! Sub-module where RPC capabilities are located.
 //!
-//! [`capnp`] exposes RPC using Cap'n Proto protocol.
+//! [`Protocol`] abstracts the RPC protocol run over the streams opened by the
+//! [`attach`](crate::attach) module.
+//!
+//! [`capnp`] exposes RPC using Cap'n Proto protocol, see
+//! [`CapnpProtocol`](capnp::CapnpProtocol). `jsonrpc` exposes JSON-RPC, if the `jsonrpc` feature
+//! is enabled.
+//!
+//! [`raw`] echoes length-prefixed bytes, without any RPC protocol, to check that the attach
+//! transport works.
 
 pub mod capnp;
+#[cfg(feature = "jsonrpc")]
+pub mod jsonrpc;
+pub mod raw;
+
+use std::future::Future;
+
+use futures::{AsyncRead, AsyncWrite};
+
+/// RPC protocol run over a connected stream.
+///
+/// The attach mechanisms only deal with streams, so any protocol implementing this trait can reuse
+/// them: the process to be teleoperated runs [`serve`](Protocol::serve) on every incoming
+/// connection, and the client runs [`connect`](Protocol::connect) on the stream it opened.
+///
+/// Both futures are `'static` so that they can be spawned, they must not borrow the protocol nor
+/// the server.
+///
+/// # Implementing a protocol
+///
+/// A protocol defines what the server shares with its connections, what the client gets once
+/// connected, and how both sides exchange messages. For instance, a protocol replying to every
+/// line with a prefixed copy of it:
+///
+/// ```
+/// use std::future::Future;
+///
+/// use futures::{
+///     io::BufReader, AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt,
+///     StreamExt,
+/// };
+/// use teleop::operate::Protocol;
+///
+/// struct Lines;
+///
+/// impl Protocol for Lines {
+///     // Prefix of the replies
+///     type Server = String;
+///     type Client = (Box<dyn AsyncBufRead + Unpin>, Box<dyn AsyncWrite + Unpin>);
+///     type Error = std::io::Error;
+///
+///     fn serve<R, W>(
+///         &self,
+///         prefix: &String,
+///         input: R,
+///         mut output: W,
+///     ) -> impl Future<Output = Result<(), Self::Error>> + 'static
+///     where
+///         R: AsyncRead + Unpin + 'static,
+///         W: AsyncWrite + Unpin + 'static,
+///     {
+///         let prefix = prefix.clone();
+///         async move {
+///             let mut lines = BufReader::new(input).lines();
+///             while let Some(line) = lines.next().await {
+///                 output.write_all(format!("{prefix}{}\n", line?).as_bytes()).await?;
+///                 output.flush().await?;
+///             }
+///             Ok(())
+///         }
+///     }
+///
+///     fn connect<R, W>(
+///         &self,
+///         input: R,
+///         output: W,
+///     ) -> impl Future<Output = Result<Self::Client, Self::Error>> + 'static
+///     where
+///         R: AsyncRead + Unpin + 'static,
+///         W: AsyncWrite + Unpin + 'static,
+///     {
+///         let input: Box<dyn AsyncBufRead + Unpin> = Box::new(BufReader::new(input));
+///         let output: Box<dyn AsyncWrite + Unpin> = Box::new(output);
+///         futures::future::ok((input, output))
+///     }
+/// }
+/// ```
+pub trait Protocol {
+    /// State shared by the server with all its connections, e.g. the root capability.
+    type Server;
+    /// Client side of a connection.
+    type Client;
+    /// Error of a connection.
+    type Error;
+
+    /// Runs the server side of a connection until it terminates.
+    fn serve<R, W>(
+        &self,
+        server: &Self::Server,
+        input: R,
+        output: W,
+    ) -> impl Future<Output = Result<(), Self::Error>> + 'static
+    where
+        R: AsyncRead + Unpin + 'static,
+        W: AsyncWrite + Unpin + 'static;
+
+    /// Sets up the client side of a connection.
+    fn connect<R, W>(
+        &self,
+        input: R,
+        output: W,
+    ) -> impl Future<Output = Result<Self::Client, Self::Error>> + 'static
+    where
+        R: AsyncRead + Unpin + 'static,
+        W: AsyncWrite + Unpin + 'static;
+}
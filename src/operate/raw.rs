@@ -0,0 +1,171 @@
+//! Echo over raw bytes, without any RPC protocol.
+//!
+//! It verifies that the streams opened by the [`attach`](crate::attach) module work end to end,
+//! e.g. in a debugging probe, before layering a real protocol on top of them.
+//!
+//! Every message is framed with its length, as a big endian `u32`, followed by its bytes.
+//! [`serve_echo`] sends every message back as is, [`client_echo`] sends one message and waits for
+//! its echo.
+//!
+//! [`read_line`] reads line based protocols instead. A read of zero bytes means that the peer
+//! closed the connection, so retrying it would spin forever: it returns `None` instead.
+
+use std::io::{Error, ErrorKind};
+
+use futures::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Maximum size of a message, larger ones are rejected before being allocated.
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Sends every message received on the input back to the output.
+///
+/// It resolves successfully when the client closes the connection between two messages.
+pub async fn serve_echo<R, W>(mut input: R, mut output: W) -> Result<(), Error>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    while let Some(message) = read_message(&mut input).await? {
+        write_message(&mut output, &message).await?;
+    }
+    Ok(())
+}
+
+/// Sends the message to a server running [`serve_echo`] and returns its echo.
+pub async fn client_echo<R, W>(
+    input: &mut R,
+    output: &mut W,
+    message: &[u8],
+) -> Result<Vec<u8>, Error>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    write_message(output, message).await?;
+    read_message(input)
+        .await?
+        .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "connection closed by the server"))
+}
+
+/// Reads one line, including its line feed, returns `None` if the input ends before it.
+///
+/// The last line of the input is returned even if it does not end with a line feed.
+pub async fn read_line<R>(input: &mut R) -> Result<Option<String>, Error>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut line = String::new();
+    Ok(match input.read_line(&mut line).await? {
+        0 => None,
+        _ => Some(line),
+    })
+}
+
+/// Reads one message, returns `None` if the input ends before it.
+async fn read_message<R>(input: &mut R) -> Result<Option<Vec<u8>>, Error>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut len = [0; 4];
+    let mut read = 0;
+    while read < len.len() {
+        match input.read(&mut len[read..]).await? {
+            0 if read == 0 => return Ok(None),
+            0 => return Err(ErrorKind::UnexpectedEof.into()),
+            n => read += n,
+        }
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("message of {len} bytes exceeds the maximum size"),
+        ));
+    }
+    let mut message = vec![0; len];
+    input.read_exact(&mut message).await?;
+    Ok(Some(message))
+}
+
+async fn write_message<W>(output: &mut W, message: &[u8]) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    if message.len() > MAX_MESSAGE_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "message of {} bytes exceeds the maximum size",
+                message.len()
+            ),
+        ));
+    }
+    output
+        .write_all(&(message.len() as u32).to_be_bytes())
+        .await?;
+    output.write_all(message).await?;
+    output.flush().await
+}
+
+#[cfg(test)]
+#[cfg_attr(coverage_nightly, coverage(off))]
+mod tests {
+    use futures::{executor::LocalPool, task::LocalSpawnExt};
+
+    use super::*;
+
+    #[test]
+    fn test_raw_echo() {
+        let (input, mut client_output) = sluice::pipe::pipe();
+        let (mut client_input, output) = sluice::pipe::pipe();
+
+        let mut exec = LocalPool::new();
+        let server = exec
+            .spawner()
+            .spawn_local_with_handle(serve_echo(input, output))
+            .unwrap();
+
+        let res = exec.run_until(async move {
+            let messages: [&[u8]; 3] = [b"hello", b"", &[42; 100_000]];
+            for message in messages {
+                let echo = client_echo(&mut client_input, &mut client_output, message).await?;
+                assert_eq!(echo, message);
+            }
+
+            client_output.close().await?;
+            server.await?;
+
+            Ok::<_, Box<dyn std::error::Error>>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_read_line() {
+        let mut input = futures::io::Cursor::new(b"ping\npong".to_vec());
+
+        let res = futures::executor::block_on(async {
+            assert_eq!(read_line(&mut input).await?.as_deref(), Some("ping\n"));
+            assert_eq!(read_line(&mut input).await?.as_deref(), Some("pong"));
+            assert_eq!(read_line(&mut input).await?, None);
+            Ok::<_, Error>(())
+        });
+
+        res.unwrap();
+    }
+
+    #[test]
+    fn test_raw_echo_too_large() {
+        let (mut input, mut output) = sluice::pipe::pipe();
+
+        let res = futures::executor::block_on(async move {
+            output
+                .write_all(&(MAX_MESSAGE_SIZE as u32 + 1).to_be_bytes())
+                .await?;
+            read_message(&mut input).await
+        });
+
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}